@@ -40,11 +40,14 @@ use tower_http::trace::TraceLayer;
 use tracing::instrument::WithSubscriber;
 use tracing::Instrument;
 
+use super::client_certificate::APOLLO_TLS_CLIENT_CERTIFICATE;
 use super::listeners::ensure_endpoints_consistency;
 use super::listeners::ensure_listenaddrs_consistency;
 use super::listeners::extra_endpoints;
 use super::listeners::ListenersAndRouters;
+use super::utils::ConnectionInfo;
 use super::utils::PropagatingMakeSpan;
+use super::websocket;
 use super::ListenAddrAndRouter;
 use super::ENDPOINT_CALLBACK;
 use crate::axum_factory::compression::Compressor;
@@ -52,6 +55,7 @@ use crate::axum_factory::listeners::get_extra_listeners;
 use crate::axum_factory::listeners::serve_router_on_listen_addr;
 use crate::configuration::Configuration;
 use crate::configuration::ListenAddr;
+use crate::configuration::WebSocket as WebSocketConfig;
 use crate::http_server_factory::HttpServerFactory;
 use crate::http_server_factory::HttpServerHandle;
 use crate::http_server_factory::Listener;
@@ -554,12 +558,19 @@ where
 {
     let early_cancel = configuration.supergraph.early_cancel;
     let experimental_log_on_broken_pipe = configuration.supergraph.experimental_log_on_broken_pipe;
+    let websocket = configuration
+        .supergraph
+        .websocket
+        .enabled
+        .then(|| configuration.supergraph.websocket.clone());
     let mut router = Router::new().route(
         &configuration.supergraph.sanitized_path(),
         get({
+            let websocket = websocket.clone();
             move |Extension(service): Extension<RF>, request: Request<DecompressionBody<Body>>| {
-                handle_graphql(
-                    service.create().boxed(),
+                handle_get(
+                    service,
+                    websocket,
                     early_cancel,
                     experimental_log_on_broken_pipe,
                     request,
@@ -584,8 +595,9 @@ where
             get({
                 move |Extension(service): Extension<RF>,
                       request: Request<DecompressionBody<Body>>| {
-                    handle_graphql(
-                        service.create().boxed(),
+                    handle_get(
+                        service,
+                        websocket,
                         early_cancel,
                         experimental_log_on_broken_pipe,
                         request,
@@ -609,6 +621,31 @@ where
     router
 }
 
+async fn handle_get<RF>(
+    service_factory: RF,
+    websocket: Option<WebSocketConfig>,
+    early_cancel: bool,
+    experimental_log_on_broken_pipe: bool,
+    http_request: Request<DecompressionBody<Body>>,
+) -> Response
+where
+    RF: RouterFactory,
+{
+    match websocket {
+        Some(config) if websocket::is_upgrade_request(&http_request) => {
+            websocket::upgrade(service_factory, config, http_request).await
+        }
+        _ => handle_graphql(
+            service_factory.create().boxed(),
+            early_cancel,
+            experimental_log_on_broken_pipe,
+            http_request,
+        )
+        .await
+        .into_response(),
+    }
+}
+
 async fn handle_graphql(
     service: router::BoxService,
     early_cancel: bool,
@@ -623,6 +660,16 @@ async fn handle_graphql(
 
     let request: router::Request = http_request.into();
     let context = request.context.clone();
+    if let Some(client_certificate) = request
+        .router_request
+        .extensions()
+        .get::<ConnectionInfo>()
+        .and_then(|connection_info| connection_info.client_certificate.as_deref())
+    {
+        if let Err(e) = context.insert(APOLLO_TLS_CLIENT_CERTIFICATE, client_certificate.clone()) {
+            tracing::error!(%e, "could not insert the client certificate in the context");
+        }
+    }
     let accept_encoding = request
         .router_request
         .headers()
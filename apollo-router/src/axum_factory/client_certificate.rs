@@ -0,0 +1,119 @@
+//! Identity of the client certificate presented on a TLS connection
+
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use x509_cert::der::Decode;
+use x509_cert::ext::pkix::name::GeneralName;
+use x509_cert::ext::pkix::SubjectAltName;
+use x509_cert::Certificate;
+
+/// Context key containing the verified client certificate, when the supergraph listener
+/// uses TLS client authentication and the client presented a certificate
+pub(crate) const APOLLO_TLS_CLIENT_CERTIFICATE: &str = "apollo_tls::client_certificate";
+
+/// Fields of a verified client certificate
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ClientCertificate {
+    /// subject distinguished name, in RFC 4514 format
+    pub(crate) subject: String,
+    /// issuer distinguished name, in RFC 4514 format
+    pub(crate) issuer: String,
+    /// serial number, hex encoded
+    pub(crate) serial_number: String,
+    /// subject alternative names (DNS names, emails, URIs and IP addresses)
+    pub(crate) subject_alternative_names: Vec<String>,
+    /// SHA-256 hash of the DER encoded certificate, hex encoded
+    pub(crate) fingerprint: String,
+}
+
+impl ClientCertificate {
+    /// Extracts the certificate fields from its DER encoding.
+    ///
+    /// The certificate was already verified by rustls during the handshake, so this
+    /// only returns `None` if the certificate uses an encoding that we cannot parse
+    pub(crate) fn from_der(der: &[u8]) -> Option<Self> {
+        let certificate = Certificate::from_der(der)
+            .map_err(|e| tracing::warn!(%e, "could not parse the client certificate"))
+            .ok()?;
+        let tbs = &certificate.tbs_certificate;
+
+        let subject_alternative_names = match tbs.get::<SubjectAltName>() {
+            Ok(Some((_critical, SubjectAltName(names)))) => {
+                names.iter().filter_map(general_name_to_string).collect()
+            }
+            Ok(None) => Vec::new(),
+            Err(e) => {
+                tracing::warn!(%e, "could not parse the client certificate's subject alternative names");
+                Vec::new()
+            }
+        };
+
+        Some(ClientCertificate {
+            subject: tbs.subject.to_string(),
+            issuer: tbs.issuer.to_string(),
+            serial_number: hex::encode(tbs.serial_number.as_bytes()),
+            subject_alternative_names,
+            fingerprint: hex::encode(Sha256::digest(der)),
+        })
+    }
+}
+
+fn general_name_to_string(name: &GeneralName) -> Option<String> {
+    match name {
+        GeneralName::DnsName(name) => Some(format!("DNS:{}", name.as_str())),
+        GeneralName::Rfc822Name(name) => Some(format!("email:{}", name.as_str())),
+        GeneralName::UniformResourceIdentifier(name) => Some(format!("URI:{}", name.as_str())),
+        GeneralName::IpAddress(address) => {
+            let address = match address.as_bytes() {
+                bytes if bytes.len() == 4 => {
+                    IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?))
+                }
+                bytes if bytes.len() == 16 => {
+                    IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?))
+                }
+                _ => return None,
+            };
+            Some(format!("IP:{address}"))
+        }
+        GeneralName::DirectoryName(name) => Some(format!("DirName:{name}")),
+        GeneralName::OtherName(_) | GeneralName::EdiPartyName(_) | GeneralName::RegisteredId(_) => {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::load_certs;
+
+    #[test]
+    fn parse_certificate_fields() {
+        let certificate = load_certs(include_str!("../services/http/testdata/client.crt")).unwrap();
+        let certificate = ClientCertificate::from_der(&certificate[0].0).unwrap();
+        assert_eq!(
+            certificate,
+            ClientCertificate {
+                subject: "CN=router,O=Apollo GraphQL,C=FR".to_string(),
+                issuer: "CN=Apollo Test CA,O=Apollo GraphQL,C=FR".to_string(),
+                serial_number: "6baede5abef593892fa5b77b3735e00437a90f3b".to_string(),
+                subject_alternative_names: vec![],
+                fingerprint: "14163883e27d29ba6c37de3ab761af895aedab73b85cfc7a00a5ca97700e67ba"
+                    .to_string(),
+            }
+        );
+
+        let certificate = load_certs(include_str!("../services/http/testdata/server.crt")).unwrap();
+        let certificate = ClientCertificate::from_der(&certificate[0].0).unwrap();
+        assert_eq!(
+            certificate.subject_alternative_names,
+            vec!["DNS:localhost".to_string()]
+        );
+    }
+}
@@ -21,6 +21,7 @@ use tokio::sync::mpsc;
 use tokio::sync::Notify;
 use tower_service::Service;
 
+use crate::axum_factory::client_certificate::ClientCertificate;
 use crate::axum_factory::utils::ConnectionInfo;
 use crate::axum_factory::utils::InjectConnectionInfo;
 use crate::axum_factory::ENDPOINT_CALLBACK;
@@ -251,6 +252,7 @@ pub(super) fn serve_router_on_listen_addr(
                                         let app = InjectConnectionInfo::new(app, ConnectionInfo {
                                             peer_address: stream.peer_addr().ok(),
                                             server_address: stream.local_addr().ok(),
+                                            client_certificate: None,
                                         });
                                         let app = IdleConnectionChecker::new(received_first_request.clone(), app);
 
@@ -262,7 +264,8 @@ pub(super) fn serve_router_on_listen_addr(
                                             let connection = Http::new()
                                             .http1_keep_alive(true)
                                             .http1_header_read_timeout(Duration::from_secs(10))
-                                            .serve_connection(stream, app);
+                                            .serve_connection(stream, app)
+                                            .with_upgrades();
 
                                         tokio::pin!(connection);
                                         tokio::select! {
@@ -291,7 +294,8 @@ pub(super) fn serve_router_on_listen_addr(
                                         let app = IdleConnectionChecker::new(received_first_request.clone(), app);
                                         let connection = Http::new()
                                         .http1_keep_alive(true)
-                                        .serve_connection(stream, app);
+                                        .serve_connection(stream, app)
+                                        .with_upgrades();
 
                                         tokio::pin!(connection);
                                         tokio::select! {
@@ -316,6 +320,17 @@ pub(super) fn serve_router_on_listen_addr(
                                     },
                                     NetworkStream::Tls(stream) => {
                                         let received_first_request = Arc::new(AtomicBool::new(false));
+                                        // if client authentication is enabled, the certificate was verified during the handshake
+                                        let client_certificate = stream.get_ref().1
+                                            .peer_certificates()
+                                            .and_then(|certificates| certificates.first())
+                                            .and_then(|certificate| ClientCertificate::from_der(&certificate.0))
+                                            .map(Arc::new);
+                                        let app = InjectConnectionInfo::new(app, ConnectionInfo {
+                                            peer_address: stream.get_ref().0.peer_addr().ok(),
+                                            server_address: stream.get_ref().0.local_addr().ok(),
+                                            client_certificate,
+                                        });
                                         let app = IdleConnectionChecker::new(received_first_request.clone(), app);
 
                                         stream.get_ref().0
@@ -331,7 +346,8 @@ pub(super) fn serve_router_on_listen_addr(
                                             .http1_keep_alive(true)
                                             .http1_header_read_timeout(Duration::from_secs(10))
                                             .http2_only(http2)
-                                            .serve_connection(stream, app);
+                                            .serve_connection(stream, app)
+                                            .with_upgrades();
 
                                         tokio::pin!(connection);
                                         tokio::select! {
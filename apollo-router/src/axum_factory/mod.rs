@@ -1,10 +1,12 @@
 //! axum factory is useful to create an [`AxumHttpServerFactory`] which implements [`crate::http_server_factory::HttpServerFactory`]
 mod axum_http_server_factory;
+pub(crate) mod client_certificate;
 pub(crate) mod compression;
 mod listeners;
 #[cfg(test)]
 pub(crate) mod tests;
 pub(crate) mod utils;
+pub(crate) mod websocket;
 
 use std::sync::Arc;
 use std::sync::OnceLock;
@@ -1170,7 +1170,7 @@ async fn it_errors_on_bad_accept_header() -> Result<(), ApolloRouterError> {
     );
     assert_eq!(
         response.text().await.unwrap(),
-        r#"{"errors":[{"message":"'accept' header must be one of: \\\"*/*\\\", \"application/json\", \"application/graphql-response+json\", \"multipart/mixed;subscriptionSpec=1.0\" or \"multipart/mixed;deferSpec=20220824\"","extensions":{"code":"INVALID_ACCEPT_HEADER"}}]}"#
+        r#"{"errors":[{"message":"'accept' header must be one of: \\\"*/*\\\", \"application/json\", \"application/graphql-response+json\", \"multipart/mixed;subscriptionSpec=1.0\", \"multipart/mixed;deferSpec=20220824\", \"multipart/mixed;incrementalSpec=v0.2\" or \"text/event-stream\"","extensions":{"code":"INVALID_ACCEPT_HEADER"}}]}"#
     );
 
     server.shutdown().await
@@ -2387,3 +2387,125 @@ async fn test_supergraph_timeout() {
     let body = response.bytes().await.unwrap();
     assert_eq!(std::str::from_utf8(&body).unwrap(), "request timed out");
 }
+
+#[tokio::test]
+async fn it_serves_graphql_over_websocket() -> Result<(), ApolloRouterError> {
+    use futures::SinkExt;
+    use tokio_tungstenite::connect_async;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let expected_response = graphql::Response::builder()
+        .data(json!({"me": {"name": "Ada"}}))
+        .build();
+    let example_response = expected_response.clone();
+    let router_service = service_fn(move |req: router::Request| {
+        let example_response = example_response.clone();
+        async move {
+            // each operation is executed as a POST request, with the connection_init payload
+            assert_eq!(req.router_request.method(), Method::POST);
+            assert_eq!(
+                req.context
+                    .get::<_, serde_json::Value>(websocket::APOLLO_WEBSOCKET_CONNECTION_PARAMS)
+                    .unwrap(),
+                Some(json!({"token": "XXX"}))
+            );
+            Ok::<_, BoxError>(router::Response {
+                response: http::Response::builder()
+                    .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
+                    .body(serde_json::to_vec(&example_response).unwrap().into())
+                    .unwrap(),
+                context: req.context,
+            })
+        }
+    })
+    .boxed();
+
+    let mut conf = Configuration::fake_builder().build().unwrap();
+    conf.supergraph.websocket.enabled = true;
+    let (server, _client) =
+        init_with_config(router_service, Arc::new(conf), MultiMap::new()).await?;
+    let url = format!("{}/", server.graphql_listen_address().as_ref().unwrap())
+        .replace("http://", "ws://");
+
+    let mut request = url.as_str().into_client_request().unwrap();
+    request.headers_mut().insert(
+        header::SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static("graphql-transport-ws"),
+    );
+    let (mut socket, response) = connect_async(request).await.unwrap();
+    assert_eq!(
+        response.headers().get(header::SEC_WEBSOCKET_PROTOCOL),
+        Some(&HeaderValue::from_static("graphql-transport-ws"))
+    );
+
+    socket
+        .send(Message::Text(
+            json!({"type": "connection_init", "payload": {"token": "XXX"}}).to_string(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        read_json(&mut socket).await,
+        json!({"type": "connection_ack"})
+    );
+
+    socket
+        .send(Message::Text(json!({"type": "ping"}).to_string()))
+        .await
+        .unwrap();
+    assert_eq!(
+        read_json(&mut socket).await,
+        json!({"type": "pong", "payload": null})
+    );
+
+    socket
+        .send(Message::Text(
+            json!({
+                "id": "1",
+                "type": "subscribe",
+                "payload": {"query": "query { me { name } }"}
+            })
+            .to_string(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        read_json(&mut socket).await,
+        json!({"id": "1", "type": "next", "payload": expected_response})
+    );
+    assert_eq!(
+        read_json(&mut socket).await,
+        json!({"id": "1", "type": "complete"})
+    );
+
+    // a second connection_init is a protocol error
+    socket
+        .send(Message::Text(
+            json!({"type": "connection_init"}).to_string(),
+        ))
+        .await
+        .unwrap();
+    match socket.next().await.unwrap().unwrap() {
+        Message::Close(Some(frame)) => assert_eq!(u16::from(frame.code), 4429),
+        message => panic!("unexpected message: {message:?}"),
+    }
+
+    server.shutdown().await?;
+    Ok(())
+}
+
+async fn read_json<S>(socket: &mut S) -> serde_json::Value
+where
+    S: futures::Stream<
+            Item = Result<
+                tokio_tungstenite::tungstenite::Message,
+                tokio_tungstenite::tungstenite::Error,
+            >,
+        > + Unpin,
+{
+    match socket.next().await.unwrap().unwrap() {
+        tokio_tungstenite::tungstenite::Message::Text(text) => serde_json::from_str(&text).unwrap(),
+        message => panic!("unexpected message: {message:?}"),
+    }
+}
@@ -1,6 +1,7 @@
 //! Utilities used for [`super::AxumHttpServerFactory`]
 
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 use opentelemetry::global;
 use opentelemetry::trace::TraceContextExt;
@@ -8,6 +9,7 @@ use tower_http::trace::MakeSpan;
 use tower_service::Service;
 use tracing::Span;
 
+use super::client_certificate::ClientCertificate;
 use crate::plugins::telemetry::SpanMode;
 use crate::plugins::telemetry::OTEL_STATUS_CODE;
 use crate::plugins::telemetry::OTEL_STATUS_CODE_ERROR;
@@ -73,6 +75,8 @@ pub(crate) struct InjectConnectionInfo<S> {
 pub(crate) struct ConnectionInfo {
     pub(crate) peer_address: Option<SocketAddr>,
     pub(crate) server_address: Option<SocketAddr>,
+    /// verified certificate presented by the client, if TLS client authentication is enabled
+    pub(crate) client_certificate: Option<Arc<ClientCertificate>>,
 }
 
 impl<S> InjectConnectionInfo<S> {
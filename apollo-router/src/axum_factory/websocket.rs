@@ -0,0 +1,465 @@
+//! GraphQL over WebSocket for clients, with the graphql-transport-ws protocol
+//!
+//! Reference: <https://github.com/enisdenjo/graphql-ws/blob/master/PROTOCOL.md>
+
+use std::collections::HashMap;
+
+use axum::extract::ws::CloseFrame;
+use axum::extract::ws::Message;
+use axum::extract::ws::WebSocket;
+use axum::extract::FromRequestParts;
+use axum::extract::WebSocketUpgrade;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use futures::SinkExt;
+use futures::StreamExt;
+use http::header::ACCEPT;
+use http::header::CONNECTION;
+use http::header::CONTENT_LENGTH;
+use http::header::CONTENT_TYPE;
+use http::header::SEC_WEBSOCKET_PROTOCOL;
+use http::header::UPGRADE;
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Method;
+use http::Request;
+use http::Uri;
+use hyper::Body;
+use mime::APPLICATION_JSON;
+use serde_json_bytes::Value;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::task::AbortHandle;
+use tower::ServiceExt;
+use tower_http::decompression::DecompressionBody;
+
+use super::client_certificate::APOLLO_TLS_CLIENT_CERTIFICATE;
+use super::utils::ConnectionInfo;
+use crate::configuration::WebSocket as WebSocketConfig;
+use crate::graphql;
+use crate::protocols::websocket::ClientMessage;
+use crate::protocols::websocket::ServerError;
+use crate::protocols::websocket::ServerMessage;
+use crate::router_factory::RouterFactory;
+use crate::services::router;
+use crate::services::router::ResponseStreamSender;
+use crate::services::MULTIPART_DEFER_ACCEPT;
+use crate::services::MULTIPART_SUBSCRIPTION_ACCEPT;
+
+/// Context key holding the payload of the `connection_init` message
+pub(crate) const APOLLO_WEBSOCKET_CONNECTION_PARAMS: &str = "apollo_websocket::connection_params";
+
+const GRAPHQL_TRANSPORT_WS_PROTOCOL: &str = "graphql-transport-ws";
+
+// Close codes defined by the graphql-transport-ws protocol
+const INVALID_MESSAGE: u16 = 4400;
+const UNAUTHORIZED: u16 = 4401;
+const SUBPROTOCOL_NOT_ACCEPTABLE: u16 = 4406;
+const CONNECTION_INITIALISATION_TIMEOUT: u16 = 4408;
+const SUBSCRIBER_ALREADY_EXISTS: u16 = 4409;
+const TOO_MANY_INITIALISATION_REQUESTS: u16 = 4429;
+
+/// Returns true if the request asks to upgrade the connection to a WebSocket
+pub(super) fn is_upgrade_request<B>(request: &Request<B>) -> bool {
+    request.method() == Method::GET
+        && request
+            .headers()
+            .get(UPGRADE)
+            .and_then(|value| value.to_str().ok())
+            .map_or(false, |value| value.eq_ignore_ascii_case("websocket"))
+}
+
+/// Upgrades the connection, then serves GraphQL operations over it
+pub(super) async fn upgrade<RF>(
+    service_factory: RF,
+    config: WebSocketConfig,
+    request: Request<DecompressionBody<Body>>,
+) -> Response
+where
+    RF: RouterFactory,
+{
+    let (mut parts, _body) = request.into_parts();
+    let upgrade = match WebSocketUpgrade::from_request_parts(&mut parts, &()).await {
+        Ok(upgrade) => upgrade,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let accepts_protocol = parts
+        .headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|protocol| protocol.trim() == GRAPHQL_TRANSPORT_WS_PROTOCOL);
+    let connection = Connection {
+        uri: parts.uri,
+        headers: parts.headers,
+        connection_info: parts.extensions.get::<ConnectionInfo>().cloned(),
+    };
+
+    upgrade
+        .protocols([GRAPHQL_TRANSPORT_WS_PROTOCOL])
+        .max_message_size(config.max_message_size)
+        .on_upgrade(move |mut socket| async move {
+            if accepts_protocol {
+                serve(socket, service_factory, config, connection).await
+            } else {
+                let _ = socket
+                    .send(close(
+                        SUBPROTOCOL_NOT_ACCEPTABLE,
+                        "Subprotocol not acceptable",
+                    ))
+                    .await;
+            }
+        })
+}
+
+/// What we keep from the upgrade request, to create the request of each operation
+#[derive(Clone)]
+struct Connection {
+    uri: Uri,
+    headers: HeaderMap,
+    connection_info: Option<ConnectionInfo>,
+}
+
+async fn serve<RF>(
+    socket: WebSocket,
+    service_factory: RF,
+    config: WebSocketConfig,
+    connection: Connection,
+) where
+    RF: RouterFactory,
+{
+    let (mut sink, mut stream) = socket.split();
+
+    // Operations run concurrently, so messages to the client go through a single writer task
+    let (sender, mut receiver) = mpsc::channel::<Message>(32);
+    let writer = tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            let is_close = matches!(message, Message::Close(_));
+            if sink.send(message).await.is_err() || is_close {
+                break;
+            }
+        }
+        let _ = sink.close().await;
+    });
+
+    let (finished_sender, mut finished_receiver) = mpsc::unbounded_channel::<(String, u64)>();
+    let mut operations: HashMap<String, (u64, AbortHandle)> = HashMap::new();
+    let mut next_generation = 0;
+    let mut connection_params: Option<Option<Value>> = None;
+
+    let init_timeout = tokio::time::sleep(config.connection_init_timeout);
+    tokio::pin!(init_timeout);
+    let mut heartbeat = config.heartbeat_interval.into_option().map(|duration| {
+        let mut interval =
+            tokio::time::interval_at(tokio::time::Instant::now() + duration, duration);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        interval
+    });
+
+    loop {
+        let message = tokio::select! {
+            _ = &mut init_timeout, if connection_params.is_none() => {
+                let _ = sender
+                    .send(close(CONNECTION_INITIALISATION_TIMEOUT, "Connection initialisation timeout"))
+                    .await;
+                break;
+            }
+            Some((id, generation)) = finished_receiver.recv() => {
+                // the client may have reused the id for a new operation already
+                if operations.get(&id).map_or(false, |(current, _)| *current == generation) {
+                    operations.remove(&id);
+                }
+                continue;
+            }
+            _ = async { heartbeat.as_mut().expect("checked by the precondition").tick().await }, if heartbeat.is_some() => {
+                if sender.send(text(&ServerMessage::Ping { payload: None })).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            message = stream.next() => message,
+        };
+
+        let message = match message {
+            Some(Ok(Message::Text(text))) => serde_json::from_str::<ClientMessage>(&text),
+            Some(Ok(Message::Binary(bytes))) => serde_json::from_slice::<ClientMessage>(&bytes),
+            // pings are answered by the WebSocket implementation
+            Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+        };
+
+        match message {
+            Err(error) => {
+                tracing::debug!(%error, "invalid websocket message");
+                let _ = sender
+                    .send(close(INVALID_MESSAGE, "Invalid message received"))
+                    .await;
+                break;
+            }
+            Ok(ClientMessage::ConnectionInit { payload }) => {
+                if connection_params.is_some() {
+                    let _ = sender
+                        .send(close(
+                            TOO_MANY_INITIALISATION_REQUESTS,
+                            "Too many initialisation requests",
+                        ))
+                        .await;
+                    break;
+                }
+                connection_params = Some(payload);
+                if sender
+                    .send(text(&ServerMessage::ConnectionAck))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Ok(ClientMessage::Ping { payload }) => {
+                let pong = ServerMessage::Pong {
+                    payload: payload.and_then(|payload| serde_json::to_value(payload).ok()),
+                };
+                if sender.send(text(&pong)).await.is_err() {
+                    break;
+                }
+            }
+            Ok(ClientMessage::Pong { .. }) => {}
+            Ok(ClientMessage::Subscribe { id, payload }) => {
+                let Some(params) = &connection_params else {
+                    let _ = sender.send(close(UNAUTHORIZED, "Unauthorized")).await;
+                    break;
+                };
+                if operations.contains_key(&id) {
+                    let _ = sender
+                        .send(close(
+                            SUBSCRIBER_ALREADY_EXISTS,
+                            &format!("Subscriber for {id} already exists"),
+                        ))
+                        .await;
+                    break;
+                }
+                if operations.len() >= config.max_operations {
+                    let error = ServerMessage::Error {
+                        id,
+                        payload: ServerError::Errors(vec![graphql::Error::builder()
+                            .message(format!(
+                                "too many operations on this connection, the maximum is {}",
+                                config.max_operations
+                            ))
+                            .extension_code("WEBSOCKET_TOO_MANY_OPERATIONS")
+                            .build()]),
+                    };
+                    if sender.send(text(&error)).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+
+                let generation = next_generation;
+                next_generation += 1;
+                let request = operation_request(&connection, params.clone(), payload);
+                let service = service_factory.create();
+                let sender = sender.clone();
+                let finished_sender = finished_sender.clone();
+                let operation_id = id.clone();
+                let handle = tokio::spawn(async move {
+                    execute(service, request, &operation_id, &sender).await;
+                    let _ = finished_sender.send((operation_id, generation));
+                });
+                operations.insert(id, (generation, handle.abort_handle()));
+            }
+            Ok(ClientMessage::Complete { id }) => {
+                if let Some((_, operation)) = operations.remove(&id) {
+                    operation.abort();
+                }
+            }
+            // messages of the legacy subscriptions-transport-ws protocol
+            Ok(
+                ClientMessage::OldStart { .. }
+                | ClientMessage::OldStop { .. }
+                | ClientMessage::ConnectionTerminate,
+            ) => {
+                let _ = sender
+                    .send(close(INVALID_MESSAGE, "Invalid message received"))
+                    .await;
+                break;
+            }
+        }
+    }
+
+    for (_, operation) in operations.into_values() {
+        operation.abort();
+    }
+    drop(sender);
+    let _ = writer.await;
+}
+
+/// Creates the router request for an operation, with the headers of the upgrade request
+fn operation_request(
+    connection: &Connection,
+    connection_params: Option<Value>,
+    payload: graphql::Request,
+) -> router::Request {
+    let mut headers = connection.headers.clone();
+    for name in [CONNECTION, UPGRADE, CONTENT_LENGTH] {
+        headers.remove(name);
+    }
+    let websocket_headers: Vec<HeaderName> = headers
+        .keys()
+        .filter(|name| name.as_str().starts_with("sec-websocket-"))
+        .cloned()
+        .collect();
+    for name in websocket_headers {
+        headers.remove(name);
+    }
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(APPLICATION_JSON.essence_str()),
+    );
+    // the responses are not serialized, so the client accepts all of them
+    headers.insert(
+        ACCEPT,
+        HeaderValue::from_str(&format!(
+            "{}, {}, {}",
+            APPLICATION_JSON.essence_str(),
+            MULTIPART_DEFER_ACCEPT,
+            MULTIPART_SUBSCRIPTION_ACCEPT
+        ))
+        .expect("valid header value; qed"),
+    );
+
+    let body = serde_json::to_vec(&payload).expect("a GraphQL request can be serialized; qed");
+    let mut http_request = http::Request::new(Body::from(body));
+    *http_request.method_mut() = Method::POST;
+    *http_request.uri_mut() = connection.uri.clone();
+    *http_request.headers_mut() = headers;
+    if let Some(connection_info) = &connection.connection_info {
+        http_request
+            .extensions_mut()
+            .insert(connection_info.clone());
+    }
+
+    let request: router::Request = http_request.into();
+    if let Some(client_certificate) = connection
+        .connection_info
+        .as_ref()
+        .and_then(|connection_info| connection_info.client_certificate.as_deref())
+    {
+        if let Err(e) = request
+            .context
+            .insert(APOLLO_TLS_CLIENT_CERTIFICATE, client_certificate.clone())
+        {
+            tracing::error!(%e, "could not insert the client certificate in the context");
+        }
+    }
+    if let Some(connection_params) = connection_params {
+        if let Err(e) = request
+            .context
+            .insert(APOLLO_WEBSOCKET_CONNECTION_PARAMS, connection_params)
+        {
+            tracing::error!(%e, "could not insert the connection params in the context");
+        }
+    }
+    request
+}
+
+async fn execute<S>(service: S, request: router::Request, id: &str, sender: &mpsc::Sender<Message>)
+where
+    S: tower::Service<router::Request, Response = router::Response, Error = tower::BoxError>,
+{
+    let (stream_sender, mut stream_receiver) = oneshot::channel();
+    request
+        .context
+        .extensions()
+        .lock()
+        .insert(ResponseStreamSender(stream_sender));
+
+    let response = match service.oneshot(request).await {
+        Ok(response) => response,
+        Err(error) => {
+            let error = graphql::Error::builder()
+                .message(format!("router service call failed: {error}"))
+                .extension_code("INTERNAL_SERVER_ERROR")
+                .build();
+            let _ = sender.send(error_message(id, vec![error])).await;
+            return;
+        }
+    };
+
+    match stream_receiver.try_recv() {
+        Ok(mut stream) => {
+            while let Some(response) = stream.next().await {
+                // subscriptions end with an empty response
+                if response.data.is_none()
+                    && response.errors.is_empty()
+                    && response.extensions.is_empty()
+                    && response.incremental.is_empty()
+                {
+                    continue;
+                }
+                let next = ServerMessage::Next {
+                    id: id.to_string(),
+                    payload: response,
+                };
+                if sender.send(text(&next)).await.is_err() {
+                    return;
+                }
+            }
+        }
+        // the request was rejected before execution, for example by the authentication plugin
+        Err(_) => {
+            let status = response.response.status();
+            let body = hyper::body::to_bytes(response.response.into_body()).await;
+            let response = body
+                .ok()
+                .and_then(|body| serde_json::from_slice::<graphql::Response>(&body).ok());
+            match response {
+                Some(response) if response.data.is_none() && !response.errors.is_empty() => {
+                    let _ = sender.send(error_message(id, response.errors)).await;
+                    return;
+                }
+                Some(response) => {
+                    let next = ServerMessage::Next {
+                        id: id.to_string(),
+                        payload: response,
+                    };
+                    if sender.send(text(&next)).await.is_err() {
+                        return;
+                    }
+                }
+                None => {
+                    let error = graphql::Error::builder()
+                        .message(format!("the request failed with status {status}"))
+                        .extension_code("INTERNAL_SERVER_ERROR")
+                        .build();
+                    let _ = sender.send(error_message(id, vec![error])).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    let _ = sender
+        .send(text(&ServerMessage::Complete { id: id.to_string() }))
+        .await;
+}
+
+fn error_message(id: &str, errors: Vec<graphql::Error>) -> Message {
+    text(&ServerMessage::Error {
+        id: id.to_string(),
+        payload: ServerError::Errors(errors),
+    })
+}
+
+fn text(message: &ServerMessage) -> Message {
+    Message::Text(serde_json::to_string(message).expect("server messages can be serialized; qed"))
+}
+
+fn close(code: u16, reason: &str) -> Message {
+    Message::Close(Some(CloseFrame {
+        code,
+        reason: reason.to_string().into(),
+    }))
+}
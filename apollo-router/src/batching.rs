@@ -31,6 +31,40 @@ use crate::services::SubgraphRequest;
 use crate::services::SubgraphResponse;
 use crate::Context;
 
+/// The total estimated cost of the operations of a client batch, shared by all its operations.
+/// It is inserted in their context extensions when `batching.max_cost` is configured, and
+/// checked by the demand control plugin.
+#[derive(Debug)]
+pub(crate) struct BatchCost {
+    max: f64,
+    total: PMutex<f64>,
+}
+
+impl BatchCost {
+    pub(crate) fn new(max: f64) -> Self {
+        Self {
+            max,
+            total: PMutex::new(0.0),
+        }
+    }
+
+    pub(crate) fn max(&self) -> f64 {
+        self.max
+    }
+
+    /// Adds the estimated cost of an operation to the batch total, if it stays under the maximum.
+    /// Otherwise the total is left unchanged, and the total including the operation is returned
+    /// as an error.
+    pub(crate) fn add(&self, cost: f64) -> Result<(), f64> {
+        let mut total = self.total.lock();
+        if *total + cost > self.max {
+            return Err(*total + cost);
+        }
+        *total += cost;
+        Ok(())
+    }
+}
+
 /// A query that is part of a batch.
 /// Note: It's ok to make transient clones of this struct, but *do not* store clones anywhere apart
 /// from the single copy in the extensions. The batching co-ordinator relies on the fact that all
@@ -474,6 +508,7 @@ mod tests {
 
     use super::assemble_batch;
     use super::Batch;
+    use super::BatchCost;
     use super::BatchQueryInfo;
     use crate::graphql;
     use crate::plugins::traffic_shaping::Http2Config;
@@ -564,6 +599,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn it_limits_the_batch_cost() {
+        let cost = BatchCost::new(10.0);
+        assert_eq!(cost.add(4.0), Ok(()));
+        assert_eq!(cost.add(8.0), Err(12.0));
+        // rejected operations do not count towards the total
+        assert_eq!(cost.add(6.0), Ok(()));
+        assert_eq!(cost.add(1.0), Err(11.0));
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn it_rejects_index_out_of_bounds() {
         let batch = Arc::new(Batch::spawn_handler(2));
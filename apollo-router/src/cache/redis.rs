@@ -4,6 +4,8 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use fred::interfaces::EventInterface;
+use fred::interfaces::LuaInterface;
+use fred::interfaces::PubsubInterface;
 #[cfg(test)]
 use fred::mocks::Mocks;
 use fred::prelude::ClientLike;
@@ -17,9 +19,12 @@ use fred::types::FromRedis;
 use fred::types::PerformanceConfig;
 use fred::types::ReconnectPolicy;
 use fred::types::RedisConfig;
+use fred::types::SetOptions;
 use fred::types::TlsConfig;
 use fred::types::TlsHostMapping;
 use futures::FutureExt;
+use futures::Stream;
+use tokio::sync::broadcast::error::RecvError;
 use tower::BoxError;
 use url::Url;
 
@@ -28,6 +33,12 @@ use super::ValueType;
 use crate::configuration::RedisCache;
 use crate::services::generate_tls_client_config;
 
+// Compare and set operations are run as scripts to be atomic
+const EXPIRE_IF_EQUALS_SCRIPT: &str = "if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('PEXPIRE', KEYS[1], ARGV[2]) else return 0 end";
+const DELETE_IF_EQUALS_SCRIPT: &str = "if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) else return 0 end";
+// like INCRBY followed by PEXPIRE NX, which is only available from Redis 7
+const INCR_SCRIPT: &str = "local value = redis.call('INCRBY', KEYS[1], ARGV[1]) if ARGV[2] and redis.call('PTTL', KEYS[1]) == -1 then redis.call('PEXPIRE', KEYS[1], ARGV[2]) end return value";
+
 const SUPPORTED_REDIS_SCHEMES: [&str; 6] = [
     "redis",
     "rediss",
@@ -527,6 +538,201 @@ impl RedisCacheStorage {
         tracing::trace!("insert result {:?}", r);
     }
 
+    pub(crate) async fn delete<K: KeyType>(&self, key: RedisKey<K>) -> Option<u32> {
+        self.inner
+            .del::<u32, _>(self.make_key(key))
+            .await
+            .map_err(|e| {
+                tracing::error!(error = %e, "redis del error");
+                e
+            })
+            .ok()
+    }
+
+    /// Increments the integer stored at `key`, and returns the new value
+    ///
+    /// If the key has no expiration yet, it is given the `ttl` expiration, or the default one.
+    /// Both are done atomically, so the expiration of an existing counter is never reset.
+    pub(crate) async fn incr<K: KeyType>(
+        &self,
+        key: RedisKey<K>,
+        by: i64,
+        ttl: Option<Duration>,
+    ) -> Option<i64> {
+        let mut args = vec![by.to_string()];
+        if let Some(ttl) = ttl.as_ref().or(self.ttl.as_ref()) {
+            args.push(ttl.as_millis().to_string());
+        }
+        self.inner
+            .eval::<i64, _, _, _>(INCR_SCRIPT, self.make_key(key), args)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = %e, "redis incr error");
+                e
+            })
+            .ok()
+    }
+
+    /// Sets `key` to `value` with the `ttl` expiration, only if the key does not exist
+    ///
+    /// Returns whether the key was set.
+    pub(crate) async fn insert_if_not_exists<K: KeyType>(
+        &self,
+        key: RedisKey<K>,
+        value: &str,
+        ttl: Duration,
+    ) -> Option<bool> {
+        self.inner
+            .set::<Option<String>, _, _>(
+                self.make_key(key),
+                value,
+                Some(Expiration::PX(ttl.as_millis() as i64)),
+                Some(SetOptions::NX),
+                false,
+            )
+            .await
+            .map(|result| result.is_some())
+            .map_err(|e| {
+                tracing::error!(error = %e, "redis set error");
+                e
+            })
+            .ok()
+    }
+
+    /// Resets the expiration of `key` to `ttl`, only if it is still set to `value`
+    ///
+    /// Returns whether the expiration was reset.
+    pub(crate) async fn expire_if_equals<K: KeyType>(
+        &self,
+        key: RedisKey<K>,
+        value: &str,
+        ttl: Duration,
+    ) -> Option<bool> {
+        self.inner
+            .eval::<i64, _, _, _>(
+                EXPIRE_IF_EQUALS_SCRIPT,
+                self.make_key(key),
+                vec![value.to_string(), ttl.as_millis().to_string()],
+            )
+            .await
+            .map(|result| result == 1)
+            .map_err(|e| {
+                tracing::error!(error = %e, "redis expire error");
+                e
+            })
+            .ok()
+    }
+
+    /// Deletes `key`, only if it is still set to `value`
+    pub(crate) async fn delete_if_equals<K: KeyType>(
+        &self,
+        key: RedisKey<K>,
+        value: &str,
+    ) -> Option<bool> {
+        self.inner
+            .eval::<i64, _, _, _>(DELETE_IF_EQUALS_SCRIPT, self.make_key(key), value)
+            .await
+            .map(|result| result == 1)
+            .map_err(|e| {
+                tracing::error!(error = %e, "redis del error");
+                e
+            })
+            .ok()
+    }
+
+    pub(crate) async fn exists<K: KeyType>(&self, key: RedisKey<K>) -> Option<bool> {
+        self.inner
+            .exists::<u32, _>(self.make_key(key))
+            .await
+            .map(|count| count > 0)
+            .map_err(|e| {
+                tracing::error!(error = %e, "redis exists error");
+                e
+            })
+            .ok()
+    }
+
+    /// Publishes `message` on `channel`, and returns the number of clients that received it
+    pub(crate) async fn publish<K: KeyType>(
+        &self,
+        channel: RedisKey<K>,
+        message: String,
+    ) -> Option<u32> {
+        self.inner
+            .publish::<u32, _, _>(self.make_key(channel), message)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = %e, "redis publish error");
+                e
+            })
+            .ok()
+    }
+
+    /// Returns the number of clients subscribed to `channel`
+    pub(crate) async fn subscribers<K: KeyType>(&self, channel: RedisKey<K>) -> Option<u32> {
+        self.inner
+            .pubsub_numsub::<Vec<fred::types::RedisValue>, _>(self.make_key(channel))
+            .await
+            .map_err(|e| {
+                tracing::error!(error = %e, "redis pubsub numsub error");
+                e
+            })
+            .ok()
+            .and_then(|result| result.get(1).and_then(|count| count.as_u64()))
+            .map(|count| count as u32)
+    }
+
+    /// Subscribes to `channel`, and returns the stream of messages published on it
+    ///
+    /// A connection that subscribed to a channel cannot send other commands, so this storage
+    /// should be dedicated to subscriptions.
+    pub(crate) async fn subscribe<K: KeyType>(
+        &self,
+        channel: RedisKey<K>,
+    ) -> Result<impl Stream<Item = String>, RedisError> {
+        let channel = self.make_key(channel);
+        let client = self.inner.clone();
+        let messages = client.on_message();
+        let reconnections = client.reconnect_rx();
+        client.subscribe::<(), _>(channel.as_str()).await?;
+
+        Ok(futures::stream::unfold(
+            (client, channel, messages, reconnections),
+            |(client, channel, mut messages, mut reconnections)| async move {
+                loop {
+                    tokio::select! {
+                        message = messages.recv() => match message {
+                            Ok(message) if &*message.channel == channel.as_str() => {
+                                if let Some(message) = message.value.into_string() {
+                                    return Some((message, (client, channel, messages, reconnections)));
+                                }
+                            }
+                            Ok(_) => {}
+                            Err(RecvError::Lagged(count)) => {
+                                tracing::warn!("skipped {count} messages published on {channel}");
+                            }
+                            Err(RecvError::Closed) => return None,
+                        },
+                        reconnection = reconnections.recv() => match reconnection {
+                            // Subscriptions are not restored when the client reconnects
+                            Ok(_) | Err(RecvError::Lagged(_)) => {
+                                if let Err(e) = client.subscribe::<(), _>(channel.as_str()).await {
+                                    tracing::error!(error = %e, "redis subscribe error");
+                                }
+                            }
+                            Err(RecvError::Closed) => return None,
+                        },
+                    }
+                }
+            },
+        ))
+    }
+
+    pub(crate) async fn unsubscribe<K: KeyType>(&self, channel: RedisKey<K>) {
+        let r = self.inner.unsubscribe(self.make_key(channel)).await;
+        tracing::trace!("unsubscribe result {:?}", r);
+    }
+
     pub(crate) async fn insert_multiple<K: KeyType, V: ValueType>(
         &self,
         data: &[(RedisKey<K>, RedisValue<V>)],
@@ -561,10 +767,39 @@ impl RedisCacheStorage {
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
     use std::time::SystemTime;
 
+    use fred::mocks::Buffer;
     use url::Url;
 
+    #[tokio::test]
+    async fn incr_sets_the_expiration_atomically() {
+        let commands = Arc::new(Buffer::new());
+        let storage = super::RedisCacheStorage::from_mocks(commands.clone())
+            .await
+            .unwrap();
+
+        storage
+            .incr(
+                super::RedisKey("counter".to_string()),
+                2,
+                Some(Duration::from_secs(60)),
+            )
+            .await;
+
+        let commands = commands.take();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(&*commands[0].cmd, "EVAL");
+        let args = commands[0]
+            .args
+            .iter()
+            .map(|arg| arg.as_string().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(args, [super::INCR_SCRIPT, "1", "counter", "2", "60000"]);
+    }
+
     #[test]
     fn ensure_invalid_payload_serialization_doesnt_fail() {
         #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
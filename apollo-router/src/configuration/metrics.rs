@@ -165,6 +165,10 @@ impl InstrumentData {
             apollo.router.config.authentication.jwt,
             "$.authentication[?(@..jwt)]"
         );
+        populate_config_instrument!(
+            apollo.router.config.authentication.api_key,
+            "$.authentication[?(@..api_key)]"
+        );
         populate_config_instrument!(
             apollo.router.config.authentication.aws.sigv4,
             "$.authentication[?(@.subgraph..aws_sig_v4)]"
@@ -175,24 +179,42 @@ impl InstrumentData {
             opt.require_authentication,
             "$[?(@.require_authentication == true)]",
             opt.directives,
-            "$.directives[?(@.enabled == true)]"
+            "$.directives[?(@.enabled == true)]",
+            opt.audit,
+            "$.audit"
         );
         populate_config_instrument!(
             apollo.router.config.coprocessor,
             "$.coprocessor",
             opt.router.request,
-            "$.router.request",
+            "$..router.request",
             opt.router.response,
-            "$.router.response",
+            "$..router.response",
             // Note that supergraph is not supported yet so these will always be empty
             opt.supergraph.request,
-            "$.supergraph.response",
+            "$..supergraph.response",
             opt.supergraph.response,
-            "$.supergraph.request",
+            "$..supergraph.request",
             opt.subgraph.request,
-            "$.subgraph..request",
+            "$..subgraph..request",
             opt.subgraph.response,
-            "$.subgraph..response"
+            "$..subgraph..response",
+            opt.query_planner.request,
+            "$..query_planner.request",
+            opt.query_planner.response,
+            "$..query_planner.response",
+            opt.transport.grpc,
+            "$[?(@.transport == 'grpc')]",
+            opt.chain,
+            "$.coprocessors",
+            opt.chain.transport.grpc,
+            "$.coprocessors[?(@.transport == 'grpc')]",
+            opt.on_error.continue,
+            "$..*[?(@.on_error == 'continue')]",
+            opt.retry,
+            "$..retry[?(@.attempts > 0)]",
+            opt.circuit_breaker,
+            "$..circuit_breaker[?(@.enabled == true)]"
         );
         populate_config_instrument!(
             apollo.router.config.persisted_queries,
@@ -255,6 +277,8 @@ impl InstrumentData {
             "$.tls",
             opt.router.tls.server,
             "$.supergraph",
+            opt.router.tls.server.client_authentication,
+            "$.supergraph.client_authentication",
             opt.router.tls.subgraph.ca_override,
             "$[?(@.subgraph..certificate_authorities)]",
             opt.router.tls.subgraph.client_authentication,
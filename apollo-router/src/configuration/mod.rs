@@ -18,8 +18,11 @@ pub(crate) use persisted_queries::PersistedQueries;
 #[cfg(test)]
 pub(crate) use persisted_queries::PersistedQueriesSafelist;
 use regex::Regex;
+use rustls::server::AllowAnyAnonymousOrAuthenticatedClient;
+use rustls::server::AllowAnyAuthenticatedClient;
 use rustls::Certificate;
 use rustls::PrivateKey;
+use rustls::RootCertStore;
 use rustls::ServerConfig;
 use rustls_pemfile::certs;
 use rustls_pemfile::read_one;
@@ -47,6 +50,9 @@ use crate::configuration::schema::Mode;
 use crate::graphql;
 use crate::notification::Notify;
 use crate::plugin::plugins;
+use crate::plugins::demand_control::DemandControlConfig;
+use crate::plugins::demand_control::APOLLO_DEMAND_CONTROL_PLUGIN_NAME;
+use crate::plugins::subscription::HeartbeatInterval;
 use crate::plugins::subscription::SubscriptionConfig;
 use crate::plugins::subscription::APOLLO_SUBSCRIPTION_PLUGIN;
 use crate::plugins::subscription::APOLLO_SUBSCRIPTION_PLUGIN_NAME;
@@ -562,6 +568,31 @@ impl Configuration {
             }
         }
 
+        // Subgraph batches wait for all the operations of the client batch
+        if self.batching.max_in_flight.is_some() && self.batching.subgraph_batching_enabled() {
+            return Err(ConfigurationError::InvalidConfiguration {
+                message: "`batching.max_in_flight` cannot be used with subgraph batching",
+                error: "either remove batching.max_in_flight or disable batching.subgraph in your router yaml configuration".into()
+            });
+        }
+
+        // The batch cost is the sum of the estimates made by demand control
+        if self.batching.max_cost.is_some() {
+            let estimates_static_cost = self
+                .apollo_plugins
+                .plugins
+                .get(APOLLO_DEMAND_CONTROL_PLUGIN_NAME)
+                .and_then(|conf| serde_json::from_value::<DemandControlConfig>(conf.clone()).ok())
+                .map(|conf| conf.estimates_static_cost())
+                .unwrap_or_default();
+            if !estimates_static_cost {
+                return Err(ConfigurationError::InvalidConfiguration {
+                    message: "`batching.max_cost` requires demand control with the `static_estimated` strategy",
+                    error: "either remove batching.max_cost or enable experimental_demand_control with the static_estimated strategy in your router yaml configuration".into()
+                });
+            }
+        }
+
         if self.experimental_query_planner_mode == QueryPlannerMode::New
             && self.experimental_apollo_metrics_generation_mode != ApolloMetricsGenerationMode::New
         {
@@ -700,6 +731,23 @@ pub(crate) struct Supergraph {
     /// Log a message if the client closes the connection before the response is sent.
     /// Default: false.
     pub(crate) experimental_log_on_broken_pipe: bool,
+
+    /// GraphQL over WebSocket for clients, with the graphql-transport-ws protocol
+    pub(crate) websocket: WebSocket,
+
+    /// Pass `@defer` through to subgraphs that support incremental delivery
+    pub(crate) experimental_defer_passthrough: DeferPassthrough,
+}
+
+/// Defer passthrough configuration
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct DeferPassthrough {
+    /// Subgraphs that support `@defer` with multipart responses. A deferred fragment that
+    /// would be fetched from the same subgraph as its parent is sent to that subgraph
+    /// as part of the parent fetch, instead of with an additional fetch
+    /// Default: no subgraph
+    pub(crate) subgraphs: Vec<String>,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
@@ -757,6 +805,8 @@ impl Supergraph {
             generate_query_fragments: generate_query_fragments.unwrap_or_default(),
             early_cancel: early_cancel.unwrap_or_default(),
             experimental_log_on_broken_pipe: experimental_log_on_broken_pipe.unwrap_or_default(),
+            websocket: Default::default(),
+            experimental_defer_passthrough: Default::default(),
         }
     }
 }
@@ -794,6 +844,8 @@ impl Supergraph {
             generate_query_fragments: generate_query_fragments.unwrap_or_default(),
             early_cancel: early_cancel.unwrap_or_default(),
             experimental_log_on_broken_pipe: experimental_log_on_broken_pipe.unwrap_or_default(),
+            websocket: Default::default(),
+            experimental_defer_passthrough: Default::default(),
         }
     }
 }
@@ -821,6 +873,45 @@ impl Supergraph {
     }
 }
 
+/// GraphQL over WebSocket configuration
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct WebSocket {
+    /// Accept WebSocket connections using the graphql-transport-ws protocol on the GraphQL path
+    /// Default: false
+    pub(crate) enabled: bool,
+
+    /// Time allowed to the client to send the `connection_init` message after opening the connection
+    /// Default: 10s
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[serde(serialize_with = "humantime_serde::serialize")]
+    #[schemars(with = "String")]
+    pub(crate) connection_init_timeout: Duration,
+
+    /// Maximum number of operations running at the same time on one connection
+    /// Default: 100
+    pub(crate) max_operations: usize,
+
+    /// Maximum size of a message sent by the client, in bytes
+    /// Default: 1MB
+    pub(crate) max_message_size: usize,
+
+    /// Interval between the pings sent to the client (default: 5s)
+    pub(crate) heartbeat_interval: HeartbeatInterval,
+}
+
+impl Default for WebSocket {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            connection_init_timeout: Duration::from_secs(10),
+            max_operations: 100,
+            max_message_size: 1024 * 1024,
+            heartbeat_interval: HeartbeatInterval::new_enabled(),
+        }
+    }
+}
+
 /// Configuration for operation limits, parser limits, HTTP limits, etc.
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields, default)]
@@ -1210,6 +1301,34 @@ pub(crate) struct TlsSupergraph {
     #[serde(deserialize_with = "deserialize_certificate_chain", skip_serializing)]
     #[schemars(with = "String")]
     pub(crate) certificate_chain: Vec<Certificate>,
+    /// client certificate verification
+    #[serde(default)]
+    pub(crate) client_authentication: Option<TlsSupergraphClientAuth>,
+}
+
+/// TLS client certificate verification on the supergraph listener
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TlsSupergraphClientAuth {
+    /// whether clients must present a certificate
+    #[serde(default)]
+    pub(crate) mode: ClientAuthMode,
+    /// list of certificate authorities used to verify client certificates, in PEM format
+    #[serde(deserialize_with = "deserialize_certificate_chain", skip_serializing)]
+    #[schemars(with = "String")]
+    pub(crate) certificate_authorities: Vec<Certificate>,
+}
+
+/// TLS client certificate verification mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ClientAuthMode {
+    /// connections without a client certificate are accepted, but if a certificate is
+    /// presented, it must be valid
+    Optional,
+    /// connections without a valid client certificate are refused
+    #[default]
+    Required,
 }
 
 impl TlsSupergraph {
@@ -1217,9 +1336,30 @@ impl TlsSupergraph {
         let mut certificates = vec![self.certificate.clone()];
         certificates.extend(self.certificate_chain.iter().cloned());
 
-        let mut config = ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &self.client_authentication {
+            None => builder.with_no_client_auth(),
+            Some(client_authentication) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in &client_authentication.certificate_authorities {
+                    roots.add(certificate).map_err(|e| {
+                        ApolloRouterError::Rustls(rustls::Error::General(format!(
+                            "invalid client certificate authority: {e}"
+                        )))
+                    })?;
+                }
+
+                let verifier = match client_authentication.mode {
+                    ClientAuthMode::Optional => {
+                        AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+                    }
+                    ClientAuthMode::Required => AllowAnyAuthenticatedClient::new(roots).boxed(),
+                };
+                builder.with_client_cert_verifier(verifier)
+            }
+        };
+
+        let mut config = builder
             .with_single_cert(certificates, self.key.clone())
             .map_err(ApolloRouterError::Rustls)?;
         config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
@@ -1632,6 +1772,21 @@ pub(crate) struct Batching {
 
     /// Subgraph options for batching
     pub(crate) subgraph: Option<SubgraphConfiguration<CommonBatchingConfig>>,
+
+    /// Maximum number of operations in a batch. Larger batches are rejected (no limit by default)
+    #[serde(default)]
+    pub(crate) max_size: Option<usize>,
+
+    /// Maximum total estimated cost of the operations of a batch, as computed by the demand
+    /// control plugin. Operations that would exceed it are rejected (no limit by default).
+    /// Requires demand control with the `static_estimated` strategy
+    #[serde(default)]
+    pub(crate) max_cost: Option<f64>,
+
+    /// Maximum number of operations of a batch executed concurrently (no limit by default).
+    /// Cannot be used with subgraph batching
+    #[serde(default)]
+    pub(crate) max_in_flight: Option<NonZeroUsize>,
 }
 
 /// Common options for configuring subgraph batching
@@ -1642,6 +1797,20 @@ pub(crate) struct CommonBatchingConfig {
 }
 
 impl Batching {
+    // Check if subgraph batching is enabled for at least one subgraph
+    pub(crate) fn subgraph_batching_enabled(&self) -> bool {
+        self.subgraph
+            .as_ref()
+            .map(|subgraph_batching_config| {
+                subgraph_batching_config.all.enabled
+                    || subgraph_batching_config
+                        .subgraphs
+                        .values()
+                        .any(|v| v.enabled)
+            })
+            .unwrap_or(false)
+    }
+
     // Check if we should enable batching for a particular subgraph (service_name)
     pub(crate) fn batch_include(&self, service_name: &str) -> bool {
         match &self.subgraph {
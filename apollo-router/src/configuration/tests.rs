@@ -1094,6 +1094,69 @@ fn it_processes_batching_subgraph_accounts_override_enabled_correctly() {
     assert!(config.batch_include("accounts"));
 }
 
+#[test]
+fn it_prevents_batching_max_in_flight_with_subgraph_batching() {
+    let batching = |subgraph_enabled: bool| -> Batching {
+        serde_json::from_value(json!({
+            "enabled": true,
+            "mode": "batch_http_link",
+            "max_in_flight": 2,
+            "subgraph": {
+                "all": {
+                    "enabled": subgraph_enabled
+                }
+            }
+        }))
+        .unwrap()
+    };
+
+    assert!(Configuration::builder()
+        .batching(batching(false))
+        .build()
+        .is_ok());
+    assert!(Configuration::builder()
+        .batching(batching(true))
+        .build()
+        .is_err());
+}
+
+#[test]
+fn it_requires_static_cost_estimation_for_batching_max_cost() {
+    let batching: Batching = serde_json::from_value(json!({
+        "enabled": true,
+        "mode": "batch_http_link",
+        "max_cost": 100.0
+    }))
+    .unwrap();
+    let demand_control = |enabled: bool| {
+        json!({
+            "enabled": enabled,
+            "mode": "enforce",
+            "strategy": {
+                "static_estimated": {
+                    "list_size": 10,
+                    "max": 50.0
+                }
+            }
+        })
+    };
+
+    assert!(Configuration::builder()
+        .batching(batching.clone())
+        .build()
+        .is_err());
+    assert!(Configuration::builder()
+        .batching(batching.clone())
+        .apollo_plugin("experimental_demand_control", demand_control(false))
+        .build()
+        .is_err());
+    assert!(Configuration::builder()
+        .batching(batching)
+        .apollo_plugin("experimental_demand_control", demand_control(true))
+        .build()
+        .is_ok());
+}
+
 fn has_field_level_serde_defaults(lines: &[&str], line_number: usize) -> bool {
     let serde_field_default = Regex::new(
         r#"^\s*#[\s\n]*\[serde\s*\((.*,)?\s*default\s*=\s*"[a-zA-Z0-9_:]+"\s*(,.*)?\)\s*\]\s*$"#,
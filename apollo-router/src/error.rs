@@ -23,6 +23,7 @@ use crate::graphql::Location as ErrorLocation;
 use crate::graphql::Response;
 use crate::json_ext::Path;
 use crate::json_ext::Value;
+use crate::plugins::authorization::UnauthorizedPath;
 use crate::spec::operation_limits::OperationLimits;
 use crate::spec::SpecError;
 
@@ -310,7 +311,7 @@ pub(crate) enum QueryPlannerError {
     LimitExceeded(OperationLimits<bool>),
 
     /// Unauthorized field or type
-    Unauthorized(Vec<Path>),
+    Unauthorized(Vec<UnauthorizedPath>),
 
     /// Query planner pool error: {0}
     PoolProcessing(String),
@@ -318,6 +319,9 @@ pub(crate) enum QueryPlannerError {
     /// Federation error: {0}
     // TODO: make `FederationError` serializable and store it as-is?
     FederationError(String),
+
+    /// the query was rejected by a query planner plugin
+    Rejected(Vec<Error>),
 }
 
 impl IntoGraphQLErrors for Vec<apollo_compiler::execution::GraphQLError> {
@@ -407,6 +411,7 @@ impl IntoGraphQLErrors for QueryPlannerError {
                 );
                 Ok(errors)
             }
+            QueryPlannerError::Rejected(errors) => Ok(errors),
             err => Err(err),
         }
     }
@@ -24,7 +24,9 @@ use crate::json_ext::Path;
 pub use crate::json_ext::Path as JsonPath;
 pub use crate::json_ext::PathElement as JsonPathElement;
 pub use crate::request::Request;
+pub use crate::response::CompletedResponse;
 pub use crate::response::IncrementalResponse;
+pub use crate::response::PendingResponse;
 pub use crate::response::Response;
 
 /// An asynchronous [`Stream`] of GraphQL [`Response`]s.
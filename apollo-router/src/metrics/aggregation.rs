@@ -86,6 +86,7 @@ pub(crate) enum InstrumentWrapper {
     U64Histogram(Arc<Histogram<u64>>),
     F64Histogram(Arc<Histogram<f64>>),
     U64Gauge(Arc<ObservableGauge<u64>>),
+    F64Gauge(Arc<ObservableGauge<f64>>),
 }
 
 #[derive(Eq, PartialEq, Hash)]
@@ -30,6 +30,8 @@ use crate::graphql;
 use crate::spec::Schema;
 use crate::Configuration;
 
+pub(crate) mod redis;
+
 static NOTIFY_CHANNEL_SIZE: usize = 1024;
 static DEFAULT_MSG_CHANNEL_SIZE: usize = 128;
 
@@ -539,6 +541,11 @@ where
     K: Clone,
     V: Clone + 'static + Send,
 {
+    /// Number of handles reading the subscribed topic
+    pub(crate) fn subscriber_count(&self) -> usize {
+        self.msg_sender.receiver_count()
+    }
+
     /// Send data to the subscribed topic
     pub(crate) fn send_sync(&mut self, data: V) -> Result<(), NotifyError<V>> {
         self.msg_sender.send(data.into()).map_err(|err| {
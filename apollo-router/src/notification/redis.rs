@@ -0,0 +1,669 @@
+//! Deduplication of subscriptions between router instances, through Redis
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::SinkExt;
+use futures::StreamExt;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde::Serialize;
+use tower::BoxError;
+use uuid::Uuid;
+
+use super::Handle;
+use super::HandleSink;
+use super::Notify;
+use crate::cache::redis::RedisCacheStorage;
+use crate::cache::redis::RedisKey;
+use crate::configuration::RedisCache;
+use crate::graphql;
+
+const DEFAULT_LEASE: Duration = Duration::from_secs(10);
+
+/// Opens a subscription to the subgraph
+///
+/// Returns the stream of events, or `None` if the events are received through another path,
+/// like the callback endpoint.
+pub(crate) type OpenSubscription = Arc<
+    dyn Fn() -> BoxFuture<'static, Result<Option<BoxStream<'static, graphql::Response>>, BoxError>>
+        + Send
+        + Sync,
+>;
+
+/// Message published on the channels of a subscription
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum Event {
+    Next {
+        payload: graphql::Response,
+    },
+    Complete,
+    /// Only relayed from the callback endpoint to the owner, to keep its subscription alive
+    Heartbeat,
+}
+
+/// Shares subscriptions between router instances
+///
+/// Each subscription is owned by the instance holding its lease in Redis. The owner opens the
+/// subscription to the subgraph and publishes its events on a Redis channel, and the other
+/// instances forward them to their clients. If the owner stops renewing its lease, another
+/// instance takes over and opens the subscription again.
+///
+/// In callback mode, the subgraph may send its callbacks to any instance if they share the same
+/// `public_url`. The instances that do not own the subscription relay them to the owner.
+#[derive(Clone)]
+pub(crate) struct RedisNotify {
+    storage: RedisCacheStorage,
+    // A connection that subscribed to a channel cannot send other commands
+    subscriber: RedisCacheStorage,
+    instance_id: Arc<String>,
+    lease: Duration,
+    /// Topics of the subscriptions owned by this instance
+    owned: Arc<Mutex<HashSet<String>>>,
+}
+
+impl fmt::Debug for RedisNotify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisNotify")
+            .field("instance_id", &self.instance_id)
+            .field("lease", &self.lease)
+            .finish()
+    }
+}
+
+impl RedisNotify {
+    /// The TTL of the Redis configuration is the lease of the owner (default: 10s)
+    pub(crate) async fn new(config: RedisCache) -> Result<Self, BoxError> {
+        let lease = config.ttl.unwrap_or(DEFAULT_LEASE);
+        Ok(Self {
+            storage: RedisCacheStorage::new(config.clone()).await?,
+            subscriber: RedisCacheStorage::new(config).await?,
+            instance_id: Arc::new(Uuid::new_v4().to_string()),
+            lease,
+            owned: Default::default(),
+        })
+    }
+
+    /// Tries to become the owner of the subscription
+    ///
+    /// If Redis cannot be reached, the subscription is opened by this instance.
+    pub(crate) async fn acquire(&self, topic: &str) -> bool {
+        self.storage
+            .insert_if_not_exists(lease_key(topic), &self.instance_id, self.lease)
+            .await
+            .unwrap_or(true)
+    }
+
+    /// Gives up the ownership of the subscription, so that another instance can open it
+    pub(crate) async fn release(&self, topic: &str) {
+        self.storage
+            .delete_if_equals(lease_key(topic), &self.instance_id)
+            .await;
+    }
+
+    /// Publishes the events of an owned subscription to the other instances
+    ///
+    /// The subscription is kept open as long as this instance or another one has clients for it.
+    /// In callback mode, `callbacks` is used to handle the callbacks relayed by the other instances.
+    pub(crate) fn lead(
+        &self,
+        topic: String,
+        handle: Handle<String, graphql::Response>,
+        callbacks: Option<Notify<String, graphql::Response>>,
+    ) {
+        let this = self.clone();
+        tokio::task::spawn(async move { this.publish_events(topic, handle, callbacks).await });
+    }
+
+    /// Relays a callback to the owner of the subscription, if it is owned by another instance
+    ///
+    /// Returns `false` if the callback must be handled by this instance.
+    pub(crate) async fn relay(&self, topic: &str, event: Event) -> bool {
+        if self.owned.lock().contains(topic) {
+            return false;
+        }
+        match serde_json::to_string(&event) {
+            // The owner is the only subscriber of the channel
+            Ok(message) => {
+                self.storage
+                    .publish(callbacks_channel(topic), message)
+                    .await
+                    .unwrap_or_default()
+                    > 0
+            }
+            Err(err) => {
+                tracing::error!("cannot serialize the subscription event: {err}");
+                false
+            }
+        }
+    }
+
+    /// Forwards the events published by the owner of the subscription to the local clients
+    ///
+    /// If the owner stops renewing its lease, this instance tries to take over with `open`.
+    pub(crate) fn follow(
+        &self,
+        notify: Notify<String, graphql::Response>,
+        topic: String,
+        sink: HandleSink<String, graphql::Response>,
+        open: OpenSubscription,
+    ) {
+        let this = self.clone();
+        tokio::task::spawn(async move { this.forward_events(notify, topic, sink, open).await });
+    }
+
+    async fn publish_events(
+        &self,
+        topic: String,
+        handle: Handle<String, graphql::Response>,
+        mut callbacks: Option<Notify<String, graphql::Response>>,
+    ) {
+        self.owned.lock().insert(topic.clone());
+        let (mut sink, mut events) = handle.split();
+        let mut relayed = match callbacks {
+            Some(_) => match self.subscriber.subscribe(callbacks_channel(&topic)).await {
+                Ok(relayed) => relayed.boxed(),
+                Err(err) => {
+                    tracing::error!(
+                        "cannot receive the callbacks relayed by other router instances: {err}"
+                    );
+                    futures::stream::pending().boxed()
+                }
+            },
+            None => futures::stream::pending().boxed(),
+        };
+        let mut renewal = tokio::time::interval(self.lease / 3);
+        // The first tick completes immediately
+        renewal.tick().await;
+
+        let released = loop {
+            tokio::select! {
+                event = relayed.next() => match event.map(|event| serde_json::from_str(&event)) {
+                    Some(Ok(Event::Next { payload })) => {
+                        let _ = sink.send_sync(payload);
+                    }
+                    // Closing the topic completes the events
+                    Some(Ok(Event::Complete)) => {
+                        let _ = sink.close().await;
+                    }
+                    Some(Ok(Event::Heartbeat)) => {
+                        if let Some(callbacks) = &mut callbacks {
+                            let _ = callbacks.exist(topic.clone()).await;
+                        }
+                    }
+                    Some(Err(err)) => {
+                        tracing::error!("cannot deserialize the subscription event: {err}");
+                    }
+                    None => relayed = futures::stream::pending().boxed(),
+                },
+                event = events.next() => {
+                    let event = match event {
+                        Some(payload) => Event::Next { payload },
+                        None => Event::Complete,
+                    };
+                    let complete = matches!(event, Event::Complete);
+                    match serde_json::to_string(&event) {
+                        Ok(message) => {
+                            self.storage.publish(channel(&topic), message).await;
+                        }
+                        Err(err) => {
+                            tracing::error!("cannot serialize the subscription event: {err}");
+                        }
+                    }
+                    if complete {
+                        break true;
+                    }
+                }
+                _ = renewal.tick() => {
+                    // This task is the only local subscriber and no other instance follows
+                    if sink.subscriber_count() <= 1
+                        && self.storage.subscribers(channel(&topic)).await == Some(0)
+                    {
+                        break true;
+                    }
+                    if self
+                        .storage
+                        .expire_if_equals(lease_key(&topic), &self.instance_id, self.lease)
+                        .await
+                        == Some(false)
+                    {
+                        tracing::warn!(
+                            "the lease of a subscription was taken over by another router instance"
+                        );
+                        break false;
+                    }
+                }
+            }
+        };
+        self.owned.lock().remove(&topic);
+        if callbacks.is_some() {
+            self.subscriber.unsubscribe(callbacks_channel(&topic)).await;
+        }
+        if released {
+            self.release(&topic).await;
+        }
+    }
+
+    async fn forward_events(
+        &self,
+        mut notify: Notify<String, graphql::Response>,
+        topic: String,
+        mut sink: HandleSink<String, graphql::Response>,
+        open: OpenSubscription,
+    ) {
+        let mut events = match self.subscriber.subscribe(channel(&topic)).await {
+            Ok(events) => events.boxed(),
+            Err(err) => {
+                tracing::error!(
+                    "cannot follow the subscription of another router instance, opening it locally: {err}"
+                );
+                return self.open(topic, sink, open, None).await;
+            }
+        };
+        let mut check = tokio::time::interval(self.lease / 3);
+        check.tick().await;
+
+        loop {
+            tokio::select! {
+                event = events.next() => match event.map(|event| serde_json::from_str(&event)) {
+                    Some(Ok(Event::Next { payload })) => {
+                        // There are no local clients anymore
+                        if sink.send_sync(payload).is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(Event::Complete)) | None => {
+                        let _ = sink.close().await;
+                        break;
+                    }
+                    Some(Err(err)) => {
+                        tracing::error!("cannot deserialize the subscription event: {err}");
+                    }
+                    // Not published on this channel
+                    Some(Ok(Event::Heartbeat)) => {}
+                },
+                _ = check.tick() => {
+                    if sink.subscriber_count() == 0 {
+                        break;
+                    }
+                    // Heartbeats in callback mode are only received by the owner
+                    let _ = notify.exist(topic.clone()).await;
+                    if self.storage.exists(lease_key(&topic)).await == Some(false)
+                        && self.acquire(&topic).await
+                    {
+                        self.subscriber.unsubscribe(channel(&topic)).await;
+                        tracing::info!(
+                            monotonic_counter.apollo.router.operations.subscriptions.takeovers = 1u64,
+                            "taking over a subscription from another router instance"
+                        );
+                        return self.open(topic, sink, open, Some(notify)).await;
+                    }
+                }
+            }
+        }
+        self.subscriber.unsubscribe(channel(&topic)).await;
+    }
+
+    // Opens the subscription locally. The events are published to the other instances if this
+    // instance owns the subscription, with the notify used to read them.
+    async fn open(
+        &self,
+        topic: String,
+        mut sink: HandleSink<String, graphql::Response>,
+        open: OpenSubscription,
+        notify: Option<Notify<String, graphql::Response>>,
+    ) {
+        // Subscribe before opening, to publish the first events
+        let handle = match notify.clone() {
+            Some(mut notify) => match notify.subscribe(topic.clone()).await {
+                Ok(handle) => Some(handle),
+                Err(_) => {
+                    self.release(&topic).await;
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        match open().await {
+            Ok(stream) => {
+                let callbacks = match stream {
+                    Some(stream) => {
+                        tokio::task::spawn(async move {
+                            let _ = stream.map(Ok::<_, graphql::Error>).forward(sink).await;
+                        });
+                        None
+                    }
+                    // The events are received by the callback endpoint
+                    None => notify.clone(),
+                };
+                if let Some(handle) = handle {
+                    self.publish_events(topic, handle, callbacks).await;
+                }
+            }
+            Err(err) => {
+                tracing::error!("cannot open the subscription: {err}");
+                if handle.is_some() {
+                    self.release(&topic).await;
+                }
+                let _ = sink.send_sync(
+                    graphql::Response::builder()
+                        .error(
+                            graphql::Error::builder()
+                                .message(format!("cannot open the subscription: {err}"))
+                                .extension_code("SUBSCRIPTION_TAKEOVER_ERROR")
+                                .build(),
+                        )
+                        .build(),
+                );
+                let _ = sink.close().await;
+            }
+        }
+    }
+}
+
+fn lease_key(topic: &str) -> RedisKey<String> {
+    RedisKey(format!("subscription:owner:{topic}"))
+}
+
+fn channel(topic: &str) -> RedisKey<String> {
+    RedisKey(format!("subscription:events:{topic}"))
+}
+
+fn callbacks_channel(topic: &str) -> RedisKey<String> {
+    RedisKey(format!("subscription:callbacks:{topic}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::time::Instant;
+
+    use fred::error::RedisError;
+    use fred::error::RedisErrorKind;
+    use fred::mocks::MockCommand;
+    use fred::mocks::Mocks;
+    use fred::types::RedisValue;
+    use futures::FutureExt;
+
+    use super::*;
+
+    // Only supports the commands used to manage leases and channels. Published messages are
+    // recorded instead of being delivered.
+    #[derive(Debug, Default)]
+    struct MockStore {
+        map: Mutex<HashMap<String, (String, Instant)>>,
+        subscribers: Mutex<HashMap<String, i64>>,
+        published: Mutex<Vec<(String, String)>>,
+    }
+
+    impl MockStore {
+        fn owner(&self, key: &str) -> Option<String> {
+            self.map
+                .lock()
+                .get(key)
+                .filter(|(_, expires_at)| *expires_at > Instant::now())
+                .map(|(value, _)| value.clone())
+        }
+    }
+
+    impl Mocks for MockStore {
+        fn process_command(&self, command: MockCommand) -> Result<RedisValue, RedisError> {
+            let args: Vec<String> = command
+                .args
+                .iter()
+                .filter_map(|arg| arg.as_string())
+                .collect();
+            let expiration =
+                |millis: &str| Instant::now() + Duration::from_millis(millis.parse().unwrap());
+            match &*command.cmd {
+                // The arguments are the key, the value, PX and the TTL
+                "SET" if self.owner(&args[0]).is_some() => Ok(RedisValue::Null),
+                "SET" => {
+                    self.map
+                        .lock()
+                        .insert(args[0].clone(), (args[1].clone(), expiration(&args[3])));
+                    Ok(RedisValue::String("OK".into()))
+                }
+                "EXISTS" => Ok(RedisValue::Integer(self.owner(&args[0]).is_some() as i64)),
+                // The arguments are the script, the number of keys, the key, the value and the TTL
+                "EVAL" => {
+                    let owned = self.owner(&args[2]).as_ref() == Some(&args[3]);
+                    if owned && args[0].contains("'DEL'") {
+                        self.map.lock().remove(&args[2]);
+                    } else if owned {
+                        self.map
+                            .lock()
+                            .insert(args[2].clone(), (args[3].clone(), expiration(&args[4])));
+                    }
+                    Ok(RedisValue::Integer(owned as i64))
+                }
+                "SUBSCRIBE" | "UNSUBSCRIBE" => {
+                    let change = if &*command.cmd == "SUBSCRIBE" { 1 } else { -1 };
+                    *self.subscribers.lock().entry(args[0].clone()).or_default() += change;
+                    Ok(RedisValue::Null)
+                }
+                "PUBSUB" => Ok(RedisValue::Array(vec![
+                    args[0].clone().into(),
+                    self.subscribers(&args[0]).into(),
+                ])),
+                "PUBLISH" => {
+                    self.published
+                        .lock()
+                        .push((args[0].clone(), args[1].clone()));
+                    Ok(RedisValue::Integer(self.subscribers(&args[0])))
+                }
+                _ => Err(RedisError::new(RedisErrorKind::NotFound, "mock not found")),
+            }
+        }
+    }
+
+    impl MockStore {
+        fn subscribers(&self, channel: &str) -> i64 {
+            self.subscribers
+                .lock()
+                .get(channel)
+                .copied()
+                .unwrap_or_default()
+        }
+    }
+
+    async fn redis_notify(store: Arc<MockStore>, lease: Duration) -> RedisNotify {
+        RedisNotify {
+            storage: RedisCacheStorage::from_mocks(store.clone()).await.unwrap(),
+            subscriber: RedisCacheStorage::from_mocks(store).await.unwrap(),
+            instance_id: Arc::new(Uuid::new_v4().to_string()),
+            lease,
+            owned: Default::default(),
+        }
+    }
+
+    fn event(name: &str) -> graphql::Response {
+        graphql::Response::builder()
+            .data(serde_json_bytes::json!({ "userWasCreated": { "name": name } }))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn it_takes_over_a_subscription_when_the_owner_stops_renewing() {
+        let store = Arc::new(MockStore::default());
+        let lease = Duration::from_millis(300);
+        let owner = redis_notify(store.clone(), lease).await;
+        let follower = redis_notify(store.clone(), lease).await;
+
+        // The owner acquires the lease, then stops without renewing nor releasing it
+        assert!(owner.acquire("topic").await);
+
+        let mut notify = Notify::builder().build();
+        let (handle, created) = notify
+            .create_or_subscribe("topic".to_string(), false)
+            .await
+            .unwrap();
+        assert!(created);
+        let (sink, client) = handle.split();
+
+        let opened = Arc::new(AtomicUsize::new(0));
+        let open: OpenSubscription = {
+            let opened = opened.clone();
+            Arc::new(move || {
+                opened.fetch_add(1, Ordering::SeqCst);
+                async {
+                    Ok(Some(
+                        futures::stream::iter([event("Ada"), event("Grace")])
+                            .chain(futures::stream::pending())
+                            .boxed(),
+                    ))
+                }
+                .boxed()
+            })
+        };
+        follower.follow(notify.clone(), "topic".to_string(), sink, open);
+
+        // The events of the reopened subscription are sent to the local client
+        let events =
+            tokio::time::timeout(Duration::from_secs(5), client.take(2).collect::<Vec<_>>())
+                .await
+                .unwrap();
+        assert_eq!(events, vec![event("Ada"), event("Grace")]);
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            store.owner("subscription:owner:topic"),
+            Some(follower.instance_id.to_string())
+        );
+
+        // and published to the other instances
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let published = store.published.lock().clone();
+        assert_eq!(
+            published,
+            vec![
+                (
+                    "subscription:events:topic".to_string(),
+                    serde_json::to_string(&Event::Next {
+                        payload: event("Ada")
+                    })
+                    .unwrap()
+                ),
+                (
+                    "subscription:events:topic".to_string(),
+                    serde_json::to_string(&Event::Next {
+                        payload: event("Grace")
+                    })
+                    .unwrap()
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_relays_callbacks_to_the_owner() {
+        let store = Arc::new(MockStore::default());
+        let owner = redis_notify(store.clone(), DEFAULT_LEASE).await;
+        let follower = redis_notify(store.clone(), DEFAULT_LEASE).await;
+
+        // Nobody owns the subscription yet
+        assert!(!follower.relay("topic", Event::Heartbeat).await);
+
+        assert!(owner.acquire("topic").await);
+        let mut notify = Notify::builder().build();
+        let (handle, _) = notify
+            .create_or_subscribe("topic".to_string(), true)
+            .await
+            .unwrap();
+        owner.lead("topic".to_string(), handle.clone(), Some(notify));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // The owner handles its callbacks, the other instances relay them
+        assert!(!owner.relay("topic", Event::Heartbeat).await);
+        assert!(
+            follower
+                .relay(
+                    "topic",
+                    Event::Next {
+                        payload: event("Ada")
+                    }
+                )
+                .await
+        );
+        assert_eq!(
+            store.published.lock().last().cloned(),
+            Some((
+                "subscription:callbacks:topic".to_string(),
+                serde_json::to_string(&Event::Next {
+                    payload: event("Ada")
+                })
+                .unwrap()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_elects_a_single_owner() {
+        let store = Arc::new(MockStore::default());
+        let first = redis_notify(store.clone(), DEFAULT_LEASE).await;
+        let second = redis_notify(store, DEFAULT_LEASE).await;
+
+        assert!(first.acquire("topic").await);
+        assert!(!second.acquire("topic").await);
+        assert!(second.acquire("other_topic").await);
+
+        // Only the owner can release the lease
+        second.release("topic").await;
+        assert!(!second.acquire("topic").await);
+        first.release("topic").await;
+        assert!(second.acquire("topic").await);
+    }
+
+    #[tokio::test]
+    async fn it_renews_the_lease_of_the_owner() {
+        let store = Arc::new(MockStore::default());
+        let first = redis_notify(store.clone(), DEFAULT_LEASE).await;
+        let second = redis_notify(store, DEFAULT_LEASE).await;
+
+        assert!(first.acquire("topic").await);
+        assert_eq!(
+            first
+                .storage
+                .expire_if_equals(lease_key("topic"), &first.instance_id, first.lease)
+                .await,
+            Some(true)
+        );
+        assert_eq!(
+            second
+                .storage
+                .expire_if_equals(lease_key("topic"), &second.instance_id, second.lease)
+                .await,
+            Some(false)
+        );
+        assert_eq!(second.storage.exists(lease_key("topic")).await, Some(true));
+    }
+
+    #[test]
+    fn it_serializes_events() {
+        let event = Event::Next {
+            payload: graphql::Response::builder()
+                .data(serde_json_bytes::json!({"userWasCreated": {"name": "Ada"}}))
+                .build(),
+        };
+        let message = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            message,
+            r#"{"kind":"next","payload":{"data":{"userWasCreated":{"name":"Ada"}}}}"#
+        );
+        assert!(matches!(
+            serde_json::from_str(&message).unwrap(),
+            Event::Next { payload } if payload.data.is_some()
+        ));
+        assert!(matches!(
+            serde_json::from_str(r#"{"kind":"complete"}"#).unwrap(),
+            Event::Complete
+        ));
+    }
+}
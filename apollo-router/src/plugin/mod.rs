@@ -47,6 +47,7 @@ use crate::notification::Notify;
 use crate::query_planner::fetch::SubgraphSchemas;
 use crate::router_factory::Endpoint;
 use crate::services::execution;
+use crate::services::query_planner;
 use crate::services::router;
 use crate::services::subgraph;
 use crate::services::supergraph;
@@ -391,6 +392,17 @@ pub trait Plugin: Send + Sync + 'static {
         service
     }
 
+    /// This service runs between query parsing and query planning, right after the query planner cache,
+    /// which means that it will be called once per unique query, unless the cache entry was evicted.
+    /// Define `query_planner_service` to rewrite the operation before it is planned, or to inspect or reject the query plan.
+    /// A response without content rejects the query with its errors, and is not cached.
+    fn query_planner_service(
+        &self,
+        service: query_planner::BoxService,
+    ) -> query_planner::BoxService {
+        service
+    }
+
     /// Return the name of the plugin.
     fn name(&self) -> &'static str
     where
@@ -465,6 +477,17 @@ pub trait PluginUnstable: Send + Sync + 'static {
         service
     }
 
+    /// This service runs between query parsing and query planning, right after the query planner cache,
+    /// which means that it will be called once per unique query, unless the cache entry was evicted.
+    /// Define `query_planner_service` to rewrite the operation before it is planned, or to inspect or reject the query plan.
+    /// A response without content rejects the query with its errors, and is not cached.
+    fn query_planner_service(
+        &self,
+        service: query_planner::BoxService,
+    ) -> query_planner::BoxService {
+        service
+    }
+
     /// Return the name of the plugin.
     fn name(&self) -> &'static str
     where
@@ -518,6 +541,13 @@ where
         Plugin::subgraph_service(self, subgraph_name, service)
     }
 
+    fn query_planner_service(
+        &self,
+        service: query_planner::BoxService,
+    ) -> query_planner::BoxService {
+        Plugin::query_planner_service(self, service)
+    }
+
     /// Return the name of the plugin.
     fn name(&self) -> &'static str
     where
@@ -603,6 +633,16 @@ pub(crate) trait PluginPrivate: Send + Sync + 'static {
         service
     }
 
+    /// This service runs between query parsing and query planning, right after the query planner cache,
+    /// which means that it will be called once per unique query, unless the cache entry was evicted.
+    /// Define `query_planner_service` to rewrite the operation before it is planned, or to inspect or reject the query plan.
+    fn query_planner_service(
+        &self,
+        service: query_planner::BoxService,
+    ) -> query_planner::BoxService {
+        service
+    }
+
     /// Return the name of the plugin.
     fn name(&self) -> &'static str
     where
@@ -653,6 +693,13 @@ where
         PluginUnstable::subgraph_service(self, subgraph_name, service)
     }
 
+    fn query_planner_service(
+        &self,
+        service: query_planner::BoxService,
+    ) -> query_planner::BoxService {
+        PluginUnstable::query_planner_service(self, service)
+    }
+
     /// Return the name of the plugin.
     fn name(&self) -> &'static str
     where
@@ -708,6 +755,13 @@ pub(crate) trait DynPlugin: Send + Sync + 'static {
         service: crate::services::http::BoxService,
     ) -> crate::services::http::BoxService;
 
+    /// This service runs between query parsing and query planning, right after the query planner cache,
+    /// which means that it will be called once per unique query, unless the cache entry was evicted.
+    fn query_planner_service(
+        &self,
+        service: crate::services::query_planner::BoxService,
+    ) -> crate::services::query_planner::BoxService;
+
     /// Return the name of the plugin.
     fn name(&self) -> &'static str;
 
@@ -752,6 +806,13 @@ where
         self.http_client_service(name, service)
     }
 
+    fn query_planner_service(
+        &self,
+        service: crate::services::query_planner::BoxService,
+    ) -> crate::services::query_planner::BoxService {
+        self.query_planner_service(service)
+    }
+
     fn name(&self) -> &'static str {
         self.name()
     }
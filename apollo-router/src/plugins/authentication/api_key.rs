@@ -0,0 +1,678 @@
+//! API key authentication
+//!
+//! Static API keys are never stored in clear text: the key store only contains hashes of the
+//! keys (SHA-256 or argon2), along with the metadata attached to each key. When a request
+//! presents a known key, that metadata is inserted in the context as claims, the same way
+//! JWT claims are, so that authorization directives, header propagation and telemetry
+//! selectors can use it.
+
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use argon2::password_hash::PasswordHash;
+use argon2::password_hash::PasswordVerifier;
+use argon2::Argon2;
+use futures::future::select;
+use futures::future::Either;
+use futures::pin_mut;
+use futures::StreamExt;
+use http::HeaderMap;
+use http::StatusCode;
+use http::Uri;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Digest;
+use sha2::Sha256;
+use tokio::sync::oneshot;
+use tower::BoxError;
+
+use super::AuthenticationError;
+use super::APOLLO_AUTHENTICATION_JWT_CLAIMS;
+use crate::cache::redis::RedisCacheStorage;
+use crate::cache::redis::RedisKey;
+use crate::configuration::RedisCache;
+use crate::graphql;
+use crate::services::router;
+use crate::Context;
+
+const AUTHENTICATION_KIND: &str = "API_KEY";
+const SHA256_PREFIX: &str = "sha256:";
+const REDIS_KEY_PREFIX: &str = "api_key";
+
+/// API key authentication configuration
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct Config {
+    /// Where to look for the API key. The first source containing a key is used.
+    /// Defaults to the `X-API-Key` header
+    #[serde(default = "default_sources")]
+    sources: Vec<Source>,
+    /// Where the hashed keys and their metadata are stored
+    store: StoreConfig,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "lowercase", tag = "type")]
+enum Source {
+    Header {
+        /// HTTP header expected to contain the API key
+        #[serde(default = "default_header_name")]
+        name: String,
+        /// Header value prefix. If set, the API key is the part of the header value
+        /// following that prefix
+        #[serde(default)]
+        value_prefix: String,
+    },
+    Query {
+        /// Name of the query parameter containing the API key
+        name: String,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+enum StoreConfig {
+    /// Keys are loaded from a local JSON or YAML file
+    File {
+        /// Path to the key file
+        path: PathBuf,
+        /// Reload the key file when it changes
+        #[serde(default)]
+        watch: bool,
+    },
+    /// Keys are looked up in Redis, using the SHA-256 hash of the key, encoded in hexadecimal,
+    /// as Redis key (`api_key:<hash>`). The value must be a JSON object containing the key's
+    /// metadata
+    Redis(RedisCache),
+}
+
+fn default_sources() -> Vec<Source> {
+    vec![Source::Header {
+        name: default_header_name(),
+        value_prefix: String::new(),
+    }]
+}
+
+fn default_header_name() -> String {
+    "x-api-key".to_string()
+}
+
+/// Content of the key file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyFile {
+    keys: Vec<KeyEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyEntry {
+    /// `sha256:<hex encoded hash>` or an argon2 PHC string (`$argon2id$v=19$...`)
+    hash: String,
+    /// Non-secret beginning of the key, required for argon2 hashes: only the argon2 hash of
+    /// the entry matching the prefix of a presented key is verified
+    #[serde(default)]
+    prefix: Option<String>,
+    /// Metadata attached to the key (tenant, scopes, plan...), inserted as claims
+    #[serde(default = "default_metadata")]
+    metadata: Value,
+}
+
+fn default_metadata() -> Value {
+    Value::Object(Default::default())
+}
+
+#[derive(Debug, Default)]
+struct KeyIndex {
+    /// metadata indexed by the hex encoded SHA-256 hash of the key
+    sha256: HashMap<String, Value>,
+    /// argon2 hashes cannot be indexed since they are salted, so they are found by the
+    /// prefix of the key. Prefixes never start with one another, so a key matches at most
+    /// one entry
+    argon2: Vec<Argon2Entry>,
+    /// successful argon2 verifications, indexed by the SHA-256 hash of the key, to avoid
+    /// paying for the argon2 verification on every request. This is bounded by the number
+    /// of argon2 entries since only valid keys are inserted
+    verified: HashMap<String, Value>,
+}
+
+#[derive(Clone, Debug)]
+struct Argon2Entry {
+    prefix: String,
+    hash: String,
+    metadata: Value,
+}
+
+impl KeyIndex {
+    fn parse(path: &Path, content: &str) -> Result<Self, BoxError> {
+        let file: KeyFile = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(content)?,
+            _ => serde_yaml::from_str(content)?,
+        };
+
+        let mut index = KeyIndex::default();
+        for (position, entry) in file.keys.into_iter().enumerate() {
+            if !entry.metadata.is_object() {
+                return Err(format!(
+                    "metadata of the API key at index {position} must be an object"
+                )
+                .into());
+            }
+
+            if let Some(hash) = entry.hash.strip_prefix(SHA256_PREFIX) {
+                let hash = hash.to_ascii_lowercase();
+                if hex::decode(&hash).map(|h| h.len()) != Ok(32) {
+                    return Err(format!(
+                        "the API key at index {position} is not a valid hex encoded SHA-256 hash"
+                    )
+                    .into());
+                }
+                index.sha256.insert(hash, entry.metadata);
+            } else if entry.hash.starts_with("$argon2") {
+                PasswordHash::new(&entry.hash).map_err(|e| {
+                    format!("the API key at index {position} is not a valid argon2 hash: {e}")
+                })?;
+                let prefix = match entry.prefix {
+                    Some(prefix) if !prefix.is_empty() => prefix,
+                    _ => {
+                        return Err(format!(
+                            "the argon2 hashed API key at index {position} must have a prefix"
+                        )
+                        .into())
+                    }
+                };
+                if index.argon2.iter().any(|other| {
+                    other.prefix.starts_with(&prefix) || prefix.starts_with(&other.prefix)
+                }) {
+                    return Err(format!(
+                        "the prefix of the API key at index {position} overlaps with the prefix of another key"
+                    )
+                    .into());
+                }
+                index.argon2.push(Argon2Entry {
+                    prefix,
+                    hash: entry.hash,
+                    metadata: entry.metadata,
+                });
+            } else {
+                return Err(format!(
+                    "the API key at index {position} must be either prefixed by '{SHA256_PREFIX}' or an argon2 hash"
+                )
+                .into());
+            }
+        }
+
+        Ok(index)
+    }
+}
+
+#[derive(Clone)]
+enum KeyStore {
+    File {
+        index: Arc<RwLock<KeyIndex>>,
+        _drop_signal: Arc<oneshot::Sender<()>>,
+    },
+    Redis(RedisCacheStorage),
+}
+
+impl KeyStore {
+    async fn lookup(&self, key: &str) -> Option<Value> {
+        let digest = hex::encode(Sha256::digest(key.as_bytes()));
+        match self {
+            KeyStore::File { index, .. } => {
+                let candidate = {
+                    let index = index.read().ok()?;
+                    if let Some(metadata) = index
+                        .sha256
+                        .get(&digest)
+                        .or_else(|| index.verified.get(&digest))
+                    {
+                        return Some(metadata.clone());
+                    }
+                    index
+                        .argon2
+                        .iter()
+                        .find(|entry| key.starts_with(&entry.prefix))
+                        .cloned()?
+                };
+
+                // argon2 verification is purposely expensive, so it runs on the blocking pool
+                let key = key.to_string();
+                let metadata = tokio::task::spawn_blocking(move || {
+                    let hash = PasswordHash::new(&candidate.hash).ok()?;
+                    Argon2::default()
+                        .verify_password(key.as_bytes(), &hash)
+                        .ok()
+                        .map(|_| candidate.metadata)
+                })
+                .await
+                .ok()??;
+
+                if let Ok(mut index) = index.write() {
+                    index.verified.insert(digest, metadata.clone());
+                }
+                Some(metadata)
+            }
+            KeyStore::Redis(storage) => storage
+                .get::<String, Value>(RedisKey(format!("{REDIS_KEY_PREFIX}:{digest}")))
+                .await
+                .map(|value| value.0),
+        }
+    }
+}
+
+async fn load_key_file(path: &Path) -> Result<KeyIndex, BoxError> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("could not read API key file '{}': {e}", path.display()))?;
+    KeyIndex::parse(path, &content)
+}
+
+async fn watch_key_file(
+    path: PathBuf,
+    index: Arc<RwLock<KeyIndex>>,
+    drop_receiver: oneshot::Receiver<()>,
+) {
+    let changes = crate::files::watch(&path);
+    pin_mut!(changes);
+    pin_mut!(drop_receiver);
+
+    loop {
+        let next = changes.next();
+        pin_mut!(next);
+
+        match select(drop_receiver, next).await {
+            // the _drop_signal was dropped, we must shut down the task
+            Either::Left((_res, _)) => return,
+            Either::Right((Some(()), receiver)) => {
+                drop_receiver = receiver;
+                match load_key_file(&path).await {
+                    Ok(new_index) => {
+                        tracing::info!("reloaded API key file");
+                        if let Ok(mut index) = index.write() {
+                            *index = new_index;
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!(%e, "could not reload API key file, keeping the previous keys");
+                    }
+                }
+            }
+            Either::Right((None, _)) => return,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct ApiKeyAuth {
+    sources: Arc<Vec<Source>>,
+    store: KeyStore,
+}
+
+impl ApiKeyAuth {
+    pub(super) async fn new(config: Config) -> Result<Self, BoxError> {
+        for source in &config.sources {
+            if let Source::Header { value_prefix, .. } = source {
+                if value_prefix.as_bytes().iter().any(u8::is_ascii_whitespace) {
+                    return Err(super::Error::BadHeaderValuePrefix.into());
+                }
+            }
+        }
+
+        let store = match config.store {
+            StoreConfig::File { path, watch } => {
+                let index = Arc::new(RwLock::new(load_key_file(&path).await?));
+                let (_drop_signal, drop_receiver) = oneshot::channel::<()>();
+                if watch {
+                    tokio::task::spawn(watch_key_file(path, index.clone(), drop_receiver));
+                }
+                KeyStore::File {
+                    index,
+                    _drop_signal: Arc::new(_drop_signal),
+                }
+            }
+            StoreConfig::Redis(redis) => KeyStore::Redis(RedisCacheStorage::new(redis).await?),
+        };
+
+        Ok(Self {
+            sources: Arc::new(config.sources),
+            store,
+        })
+    }
+
+    #[cfg(test)]
+    fn from_key_file(sources: Vec<Source>, path: &Path, content: &str) -> Result<Self, BoxError> {
+        let (_drop_signal, _) = oneshot::channel::<()>();
+        Ok(Self {
+            sources: Arc::new(sources),
+            store: KeyStore::File {
+                index: Arc::new(RwLock::new(KeyIndex::parse(path, content)?)),
+                _drop_signal: Arc::new(_drop_signal),
+            },
+        })
+    }
+
+    pub(super) async fn authenticate(
+        &self,
+        request: router::Request,
+    ) -> ControlFlow<router::Response, router::Request> {
+        // a previous authentication mechanism already identified the client
+        if request
+            .context
+            .contains_key(APOLLO_AUTHENTICATION_JWT_CLAIMS)
+        {
+            return ControlFlow::Continue(request);
+        }
+
+        let mut key = None;
+        for source in self.sources.iter() {
+            match extract_key(
+                source,
+                request.router_request.headers(),
+                request.router_request.uri(),
+            ) {
+                None => continue,
+                Some(Err(error)) => {
+                    return failure_message(request.context, error, StatusCode::BAD_REQUEST)
+                }
+                Some(Ok(extracted_key)) => {
+                    key = Some(extracted_key);
+                    break;
+                }
+            }
+        }
+
+        let key = match key {
+            Some(key) => key,
+            None => return ControlFlow::Continue(request),
+        };
+
+        let metadata = match self.store.lookup(&key).await {
+            Some(metadata) => metadata,
+            None => {
+                return failure_message(
+                    request.context,
+                    AuthenticationError::InvalidApiKey,
+                    StatusCode::UNAUTHORIZED,
+                )
+            }
+        };
+
+        if let Err(e) = request
+            .context
+            .insert(APOLLO_AUTHENTICATION_JWT_CLAIMS, metadata)
+        {
+            return failure_message(
+                request.context,
+                AuthenticationError::CannotInsertClaimsIntoContext(e),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            );
+        }
+
+        // This is a metric and will not appear in the logs
+        tracing::info!(
+            monotonic_counter.apollo_authentication_success_count = 1u64,
+            kind = %AUTHENTICATION_KIND
+        );
+        tracing::info!(
+            monotonic_counter
+                .apollo
+                .router
+                .operations
+                .authentication
+                .api_key = 1u64
+        );
+        ControlFlow::Continue(request)
+    }
+}
+
+fn failure_message(
+    context: Context,
+    error: AuthenticationError,
+    status: StatusCode,
+) -> ControlFlow<router::Response, router::Request> {
+    // This is a metric and will not appear in the logs
+    tracing::info!(
+        monotonic_counter.apollo_authentication_failure_count = 1u64,
+        kind = %AUTHENTICATION_KIND
+    );
+    tracing::info!(
+        monotonic_counter
+            .apollo
+            .router
+            .operations
+            .authentication
+            .api_key = 1,
+        authentication.api_key.failed = true
+    );
+    tracing::info!(message = %error, "API key authentication failure");
+    let response = router::Response::infallible_builder()
+        .error(
+            graphql::Error::builder()
+                .message(error.to_string())
+                .extension_code("AUTH_ERROR")
+                .build(),
+        )
+        .status_code(status)
+        .context(context)
+        .build();
+    ControlFlow::Break(response)
+}
+
+fn extract_key<'a>(
+    source: &'a Source,
+    headers: &HeaderMap,
+    uri: &Uri,
+) -> Option<Result<String, AuthenticationError<'a>>> {
+    match source {
+        Source::Header { name, value_prefix } => {
+            let value = match headers.get(name)?.to_str() {
+                Ok(value) => value.trim(),
+                Err(_not_a_string_error) => {
+                    return Some(Err(AuthenticationError::CannotConvertToString))
+                }
+            };
+
+            if value_prefix.is_empty() {
+                return Some(Ok(value.to_string()));
+            }
+
+            // Technically, the prefix comparison should be case sensitive, but let's accept
+            // case variations, as we do for JWT
+            let prefix_len = value_prefix.len();
+            if value.len() <= prefix_len
+                || !value.is_char_boundary(prefix_len)
+                || !value[..prefix_len].eq_ignore_ascii_case(value_prefix)
+            {
+                return Some(Err(AuthenticationError::InvalidPrefix(
+                    HEADER_KEY_TRUNCATED,
+                    value_prefix,
+                )));
+            }
+
+            Some(Ok(value[prefix_len..].trim_start().to_string()))
+        }
+        Source::Query { name } => url::form_urlencoded::parse(uri.query()?.as_bytes())
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| Ok(value.into_owned())),
+    }
+}
+
+// API keys are long lived, so contrary to JWTs, we never reflect them in error messages
+const HEADER_KEY_TRUNCATED: &str = "(redacted)";
+
+#[cfg(test)]
+mod tests {
+    use serde_json_bytes::json;
+
+    use super::*;
+
+    fn key_file() -> String {
+        let key2_hash = argon2::password_hash::PasswordHasher::hash_password(
+            &Argon2::default(),
+            b"key2",
+            &argon2::password_hash::SaltString::from_b64("c2FsdHNhbHRzYWx0").unwrap(),
+        )
+        .unwrap()
+        .to_string();
+
+        format!(
+            r#"
+keys:
+  - hash: "sha256:{}"
+    metadata:
+      tenant: acme
+      scope: "read write"
+  - hash: "{key2_hash}"
+    prefix: key2
+    metadata:
+      tenant: globex
+      plan: gold
+"#,
+            hex::encode(Sha256::digest(b"key1"))
+        )
+    }
+
+    fn auth(sources: Vec<Source>) -> ApiKeyAuth {
+        ApiKeyAuth::from_key_file(sources, Path::new("keys.yaml"), &key_file()).unwrap()
+    }
+
+    fn request(uri: &str, header: Option<&str>) -> router::Request {
+        let mut builder = http::Request::builder().uri(uri);
+        if let Some(value) = header {
+            builder = builder.header("x-api-key", value);
+        }
+        router::Request {
+            router_request: builder.body(hyper::Body::empty()).unwrap(),
+            context: Context::new(),
+        }
+    }
+
+    fn claims(request: &router::Request) -> Option<serde_json_bytes::Value> {
+        request
+            .context
+            .get(APOLLO_AUTHENTICATION_JWT_CLAIMS)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn sha256_key_in_header() {
+        let auth = auth(default_sources());
+        match auth
+            .authenticate(request("http://localhost/", Some("key1")))
+            .await
+        {
+            ControlFlow::Continue(request) => assert_eq!(
+                claims(&request),
+                Some(json!({"tenant": "acme", "scope": "read write"}))
+            ),
+            ControlFlow::Break(_) => panic!("the key should be accepted"),
+        }
+    }
+
+    #[tokio::test]
+    async fn argon2_key_in_query() {
+        let auth = auth(vec![Source::Query {
+            name: "api_key".to_string(),
+        }]);
+        for _ in 0..2 {
+            match auth
+                .authenticate(request("http://localhost/?a=b&api_key=key2", None))
+                .await
+            {
+                ControlFlow::Continue(request) => assert_eq!(
+                    claims(&request),
+                    Some(json!({"tenant": "globex", "plan": "gold"}))
+                ),
+                ControlFlow::Break(_) => panic!("the key should be accepted"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_key_is_rejected() {
+        let auth = auth(default_sources());
+        match auth
+            .authenticate(request("http://localhost/", Some("key3")))
+            .await
+        {
+            ControlFlow::Continue(_) => panic!("the key should be rejected"),
+            ControlFlow::Break(response) => {
+                assert_eq!(response.response.status(), StatusCode::UNAUTHORIZED)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_key_is_anonymous() {
+        let auth = auth(default_sources());
+        match auth.authenticate(request("http://localhost/", None)).await {
+            ControlFlow::Continue(request) => assert_eq!(claims(&request), None),
+            ControlFlow::Break(_) => panic!("anonymous requests should go through"),
+        }
+    }
+
+    #[tokio::test]
+    async fn header_prefix() {
+        let auth = auth(vec![Source::Header {
+            name: "x-api-key".to_string(),
+            value_prefix: "ApiKey".to_string(),
+        }]);
+        assert!(matches!(
+            auth.authenticate(request("http://localhost/", Some("ApiKey key1")))
+                .await,
+            ControlFlow::Continue(_)
+        ));
+        match auth
+            .authenticate(request("http://localhost/", Some("Bearer key1")))
+            .await
+        {
+            ControlFlow::Continue(_) => panic!("the prefix should be checked"),
+            ControlFlow::Break(response) => {
+                assert_eq!(response.response.status(), StatusCode::BAD_REQUEST)
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_key_file() {
+        assert!(
+            KeyIndex::parse(Path::new("keys.yaml"), "keys:\n  - hash: \"md5:abcd\"\n").is_err()
+        );
+        assert!(
+            KeyIndex::parse(Path::new("keys.yaml"), "keys:\n  - hash: \"sha256:abcd\"\n").is_err()
+        );
+        assert!(KeyIndex::parse(Path::new("keys.json"), r#"{"keys": []}"#).is_ok());
+
+        let argon2_hash = argon2::password_hash::PasswordHasher::hash_password(
+            &Argon2::default(),
+            b"abkey",
+            &argon2::password_hash::SaltString::from_b64("c2FsdHNhbHRzYWx0").unwrap(),
+        )
+        .unwrap()
+        .to_string();
+        let argon2_keys = |prefixes: &[&str]| {
+            let mut content = "keys:\n".to_string();
+            for prefix in prefixes {
+                content.push_str(&format!(
+                    "  - hash: \"{argon2_hash}\"\n    prefix: {prefix}\n"
+                ));
+            }
+            content
+        };
+        assert!(KeyIndex::parse(Path::new("keys.yaml"), &argon2_keys(&["ab", "cd"])).is_ok());
+        // argon2 hashes need a prefix
+        assert!(KeyIndex::parse(
+            Path::new("keys.yaml"),
+            &format!("keys:\n  - hash: \"{argon2_hash}\"\n")
+        )
+        .is_err());
+        // a key could match both entries
+        assert!(KeyIndex::parse(Path::new("keys.yaml"), &argon2_keys(&["ab", "abc"])).is_err());
+    }
+}
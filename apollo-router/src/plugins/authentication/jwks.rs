@@ -28,19 +28,19 @@ use super::CLIENT;
 use super::DEFAULT_AUTHENTICATION_NETWORK_TIMEOUT;
 
 #[derive(Clone)]
-pub(super) struct JwksManager {
+pub(crate) struct JwksManager {
     list: Vec<JwksConfig>,
     jwks_map: Arc<RwLock<HashMap<Url, JwkSet>>>,
     _drop_signal: Arc<oneshot::Sender<()>>,
 }
 
 #[derive(Clone)]
-pub(super) struct JwksConfig {
-    pub(super) url: Url,
-    pub(super) issuer: Option<String>,
-    pub(super) algorithms: Option<HashSet<Algorithm>>,
-    pub(super) poll_interval: Duration,
-    pub(super) headers: Vec<Header>,
+pub(crate) struct JwksConfig {
+    pub(crate) url: Url,
+    pub(crate) issuer: Option<String>,
+    pub(crate) algorithms: Option<HashSet<Algorithm>>,
+    pub(crate) poll_interval: Duration,
+    pub(crate) headers: Vec<Header>,
 }
 
 #[derive(Clone)]
@@ -80,7 +80,7 @@ impl JwksManager {
     }
 
     #[cfg(test)]
-    pub(super) fn new_test(list: Vec<JwksConfig>, jwks: HashMap<Url, JwkSet>) -> Self {
+    pub(crate) fn new_test(list: Vec<JwksConfig>, jwks: HashMap<Url, JwkSet>) -> Self {
         let (_drop_signal, _) = oneshot::channel::<()>();
 
         JwksManager {
@@ -8,6 +8,7 @@ use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
 use displaydoc::Display;
+use futures::FutureExt;
 use http::HeaderMap;
 use http::HeaderName;
 use http::HeaderValue;
@@ -36,10 +37,12 @@ use tower::ServiceBuilder;
 use tower::ServiceExt;
 use url::Url;
 
+use self::api_key::ApiKeyAuth;
 use self::jwks::JwksManager;
 use self::subgraph::SigningParams;
 use self::subgraph::SigningParamsConfig;
 use self::subgraph::SubgraphAuth;
+use crate::axum_factory::websocket::APOLLO_WEBSOCKET_CONNECTION_PARAMS;
 use crate::graphql;
 use crate::layers::ServiceBuilderExt;
 use crate::plugin::serde::deserialize_header_name;
@@ -52,7 +55,8 @@ use crate::register_plugin;
 use crate::services::router;
 use crate::Context;
 
-mod jwks;
+mod api_key;
+pub(crate) mod jwks;
 pub(crate) mod subgraph;
 
 #[cfg(test)]
@@ -99,6 +103,9 @@ pub(crate) enum AuthenticationError<'a> {
 
     /// Unsupported key algorithm: {0}
     UnsupportedKeyAlgorithm(KeyAlgorithm),
+
+    /// Invalid API key
+    InvalidApiKey,
 }
 
 const DEFAULT_AUTHENTICATION_NETWORK_TIMEOUT: Duration = Duration::from_secs(15);
@@ -117,8 +124,9 @@ struct Router {
     jwks_manager: JwksManager,
 }
 
-struct AuthenticationPlugin {
+pub(crate) struct AuthenticationPlugin {
     router: Option<Router>,
+    api_key: Option<ApiKeyAuth>,
     subgraph: Option<SubgraphAuth>,
 }
 
@@ -167,7 +175,7 @@ struct JwksConf {
 #[derive(Clone, Debug, JsonSchema, Deserialize)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 /// Insert a header
-struct Header {
+pub(crate) struct Header {
     /// The name of the header
     #[schemars(with = "String")]
     #[serde(deserialize_with = "deserialize_header_name")]
@@ -199,21 +207,22 @@ enum Source {
 /// Authentication
 #[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
-struct Conf {
+pub(crate) struct Conf {
     /// Router configuration
     router: Option<RouterConf>,
     /// Subgraph configuration
     subgraph: Option<subgraph::Config>,
 }
 
-// We may support additional authentication mechanisms in future, so all
-// configuration (which is currently JWT specific) is isolated to the
-// JWTConf structure.
+// Each authentication mechanism has its own configuration structure. If several
+// mechanisms are configured, JWT authentication is attempted first.
 #[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct RouterConf {
     /// The JWT configuration
-    jwt: JWTConf,
+    jwt: Option<JWTConf>,
+    /// The API key configuration
+    api_key: Option<api_key::Config>,
 }
 
 fn default_header_name() -> String {
@@ -405,6 +414,15 @@ fn search_jwks(
     }
 }
 
+impl AuthenticationPlugin {
+    /// The JWKS used for JWT authentication, if it is configured
+    pub(crate) fn jwks_manager(&self) -> Option<JwksManager> {
+        self.router
+            .as_ref()
+            .map(|router| router.jwks_manager.clone())
+    }
+}
+
 #[async_trait::async_trait]
 impl Plugin for AuthenticationPlugin {
     type Config = Conf;
@@ -432,9 +450,13 @@ impl Plugin for AuthenticationPlugin {
             None
         };
 
-        let router = if let Some(mut router_conf) = init.config.router {
-            if router_conf
-                .jwt
+        let (jwt_conf, api_key_conf) = match init.config.router {
+            Some(router_conf) => (router_conf.jwt, router_conf.api_key),
+            None => (None, None),
+        };
+
+        let router = if let Some(mut jwt_conf) = jwt_conf {
+            if jwt_conf
                 .header_value_prefix
                 .as_bytes()
                 .iter()
@@ -443,7 +465,7 @@ impl Plugin for AuthenticationPlugin {
                 return Err(Error::BadHeaderValuePrefix.into());
             }
 
-            for source in &router_conf.jwt.sources {
+            for source in &jwt_conf.sources {
                 if let Source::Header { value_prefix, .. } = source {
                     if value_prefix.as_bytes().iter().any(u8::is_ascii_whitespace) {
                         return Err(Error::BadHeaderValuePrefix.into());
@@ -451,16 +473,16 @@ impl Plugin for AuthenticationPlugin {
                 }
             }
 
-            router_conf.jwt.sources.insert(
+            jwt_conf.sources.insert(
                 0,
                 Source::Header {
-                    name: router_conf.jwt.header_name.clone(),
-                    value_prefix: router_conf.jwt.header_value_prefix.clone(),
+                    name: jwt_conf.header_name.clone(),
+                    value_prefix: jwt_conf.header_value_prefix.clone(),
                 },
             );
 
             let mut list = vec![];
-            for jwks_conf in &router_conf.jwt.jwks {
+            for jwks_conf in &jwt_conf.jwks {
                 let url: Url = Url::from_str(jwks_conf.url.as_str())?;
                 list.push(JwksConfig {
                     url,
@@ -474,22 +496,46 @@ impl Plugin for AuthenticationPlugin {
                 });
             }
 
-            tracing::info!(jwks=?router_conf.jwt.jwks, "JWT authentication using JWKSets from");
+            tracing::info!(jwks=?jwt_conf.jwks, "JWT authentication using JWKSets from");
 
             let jwks_manager = JwksManager::new(list).await?;
 
             Some(Router {
-                configuration: router_conf.jwt,
+                configuration: jwt_conf,
                 jwks_manager,
             })
         } else {
             None
         };
 
-        Ok(Self { router, subgraph })
+        let api_key = match api_key_conf {
+            Some(conf) => Some(ApiKeyAuth::new(conf).await?),
+            None => None,
+        };
+
+        Ok(Self {
+            router,
+            api_key,
+            subgraph,
+        })
     }
 
     fn router_service(&self, service: router::BoxService) -> router::BoxService {
+        // API key authentication runs after JWT authentication, and is skipped if
+        // the request was already authenticated
+        let service = if let Some(api_key) = &self.api_key {
+            let api_key = api_key.clone();
+            ServiceBuilder::new()
+                .oneshot_checkpoint_async(move |request: router::Request| {
+                    let api_key = api_key.clone();
+                    async move { Ok(api_key.authenticate(request).await) }.boxed()
+                })
+                .service(service)
+                .boxed()
+        } else {
+            service
+        };
+
         if let Some(config) = &self.router {
             let jwks_manager = config.jwks_manager.clone();
             let configuration = config.configuration.clone();
@@ -571,13 +617,15 @@ fn authenticate(
         ControlFlow::Break(response)
     }
 
+    // Over WebSocket, clients cannot set headers, so they send them in the `connection_init` payload
+    let connection_params_headers = connection_params_headers(config, &request);
+    let headers = connection_params_headers
+        .as_ref()
+        .unwrap_or(request.router_request.headers());
+
     let mut jwt = None;
     for source in &config.sources {
-        match extract_jwt(
-            source,
-            config.ignore_other_prefixes,
-            request.router_request.headers(),
-        ) {
+        match extract_jwt(source, config.ignore_other_prefixes, headers) {
             None => continue,
             Some(Err(error)) => {
                 return failure_message(request.context, error, StatusCode::BAD_REQUEST)
@@ -594,17 +642,77 @@ fn authenticate(
         None => return ControlFlow::Continue(request),
     };
 
+    let claims = match verify_jwt(jwks_manager, jwt) {
+        Ok(claims) => claims,
+        Err((auth_error, status_code)) => {
+            return failure_message(request.context, auth_error, status_code);
+        }
+    };
+
+    if let Err(e) = request
+        .context
+        .insert(APOLLO_AUTHENTICATION_JWT_CLAIMS, claims)
+    {
+        return failure_message(
+            request.context,
+            AuthenticationError::CannotInsertClaimsIntoContext(e),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        );
+    }
+    // This is a metric and will not appear in the logs
+    tracing::info!(
+        monotonic_counter.apollo_authentication_success_count = 1u64,
+        kind = %AUTHENTICATION_KIND
+    );
+    tracing::info!(monotonic_counter.apollo.router.operations.jwt = 1u64);
+    ControlFlow::Continue(request)
+}
+
+/// Returns the request headers, completed with the header sources found in the payload of the
+/// WebSocket `connection_init` message, if the request was sent over WebSocket.
+fn connection_params_headers(config: &JWTConf, request: &router::Request) -> Option<HeaderMap> {
+    let connection_params = request
+        .context
+        .get::<_, serde_json::Map<String, Value>>(APOLLO_WEBSOCKET_CONNECTION_PARAMS)
+        .ok()
+        .flatten()?;
+    let mut headers = request.router_request.headers().clone();
+    for source in &config.sources {
+        if let Source::Header { name, .. } = source {
+            if headers.contains_key(name.as_str()) {
+                continue;
+            }
+            let value = connection_params
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .and_then(|(_, value)| value.as_str())
+                .and_then(|value| HeaderValue::from_str(value).ok());
+            if let (Ok(name), Some(value)) = (HeaderName::from_str(name), value) {
+                headers.insert(name, value);
+            }
+        }
+    }
+    Some(headers)
+}
+
+/// Verify a JWT against the keys of the JWKS list, and return its claims.
+///
+/// The JWT must be signed by one of the keys, and its issuer must match the issuer configured
+/// for the JWKS of that key.
+pub(crate) fn verify_jwt(
+    jwks_manager: &JwksManager,
+    jwt: &str,
+) -> Result<Value, (AuthenticationError<'static>, StatusCode)> {
     // Try to create a valid header to work with
     let jwt_header = match decode_header(jwt) {
         Ok(h) => h,
         Err(e) => {
             // Don't reflect the jwt on error, just reply with a fixed
             // error message.
-            return failure_message(
-                request.context,
+            return Err((
                 AuthenticationError::InvalidHeader(HEADER_TOKEN_TRUNCATED, e),
                 StatusCode::BAD_REQUEST,
-            );
+            ));
         }
     };
 
@@ -618,12 +726,7 @@ fn authenticate(
     // Note: This will search through JWKS in the order in which they are defined
     // in configuration.
     if let Some(keys) = search_jwks(jwks_manager, &criteria) {
-        let (issuer, token_data) = match decode_jwt(jwt, keys, criteria) {
-            Ok(data) => data,
-            Err((auth_error, status_code)) => {
-                return failure_message(request.context, auth_error, status_code);
-            }
-        };
+        let (issuer, token_data) = decode_jwt(jwt, keys, criteria)?;
 
         if let Some(configured_issuer) = issuer {
             if let Some(token_issuer) = token_data
@@ -633,50 +736,31 @@ fn authenticate(
                 .and_then(|value| value.as_str())
             {
                 if configured_issuer != token_issuer {
-                    return failure_message(
-                        request.context,
+                    return Err((
                         AuthenticationError::InvalidIssuer {
                             expected: configured_issuer,
                             token: token_issuer.to_string(),
                         },
                         StatusCode::INTERNAL_SERVER_ERROR,
-                    );
+                    ));
                 }
             }
         }
 
-        if let Err(e) = request
-            .context
-            .insert(APOLLO_AUTHENTICATION_JWT_CLAIMS, token_data.claims)
-        {
-            return failure_message(
-                request.context,
-                AuthenticationError::CannotInsertClaimsIntoContext(e),
-                StatusCode::INTERNAL_SERVER_ERROR,
-            );
-        }
-        // This is a metric and will not appear in the logs
-        tracing::info!(
-            monotonic_counter.apollo_authentication_success_count = 1u64,
-            kind = %AUTHENTICATION_KIND
-        );
-        tracing::info!(monotonic_counter.apollo.router.operations.jwt = 1u64);
-        return ControlFlow::Continue(request);
+        return Ok(token_data.claims);
     }
 
     // We can't find a key to process this JWT.
     if criteria.kid.is_some() {
-        failure_message(
-            request.context,
+        Err((
             AuthenticationError::CannotFindKID(criteria.kid),
             StatusCode::UNAUTHORIZED,
-        )
+        ))
     } else {
-        failure_message(
-            request.context,
+        Err((
             AuthenticationError::CannotFindSuitableKey(criteria.alg, criteria.kid),
             StatusCode::UNAUTHORIZED,
-        )
+        ))
     }
 }
 
@@ -777,7 +861,10 @@ fn decode_jwt(
     jwt: &str,
     keys: Vec<(Option<String>, Jwk)>,
     criteria: JWTCriteria,
-) -> Result<(Option<String>, TokenData<serde_json::Value>), (AuthenticationError, StatusCode)> {
+) -> Result<
+    (Option<String>, TokenData<serde_json::Value>),
+    (AuthenticationError<'static>, StatusCode),
+> {
     let mut error = None;
     for (issuer, jwk) in keys.into_iter() {
         let decoding_key = match DecodingKey::from_jwk(&jwk) {
@@ -0,0 +1,488 @@
+//! Authorization plugin
+//!
+//! Audit records of the authorization decisions: for each sampled request where the authorization
+//! directives removed parts of the query, or where the query selected one of the audited types, a
+//! record is sent to the configured sink.
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use apollo_compiler::executable;
+use apollo_compiler::ExecutableDocument;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json_bytes::ByteString;
+use serde_json_bytes::Map;
+use serde_json_bytes::Value;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tower::BoxError;
+use url::Url;
+
+use super::AuthorizationDirective;
+use super::UnauthorizedPaths;
+use crate::context::OPERATION_NAME;
+use crate::plugins::authentication::APOLLO_AUTHENTICATION_JWT_CLAIMS;
+use crate::plugins::telemetry::CLIENT_NAME;
+use crate::plugins::telemetry::CLIENT_VERSION;
+use crate::services::layers::query_analysis::ParsedDocument;
+use crate::Context;
+
+const AUDIT_CHANNEL_SIZE: usize = 1024;
+const AUDIT_LOG_TARGET: &str = "apollo_router::authorization::audit";
+
+/// Authorization audit configuration
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    /// destination of the audit records
+    sink: Sink,
+    /// ratio of requests for which a record is emitted, between 0 and 1
+    #[serde(default = "default_sampling")]
+    sampling: f64,
+    /// claim identifying the subject of the request
+    #[serde(default = "default_subject_claim")]
+    subject_claim: String,
+    /// claims copied to the records. Other claims are never recorded
+    #[serde(default)]
+    claims: Vec<String>,
+    /// types for which allowed accesses are recorded in addition to denials
+    // read from the raw configuration during query planning, in `AuthorizationPlugin::audited_types`
+    #[serde(default)]
+    #[allow(dead_code)]
+    types: Vec<String>,
+}
+
+fn default_sampling() -> f64 {
+    1.0
+}
+
+fn default_subject_claim() -> String {
+    "sub".to_string()
+}
+
+fn default_webhook_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum Sink {
+    /// append the records as JSON lines to a file
+    File {
+        /// path of the file
+        path: PathBuf,
+    },
+    /// emit the records as log events, exported with the router's logs
+    Log,
+    /// send each record as a JSON object in a POST request
+    Webhook {
+        /// URL of the webhook
+        #[schemars(with = "String")]
+        url: Url,
+        /// timeout for webhook requests
+        #[serde(deserialize_with = "humantime_serde::deserialize")]
+        #[schemars(with = "String", default = "default_webhook_timeout")]
+        #[serde(default = "default_webhook_timeout")]
+        timeout: Duration,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct AuditRecord {
+    timestamp: String,
+    decision: Decision,
+    subject: Option<Value>,
+    claims: Map<ByteString, Value>,
+    client_name: Option<String>,
+    client_version: Option<String>,
+    operation_name: Option<String>,
+    operation_hash: Option<String>,
+    denied: Vec<DeniedPath>,
+    allowed_types: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Decision {
+    /// parts of the query were removed, or the entire query was rejected
+    Denied,
+    /// the query was executed without filtering and selects audited types
+    Allowed,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+struct DeniedPath {
+    path: String,
+    directive: AuthorizationDirective,
+}
+
+pub(crate) struct Auditor {
+    config: Config,
+    sender: Option<mpsc::Sender<AuditRecord>>,
+}
+
+impl Auditor {
+    pub(crate) async fn new(config: Config) -> Result<Self, BoxError> {
+        if !(0.0..=1.0).contains(&config.sampling) {
+            return Err("the audit sampling ratio must be between 0 and 1".into());
+        }
+
+        let sender = match &config.sink {
+            Sink::Log => None,
+            Sink::File { path } => {
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(|e| {
+                        format!("could not open the audit file {}: {e}", path.display())
+                    })?;
+                let (sender, mut receiver) = mpsc::channel::<AuditRecord>(AUDIT_CHANNEL_SIZE);
+
+                // the task stops once the auditor, and its sender, are dropped
+                tokio::task::spawn(async move {
+                    while let Some(record) = receiver.recv().await {
+                        let mut line = match serde_json::to_vec(&record) {
+                            Ok(line) => line,
+                            Err(e) => {
+                                tracing::error!("could not serialize the audit record: {e}");
+                                continue;
+                            }
+                        };
+                        line.push(b'\n');
+                        if let Err(e) = file.write_all(&line).await {
+                            tracing::error!("could not write the audit record: {e}");
+                        }
+                    }
+                    let _ = file.flush().await;
+                });
+                Some(sender)
+            }
+            Sink::Webhook { url, timeout } => {
+                let client = reqwest::Client::builder().timeout(*timeout).build()?;
+                let url = url.clone();
+                let (sender, mut receiver) = mpsc::channel::<AuditRecord>(AUDIT_CHANNEL_SIZE);
+
+                tokio::task::spawn(async move {
+                    while let Some(record) = receiver.recv().await {
+                        let res = client
+                            .post(url.clone())
+                            .json(&record)
+                            .send()
+                            .await
+                            .and_then(|response| response.error_for_status());
+                        if let Err(e) = res {
+                            tracing::error!("could not send the audit record: {e}");
+                        }
+                    }
+                });
+                Some(sender)
+            }
+        };
+
+        Ok(Self { config, sender })
+    }
+
+    /// Emits an audit record for this request if the authorization directives denied access
+    /// to parts of the query, or if it selected audited types
+    pub(crate) fn audit(&self, context: &Context, unauthorized: &UnauthorizedPaths) {
+        if unauthorized.paths.is_empty() && unauthorized.audited_types.is_empty() {
+            return;
+        }
+
+        if self.config.sampling < 1.0 && !rand::thread_rng().gen_bool(self.config.sampling) {
+            return;
+        }
+
+        let record = self.record(context, unauthorized);
+        match &self.sender {
+            None => match serde_json::to_string(&record) {
+                Ok(record) => {
+                    tracing::info!(target: AUDIT_LOG_TARGET, record, "authorization audit")
+                }
+                Err(e) => tracing::error!("could not serialize the audit record: {e}"),
+            },
+            Some(sender) => {
+                if sender.try_send(record).is_err() {
+                    u64_counter!(
+                        "apollo.router.operations.authorization.audit.dropped",
+                        "Number of authorization audit records dropped because the sink is too slow",
+                        1
+                    );
+                }
+            }
+        }
+    }
+
+    fn record(&self, context: &Context, unauthorized: &UnauthorizedPaths) -> AuditRecord {
+        let claims = context
+            .get_json_value(APOLLO_AUTHENTICATION_JWT_CLAIMS)
+            .and_then(|claims| match claims {
+                Value::Object(claims) => Some(claims),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let operation_hash = context
+            .extensions()
+            .lock()
+            .get::<ParsedDocument>()
+            .map(|doc| doc.hash.to_string());
+
+        AuditRecord {
+            timestamp: time::OffsetDateTime::now_utc()
+                .format(&time::format_description::well_known::Iso8601::DEFAULT)
+                .unwrap_or_default(),
+            decision: if unauthorized.paths.is_empty() {
+                Decision::Allowed
+            } else {
+                Decision::Denied
+            },
+            subject: claims.get(self.config.subject_claim.as_str()).cloned(),
+            claims: self
+                .config
+                .claims
+                .iter()
+                .filter_map(|name| {
+                    claims
+                        .get(name.as_str())
+                        .map(|value| (name.as_str().into(), value.clone()))
+                })
+                .collect(),
+            client_name: context.get(CLIENT_NAME).ok().flatten(),
+            client_version: context.get(CLIENT_VERSION).ok().flatten(),
+            operation_name: context.get(OPERATION_NAME).ok().flatten(),
+            operation_hash,
+            denied: unauthorized
+                .paths
+                .iter()
+                .map(|unauthorized| DeniedPath {
+                    path: unauthorized.path.to_string(),
+                    directive: unauthorized.directive,
+                })
+                .collect(),
+            allowed_types: unauthorized.audited_types.clone(),
+        }
+    }
+}
+
+/// Lists the audited types selected by an operation
+pub(crate) fn selected_types(
+    document: &ExecutableDocument,
+    operation_name: Option<&str>,
+    audited_types: &HashSet<String>,
+) -> Vec<String> {
+    let Ok(operation) = document.get_operation(operation_name) else {
+        return Vec::new();
+    };
+
+    let mut types = HashSet::new();
+    let mut visited_fragments = HashSet::new();
+    collect_types(
+        document,
+        &operation.selection_set,
+        audited_types,
+        &mut types,
+        &mut visited_fragments,
+    );
+
+    let mut types: Vec<String> = types.into_iter().collect();
+    types.sort();
+    types
+}
+
+fn collect_types<'a>(
+    document: &'a ExecutableDocument,
+    selection_set: &'a executable::SelectionSet,
+    audited_types: &HashSet<String>,
+    types: &mut HashSet<String>,
+    visited_fragments: &mut HashSet<&'a executable::Name>,
+) {
+    if audited_types.contains(selection_set.ty.as_str()) {
+        types.insert(selection_set.ty.to_string());
+    }
+
+    for selection in &selection_set.selections {
+        match selection {
+            executable::Selection::Field(field) => collect_types(
+                document,
+                &field.selection_set,
+                audited_types,
+                types,
+                visited_fragments,
+            ),
+            executable::Selection::InlineFragment(fragment) => collect_types(
+                document,
+                &fragment.selection_set,
+                audited_types,
+                types,
+                visited_fragments,
+            ),
+            executable::Selection::FragmentSpread(spread) => {
+                if visited_fragments.insert(&spread.fragment_name) {
+                    if let Some(fragment) = document.fragments.get(&spread.fragment_name) {
+                        collect_types(
+                            document,
+                            &fragment.selection_set,
+                            audited_types,
+                            types,
+                            visited_fragments,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apollo_compiler::Schema;
+
+    use super::*;
+    use crate::json_ext::Path;
+    use crate::plugins::authorization::UnauthorizedPath;
+
+    const SCHEMA: &str = r#"
+    type Query {
+        me: User
+        product(id: ID!): Product
+    }
+
+    type User {
+        id: ID!
+        name: String
+        payment: PaymentMethod
+    }
+
+    type PaymentMethod {
+        last4: String
+    }
+
+    type Product {
+        id: ID!
+        name: String
+    }
+    "#;
+
+    #[test]
+    fn audited_types_in_operation() {
+        let schema = Schema::parse_and_validate(SCHEMA, "schema.graphql").unwrap();
+        let audited_types: HashSet<String> = ["PaymentMethod".to_string(), "User".to_string()]
+            .into_iter()
+            .collect();
+
+        let document = ExecutableDocument::parse_and_validate(
+            &schema,
+            r#"
+            query A { product(id: "1") { name } }
+            query B { me { ...UserFields } }
+            fragment UserFields on User { name payment { last4 } }
+            "#,
+            "query.graphql",
+        )
+        .unwrap();
+
+        assert!(selected_types(&document, Some("A"), &audited_types).is_empty());
+        assert_eq!(
+            selected_types(&document, Some("B"), &audited_types),
+            vec!["PaymentMethod", "User"]
+        );
+    }
+
+    #[tokio::test]
+    async fn audit_record() {
+        let auditor = Auditor::new(
+            serde_json::from_value(serde_json::json!({
+                "sink": "log",
+                "claims": ["tenant"]
+            }))
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let context = Context::new();
+        context
+            .insert(
+                APOLLO_AUTHENTICATION_JWT_CLAIMS,
+                serde_json_bytes::json!({ "sub": "user-1", "tenant": "acme", "email": "a@b.c" }),
+            )
+            .unwrap();
+        context.insert(CLIENT_NAME, "web".to_string()).unwrap();
+        context.insert(OPERATION_NAME, "B".to_string()).unwrap();
+
+        let record = auditor.record(
+            &context,
+            &UnauthorizedPaths {
+                paths: vec![UnauthorizedPath {
+                    path: Path::from("me/payment"),
+                    directive: AuthorizationDirective::RequiresScopes,
+                }],
+                errors: Default::default(),
+                audited_types: vec!["User".to_string()],
+            },
+        );
+
+        let mut record = serde_json::to_value(record).unwrap();
+        record["timestamp"] = "[timestamp]".into();
+        assert_eq!(
+            record,
+            serde_json::json!({
+                "timestamp": "[timestamp]",
+                "decision": "denied",
+                "subject": "user-1",
+                "claims": { "tenant": "acme" },
+                "client_name": "web",
+                "client_version": null,
+                "operation_name": "B",
+                "operation_hash": null,
+                "denied": [{ "path": "/me/payment", "directive": "requires_scopes" }],
+                "allowed_types": ["User"]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn file_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let auditor = Auditor::new(
+            serde_json::from_value(serde_json::json!({
+                "sink": { "file": { "path": path } }
+            }))
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+
+        auditor.audit(
+            &Context::new(),
+            &UnauthorizedPaths {
+                paths: vec![],
+                errors: Default::default(),
+                audited_types: vec!["User".to_string()],
+            },
+        );
+        // nothing to audit
+        auditor.audit(&Context::new(), &UnauthorizedPaths::default());
+        drop(auditor);
+
+        let mut lines = Vec::new();
+        for _ in 0..50 {
+            let content = tokio::fs::read_to_string(&path).await.unwrap();
+            lines = content.lines().map(|line| line.to_string()).collect();
+            if !lines.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(lines.len(), 1);
+        let record: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(record["decision"], "allowed");
+        assert_eq!(record["allowed_types"], serde_json::json!(["User"]));
+    }
+}
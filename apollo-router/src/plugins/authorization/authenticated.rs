@@ -1663,7 +1663,9 @@ mod tests {
         headers.insert("Accept".into(), "multipart/mixed;deferSpec=20220824".into());
         context.extensions().lock().insert(ClientRequestAccepts {
             multipart_defer: true,
+            multipart_incremental: false,
             multipart_subscription: true,
+            event_stream: true,
             json: true,
             wildcard: true,
         });
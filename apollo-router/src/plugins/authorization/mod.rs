@@ -3,10 +3,14 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::ops::ControlFlow;
+use std::str::FromStr;
+use std::sync::Arc;
 
 use apollo_compiler::ast;
 use apollo_compiler::ExecutableDocument;
+use derivative::Derivative;
 use http::StatusCode;
+use jsonpath_rust::JsonPathInst;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
@@ -15,6 +19,7 @@ use tower::BoxError;
 use tower::ServiceBuilder;
 use tower::ServiceExt;
 
+use self::audit::Auditor;
 use self::authenticated::AuthenticatedCheckVisitor;
 use self::authenticated::AuthenticatedVisitor;
 use self::authenticated::AUTHENTICATED_SPEC_BASE_URL;
@@ -23,6 +28,7 @@ use self::policy::PolicyExtractionVisitor;
 use self::policy::PolicyFilteringVisitor;
 use self::policy::POLICY_SPEC_BASE_URL;
 use self::policy::POLICY_SPEC_VERSION_RANGE;
+use self::policy_engine::PolicyEngine;
 use self::scopes::ScopeExtractionVisitor;
 use self::scopes::ScopeFilteringVisitor;
 use self::scopes::REQUIRES_SCOPES_SPEC_BASE_URL;
@@ -32,6 +38,7 @@ use crate::error::ServiceBuildError;
 use crate::graphql;
 use crate::json_ext::Path;
 use crate::layers::ServiceBuilderExt;
+use crate::plugin::serde::deserialize_jsonpath;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
 use crate::plugins::authentication::APOLLO_AUTHENTICATION_JWT_CLAIMS;
@@ -48,8 +55,10 @@ use crate::spec::SpecError;
 use crate::Configuration;
 use crate::Context;
 
+pub(crate) mod audit;
 pub(crate) mod authenticated;
 pub(crate) mod policy;
+pub(crate) mod policy_engine;
 pub(crate) mod scopes;
 
 const AUTHENTICATED_KEY: &str = "apollo_authorization::authenticated::required";
@@ -73,6 +82,9 @@ pub(crate) struct Conf {
     /// `@authenticated`, `@requiresScopes` and `@policy` directives
     #[serde(default)]
     directives: Directives,
+    /// audit records of the authorization decisions
+    #[serde(default)]
+    audit: Option<audit::Config>,
 }
 
 #[derive(Clone, Debug, serde_derive_default::Default, Deserialize, JsonSchema)]
@@ -90,6 +102,99 @@ pub(crate) struct Directives {
     /// authorization errors behaviour
     #[serde(default)]
     errors: ErrorConfig,
+    /// location of the request's scopes in the claims, for `@requiresScopes`
+    #[serde(default)]
+    scopes: ScopesConfig,
+    /// policies evaluated by the router for `@policy`, as expressions over the `claims`,
+    /// `headers` and `context` variables. Policies that are not listed here are left to
+    /// coprocessors and Rhai scripts
+    #[serde(default)]
+    policies: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, serde_derive_default::Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ScopesConfig {
+    /// claims containing the request's scopes. Scopes found in all sources are merged
+    #[serde(default = "default_scope_sources")]
+    sources: Vec<ScopeSource>,
+}
+
+fn default_scope_sources() -> Vec<ScopeSource> {
+    vec![ScopeSource {
+        claim: JsonPathInst::from_str("$.scope").expect("JSON path must be valid"),
+        format: ScopeFormat::SpaceDelimited,
+    }]
+}
+
+impl ScopesConfig {
+    /// Extracts the request's scopes from the claims
+    pub(crate) fn extract(&self, claims: &serde_json::Value) -> HashSet<String> {
+        self.sources
+            .iter()
+            .flat_map(|source| {
+                source
+                    .claim
+                    .find_slice(claims)
+                    .into_iter()
+                    .flat_map(|value| source.format.parse(&value))
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Derivative, Deserialize, JsonSchema)]
+#[derivative(Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ScopeSource {
+    /// JSON path to the claim, like `$.scope` or `$.realm_access.roles`
+    #[schemars(with = "String")]
+    #[derivative(Debug = "ignore")]
+    #[serde(deserialize_with = "deserialize_jsonpath")]
+    claim: JsonPathInst,
+    /// format of the claim's value
+    #[serde(default)]
+    format: ScopeFormat,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ScopeFormat {
+    /// a string of scopes separated by spaces, as defined in RFC 6749
+    #[default]
+    SpaceDelimited,
+    /// a string of scopes separated by commas
+    CommaDelimited,
+    /// an array of strings
+    Array,
+}
+
+impl ScopeFormat {
+    fn parse(&self, value: &serde_json::Value) -> Vec<String> {
+        let delimiter = match (self, value) {
+            (ScopeFormat::Array, serde_json::Value::Array(array)) => {
+                return array
+                    .iter()
+                    .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                    .collect();
+            }
+            (ScopeFormat::SpaceDelimited, serde_json::Value::String(_)) => ' ',
+            (ScopeFormat::CommaDelimited, serde_json::Value::String(_)) => ',',
+            _ => {
+                tracing::debug!("the scope claim does not match the {self:?} format");
+                return Vec::new();
+            }
+        };
+
+        value
+            .as_str()
+            .unwrap_or_default()
+            .split(delimiter)
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect()
+    }
 }
 
 #[derive(
@@ -122,9 +227,27 @@ pub(crate) enum ErrorLocation {
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
-pub(crate) struct UnauthorizedPaths {
-    pub(crate) paths: Vec<Path>,
+pub struct UnauthorizedPaths {
+    pub(crate) paths: Vec<UnauthorizedPath>,
     pub(crate) errors: ErrorConfig,
+    /// audited types still selected by the query after filtering
+    #[serde(default)]
+    pub(crate) audited_types: Vec<String>,
+}
+
+/// A path removed from the query, with the directive that required it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct UnauthorizedPath {
+    pub(crate) path: Path,
+    pub(crate) directive: AuthorizationDirective,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AuthorizationDirective {
+    Authenticated,
+    RequiresScopes,
+    Policy,
 }
 
 fn default_enable_directives() -> bool {
@@ -133,6 +256,8 @@ fn default_enable_directives() -> bool {
 
 pub(crate) struct AuthorizationPlugin {
     require_authentication: bool,
+    policy_engine: Option<Arc<PolicyEngine>>,
+    auditor: Option<Arc<Auditor>>,
 }
 
 impl AuthorizationPlugin {
@@ -174,6 +299,42 @@ impl AuthorizationPlugin {
             .unwrap_or_default()
     }
 
+    pub(crate) fn scopes_config(configuration: &Configuration) -> ScopesConfig {
+        configuration
+            .apollo_plugins
+            .plugins
+            .iter()
+            .find(|(s, _)| s.as_str() == "authorization")
+            .and_then(|(_, v)| v.get("directives").and_then(|v| v.as_object()))
+            .and_then(|v| {
+                v.get("scopes")
+                    .and_then(|v| serde_json::from_value(v.clone()).ok())
+            })
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn audited_types(
+        configuration: &Configuration,
+        document: &ExecutableDocument,
+        operation_name: Option<&str>,
+    ) -> Vec<String> {
+        let audited_types: HashSet<String> = configuration
+            .apollo_plugins
+            .plugins
+            .iter()
+            .find(|(s, _)| s.as_str() == "authorization")
+            .and_then(|(_, v)| v.get("audit"))
+            .and_then(|v| v.get("types"))
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default();
+
+        if audited_types.is_empty() {
+            Vec::new()
+        } else {
+            audit::selected_types(document, operation_name, &audited_types)
+        }
+    }
+
     pub(crate) fn query_analysis(
         doc: &ParsedDocumentInner,
         operation_name: Option<&str>,
@@ -248,19 +409,14 @@ impl AuthorizationPlugin {
         }
     }
 
-    pub(crate) fn update_cache_key(context: &Context) {
+    pub(crate) fn update_cache_key(context: &Context, scopes_config: &ScopesConfig) {
         let is_authenticated = context.contains_key(APOLLO_AUTHENTICATION_JWT_CLAIMS);
 
         let request_scopes = context
-            .get_json_value(APOLLO_AUTHENTICATION_JWT_CLAIMS)
-            .and_then(|value| {
-                value.as_object().and_then(|object| {
-                    object.get("scope").and_then(|v| {
-                        v.as_str()
-                            .map(|s| s.split(' ').map(|s| s.to_string()).collect::<HashSet<_>>())
-                    })
-                })
-            });
+            .get::<_, serde_json::Value>(APOLLO_AUTHENTICATION_JWT_CLAIMS)
+            .ok()
+            .flatten()
+            .map(|claims| scopes_config.extract(&claims));
         let query_scopes = context.get_json_value(REQUIRED_SCOPES_KEY).and_then(|v| {
             v.as_array().map(|v| {
                 v.iter()
@@ -360,14 +516,17 @@ impl AuthorizationPlugin {
         let policies = &key.metadata.policies;
 
         let mut is_filtered = false;
-        let mut unauthorized_paths: Vec<Path> = vec![];
+        let mut unauthorized_paths: Vec<UnauthorizedPath> = vec![];
 
         let filter_res = Self::authenticated_filter_query(schema, dry_run, &doc, is_authenticated)?;
 
         let doc = match filter_res {
             None => doc,
             Some((filtered_doc, paths)) => {
-                unauthorized_paths.extend(paths);
+                unauthorized_paths.extend(paths.into_iter().map(|path| UnauthorizedPath {
+                    path,
+                    directive: AuthorizationDirective::Authenticated,
+                }));
 
                 // FIXME: consider only `filtered_doc.get_operation(key.operation_name)`?
                 if filtered_doc.definitions.is_empty() {
@@ -385,7 +544,10 @@ impl AuthorizationPlugin {
         let doc = match filter_res {
             None => doc,
             Some((filtered_doc, paths)) => {
-                unauthorized_paths.extend(paths);
+                unauthorized_paths.extend(paths.into_iter().map(|path| UnauthorizedPath {
+                    path,
+                    directive: AuthorizationDirective::RequiresScopes,
+                }));
 
                 // FIXME: consider only `filtered_doc.get_operation(key.operation_name)`?
                 if filtered_doc.definitions.is_empty() {
@@ -403,7 +565,10 @@ impl AuthorizationPlugin {
         let doc = match filter_res {
             None => doc,
             Some((filtered_doc, paths)) => {
-                unauthorized_paths.extend(paths);
+                unauthorized_paths.extend(paths.into_iter().map(|path| UnauthorizedPath {
+                    path,
+                    directive: AuthorizationDirective::Policy,
+                }));
 
                 // FIXME: consider only `filtered_doc.get_operation(key.operation_name)`?
                 if filtered_doc.definitions.is_empty() {
@@ -541,12 +706,75 @@ impl Plugin for AuthorizationPlugin {
     type Config = Conf;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let directives = &init.config.directives;
+        let policy_engine = if directives.enabled && !directives.policies.is_empty() {
+            Some(Arc::new(PolicyEngine::new(&directives.policies)?))
+        } else {
+            None
+        };
+
+        let auditor = match init.config.audit {
+            Some(config) => Some(Arc::new(Auditor::new(config).await?)),
+            None => None,
+        };
+
         Ok(AuthorizationPlugin {
             require_authentication: init.config.require_authentication,
+            policy_engine,
+            auditor,
         })
     }
 
     fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        let service = match &self.policy_engine {
+            Some(policy_engine) => {
+                let policy_engine = policy_engine.clone();
+                ServiceBuilder::new()
+                    .map_request(move |request: supergraph::Request| {
+                        if let Ok(Some(mut policies)) = request
+                            .context
+                            .get::<_, HashMap<String, Option<bool>>>(REQUIRED_POLICIES_KEY)
+                        {
+                            policy_engine.evaluate(
+                                &mut policies,
+                                request.supergraph_request.headers(),
+                                &request.context,
+                            );
+                            if let Err(e) = request.context.insert(REQUIRED_POLICIES_KEY, policies)
+                            {
+                                tracing::error!("could not store the policies evaluation: {e}");
+                            }
+                        }
+                        request
+                    })
+                    .service(service)
+                    .boxed()
+            }
+            None => service,
+        };
+
+        let service = match &self.auditor {
+            Some(auditor) => {
+                let auditor = auditor.clone();
+                ServiceBuilder::new()
+                    .map_response(move |response: supergraph::Response| {
+                        let unauthorized = response
+                            .context
+                            .extensions()
+                            .lock()
+                            .get::<UnauthorizedPaths>()
+                            .cloned();
+                        if let Some(unauthorized) = unauthorized {
+                            auditor.audit(&response.context, &unauthorized);
+                        }
+                        response
+                    })
+                    .service(service)
+                    .boxed()
+            }
+            None => service,
+        };
+
         if self.require_authentication {
             ServiceBuilder::new()
                 .checkpoint(move |request: supergraph::Request| {
@@ -0,0 +1,213 @@
+//! Authorization plugin
+//!
+//! Evaluation of the `@policy` directive's policies from expressions declared in the configuration:
+//!
+//! ```yaml
+//! authorization:
+//!   directives:
+//!     policies:
+//!       acme_admin: 'claims.tenant == "acme" && "admin" in claims.roles'
+//! ```
+//!
+//! Expressions are Rhai expressions (no statements, loops or function definitions) with access to
+//! the `claims`, `headers` and `context` variables.
+use std::collections::HashMap;
+
+use http::HeaderMap;
+use rhai::Dynamic;
+use rhai::Engine;
+use rhai::Map;
+use rhai::Scope;
+use rhai::AST;
+use tower::BoxError;
+
+use crate::plugins::authentication::APOLLO_AUTHENTICATION_JWT_CLAIMS;
+use crate::Context;
+
+const MAX_OPERATIONS: u64 = 10_000;
+const MAX_EXPRESSION_DEPTH: usize = 32;
+
+pub(crate) struct PolicyEngine {
+    engine: Engine,
+    policies: HashMap<String, AST>,
+}
+
+impl PolicyEngine {
+    pub(crate) fn new(policies: &HashMap<String, String>) -> Result<Self, BoxError> {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_expr_depths(MAX_EXPRESSION_DEPTH, MAX_EXPRESSION_DEPTH);
+
+        let policies = policies
+            .iter()
+            .map(|(name, expression)| {
+                engine
+                    .compile_expression(expression)
+                    .map(|ast| (name.clone(), ast))
+                    .map_err(|e| format!("invalid expression for the policy '{name}': {e}").into())
+            })
+            .collect::<Result<_, BoxError>>()?;
+
+        Ok(Self { engine, policies })
+    }
+
+    /// Evaluates the policies that are declared in the configuration, and that no other
+    /// plugin evaluated yet
+    pub(crate) fn evaluate(
+        &self,
+        required: &mut HashMap<String, Option<bool>>,
+        headers: &HeaderMap,
+        context: &Context,
+    ) {
+        let mut scope = None;
+
+        for (name, result) in required.iter_mut() {
+            if result.is_some() {
+                continue;
+            }
+
+            if let Some(ast) = self.policies.get(name) {
+                let scope = scope.get_or_insert_with(|| Self::scope(headers, context));
+                let value = self.engine.eval_ast_with_scope::<Dynamic>(scope, ast);
+                *result = Some(match value {
+                    Ok(value) => value.as_bool().unwrap_or_else(|_| {
+                        tracing::debug!(
+                            "the policy '{name}' evaluated to a {} instead of a boolean",
+                            value.type_name()
+                        );
+                        false
+                    }),
+                    Err(e) => {
+                        tracing::debug!("could not evaluate the policy '{name}': {e}");
+                        false
+                    }
+                });
+            }
+        }
+    }
+
+    fn scope(headers: &HeaderMap, context: &Context) -> Scope<'static> {
+        let claims = context
+            .get_json_value(APOLLO_AUTHENTICATION_JWT_CLAIMS)
+            .and_then(|claims| rhai::serde::to_dynamic(claims).ok())
+            .unwrap_or_default();
+
+        let headers: Map = headers
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|value| (name.as_str().into(), value.into()))
+            })
+            .collect();
+
+        let context: Map = context
+            .iter()
+            .filter_map(|entry| {
+                rhai::serde::to_dynamic(entry.value())
+                    .ok()
+                    .map(|value| (entry.key().as_str().into(), value))
+            })
+            .collect();
+
+        let mut scope = Scope::new();
+        scope.push_constant("claims", claims);
+        scope.push_constant("headers", headers);
+        scope.push_constant("context", context);
+        scope
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+    use serde_json_bytes::json;
+
+    use super::*;
+
+    fn evaluate(policies: &[(&str, &str)], context: &Context) -> HashMap<String, Option<bool>> {
+        let engine = PolicyEngine::new(
+            &policies
+                .iter()
+                .map(|(name, expression)| (name.to_string(), expression.to_string()))
+                .collect(),
+        )
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-tenant", HeaderValue::from_static("acme"));
+
+        let mut required: HashMap<String, Option<bool>> = policies
+            .iter()
+            .map(|(name, _)| (name.to_string(), None))
+            .collect();
+        required.insert("evaluated".to_string(), Some(true));
+        required.insert("unknown".to_string(), None);
+
+        engine.evaluate(&mut required, &headers, context);
+        required
+    }
+
+    #[test]
+    fn evaluate_policies() {
+        let context = Context::new();
+        context
+            .insert(
+                APOLLO_AUTHENTICATION_JWT_CLAIMS,
+                json!({ "tenant": "acme", "roles": ["admin", "user"] }),
+            )
+            .unwrap();
+        context.insert("region", "eu".to_string()).unwrap();
+
+        let required = evaluate(
+            &[
+                (
+                    "admin",
+                    r#"claims.tenant == "acme" && "admin" in claims.roles"#,
+                ),
+                ("editor", r#""editor" in claims.roles"#),
+                ("header", r#"headers["x-tenant"] == claims.tenant"#),
+                ("context", r#"context.region == "eu""#),
+                ("missing", r#"claims.missing == "value""#),
+                ("not_a_boolean", r#"claims.tenant"#),
+                ("evaluated", "false"),
+            ],
+            &context,
+        );
+
+        assert_eq!(required["admin"], Some(true));
+        assert_eq!(required["editor"], Some(false));
+        assert_eq!(required["header"], Some(true));
+        assert_eq!(required["context"], Some(true));
+        assert_eq!(required["missing"], Some(false));
+        assert_eq!(required["not_a_boolean"], Some(false));
+        // policies already evaluated by another plugin are not modified
+        assert_eq!(required["evaluated"], Some(true));
+        // policies missing from the configuration are left to other plugins
+        assert_eq!(required["unknown"], None);
+    }
+
+    #[test]
+    fn unauthenticated_request() {
+        let required = evaluate(
+            &[("admin", r#""admin" in claims.roles"#), ("always", "true")],
+            &Context::new(),
+        );
+
+        assert_eq!(required["admin"], Some(false));
+        assert_eq!(required["always"], Some(true));
+    }
+
+    #[test]
+    fn invalid_expression() {
+        assert!(
+            PolicyEngine::new(&[("admin".to_string(), "let x = 1;".to_string())].into()).is_err()
+        );
+        assert!(
+            PolicyEngine::new(&[("admin".to_string(), "claims.tenant ==".to_string())].into())
+                .is_err()
+        );
+    }
+}
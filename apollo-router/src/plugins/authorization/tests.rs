@@ -8,6 +8,7 @@ use crate::graphql;
 use crate::plugin::test::MockSubgraph;
 use crate::plugin::test::MockSubgraphService;
 use crate::plugins::authorization::CacheKeyMetadata;
+use crate::plugins::authorization::ScopesConfig;
 use crate::services::router;
 use crate::services::subgraph;
 use crate::services::supergraph;
@@ -1157,3 +1158,49 @@ async fn cache_key_metadata() {
 
     insta::assert_json_snapshot!(response);
 }
+
+#[test]
+fn scopes_from_claims() {
+    let claims = serde_json::json!({
+        "scope": "profile email",
+        "scp": ["user:read", "user:write"],
+        "roles": "admin, editor",
+        "realm_access": { "roles": ["realm:admin"] },
+        "https://example.com/groups": ["staff"]
+    });
+
+    let default_config = ScopesConfig::default();
+    let mut scopes = default_config
+        .extract(&claims)
+        .into_iter()
+        .collect::<Vec<_>>();
+    scopes.sort();
+    assert_eq!(scopes, vec!["email", "profile"]);
+
+    let config: ScopesConfig = serde_json::from_value(serde_json::json!({
+        "sources": [
+            { "claim": "$.scp", "format": "array" },
+            { "claim": "$.roles", "format": "comma_delimited" },
+            { "claim": "$.realm_access.roles", "format": "array" },
+            { "claim": "$['https://example.com/groups']", "format": "array" },
+            // format mismatch: ignored
+            { "claim": "$.scope", "format": "array" },
+            // missing claim: ignored
+            { "claim": "$.groups" }
+        ]
+    }))
+    .unwrap();
+    let mut scopes = config.extract(&claims).into_iter().collect::<Vec<_>>();
+    scopes.sort();
+    assert_eq!(
+        scopes,
+        vec![
+            "admin",
+            "editor",
+            "realm:admin",
+            "staff",
+            "user:read",
+            "user:write"
+        ]
+    );
+}
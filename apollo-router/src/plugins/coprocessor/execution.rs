@@ -1,5 +1,6 @@
 use std::ops::ControlFlow;
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures::future;
 use futures::stream;
@@ -16,6 +17,8 @@ use super::*;
 use crate::graphql;
 use crate::layers::async_checkpoint::OneShotAsyncCheckpointLayer;
 use crate::layers::ServiceBuilderExt;
+use crate::plugins::coprocessor::record_coprocessor_call;
+use crate::plugins::coprocessor::record_coprocessor_duration;
 use crate::plugins::coprocessor::EXTERNAL_SPAN_NAME;
 use crate::response;
 use crate::services::execution;
@@ -38,6 +41,8 @@ pub(super) struct ExecutionRequestConf {
     pub(super) query_plan: bool,
     /// Handles the request without waiting for the coprocessor to respond
     pub(super) detached: bool,
+    /// What to do when the coprocessor call fails or returns an invalid request
+    pub(super) on_error: OnError,
     /// The url you'd like to offload processing to
     #[schemars(with = "String")]
     pub(super) url: Option<Url>,
@@ -59,6 +64,8 @@ pub(super) struct ExecutionResponseConf {
     pub(super) status_code: bool,
     /// Handles the response without waiting for the coprocessor to respond
     pub(super) detached: bool,
+    /// What to do when the coprocessor call fails or returns an invalid response
+    pub(super) on_error: OnError,
     /// The url you'd like to offload processing to
     #[schemars(with = "String")]
     pub(super) url: Option<Url>,
@@ -74,11 +81,15 @@ pub(super) struct ExecutionStage {
 }
 
 impl ExecutionStage {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn as_service<C>(
         &self,
         http_client: C,
         service: execution::BoxService,
         coprocessor_url: Url,
+        transport: Transport,
+        timeout: Duration,
+        name: Option<String>,
         sdl: Arc<String>,
     ) -> execution::BoxService
     where
@@ -89,6 +100,7 @@ impl ExecutionStage {
             + 'static,
         <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
     {
+        let span_name = name.clone();
         let request_layer = (self.request != Default::default()).then_some({
             let request_config = self.request.clone();
             let coprocessor_url = request_config
@@ -96,12 +108,14 @@ impl ExecutionStage {
                 .clone()
                 .unwrap_or_else(|| coprocessor_url.clone());
             let http_client = http_client.clone();
+            let name = name.clone();
             let sdl = sdl.clone();
 
             OneShotAsyncCheckpointLayer::new(move |request: execution::Request| {
                 let request_config = request_config.clone();
                 let coprocessor_url = coprocessor_url.clone();
                 let http_client = http_client.clone();
+                let name = name.clone();
                 let sdl = sdl.clone();
 
                 async move {
@@ -109,6 +123,8 @@ impl ExecutionStage {
                     let result = process_execution_request_stage(
                         http_client,
                         coprocessor_url,
+                        transport,
+                        name.clone(),
                         sdl,
                         request,
                         request_config,
@@ -117,17 +133,16 @@ impl ExecutionStage {
                     .map_err(|error| {
                         succeeded = false;
                         tracing::error!(
+                            coprocessor.name = name.as_deref(),
                             "external extensibility: execution request stage error: {error}"
                         );
                         error
                     });
 
-                    u64_counter!(
-                        "apollo.router.operations.coprocessor",
-                        "Total operations with co-processors enabled",
-                        1,
-                        "coprocessor.stage" = PipelineStep::ExecutionRequest,
-                        "coprocessor.succeeded" = succeeded
+                    record_coprocessor_call(
+                        name.as_deref(),
+                        PipelineStep::ExecutionRequest,
+                        succeeded,
                     );
                     result
                 }
@@ -140,6 +155,7 @@ impl ExecutionStage {
             MapFutureLayer::new(move |fut| {
                 let sdl: Arc<String> = sdl.clone();
                 let http_client = http_client.clone();
+                let name = name.clone();
                 let response_config = response_config.clone();
                 let coprocessor_url = response_config
                     .url
@@ -153,6 +169,9 @@ impl ExecutionStage {
                     let result = process_execution_response_stage(
                         http_client,
                         coprocessor_url,
+                        transport,
+                        timeout,
+                        name.clone(),
                         sdl,
                         response,
                         response_config,
@@ -161,35 +180,37 @@ impl ExecutionStage {
                     .map_err(|error| {
                         succeeded = false;
                         tracing::error!(
+                            coprocessor.name = name.as_deref(),
                             "external extensibility: execution response stage error: {error}"
                         );
                         error
                     });
 
-                    u64_counter!(
-                        "apollo.router.operations.coprocessor",
-                        "Total operations with co-processors enabled",
-                        1,
-                        "coprocessor.stage" = PipelineStep::ExecutionResponse,
-                        "coprocessor.succeeded" = succeeded
+                    record_coprocessor_call(
+                        name.as_deref(),
+                        PipelineStep::ExecutionResponse,
+                        succeeded,
                     );
                     result
                 }
             })
         });
 
-        fn external_service_span() -> impl Fn(&execution::Request) -> tracing::Span + Clone {
+        fn external_service_span(
+            name: Option<String>,
+        ) -> impl Fn(&execution::Request) -> tracing::Span + Clone {
             move |_request: &execution::Request| {
                 tracing::info_span!(
                     EXTERNAL_SPAN_NAME,
                     "external service" = stringify!(execution::Request),
+                    "coprocessor.name" = name.as_deref(),
                     "otel.kind" = "INTERNAL"
                 )
             }
         }
 
         ServiceBuilder::new()
-            .instrument(external_service_span())
+            .instrument(external_service_span(span_name))
             .option_layer(request_layer)
             .option_layer(response_layer)
             .service(service)
@@ -200,6 +221,8 @@ impl ExecutionStage {
 async fn process_execution_request_stage<C>(
     http_client: C,
     coprocessor_url: Url,
+    transport: Transport,
+    name: Option<String>,
     sdl: Arc<String>,
     mut request: execution::Request,
     request_config: ExecutionRequestConf,
@@ -250,12 +273,9 @@ where
         tokio::task::spawn(async move {
             tracing::debug!(?payload, "externalized output");
             let start = Instant::now();
-            let _ = payload.call(http_client, &coprocessor_url).await;
+            let _ = payload.call(http_client, &coprocessor_url, transport).await;
             let duration = start.elapsed().as_secs_f64();
-            tracing::info!(
-                histogram.apollo.router.operations.coprocessor.duration = duration,
-                coprocessor.stage = %PipelineStep::ExecutionRequest,
-            );
+            record_coprocessor_duration(name.as_deref(), PipelineStep::ExecutionRequest, duration);
         });
 
         request.supergraph_request = http::Request::from_parts(parts, body);
@@ -265,30 +285,23 @@ where
     tracing::debug!(?payload, "externalized output");
     let guard = request.context.enter_active_request();
     let start = Instant::now();
-    let co_processor_result = payload.call(http_client, &coprocessor_url).await;
+    let co_processor_result = payload.call(http_client, &coprocessor_url, transport).await;
     let duration = start.elapsed().as_secs_f64();
     drop(guard);
-    tracing::info!(
-        histogram.apollo.router.operations.coprocessor.duration = duration,
-        coprocessor.stage = %PipelineStep::ExecutionRequest,
-    );
+    record_coprocessor_duration(name.as_deref(), PipelineStep::ExecutionRequest, duration);
 
     tracing::debug!(?co_processor_result, "co-processor returned");
-    let co_processor_output = co_processor_result?;
-    validate_coprocessor_output(&co_processor_output, PipelineStep::ExecutionRequest)?;
-    // unwrap is safe here because validate_coprocessor_output made sure control is available
-    let control = co_processor_output.control.expect("validated above; qed");
-
-    // Thirdly, we need to interpret the control flow which may have been
-    // updated by our co-processor and decide if we should proceed or stop.
-
-    if matches!(control, Control::Break(_)) {
-        // Ensure the code is a valid http status code
-        let code = control.get_http_status()?;
-
-        let res = {
-            let graphql_response: crate::graphql::Response =
-                serde_json::from_value(co_processor_output.body.unwrap_or(serde_json::Value::Null))
+    let co_processor_output = handle_coprocessor_output(
+        co_processor_result,
+        name.as_deref(),
+        PipelineStep::ExecutionRequest,
+        request_config.on_error,
+        |body, control| {
+            Ok(match control {
+                Some(Control::Break(_)) => ControlFlow::Break(
+                    serde_json::from_value::<crate::graphql::Response>(
+                        body.unwrap_or(serde_json::Value::Null),
+                    )
                     .unwrap_or_else(|error| {
                         crate::graphql::Response::builder()
                             .errors(vec![Error::builder()
@@ -298,66 +311,67 @@ where
                                 .extension_code("EXTERNAL_DESERIALIZATION_ERROR")
                                 .build()])
                             .build()
-                    });
+                    }),
+                ),
+                _ => ControlFlow::Continue(body.map(serde_json::from_value).transpose()?),
+            })
+        },
+    )?;
+
+    // Thirdly, we need to interpret the control flow which may have been
+    // updated by our co-processor and decide if we should proceed or stop.
+
+    let new_body: Option<crate::graphql::Request> = match co_processor_output.body {
+        ControlFlow::Break(graphql_response) => {
+            let code = co_processor_output
+                .status_code
+                .expect("request stages have a control; qed");
 
             let mut http_response = http::Response::builder()
                 .status(code)
                 .body(stream::once(future::ready(graphql_response)).boxed())?;
             if let Some(headers) = co_processor_output.headers {
-                *http_response.headers_mut() = internalize_header_map(headers)?;
+                *http_response.headers_mut() = headers;
             }
 
             let execution_response = execution::Response {
                 response: http_response,
                 context: request.context,
             };
+            co_processor_output
+                .context
+                .merge_into(&execution_response.context);
 
-            if let Some(context) = co_processor_output.context {
-                for (key, value) in context.try_into_iter()? {
-                    execution_response
-                        .context
-                        .upsert_json_value(key, move |_current| value);
-                }
-            }
-
-            execution_response
-        };
-        return Ok(ControlFlow::Break(res));
-    }
+            return Ok(ControlFlow::Break(execution_response));
+        }
+        ControlFlow::Continue(new_body) => new_body,
+    };
 
     // Finally, process our reply and act on the contents. Our processing logic is
     // that we replace "bits" of our incoming request with the updated bits if they
     // are present in our co_processor_output.
 
-    let new_body: crate::graphql::Request = match co_processor_output.body {
-        Some(value) => serde_json::from_value(value)?,
-        None => body,
-    };
-
-    request.supergraph_request = http::Request::from_parts(parts, new_body);
-
-    if let Some(context) = co_processor_output.context {
-        for (key, value) in context.try_into_iter()? {
-            request
-                .context
-                .upsert_json_value(key, move |_current| value);
-        }
-    }
+    request.supergraph_request = http::Request::from_parts(parts, new_body.unwrap_or(body));
+    co_processor_output.context.merge_into(&request.context);
 
     if let Some(headers) = co_processor_output.headers {
-        *request.supergraph_request.headers_mut() = internalize_header_map(headers)?;
+        *request.supergraph_request.headers_mut() = headers;
     }
 
     if let Some(uri) = co_processor_output.uri {
-        *request.supergraph_request.uri_mut() = uri.parse()?;
+        *request.supergraph_request.uri_mut() = uri;
     }
 
     Ok(ControlFlow::Continue(request))
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn process_execution_response_stage<C>(
     http_client: C,
     coprocessor_url: Url,
+    transport: Transport,
+    timeout: Duration,
+    name: Option<String>,
     sdl: Arc<String>,
     mut response: execution::Response,
     response_config: ExecutionResponseConf,
@@ -410,15 +424,13 @@ where
     if response_config.detached {
         let http_client2 = http_client.clone();
         let coprocessor_url2 = coprocessor_url.clone();
+        let name2 = name.clone();
         tokio::task::spawn(async move {
             tracing::debug!(?payload, "externalized output");
             let start = Instant::now();
-            let _ = payload.call(http_client, &coprocessor_url).await;
+            let _ = payload.call(http_client, &coprocessor_url, transport).await;
             let duration = start.elapsed().as_secs_f64();
-            tracing::info!(
-                histogram.apollo.router.operations.coprocessor.duration = duration,
-                coprocessor.stage = %PipelineStep::ExecutionResponse,
-            );
+            record_coprocessor_duration(name.as_deref(), PipelineStep::ExecutionResponse, duration);
         });
 
         let map_context = response.context.clone();
@@ -426,6 +438,7 @@ where
         let mapped_stream = rest.map(move |deferred_response| {
             let generator_client = http_client2.clone();
             let generator_coprocessor_url = coprocessor_url2.clone();
+            let generator_name = name2.clone();
             let generator_map_context = map_context.clone();
             let generator_sdl_to_send = sdl_to_send.clone();
             let generator_id = map_context.id.clone();
@@ -453,12 +466,13 @@ where
                 tracing::debug!(?payload, "externalized output");
                 let start = Instant::now();
                 let _ = payload
-                    .call(generator_client, &generator_coprocessor_url)
+                    .call(generator_client, &generator_coprocessor_url, transport)
                     .await;
                 let duration = start.elapsed().as_secs_f64();
-                tracing::info!(
-                    histogram.apollo.router.operations.coprocessor.duration = duration,
-                    coprocessor.stage = %PipelineStep::ExecutionResponse,
+                record_coprocessor_duration(
+                    generator_name.as_deref(),
+                    PipelineStep::ExecutionResponse,
+                    duration,
                 );
             });
 
@@ -477,53 +491,53 @@ where
     tracing::debug!(?payload, "externalized output");
     let guard = response.context.enter_active_request();
     let start = Instant::now();
-    let co_processor_result = payload.call(http_client.clone(), &coprocessor_url).await;
+    let co_processor_result = payload
+        .call(http_client.clone(), &coprocessor_url, transport)
+        .await;
     let duration = start.elapsed().as_secs_f64();
     drop(guard);
-    tracing::info!(
-        histogram.apollo.router.operations.coprocessor.duration = duration,
-        coprocessor.stage = %PipelineStep::ExecutionResponse,
-    );
+    record_coprocessor_duration(name.as_deref(), PipelineStep::ExecutionResponse, duration);
 
     tracing::debug!(?co_processor_result, "co-processor returned");
-    let co_processor_output = co_processor_result?;
-
-    validate_coprocessor_output(&co_processor_output, PipelineStep::ExecutionResponse)?;
+    let co_processor_output = handle_coprocessor_output(
+        co_processor_result,
+        name.as_deref(),
+        PipelineStep::ExecutionResponse,
+        response_config.on_error,
+        |body, _| Ok(body.map(serde_json::from_value).transpose()?),
+    )?;
 
     // Third, process our reply and act on the contents. Our processing logic is
     // that we replace "bits" of our incoming response with the updated bits if they
     // are present in our co_processor_output. If they aren't present, just use the
     // bits that we sent to the co_processor.
-    let new_body: crate::response::Response = match co_processor_output.body {
-        Some(value) => serde_json::from_value(value)?,
-        None => first,
-    };
+    let new_body: crate::response::Response = co_processor_output.body.unwrap_or(first);
 
-    if let Some(control) = co_processor_output.control {
-        parts.status = control.get_http_status()?
+    if let Some(status_code) = co_processor_output.status_code {
+        parts.status = status_code;
     }
 
-    if let Some(context) = co_processor_output.context {
-        for (key, value) in context.try_into_iter()? {
-            response
-                .context
-                .upsert_json_value(key, move |_current| value);
-        }
-    }
+    co_processor_output.context.merge_into(&response.context);
 
     if let Some(headers) = co_processor_output.headers {
-        parts.headers = internalize_header_map(headers)?;
+        parts.headers = headers;
     }
 
     // Clone all the bits we need
+    let deferred_calls = Arc::new(DeferredCalls::new(
+        http_client,
+        coprocessor_url,
+        transport,
+        timeout,
+    ));
     let context = response.context.clone();
     let map_context = response.context.clone();
 
     // Map the rest of our body to process subsequent chunks of response
     let mapped_stream = rest
         .then(move |deferred_response| {
-            let generator_client = http_client.clone();
-            let generator_coprocessor_url = coprocessor_url.clone();
+            let deferred_calls = deferred_calls.clone();
+            let generator_name = name.clone();
             let generator_map_context = map_context.clone();
             let generator_sdl_to_send = sdl_to_send.clone();
             let generator_id = map_context.id.clone();
@@ -552,34 +566,31 @@ where
                 tracing::debug!(?payload, "externalized output");
                 let start = Instant::now();
                 let guard = generator_map_context.enter_active_request();
-                let co_processor_result = payload
-                    .call(generator_client, &generator_coprocessor_url)
-                    .await;
+                let co_processor_result = deferred_calls.call(payload).await;
                 let duration = start.elapsed().as_secs_f64();
                 drop(guard);
-                tracing::info!(
-                    histogram.apollo.router.operations.coprocessor.duration = duration,
-                    coprocessor.stage = %PipelineStep::ExecutionResponse,
+                record_coprocessor_duration(
+                    generator_name.as_deref(),
+                    PipelineStep::ExecutionResponse,
+                    duration,
                 );
-                let co_processor_output = co_processor_result?;
-
-                validate_coprocessor_output(&co_processor_output, PipelineStep::ExecutionResponse)?;
+                let co_processor_output = handle_coprocessor_output(
+                    co_processor_result,
+                    generator_name.as_deref(),
+                    PipelineStep::ExecutionResponse,
+                    response_config.on_error,
+                    |body, _| Ok(body.map(serde_json::from_value).transpose()?),
+                )?;
 
                 // Third, process our reply and act on the contents. Our processing logic is
                 // that we replace "bits" of our incoming response with the updated bits if they
                 // are present in our co_processor_output. If they aren't present, just use the
                 // bits that we sent to the co_processor.
                 let new_deferred_response: crate::response::Response =
-                    match co_processor_output.body {
-                        Some(value) => serde_json::from_value(value)?,
-                        None => deferred_response,
-                    };
-
-                if let Some(context) = co_processor_output.context {
-                    for (key, value) in context.try_into_iter()? {
-                        generator_map_context.upsert_json_value(key, move |_current| value);
-                    }
-                }
+                    co_processor_output.body.unwrap_or(deferred_response);
+                co_processor_output
+                    .context
+                    .merge_into(&generator_map_context);
 
                 // We return the deferred_response into our stream of response chunks
                 Ok(new_deferred_response)
@@ -614,6 +625,7 @@ where
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
+    use std::time::Duration;
 
     use futures::future::BoxFuture;
     use http::StatusCode;
@@ -808,6 +820,9 @@ mod tests {
             mock_http_client,
             mock_execution_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -873,6 +888,9 @@ mod tests {
             mock_http_client,
             mock_execution_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -930,6 +948,9 @@ mod tests {
             mock_http_client,
             mock_execution_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -1052,6 +1073,9 @@ mod tests {
             mock_http_client,
             mock_execution_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -1163,6 +1187,9 @@ mod tests {
             mock_http_client,
             mock_execution_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -1226,6 +1253,9 @@ mod tests {
             mock_http_client,
             mock_execution_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
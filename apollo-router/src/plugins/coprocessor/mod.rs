@@ -1,9 +1,11 @@
 //! Externalization plugin
 
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::ops::ControlFlow;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::task::Poll;
 use std::time::Duration;
 use std::time::Instant;
 
@@ -20,8 +22,12 @@ use hyper::client::HttpConnector;
 use hyper::Body;
 use hyper_rustls::ConfigBuilderExt;
 use hyper_rustls::HttpsConnector;
+#[cfg(unix)]
+use hyperlocal::UnixConnector;
 use schemars::JsonSchema;
+use serde::de::Error as _;
 use serde::Deserialize;
+use serde::Deserializer;
 use serde::Serialize;
 use tower::timeout::TimeoutLayer;
 use tower::util::MapFutureLayer;
@@ -31,17 +37,28 @@ use tower::ServiceBuilder;
 use tower::ServiceExt;
 use url::Url;
 
+use self::resilience::CircuitBreakerConf;
+use self::resilience::CircuitOpen;
+use self::resilience::ResilientClient;
+use self::resilience::RetryConf;
 use crate::error::Error;
 use crate::layers::async_checkpoint::OneShotAsyncCheckpointLayer;
+use crate::layers::map_future_with_request_data::MapFutureWithRequestDataLayer;
 use crate::layers::ServiceBuilderExt;
-use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
-use crate::register_plugin;
+use crate::plugin::PluginPrivate;
+use crate::plugins::telemetry::config_new::conditions::Condition;
+use crate::plugins::telemetry::config_new::selectors::RouterSelector;
+use crate::plugins::telemetry::config_new::selectors::SubgraphSelector;
+use crate::plugins::telemetry::config_new::Selector;
+use crate::register_private_plugin;
 use crate::services;
 use crate::services::external::externalize_header_map;
 use crate::services::external::Control;
+use crate::services::external::DeferredCalls;
 use crate::services::external::Externalizable;
 use crate::services::external::PipelineStep;
+use crate::services::external::Transport;
 use crate::services::external::DEFAULT_EXTERNALIZATION_TIMEOUT;
 use crate::services::external::EXTERNALIZABLE_VERSION;
 use crate::services::router;
@@ -53,6 +70,8 @@ use crate::services::trust_dns_connector::AsyncHyperResolver;
 mod test;
 
 mod execution;
+mod query_planner;
+mod resilience;
 mod supergraph;
 
 pub(crate) const EXTERNAL_SPAN_NAME: &str = "external_plugin";
@@ -60,40 +79,24 @@ const POOL_IDLE_TIMEOUT_DURATION: Option<Duration> = Some(Duration::from_secs(5)
 const COPROCESSOR_ERROR_EXTENSION: &str = "ERROR";
 const COPROCESSOR_DESERIALIZATION_ERROR_EXTENSION: &str = "EXTERNAL_DESERIALIZATION_ERROR";
 
-type HTTPClientService =
-    tower::timeout::Timeout<hyper::Client<HttpsConnector<HttpConnector<AsyncHyperResolver>>, Body>>;
+type HTTPClient = hyper::Client<HttpsConnector<HttpConnector<AsyncHyperResolver>>, Body>;
+#[cfg(unix)]
+type UnixHTTPClient = hyper::Client<UnixConnector, Body>;
+type HTTPClientService = ResilientClient<tower::timeout::Timeout<MixedClient>>;
 
 #[async_trait::async_trait]
-impl Plugin for CoprocessorPlugin<HTTPClientService> {
+impl PluginPrivate for CoprocessorPlugin<HTTPClientService> {
     type Config = Conf;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
-        let mut http_connector = new_async_http_connector()?;
-        http_connector.set_nodelay(true);
-        http_connector.set_keepalive(Some(std::time::Duration::from_secs(60)));
-        http_connector.enforce_http(false);
-
-        let tls_config = rustls::ClientConfig::builder()
-            .with_safe_defaults()
-            .with_native_roots()
-            .with_no_client_auth();
-
-        let connector = hyper_rustls::HttpsConnectorBuilder::new()
-            .with_tls_config(tls_config)
-            .https_or_http()
-            .enable_http1()
-            .enable_http2()
-            .wrap_connector(http_connector);
-
-        let http_client = ServiceBuilder::new()
-            .layer(TimeoutLayer::new(init.config.timeout))
-            .service(
-                hyper::Client::builder()
-                    .pool_idle_timeout(POOL_IDLE_TIMEOUT_DURATION)
-                    .build(connector),
-            );
-
-        CoprocessorPlugin::new(http_client, init.config, init.supergraph_sdl)
+        let coprocessors = init
+            .config
+            .into_coprocessors()?
+            .into_iter()
+            .map(|configuration| Ok((new_http_client(&configuration)?, configuration)))
+            .collect::<Result<_, BoxError>>()?;
+
+        CoprocessorPlugin::new(coprocessors, init.supergraph_sdl)
     }
 
     fn router_service(&self, service: router::BoxService) -> router::BoxService {
@@ -117,14 +120,93 @@ impl Plugin for CoprocessorPlugin<HTTPClientService> {
     fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
         self.subgraph_service(name, service)
     }
+
+    fn query_planner_service(
+        &self,
+        service: services::query_planner::BoxService,
+    ) -> services::query_planner::BoxService {
+        self.query_planner_service(service)
+    }
+}
+
+fn new_http_client(config: &CoprocessorConf) -> Result<HTTPClientService, BoxError> {
+    let mut http_connector = new_async_http_connector()?;
+    http_connector.set_nodelay(true);
+    http_connector.set_keepalive(Some(std::time::Duration::from_secs(60)));
+    http_connector.enforce_http(false);
+
+    let tls_config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_native_roots()
+        .with_no_client_auth();
+
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_or_http();
+    // gRPC requires HTTP/2, including without TLS
+    let grpc = config.transport == Transport::Grpc;
+    let connector = if grpc {
+        connector.enable_http2().wrap_connector(http_connector)
+    } else {
+        connector
+            .enable_http1()
+            .enable_http2()
+            .wrap_connector(http_connector)
+    };
+
+    let mut builder = hyper::Client::builder();
+    builder
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT_DURATION)
+        .http2_only(grpc);
+
+    let client = ServiceBuilder::new()
+        .layer(TimeoutLayer::new(config.timeout))
+        .service(MixedClient {
+            http_client: builder.build(connector),
+            #[cfg(unix)]
+            unix_client: builder.build(UnixConnector),
+        });
+
+    Ok(ResilientClient::new(
+        client,
+        config.retry.clone(),
+        &config.circuit_breaker,
+    ))
+}
+
+/// HTTP client sending requests to a Unix socket for `unix://` URLs, and over TCP otherwise
+#[derive(Clone, Debug)]
+struct MixedClient {
+    http_client: HTTPClient,
+    #[cfg(unix)]
+    unix_client: UnixHTTPClient,
+}
+
+impl Service<hyper::Request<Body>> for MixedClient {
+    type Response = hyper::Response<Body>;
+    type Error = hyper::Error;
+    type Future = hyper::client::ResponseFuture;
+
+    fn poll_ready(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // hyper clients are always ready
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: hyper::Request<Body>) -> Self::Future {
+        #[cfg(unix)]
+        if request.uri().scheme_str() == Some("unix") {
+            return self.unix_client.request(request);
+        }
+        self.http_client.request(request)
+    }
 }
 
 // This macro allows us to use it in our plugin registry!
-// register_plugin takes a group name, and a plugin name.
+// register_private_plugin takes a group name, and a plugin name.
 //
 // In order to keep the plugin names consistent,
 // we use using the `Reverse domain name notation`
-register_plugin!(
+register_private_plugin!(
     "apollo",
     "coprocessor",
     CoprocessorPlugin<HTTPClientService>
@@ -144,10 +226,10 @@ where
         + Send
         + Sync
         + 'static,
-    <C as tower::Service<http::Request<Body>>>::Future: Send + Sync + 'static,
+    <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
 {
-    http_client: C,
-    configuration: Conf,
+    /// The coprocessors with their HTTP client, in the order they are applied to requests
+    coprocessors: Vec<(C, CoprocessorConf)>,
     sdl: Arc<String>,
 }
 
@@ -158,56 +240,105 @@ where
         + Send
         + Sync
         + 'static,
-    <C as tower::Service<http::Request<Body>>>::Future: Send + Sync + 'static,
+    <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
 {
-    fn new(http_client: C, configuration: Conf, sdl: Arc<String>) -> Result<Self, BoxError> {
-        Ok(Self {
-            http_client,
-            configuration,
-            sdl,
-        })
+    fn new(coprocessors: Vec<(C, CoprocessorConf)>, sdl: Arc<String>) -> Result<Self, BoxError> {
+        Ok(Self { coprocessors, sdl })
     }
 
+    // The first coprocessor wraps the others, so that it sees requests first and responses last,
+    // and a `Control::Break` from one of them short-circuits the ones after it.
+
     fn router_service(&self, service: router::BoxService) -> router::BoxService {
-        self.configuration.router.as_service(
-            self.http_client.clone(),
-            service,
-            self.configuration.url.clone(),
-            self.sdl.clone(),
-        )
+        self.coprocessors
+            .iter()
+            .rev()
+            .fold(service, |service, (http_client, configuration)| {
+                configuration.router.as_service(
+                    http_client.clone(),
+                    service,
+                    configuration.url.clone(),
+                    configuration.transport,
+                    configuration.timeout,
+                    configuration.name.clone(),
+                    self.sdl.clone(),
+                )
+            })
     }
 
     fn supergraph_service(
         &self,
         service: services::supergraph::BoxService,
     ) -> services::supergraph::BoxService {
-        self.configuration.supergraph.as_service(
-            self.http_client.clone(),
-            service,
-            self.configuration.url.clone(),
-            self.sdl.clone(),
-        )
+        self.coprocessors
+            .iter()
+            .rev()
+            .fold(service, |service, (http_client, configuration)| {
+                configuration.supergraph.as_service(
+                    http_client.clone(),
+                    service,
+                    configuration.url.clone(),
+                    configuration.transport,
+                    configuration.timeout,
+                    configuration.name.clone(),
+                    self.sdl.clone(),
+                )
+            })
     }
 
     fn execution_service(
         &self,
         service: services::execution::BoxService,
     ) -> services::execution::BoxService {
-        self.configuration.execution.as_service(
-            self.http_client.clone(),
-            service,
-            self.configuration.url.clone(),
-            self.sdl.clone(),
-        )
+        self.coprocessors
+            .iter()
+            .rev()
+            .fold(service, |service, (http_client, configuration)| {
+                configuration.execution.as_service(
+                    http_client.clone(),
+                    service,
+                    configuration.url.clone(),
+                    configuration.transport,
+                    configuration.timeout,
+                    configuration.name.clone(),
+                    self.sdl.clone(),
+                )
+            })
     }
 
     fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
-        self.configuration.subgraph.all.as_service(
-            self.http_client.clone(),
-            service,
-            self.configuration.url.clone(),
-            name.to_string(),
-        )
+        self.coprocessors
+            .iter()
+            .rev()
+            .fold(service, |service, (http_client, configuration)| {
+                configuration.subgraph.all.as_service(
+                    http_client.clone(),
+                    service,
+                    configuration.url.clone(),
+                    configuration.transport,
+                    configuration.name.clone(),
+                    name.to_string(),
+                )
+            })
+    }
+
+    fn query_planner_service(
+        &self,
+        service: services::query_planner::BoxService,
+    ) -> services::query_planner::BoxService {
+        self.coprocessors
+            .iter()
+            .rev()
+            .fold(service, |service, (http_client, configuration)| {
+                configuration.query_planner.as_service(
+                    http_client.clone(),
+                    service,
+                    configuration.url.clone(),
+                    configuration.transport,
+                    configuration.name.clone(),
+                    self.sdl.clone(),
+                )
+            })
     }
 }
 
@@ -229,6 +360,11 @@ pub(super) struct RouterRequestConf {
     pub(super) method: bool,
     /// Handles the request without waiting for the coprocessor to respond
     pub(super) detached: bool,
+    /// What to do when the coprocessor call fails or returns an invalid request
+    pub(super) on_error: OnError,
+    /// Condition to trigger this stage
+    #[serde(skip_serializing)]
+    pub(super) condition: Option<Condition<RouterSelector>>,
     /// The url you'd like to offload processing to
     #[schemars(with = "String")]
     pub(super) url: Option<Url>,
@@ -250,6 +386,11 @@ pub(super) struct RouterResponseConf {
     pub(super) status_code: bool,
     /// Handles the response without waiting for the coprocessor to respond
     pub(super) detached: bool,
+    /// What to do when the coprocessor call fails or returns an invalid response
+    pub(super) on_error: OnError,
+    /// Condition to trigger this stage
+    #[serde(skip_serializing)]
+    pub(super) condition: Option<Condition<RouterSelector>>,
     /// The url you'd like to offload processing to
     #[schemars(with = "String")]
     pub(super) url: Option<Url>,
@@ -273,6 +414,11 @@ pub(super) struct SubgraphRequestConf {
     pub(super) service_name: bool,
     /// Handles the request without waiting for the coprocessor to respond
     pub(super) detached: bool,
+    /// What to do when the coprocessor call fails or returns an invalid request
+    pub(super) on_error: OnError,
+    /// Condition to trigger this stage
+    #[serde(skip_serializing)]
+    pub(super) condition: Option<Condition<SubgraphSelector>>,
     /// The url you'd like to offload processing to
     #[schemars(with = "String")]
     pub(super) url: Option<Url>,
@@ -294,15 +440,90 @@ pub(super) struct SubgraphResponseConf {
     pub(super) status_code: bool,
     /// Handles the response without waiting for the coprocessor to respond
     pub(super) detached: bool,
+    /// What to do when the coprocessor call fails or returns an invalid response
+    pub(super) on_error: OnError,
+    /// Condition to trigger this stage
+    #[serde(skip_serializing)]
+    pub(super) condition: Option<Condition<SubgraphSelector>>,
     /// The url you'd like to offload processing to
     #[schemars(with = "String")]
     pub(super) url: Option<Url>,
 }
 
+/// What to do when a coprocessor call fails
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum OnError {
+    /// Fail the client request
+    #[default]
+    Fail,
+    /// Continue with the unchanged request or response
+    Continue,
+}
+
 /// Configures the externalization plugin
+#[derive(Clone, Debug, JsonSchema)]
+#[schemars(untagged)]
+enum Conf {
+    /// A chain of coprocessors
+    Chain(ChainConf),
+    /// A single coprocessor
+    Single(CoprocessorConf),
+}
+
+impl<'de> Deserialize<'de> for Conf {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // the variant is picked by the `coprocessors` key rather than by trying both, so that a
+        // mistake reports what is wrong instead of matching no variant
+        let value = serde_json::Value::deserialize(deserializer)?;
+        if value.get("coprocessors").is_some() {
+            serde_json::from_value(value).map(Conf::Chain)
+        } else {
+            serde_json::from_value(value).map(Conf::Single)
+        }
+        .map_err(D::Error::custom)
+    }
+}
+
+/// Configures a chain of coprocessors
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct ChainConf {
+    /// The coprocessors, in the order they are applied to requests. Each of them must have a unique name
+    coprocessors: Vec<CoprocessorConf>,
+}
+
+impl Conf {
+    fn into_coprocessors(self) -> Result<Vec<CoprocessorConf>, BoxError> {
+        match self {
+            Conf::Single(conf) => Ok(vec![conf]),
+            Conf::Chain(ChainConf { coprocessors }) => {
+                let mut names = HashSet::new();
+                for conf in &coprocessors {
+                    match &conf.name {
+                        None => return Err("coprocessors in a chain must have a name".into()),
+                        Some(name) if !names.insert(name) => {
+                            return Err(format!("duplicate coprocessor name '{name}'").into())
+                        }
+                        Some(_) => {}
+                    }
+                }
+                Ok(coprocessors)
+            }
+        }
+    }
+}
+
+/// Configures a coprocessor
 #[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
-struct Conf {
+struct CoprocessorConf {
+    /// The name of the coprocessor, required in a chain of coprocessors
+    #[serde(default)]
+    name: Option<String>,
     /// The url you'd like to offload processing to
     #[schemars(with = "String")]
     url: Url,
@@ -311,12 +532,24 @@ struct Conf {
     #[schemars(with = "String", default = "default_timeout")]
     #[serde(default = "default_timeout")]
     timeout: Duration,
+    /// The protocol used to call the coprocessor
+    #[serde(default)]
+    transport: Transport,
+    /// Retries of failed calls to the coprocessor
+    #[serde(default)]
+    retry: RetryConf,
+    /// Skips calls to the coprocessor while it is unhealthy
+    #[serde(default)]
+    circuit_breaker: CircuitBreakerConf,
     /// The router stage request/response configuration
     #[serde(default)]
     router: RouterStage,
     /// The supergraph stage request/response configuration
     #[serde(default)]
     supergraph: supergraph::SupergraphStage,
+    /// The query planner stage request/response configuration
+    #[serde(default)]
+    query_planner: query_planner::QueryPlannerStage,
     /// The execution stage request/response configuration
     #[serde(default)]
     execution: execution::ExecutionStage,
@@ -329,6 +562,42 @@ fn default_timeout() -> Duration {
     DEFAULT_EXTERNALIZATION_TIMEOUT
 }
 
+/// Counts a call to a coprocessor stage. The coprocessor name tells coprocessors of a chain apart
+pub(super) fn record_coprocessor_call(name: Option<&str>, stage: PipelineStep, succeeded: bool) {
+    let mut attributes = coprocessor_attributes(name, stage);
+    attributes.push(opentelemetry::KeyValue::new(
+        "coprocessor.succeeded",
+        succeeded,
+    ));
+    u64_counter!(
+        "apollo.router.operations.coprocessor",
+        "Total operations with co-processors enabled",
+        1,
+        &attributes
+    );
+}
+
+/// Records the time spent waiting for a coprocessor stage
+pub(super) fn record_coprocessor_duration(name: Option<&str>, stage: PipelineStep, duration: f64) {
+    f64_histogram!(
+        "apollo.router.operations.coprocessor.duration",
+        "Time spent waiting for the coprocessor to answer, in seconds",
+        duration,
+        &coprocessor_attributes(name, stage)
+    );
+}
+
+fn coprocessor_attributes(name: Option<&str>, stage: PipelineStep) -> Vec<opentelemetry::KeyValue> {
+    let mut attributes = vec![opentelemetry::KeyValue::new("coprocessor.stage", stage)];
+    if let Some(name) = name {
+        attributes.push(opentelemetry::KeyValue::new(
+            "coprocessor.name",
+            name.to_string(),
+        ));
+    }
+    attributes
+}
+
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
 #[serde(default)]
 pub(super) struct RouterStage {
@@ -339,11 +608,15 @@ pub(super) struct RouterStage {
 }
 
 impl RouterStage {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn as_service<C>(
         &self,
         http_client: C,
         service: router::BoxService,
         coprocessor_url: Url,
+        transport: Transport,
+        timeout: Duration,
+        name: Option<String>,
         sdl: Arc<String>,
     ) -> router::BoxService
     where
@@ -354,6 +627,7 @@ impl RouterStage {
             + 'static,
         <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
     {
+        let span_name = name.clone();
         let request_layer = (self.request != Default::default()).then_some({
             let request_config = self.request.clone();
             let coprocessor_url = request_config
@@ -361,19 +635,29 @@ impl RouterStage {
                 .clone()
                 .unwrap_or_else(|| coprocessor_url.clone());
             let http_client = http_client.clone();
+            let name = name.clone();
             let sdl = sdl.clone();
 
             OneShotAsyncCheckpointLayer::new(move |request: router::Request| {
                 let request_config = request_config.clone();
                 let coprocessor_url = coprocessor_url.clone();
                 let http_client = http_client.clone();
+                let name = name.clone();
                 let sdl = sdl.clone();
 
                 async move {
+                    if let Some(condition) = &request_config.condition {
+                        if condition.clone().evaluate_request(&request) != Some(true) {
+                            return Ok(ControlFlow::Continue(request));
+                        }
+                    }
+
                     let mut succeeded = true;
                     let result = process_router_request_stage(
                         http_client,
                         coprocessor_url,
+                        transport,
+                        name.clone(),
                         sdl,
                         request,
                         request_config,
@@ -382,16 +666,15 @@ impl RouterStage {
                     .map_err(|error| {
                         succeeded = false;
                         tracing::error!(
+                            coprocessor.name = name.as_deref(),
                             "external extensibility: router request stage error: {error}"
                         );
                         error
                     });
-                    u64_counter!(
-                        "apollo.router.operations.coprocessor",
-                        "Total operations with co-processors enabled",
-                        1,
-                        "coprocessor.stage" = PipelineStep::RouterRequest,
-                        "coprocessor.succeeded" = succeeded
+                    record_coprocessor_call(
+                        name.as_deref(),
+                        PipelineStep::RouterRequest,
+                        succeeded,
                     );
                     result
                 }
@@ -400,58 +683,73 @@ impl RouterStage {
 
         let response_layer = (self.response != Default::default()).then_some({
             let response_config = self.response.clone();
-            MapFutureLayer::new(move |fut| {
-                let sdl = sdl.clone();
-                let coprocessor_url = response_config
-                    .url
-                    .clone()
-                    .unwrap_or_else(|| coprocessor_url.clone());
-                let http_client = http_client.clone();
-                let response_config = response_config.clone();
-
-                async move {
-                    let response: router::Response = fut.await?;
-
-                    let mut succeeded = true;
-                    let result = process_router_response_stage(
-                        http_client,
-                        coprocessor_url,
-                        sdl,
-                        response,
-                        response_config,
-                    )
-                    .await
-                    .map_err(|error| {
-                        succeeded = false;
-                        tracing::error!(
-                            "external extensibility: router response stage error: {error}"
+            let condition = self.response.condition.clone();
+            MapFutureWithRequestDataLayer::new(
+                move |request: &router::Request| condition_on_request(&condition, request),
+                move |condition: Option<Condition<RouterSelector>>, fut| {
+                    let sdl = sdl.clone();
+                    let coprocessor_url = response_config
+                        .url
+                        .clone()
+                        .unwrap_or_else(|| coprocessor_url.clone());
+                    let http_client = http_client.clone();
+                    let name = name.clone();
+                    let response_config = response_config.clone();
+
+                    async move {
+                        let response: router::Response = fut.await?;
+                        if let Some(condition) = &condition {
+                            if !condition.evaluate_response(&response) {
+                                return Ok(response);
+                            }
+                        }
+
+                        let mut succeeded = true;
+                        let result = process_router_response_stage(
+                            http_client,
+                            coprocessor_url,
+                            transport,
+                            timeout,
+                            name.clone(),
+                            sdl,
+                            response,
+                            response_config,
+                        )
+                        .await
+                        .map_err(|error| {
+                            succeeded = false;
+                            tracing::error!(
+                                coprocessor.name = name.as_deref(),
+                                "external extensibility: router response stage error: {error}"
+                            );
+                            error
+                        });
+                        record_coprocessor_call(
+                            name.as_deref(),
+                            PipelineStep::RouterResponse,
+                            succeeded,
                         );
-                        error
-                    });
-                    u64_counter!(
-                        "apollo.router.operations.coprocessor",
-                        "Total operations with co-processors enabled",
-                        1,
-                        "coprocessor.stage" = PipelineStep::RouterResponse,
-                        "coprocessor.succeeded" = succeeded
-                    );
-                    result
-                }
-            })
+                        result
+                    }
+                },
+            )
         });
 
-        fn external_service_span() -> impl Fn(&router::Request) -> tracing::Span + Clone {
+        fn external_service_span(
+            name: Option<String>,
+        ) -> impl Fn(&router::Request) -> tracing::Span + Clone {
             move |_request: &router::Request| {
                 tracing::info_span!(
                     EXTERNAL_SPAN_NAME,
                     "external service" = stringify!(router::Request),
+                    "coprocessor.name" = name.as_deref(),
                     "otel.kind" = "INTERNAL"
                 )
             }
         }
 
         ServiceBuilder::new()
-            .instrument(external_service_span())
+            .instrument(external_service_span(span_name))
             .option_layer(request_layer)
             .option_layer(response_layer)
             .service(service)
@@ -485,6 +783,8 @@ impl SubgraphStage {
         http_client: C,
         service: subgraph::BoxService,
         coprocessor_url: Url,
+        transport: Transport,
+        name: Option<String>,
         service_name: String,
     ) -> subgraph::BoxService
     where
@@ -495,9 +795,11 @@ impl SubgraphStage {
             + 'static,
         <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
     {
+        let span_name = name.clone();
         let request_layer = (self.request != Default::default()).then_some({
             let request_config = self.request.clone();
             let http_client = http_client.clone();
+            let name = name.clone();
             let coprocessor_url = request_config
                 .url
                 .clone()
@@ -505,15 +807,24 @@ impl SubgraphStage {
             let service_name = service_name.clone();
             OneShotAsyncCheckpointLayer::new(move |request: subgraph::Request| {
                 let http_client = http_client.clone();
+                let name = name.clone();
                 let coprocessor_url = coprocessor_url.clone();
                 let service_name = service_name.clone();
                 let request_config = request_config.clone();
 
                 async move {
+                    if let Some(condition) = &request_config.condition {
+                        if condition.clone().evaluate_request(&request) != Some(true) {
+                            return Ok(ControlFlow::Continue(request));
+                        }
+                    }
+
                     let mut succeeded = true;
                     let result = process_subgraph_request_stage(
                         http_client,
                         coprocessor_url,
+                        transport,
+                        name.clone(),
                         service_name,
                         request,
                         request_config,
@@ -522,16 +833,15 @@ impl SubgraphStage {
                     .map_err(|error| {
                         succeeded = false;
                         tracing::error!(
+                            coprocessor.name = name.as_deref(),
                             "external extensibility: subgraph request stage error: {error}"
                         );
                         error
                     });
-                    u64_counter!(
-                        "apollo.router.operations.coprocessor",
-                        "Total operations with co-processors enabled",
-                        1,
-                        "coprocessor.stage" = PipelineStep::SubgraphRequest,
-                        "coprocessor.succeeded" = succeeded
+                    record_coprocessor_call(
+                        name.as_deref(),
+                        PipelineStep::SubgraphRequest,
+                        succeeded,
                     );
                     result
                 }
@@ -540,59 +850,73 @@ impl SubgraphStage {
 
         let response_layer = (self.response != Default::default()).then_some({
             let response_config = self.response.clone();
-
-            MapFutureLayer::new(move |fut| {
-                let http_client = http_client.clone();
-                let response_config = response_config.clone();
-                let coprocessor_url = response_config
-                    .url
-                    .clone()
-                    .unwrap_or_else(|| coprocessor_url.clone());
-                let service_name = service_name.clone();
-
-                async move {
-                    let response: subgraph::Response = fut.await?;
-
-                    let mut succeeded = true;
-                    let result = process_subgraph_response_stage(
-                        http_client,
-                        coprocessor_url,
-                        service_name,
-                        response,
-                        response_config,
-                    )
-                    .await
-                    .map_err(|error| {
-                        succeeded = false;
-                        tracing::error!(
-                            "external extensibility: subgraph response stage error: {error}"
+            let condition = self.response.condition.clone();
+
+            MapFutureWithRequestDataLayer::new(
+                move |request: &subgraph::Request| condition_on_request(&condition, request),
+                move |condition: Option<Condition<SubgraphSelector>>, fut| {
+                    let http_client = http_client.clone();
+                    let name = name.clone();
+                    let response_config = response_config.clone();
+                    let coprocessor_url = response_config
+                        .url
+                        .clone()
+                        .unwrap_or_else(|| coprocessor_url.clone());
+                    let service_name = service_name.clone();
+
+                    async move {
+                        let response: subgraph::Response = fut.await?;
+                        if let Some(condition) = &condition {
+                            if !condition.evaluate_response(&response) {
+                                return Ok(response);
+                            }
+                        }
+
+                        let mut succeeded = true;
+                        let result = process_subgraph_response_stage(
+                            http_client,
+                            coprocessor_url,
+                            transport,
+                            name.clone(),
+                            service_name,
+                            response,
+                            response_config,
+                        )
+                        .await
+                        .map_err(|error| {
+                            succeeded = false;
+                            tracing::error!(
+                                coprocessor.name = name.as_deref(),
+                                "external extensibility: subgraph response stage error: {error}"
+                            );
+                            error
+                        });
+                        record_coprocessor_call(
+                            name.as_deref(),
+                            PipelineStep::SubgraphResponse,
+                            succeeded,
                         );
-                        error
-                    });
-                    u64_counter!(
-                        "apollo.router.operations.coprocessor",
-                        "Total operations with co-processors enabled",
-                        1,
-                        "coprocessor.stage" = PipelineStep::SubgraphResponse,
-                        "coprocessor.succeeded" = succeeded
-                    );
-                    result
-                }
-            })
+                        result
+                    }
+                },
+            )
         });
 
-        fn external_service_span() -> impl Fn(&subgraph::Request) -> tracing::Span + Clone {
+        fn external_service_span(
+            name: Option<String>,
+        ) -> impl Fn(&subgraph::Request) -> tracing::Span + Clone {
             move |_request: &subgraph::Request| {
                 tracing::info_span!(
                     EXTERNAL_SPAN_NAME,
                     "external service" = stringify!(subgraph::Request),
+                    "coprocessor.name" = name.as_deref(),
                     "otel.kind" = "INTERNAL"
                 )
             }
         }
 
         ServiceBuilder::new()
-            .instrument(external_service_span())
+            .instrument(external_service_span(span_name))
             .option_layer(request_layer)
             .option_layer(response_layer)
             .service(service)
@@ -600,10 +924,28 @@ impl SubgraphStage {
     }
 }
 
+/// Evaluates the request selectors of a response stage condition when the request arrives, so
+/// that response stages can use selectors that only exist on requests. The returned condition is
+/// completed with `evaluate_response` once the response is received
+pub(super) fn condition_on_request<T: Selector + Clone>(
+    condition: &Option<Condition<T>>,
+    request: &T::Request,
+) -> Option<Condition<T>> {
+    condition
+        .clone()
+        .map(|mut condition| match condition.evaluate_request(request) {
+            Some(true) => Condition::True,
+            Some(false) => Condition::False,
+            None => condition,
+        })
+}
+
 // -----------------------------------------------------------------------------------------
 async fn process_router_request_stage<C>(
     http_client: C,
     coprocessor_url: Url,
+    transport: Transport,
+    name: Option<String>,
     sdl: Arc<String>,
     mut request: router::Request,
     request_config: RouterRequestConf,
@@ -655,12 +997,9 @@ where
         tokio::task::spawn(async move {
             tracing::debug!(?payload, "externalized output");
             let start = Instant::now();
-            let _ = payload.call(http_client, &coprocessor_url).await;
+            let _ = payload.call(http_client, &coprocessor_url, transport).await;
             let duration = start.elapsed().as_secs_f64();
-            tracing::info!(
-                histogram.apollo.router.operations.coprocessor.duration = duration,
-                coprocessor.stage = %PipelineStep::RouterRequest,
-            );
+            record_coprocessor_duration(name.as_deref(), PipelineStep::RouterRequest, duration);
         });
 
         request.router_request = http::Request::from_parts(parts, Body::from(bytes));
@@ -670,109 +1009,112 @@ where
     tracing::debug!(?payload, "externalized output");
     let guard = request.context.enter_active_request();
     let start = Instant::now();
-    let co_processor_result = payload.call(http_client, &coprocessor_url).await;
+    let co_processor_result = payload.call(http_client, &coprocessor_url, transport).await;
     let duration = start.elapsed().as_secs_f64();
     drop(guard);
-    tracing::info!(
-        histogram.apollo.router.operations.coprocessor.duration = duration,
-        coprocessor.stage = %PipelineStep::RouterRequest,
-    );
+    record_coprocessor_duration(name.as_deref(), PipelineStep::RouterRequest, duration);
 
     tracing::debug!(?co_processor_result, "co-processor returned");
-    let mut co_processor_output = co_processor_result?;
-
-    validate_coprocessor_output(&co_processor_output, PipelineStep::RouterRequest)?;
-    // unwrap is safe here because validate_coprocessor_output made sure control is available
-    let control = co_processor_output.control.expect("validated above; qed");
+    let co_processor_output = handle_coprocessor_output(
+        co_processor_result,
+        name.as_deref(),
+        PipelineStep::RouterRequest,
+        request_config.on_error,
+        |body, control| {
+            Ok(match control {
+                Some(Control::Break(_)) => ControlFlow::Break(router_break_response(body)),
+                _ => ControlFlow::Continue(body),
+            })
+        },
+    )?;
 
     // Thirdly, we need to interpret the control flow which may have been
     // updated by our co-processor and decide if we should proceed or stop.
 
-    if matches!(control, Control::Break(_)) {
-        // Ensure the code is a valid http status code
-        let code = control.get_http_status()?;
-
-        // At this point our body is a String. Try to get a valid JSON value from it
-        let body_as_value = co_processor_output
-            .body
-            .as_ref()
-            .and_then(|b| serde_json::from_str(b).ok())
-            .unwrap_or(serde_json::Value::Null);
-        // Now we have some JSON, let's see if it's the right "shape" to create a graphql_response.
-        // If it isn't, we create a graphql error response
-        let graphql_response: crate::graphql::Response = match body_as_value {
-            serde_json::Value::Null => crate::graphql::Response::builder()
-                .errors(vec![Error::builder()
-                    .message(co_processor_output.body.take().unwrap_or_default())
-                    .extension_code(COPROCESSOR_ERROR_EXTENSION)
-                    .build()])
-                .build(),
-            _ => serde_json::from_value(body_as_value).unwrap_or_else(|error| {
-                crate::graphql::Response::builder()
-                    .errors(vec![Error::builder()
-                        .message(format!(
-                            "couldn't deserialize coprocessor output body: {error}"
-                        ))
-                        .extension_code(COPROCESSOR_DESERIALIZATION_ERROR_EXTENSION)
-                        .build()])
-                    .build()
-            }),
-        };
-
-        let res = router::Response::builder()
-            .errors(graphql_response.errors)
-            .extensions(graphql_response.extensions)
-            .status_code(code)
-            .context(request.context);
-
-        let mut res = match (graphql_response.label, graphql_response.data) {
-            (Some(label), Some(data)) => res.label(label).data(data).build()?,
-            (Some(label), None) => res.label(label).build()?,
-            (None, Some(data)) => res.data(data).build()?,
-            (None, None) => res.build()?,
-        };
-        if let Some(headers) = co_processor_output.headers {
-            *res.response.headers_mut() = internalize_header_map(headers)?;
-        }
-
-        if let Some(context) = co_processor_output.context {
-            for (key, value) in context.try_into_iter()? {
-                res.context.upsert_json_value(key, move |_current| value);
+    let new_body = match co_processor_output.body {
+        ControlFlow::Break(graphql_response) => {
+            let code = co_processor_output
+                .status_code
+                .expect("request stages have a control; qed");
+
+            let res = router::Response::builder()
+                .errors(graphql_response.errors)
+                .extensions(graphql_response.extensions)
+                .status_code(code)
+                .context(request.context);
+
+            let mut res = match (graphql_response.label, graphql_response.data) {
+                (Some(label), Some(data)) => res.label(label).data(data).build()?,
+                (Some(label), None) => res.label(label).build()?,
+                (None, Some(data)) => res.data(data).build()?,
+                (None, None) => res.build()?,
+            };
+            if let Some(headers) = co_processor_output.headers {
+                *res.response.headers_mut() = headers;
             }
-        }
+            co_processor_output.context.merge_into(&res.context);
 
-        return Ok(ControlFlow::Break(res));
-    }
+            return Ok(ControlFlow::Break(res));
+        }
+        ControlFlow::Continue(new_body) => new_body,
+    };
 
     // Finally, process our reply and act on the contents. Our processing logic is
     // that we replace "bits" of our incoming request with the updated bits if they
     // are present in our co_processor_output.
 
-    let new_body = match co_processor_output.body {
+    let new_body = match new_body {
         Some(bytes) => Body::from(bytes),
         None => Body::from(bytes),
     };
 
     request.router_request = http::Request::from_parts(parts, new_body);
-
-    if let Some(context) = co_processor_output.context {
-        for (key, value) in context.try_into_iter()? {
-            request
-                .context
-                .upsert_json_value(key, move |_current| value);
-        }
-    }
+    co_processor_output.context.merge_into(&request.context);
 
     if let Some(headers) = co_processor_output.headers {
-        *request.router_request.headers_mut() = internalize_header_map(headers)?;
+        *request.router_request.headers_mut() = headers;
     }
 
     Ok(ControlFlow::Continue(request))
 }
 
+/// GraphQL response of a router request stage that breaks: the body is either a GraphQL response
+/// or an error message
+fn router_break_response(body: Option<String>) -> crate::graphql::Response {
+    // At this point our body is a String. Try to get a valid JSON value from it
+    let body_as_value = body
+        .as_ref()
+        .and_then(|b| serde_json::from_str(b).ok())
+        .unwrap_or(serde_json::Value::Null);
+    // Now we have some JSON, let's see if it's the right "shape" to create a graphql_response.
+    // If it isn't, we create a graphql error response
+    match body_as_value {
+        serde_json::Value::Null => crate::graphql::Response::builder()
+            .errors(vec![Error::builder()
+                .message(body.unwrap_or_default())
+                .extension_code(COPROCESSOR_ERROR_EXTENSION)
+                .build()])
+            .build(),
+        _ => serde_json::from_value(body_as_value).unwrap_or_else(|error| {
+            crate::graphql::Response::builder()
+                .errors(vec![Error::builder()
+                    .message(format!(
+                        "couldn't deserialize coprocessor output body: {error}"
+                    ))
+                    .extension_code(COPROCESSOR_DESERIALIZATION_ERROR_EXTENSION)
+                    .build()])
+                .build()
+        }),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn process_router_response_stage<C>(
     http_client: C,
     coprocessor_url: Url,
+    transport: Transport,
+    timeout: Duration,
+    name: Option<String>,
     sdl: Arc<String>,
     mut response: router::Response,
     response_config: RouterResponseConf,
@@ -835,18 +1177,18 @@ where
 
         let http_client2 = http_client.clone();
         let coprocessor_url2 = coprocessor_url.clone();
+        let name2 = name.clone();
         // Second, call our co-processor and get a reply.
         tokio::task::spawn(async move {
             tracing::debug!(?payload, "externalized output");
             let guard = context.enter_active_request();
             let start = Instant::now();
-            let _ = payload.call(http_client.clone(), &coprocessor_url).await;
+            let _ = payload
+                .call(http_client.clone(), &coprocessor_url, transport)
+                .await;
             let duration = start.elapsed().as_secs_f64();
             drop(guard);
-            tracing::info!(
-                histogram.apollo.router.operations.coprocessor.duration = duration,
-                coprocessor.stage = %PipelineStep::RouterResponse,
-            );
+            record_coprocessor_duration(name.as_deref(), PipelineStep::RouterResponse, duration);
         });
 
         let map_context = response.context.clone();
@@ -854,6 +1196,7 @@ where
         let mapped_stream = rest.map(move |deferred_response| {
             let generator_client = http_client2.clone();
             let generator_coprocessor_url = coprocessor_url2.clone();
+            let generator_name = name2.clone();
             let generator_map_context = map_context.clone();
             let generator_sdl_to_send = sdl_to_send.clone();
             let generator_id = map_context.id.clone();
@@ -884,12 +1227,13 @@ where
                     tracing::debug!(?payload, "externalized output");
                     let start = Instant::now();
                     let _ = payload
-                        .call(generator_client, &generator_coprocessor_url)
+                        .call(generator_client, &generator_coprocessor_url, transport)
                         .await;
                     let duration = start.elapsed().as_secs_f64();
-                    tracing::info!(
-                        histogram.apollo.router.operations.coprocessor.duration = duration,
-                        coprocessor.stage = %PipelineStep::RouterResponse,
+                    record_coprocessor_duration(
+                        generator_name.as_deref(),
+                        PipelineStep::RouterResponse,
+                        duration,
                     );
                 });
             }
@@ -909,18 +1253,21 @@ where
     tracing::debug!(?payload, "externalized output");
     let guard = response.context.enter_active_request();
     let start = Instant::now();
-    let co_processor_result = payload.call(http_client.clone(), &coprocessor_url).await;
+    let co_processor_result = payload
+        .call(http_client.clone(), &coprocessor_url, transport)
+        .await;
     let duration = start.elapsed().as_secs_f64();
     drop(guard);
-    tracing::info!(
-        histogram.apollo.router.operations.coprocessor.duration = duration,
-        coprocessor.stage = %PipelineStep::RouterResponse,
-    );
+    record_coprocessor_duration(name.as_deref(), PipelineStep::RouterResponse, duration);
 
     tracing::debug!(?co_processor_result, "co-processor returned");
-    let co_processor_output = co_processor_result?;
-
-    validate_coprocessor_output(&co_processor_output, PipelineStep::RouterResponse)?;
+    let co_processor_output = handle_coprocessor_output(
+        co_processor_result,
+        name.as_deref(),
+        PipelineStep::RouterResponse,
+        response_config.on_error,
+        |body, _| Ok(body),
+    )?;
 
     // Third, process our reply and act on the contents. Our processing logic is
     // that we replace "bits" of our incoming response with the updated bits if they
@@ -934,26 +1281,26 @@ where
 
     response.response = http::Response::from_parts(parts, new_body);
 
-    if let Some(control) = co_processor_output.control {
-        *response.response.status_mut() = control.get_http_status()?
+    if let Some(status_code) = co_processor_output.status_code {
+        *response.response.status_mut() = status_code;
     }
 
-    if let Some(context) = co_processor_output.context {
-        for (key, value) in context.try_into_iter()? {
-            response
-                .context
-                .upsert_json_value(key, move |_current| value);
-        }
-    }
+    co_processor_output.context.merge_into(&response.context);
 
     if let Some(headers) = co_processor_output.headers {
-        *response.response.headers_mut() = internalize_header_map(headers)?;
+        *response.response.headers_mut() = headers;
     }
 
     // Now break our co-processor modified response back into parts
     let (parts, body) = response.response.into_parts();
 
     // Clone all the bits we need
+    let deferred_calls = Arc::new(DeferredCalls::new(
+        http_client,
+        coprocessor_url,
+        transport,
+        timeout,
+    ));
     let context = response.context.clone();
     let map_context = response.context.clone();
 
@@ -961,8 +1308,8 @@ where
     let mapped_stream = rest
         .map_err(BoxError::from)
         .and_then(move |deferred_response| {
-            let generator_client = http_client.clone();
-            let generator_coprocessor_url = coprocessor_url.clone();
+            let deferred_calls = deferred_calls.clone();
+            let generator_name = name.clone();
             let generator_map_context = map_context.clone();
             let generator_sdl_to_send = sdl_to_send.clone();
             let generator_id = map_context.id.clone();
@@ -991,14 +1338,16 @@ where
                 // Second, call our co-processor and get a reply.
                 tracing::debug!(?payload, "externalized output");
                 let guard = generator_map_context.enter_active_request();
-                let co_processor_result = payload
-                    .call(generator_client, &generator_coprocessor_url)
-                    .await;
+                let co_processor_result = deferred_calls.call(payload).await;
                 drop(guard);
                 tracing::debug!(?co_processor_result, "co-processor returned");
-                let co_processor_output = co_processor_result?;
-
-                validate_coprocessor_output(&co_processor_output, PipelineStep::RouterResponse)?;
+                let co_processor_output = handle_coprocessor_output(
+                    co_processor_result,
+                    generator_name.as_deref(),
+                    PipelineStep::RouterResponse,
+                    response_config.on_error,
+                    |body, _| Ok(body),
+                )?;
 
                 // Third, process our reply and act on the contents. Our processing logic is
                 // that we replace "bits" of our incoming response with the updated bits if they
@@ -1009,11 +1358,9 @@ where
                     None => bytes.into(),
                 };
 
-                if let Some(context) = co_processor_output.context {
-                    for (key, value) in context.try_into_iter()? {
-                        generator_map_context.upsert_json_value(key, move |_current| value);
-                    }
-                }
+                co_processor_output
+                    .context
+                    .merge_into(&generator_map_context);
 
                 // We return the final_bytes into our stream of response chunks
                 Ok(final_bytes)
@@ -1036,6 +1383,8 @@ where
 async fn process_subgraph_request_stage<C>(
     http_client: C,
     coprocessor_url: Url,
+    transport: Transport,
+    name: Option<String>,
     service_name: String,
     mut request: subgraph::Request,
     request_config: SubgraphRequestConf,
@@ -1082,12 +1431,9 @@ where
         tokio::task::spawn(async move {
             tracing::debug!(?payload, "externalized output");
             let start = Instant::now();
-            let _ = payload.call(http_client, &coprocessor_url).await;
+            let _ = payload.call(http_client, &coprocessor_url, transport).await;
             let duration = start.elapsed().as_secs_f64();
-            tracing::info!(
-                histogram.apollo.router.operations.coprocessor.duration = duration,
-                coprocessor.stage = %PipelineStep::SubgraphRequest,
-            );
+            record_coprocessor_duration(name.as_deref(), PipelineStep::SubgraphRequest, duration);
         });
 
         request.subgraph_request = http::Request::from_parts(parts, body);
@@ -1097,106 +1443,100 @@ where
     tracing::debug!(?payload, "externalized output");
     let guard = request.context.enter_active_request();
     let start = Instant::now();
-    let co_processor_result = payload.call(http_client, &coprocessor_url).await;
+    let co_processor_result = payload.call(http_client, &coprocessor_url, transport).await;
     let duration = start.elapsed().as_secs_f64();
     drop(guard);
-    tracing::info!(
-        histogram.apollo.router.operations.coprocessor.duration = duration,
-        coprocessor.stage = %PipelineStep::SubgraphRequest,
-    );
+    record_coprocessor_duration(name.as_deref(), PipelineStep::SubgraphRequest, duration);
 
     tracing::debug!(?co_processor_result, "co-processor returned");
-    let co_processor_output = co_processor_result?;
-    validate_coprocessor_output(&co_processor_output, PipelineStep::SubgraphRequest)?;
-    // unwrap is safe here because validate_coprocessor_output made sure control is available
-    let control = co_processor_output.control.expect("validated above; qed");
+    let co_processor_output = handle_coprocessor_output(
+        co_processor_result,
+        name.as_deref(),
+        PipelineStep::SubgraphRequest,
+        request_config.on_error,
+        |body, control| {
+            Ok(match control {
+                Some(Control::Break(_)) => ControlFlow::Break(subgraph_break_response(body)),
+                _ => ControlFlow::Continue(body.map(serde_json::from_value).transpose()?),
+            })
+        },
+    )?;
 
     // Thirdly, we need to interpret the control flow which may have been
     // updated by our co-processor and decide if we should proceed or stop.
 
-    if matches!(control, Control::Break(_)) {
-        // Ensure the code is a valid http status code
-        let code = control.get_http_status()?;
-
-        let res = {
-            let graphql_response: crate::graphql::Response =
-                match co_processor_output.body.unwrap_or(serde_json::Value::Null) {
-                    serde_json::Value::String(s) => crate::graphql::Response::builder()
-                        .errors(vec![Error::builder()
-                            .message(s)
-                            .extension_code(COPROCESSOR_ERROR_EXTENSION)
-                            .build()])
-                        .build(),
-                    value => serde_json::from_value(value).unwrap_or_else(|error| {
-                        crate::graphql::Response::builder()
-                            .errors(vec![Error::builder()
-                                .message(format!(
-                                    "couldn't deserialize coprocessor output body: {error}"
-                                ))
-                                .extension_code(COPROCESSOR_DESERIALIZATION_ERROR_EXTENSION)
-                                .build()])
-                            .build()
-                    }),
-                };
+    let new_body: Option<crate::graphql::Request> = match co_processor_output.body {
+        ControlFlow::Break(graphql_response) => {
+            let code = co_processor_output
+                .status_code
+                .expect("request stages have a control; qed");
 
             let mut http_response = http::Response::builder()
                 .status(code)
                 .body(graphql_response)?;
             if let Some(headers) = co_processor_output.headers {
-                *http_response.headers_mut() = internalize_header_map(headers)?;
+                *http_response.headers_mut() = headers;
             }
 
             let subgraph_response = subgraph::Response {
                 response: http_response,
                 context: request.context,
             };
+            co_processor_output
+                .context
+                .merge_into(&subgraph_response.context);
 
-            if let Some(context) = co_processor_output.context {
-                for (key, value) in context.try_into_iter()? {
-                    subgraph_response
-                        .context
-                        .upsert_json_value(key, move |_current| value);
-                }
-            }
-
-            subgraph_response
-        };
-        return Ok(ControlFlow::Break(res));
-    }
+            return Ok(ControlFlow::Break(subgraph_response));
+        }
+        ControlFlow::Continue(new_body) => new_body,
+    };
 
     // Finally, process our reply and act on the contents. Our processing logic is
     // that we replace "bits" of our incoming request with the updated bits if they
     // are present in our co_processor_output.
 
-    let new_body: crate::graphql::Request = match co_processor_output.body {
-        Some(value) => serde_json::from_value(value)?,
-        None => body,
-    };
-
-    request.subgraph_request = http::Request::from_parts(parts, new_body);
-
-    if let Some(context) = co_processor_output.context {
-        for (key, value) in context.try_into_iter()? {
-            request
-                .context
-                .upsert_json_value(key, move |_current| value);
-        }
-    }
+    request.subgraph_request = http::Request::from_parts(parts, new_body.unwrap_or(body));
+    co_processor_output.context.merge_into(&request.context);
 
     if let Some(headers) = co_processor_output.headers {
-        *request.subgraph_request.headers_mut() = internalize_header_map(headers)?;
+        *request.subgraph_request.headers_mut() = headers;
     }
 
     if let Some(uri) = co_processor_output.uri {
-        *request.subgraph_request.uri_mut() = uri.parse()?;
+        *request.subgraph_request.uri_mut() = uri;
     }
 
     Ok(ControlFlow::Continue(request))
 }
 
+/// GraphQL response of a subgraph request stage that breaks: the body is either a GraphQL
+/// response or an error message
+fn subgraph_break_response(body: Option<serde_json::Value>) -> crate::graphql::Response {
+    match body.unwrap_or(serde_json::Value::Null) {
+        serde_json::Value::String(s) => crate::graphql::Response::builder()
+            .errors(vec![Error::builder()
+                .message(s)
+                .extension_code(COPROCESSOR_ERROR_EXTENSION)
+                .build()])
+            .build(),
+        value => serde_json::from_value(value).unwrap_or_else(|error| {
+            crate::graphql::Response::builder()
+                .errors(vec![Error::builder()
+                    .message(format!(
+                        "couldn't deserialize coprocessor output body: {error}"
+                    ))
+                    .extension_code(COPROCESSOR_DESERIALIZATION_ERROR_EXTENSION)
+                    .build()])
+                .build()
+        }),
+    }
+}
+
 async fn process_subgraph_response_stage<C>(
     http_client: C,
     coprocessor_url: Url,
+    transport: Transport,
+    name: Option<String>,
     service_name: String,
     mut response: subgraph::Response,
     response_config: SubgraphResponseConf,
@@ -1243,12 +1583,9 @@ where
         tokio::task::spawn(async move {
             tracing::debug!(?payload, "externalized output");
             let start = Instant::now();
-            let _ = payload.call(http_client, &coprocessor_url).await;
+            let _ = payload.call(http_client, &coprocessor_url, transport).await;
             let duration = start.elapsed().as_secs_f64();
-            tracing::info!(
-                histogram.apollo.router.operations.coprocessor.duration = duration,
-                coprocessor.stage = %PipelineStep::SubgraphResponse,
-            );
+            record_coprocessor_duration(name.as_deref(), PipelineStep::SubgraphResponse, duration);
         });
 
         response.response = http::Response::from_parts(parts, body);
@@ -1258,45 +1595,37 @@ where
     tracing::debug!(?payload, "externalized output");
     let guard = response.context.enter_active_request();
     let start = Instant::now();
-    let co_processor_result = payload.call(http_client, &coprocessor_url).await;
+    let co_processor_result = payload.call(http_client, &coprocessor_url, transport).await;
     let duration = start.elapsed().as_secs_f64();
     drop(guard);
-    tracing::info!(
-        histogram.apollo.router.operations.coprocessor.duration = duration,
-        coprocessor.stage = %PipelineStep::SubgraphResponse,
-    );
+    record_coprocessor_duration(name.as_deref(), PipelineStep::SubgraphResponse, duration);
 
     tracing::debug!(?co_processor_result, "co-processor returned");
-    let co_processor_output = co_processor_result?;
-
-    validate_coprocessor_output(&co_processor_output, PipelineStep::SubgraphResponse)?;
+    let co_processor_output = handle_coprocessor_output(
+        co_processor_result,
+        name.as_deref(),
+        PipelineStep::SubgraphResponse,
+        response_config.on_error,
+        |body, _| Ok(body.map(serde_json::from_value).transpose()?),
+    )?;
 
     // Third, process our reply and act on the contents. Our processing logic is
     // that we replace "bits" of our incoming response with the updated bits if they
     // are present in our co_processor_output. If they aren't present, just use the
     // bits that we sent to the co_processor.
 
-    let new_body: crate::graphql::Response = match co_processor_output.body {
-        Some(value) => serde_json::from_value(value)?,
-        None => body,
-    };
+    let new_body: crate::graphql::Response = co_processor_output.body.unwrap_or(body);
 
     response.response = http::Response::from_parts(parts, new_body);
 
-    if let Some(control) = co_processor_output.control {
-        *response.response.status_mut() = control.get_http_status()?
+    if let Some(status_code) = co_processor_output.status_code {
+        *response.response.status_mut() = status_code;
     }
 
-    if let Some(context) = co_processor_output.context {
-        for (key, value) in context.try_into_iter()? {
-            response
-                .context
-                .upsert_json_value(key, move |_current| value);
-        }
-    }
+    co_processor_output.context.merge_into(&response.context);
 
     if let Some(headers) = co_processor_output.headers {
-        *response.response.headers_mut() = internalize_header_map(headers)?;
+        *response.response.headers_mut() = headers;
     }
 
     Ok(response)
@@ -1304,6 +1633,102 @@ where
 
 // -----------------------------------------------------------------------------------------
 
+/// Output of a coprocessor call, converted to the router's types.
+///
+/// The whole output is converted before any of it is applied, so that an invalid field is handled
+/// according to `on_error` instead of leaving the request or response partially modified.
+pub(super) struct CoprocessorReply<B> {
+    /// HTTP status code of the `control`, always set for request stages
+    pub(super) status_code: Option<http::StatusCode>,
+    pub(super) headers: Option<HeaderMap>,
+    pub(super) uri: Option<http::Uri>,
+    /// The body, converted by the stage
+    pub(super) body: B,
+    pub(super) context: ReplyContext,
+}
+
+/// Context entries returned by a coprocessor
+pub(super) struct ReplyContext(Option<Vec<(String, serde_json_bytes::Value)>>);
+
+impl ReplyContext {
+    /// Copies the entries to `context`
+    pub(super) fn merge_into(self, context: &crate::Context) {
+        for (key, value) in self.0.into_iter().flatten() {
+            context.upsert_json_value(key, move |_current| value);
+        }
+    }
+}
+
+impl<B> CoprocessorReply<B> {
+    fn convert<T>(
+        co_processor_output: Externalizable<T>,
+        convert_body: impl Fn(Option<T>, Option<&Control>) -> Result<B, BoxError>,
+    ) -> Result<Self, BoxError> {
+        let control = co_processor_output.control;
+        Ok(Self {
+            status_code: control.as_ref().map(Control::get_http_status).transpose()?,
+            headers: co_processor_output
+                .headers
+                .map(internalize_header_map)
+                .transpose()?,
+            uri: co_processor_output.uri.map(|uri| uri.parse()).transpose()?,
+            body: convert_body(co_processor_output.body, control.as_ref())?,
+            context: ReplyContext(
+                co_processor_output
+                    .context
+                    .map(|context| {
+                        Ok::<_, BoxError>(context.try_into_iter()?.into_iter().collect())
+                    })
+                    .transpose()?,
+            ),
+        })
+    }
+}
+
+/// Validates the output of a coprocessor call and converts it with `convert_body` for the body.
+///
+/// If the call failed or its output is invalid and the stage is configured to continue on
+/// errors, this returns a reply that leaves the request or response unchanged.
+fn handle_coprocessor_output<T, B>(
+    co_processor_result: Result<Externalizable<T>, BoxError>,
+    name: Option<&str>,
+    expected_step: PipelineStep,
+    on_error: OnError,
+    convert_body: impl Fn(Option<T>, Option<&Control>) -> Result<B, BoxError>,
+) -> Result<CoprocessorReply<B>, BoxError> {
+    let converted = co_processor_result.and_then(|co_processor_output| {
+        validate_coprocessor_output(&co_processor_output, expected_step.clone())?;
+        CoprocessorReply::convert(co_processor_output, &convert_body)
+    });
+    let error = match converted {
+        Ok(reply) => return Ok(reply),
+        Err(error) => error,
+    };
+
+    match on_error {
+        OnError::Fail => Err(error),
+        OnError::Continue => {
+            let circuit_open = error.is::<CircuitOpen>();
+            tracing::warn!(
+                coprocessor.name = name,
+                "external extensibility: skipping the {expected_step} stage: {error}"
+            );
+            let mut attributes = coprocessor_attributes(name, expected_step.clone());
+            attributes.push(opentelemetry::KeyValue::new(
+                "coprocessor.circuit_open",
+                circuit_open,
+            ));
+            u64_counter!(
+                "apollo.router.operations.coprocessor.skipped",
+                "Total co-processor calls skipped because of an error",
+                1,
+                &attributes
+            );
+            CoprocessorReply::convert(Externalizable::unchanged(expected_step), convert_body)
+        }
+    }
+}
+
 fn validate_coprocessor_output<T>(
     co_processor_output: &Externalizable<T>,
     expected_step: PipelineStep,
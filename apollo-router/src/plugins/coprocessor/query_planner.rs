@@ -0,0 +1,438 @@
+use std::ops::ControlFlow;
+use std::sync::Arc;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower_service::Service;
+use url::Url;
+
+use super::*;
+use crate::graphql;
+use crate::layers::async_checkpoint::OneShotAsyncCheckpointLayer;
+use crate::layers::ServiceBuilderExt;
+use crate::plugins::coprocessor::record_coprocessor_call;
+use crate::plugins::coprocessor::record_coprocessor_duration;
+use crate::plugins::coprocessor::EXTERNAL_SPAN_NAME;
+use crate::services::query_planner;
+use crate::services::QueryPlannerContent;
+use crate::services::QueryPlannerRequest;
+use crate::services::QueryPlannerResponse;
+use crate::Context;
+
+/// What information is passed to a query planner request stage
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct QueryPlannerRequestConf {
+    /// Send the context
+    pub(super) context: bool,
+    /// Send the body, containing the query and operation name
+    pub(super) body: bool,
+    /// Send the SDL
+    pub(super) sdl: bool,
+    /// Handles the request without waiting for the coprocessor to respond
+    pub(super) detached: bool,
+    /// What to do when the coprocessor call fails or returns an invalid request
+    pub(super) on_error: OnError,
+    /// The url you'd like to offload processing to
+    #[schemars(with = "String")]
+    pub(super) url: Option<Url>,
+}
+
+/// What information is passed to a query planner response stage
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct QueryPlannerResponseConf {
+    /// Send the context
+    pub(super) context: bool,
+    /// Send the SDL
+    pub(super) sdl: bool,
+    /// Send the query plan
+    pub(super) query_plan: bool,
+    /// Handles the response without waiting for the coprocessor to respond
+    pub(super) detached: bool,
+    /// What to do when the coprocessor call fails or returns an invalid response
+    pub(super) on_error: OnError,
+    /// The url you'd like to offload processing to
+    #[schemars(with = "String")]
+    pub(super) url: Option<Url>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default)]
+pub(super) struct QueryPlannerStage {
+    /// The request configuration
+    pub(super) request: QueryPlannerRequestConf,
+    /// The response configuration
+    pub(super) response: QueryPlannerResponseConf,
+}
+
+impl QueryPlannerStage {
+    pub(crate) fn as_service<C>(
+        &self,
+        http_client: C,
+        service: query_planner::BoxService,
+        coprocessor_url: Url,
+        transport: Transport,
+        name: Option<String>,
+        sdl: Arc<String>,
+    ) -> query_planner::BoxService
+    where
+        C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
+    {
+        if *self == Default::default() {
+            return service;
+        }
+
+        let span_name = name.clone();
+        let request_layer = (self.request != Default::default()).then_some({
+            let request_config = self.request.clone();
+            let coprocessor_url = request_config
+                .url
+                .clone()
+                .unwrap_or_else(|| coprocessor_url.clone());
+            let http_client = http_client.clone();
+            let name = name.clone();
+            let sdl = sdl.clone();
+
+            OneShotAsyncCheckpointLayer::new(move |request: QueryPlannerRequest| {
+                let request_config = request_config.clone();
+                let coprocessor_url = coprocessor_url.clone();
+                let http_client = http_client.clone();
+                let name = name.clone();
+                let sdl = sdl.clone();
+
+                async move {
+                    let context = request.context.clone();
+                    let mut succeeded = true;
+                    let result = process_query_planner_request_stage(
+                        http_client,
+                        coprocessor_url,
+                        transport,
+                        name.clone(),
+                        sdl,
+                        request,
+                        request_config,
+                    )
+                    .await
+                    .unwrap_or_else(|error| {
+                        succeeded = false;
+                        tracing::error!(
+                            coprocessor.name = name.as_deref(),
+                            "external extensibility: query planner request stage error: {error}"
+                        );
+                        ControlFlow::Break(coprocessor_error_response(context, error))
+                    });
+
+                    record_coprocessor_call(
+                        name.as_deref(),
+                        PipelineStep::QueryPlannerRequest,
+                        succeeded,
+                    );
+                    Ok(result)
+                }
+            })
+        });
+
+        let response_layer = (self.response != Default::default()).then_some({
+            let response_config = self.response.clone();
+
+            MapFutureLayer::new(move |fut| {
+                let sdl: Arc<String> = sdl.clone();
+                let http_client = http_client.clone();
+                let name = name.clone();
+                let response_config = response_config.clone();
+                let coprocessor_url = response_config
+                    .url
+                    .clone()
+                    .unwrap_or_else(|| coprocessor_url.clone());
+
+                async move {
+                    let response: QueryPlannerResponse = fut.await?;
+                    let context = response.context.clone();
+
+                    let mut succeeded = true;
+                    let result = process_query_planner_response_stage(
+                        http_client,
+                        coprocessor_url,
+                        transport,
+                        name.clone(),
+                        sdl,
+                        response,
+                        response_config,
+                    )
+                    .await
+                    .unwrap_or_else(|error| {
+                        succeeded = false;
+                        tracing::error!(
+                            coprocessor.name = name.as_deref(),
+                            "external extensibility: query planner response stage error: {error}"
+                        );
+                        coprocessor_error_response(context, error)
+                    });
+
+                    record_coprocessor_call(
+                        name.as_deref(),
+                        PipelineStep::QueryPlannerResponse,
+                        succeeded,
+                    );
+                    Ok::<_, BoxError>(result)
+                }
+            })
+        });
+
+        fn external_service_span(
+            name: Option<String>,
+        ) -> impl Fn(&QueryPlannerRequest) -> tracing::Span + Clone {
+            move |_request: &QueryPlannerRequest| {
+                tracing::info_span!(
+                    EXTERNAL_SPAN_NAME,
+                    "external service" = stringify!(query_planner::Request),
+                    "coprocessor.name" = name.as_deref(),
+                    "otel.kind" = "INTERNAL"
+                )
+            }
+        }
+
+        // The stages never fail on their own: coprocessor errors reject the query instead, so
+        // that they are not cached by the query planner.
+        ServiceBuilder::new()
+            .instrument(external_service_span(span_name))
+            .option_layer(request_layer)
+            .option_layer(response_layer)
+            .service(service)
+            .boxed()
+    }
+}
+
+/// A response rejecting the query, which is answered to the client with the errors.
+///
+/// Query plans are only cached when they were produced, so the query is sent again to the
+/// coprocessor by the next client request.
+fn rejection(context: Context, errors: Vec<graphql::Error>) -> QueryPlannerResponse {
+    QueryPlannerResponse::builder()
+        .context(context)
+        .errors(errors)
+        .build()
+}
+
+fn coprocessor_error_response(context: Context, error: BoxError) -> QueryPlannerResponse {
+    rejection(
+        context,
+        vec![Error::builder()
+            .message(error.to_string())
+            .extension_code(COPROCESSOR_ERROR_EXTENSION)
+            .build()],
+    )
+}
+
+/// Reads the errors of a `Control::Break` output
+fn break_errors(body: Option<serde_json::Value>) -> Vec<graphql::Error> {
+    let errors = serde_json::from_value::<graphql::Response>(body.unwrap_or_default())
+        .map(|response| response.errors)
+        .unwrap_or_else(|error| {
+            vec![Error::builder()
+                .message(format!(
+                    "couldn't deserialize coprocessor output body: {error}"
+                ))
+                .extension_code(COPROCESSOR_DESERIALIZATION_ERROR_EXTENSION)
+                .build()]
+        });
+    if errors.is_empty() {
+        vec![Error::builder()
+            .message("the query was rejected by the coprocessor")
+            .extension_code(COPROCESSOR_ERROR_EXTENSION)
+            .build()]
+    } else {
+        errors
+    }
+}
+
+async fn process_query_planner_request_stage<C>(
+    http_client: C,
+    coprocessor_url: Url,
+    transport: Transport,
+    name: Option<String>,
+    sdl: Arc<String>,
+    mut request: QueryPlannerRequest,
+    request_config: QueryPlannerRequestConf,
+) -> Result<ControlFlow<QueryPlannerResponse, QueryPlannerRequest>, BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
+{
+    let body_to_send = request_config
+        .body
+        .then(|| {
+            serde_json::to_value(
+                graphql::Request::builder()
+                    .query(request.query.clone())
+                    .and_operation_name(request.operation_name.clone())
+                    .build(),
+            )
+        })
+        .transpose()?;
+    let context_to_send = request_config.context.then(|| request.context.clone());
+    let sdl_to_send = request_config.sdl.then(|| sdl.clone().to_string());
+
+    let payload = Externalizable::query_planner_builder()
+        .stage(PipelineStep::QueryPlannerRequest)
+        .control(Control::default())
+        .id(request.context.id.clone())
+        .and_body(body_to_send)
+        .and_context(context_to_send)
+        .and_sdl(sdl_to_send)
+        .build();
+
+    if request_config.detached {
+        tokio::task::spawn(async move {
+            tracing::debug!(?payload, "externalized output");
+            let start = Instant::now();
+            let _ = payload.call(http_client, &coprocessor_url, transport).await;
+            let duration = start.elapsed().as_secs_f64();
+            record_coprocessor_duration(
+                name.as_deref(),
+                PipelineStep::QueryPlannerRequest,
+                duration,
+            );
+        });
+
+        return Ok(ControlFlow::Continue(request));
+    }
+
+    tracing::debug!(?payload, "externalized output");
+    let start = Instant::now();
+    let co_processor_result = payload.call(http_client, &coprocessor_url, transport).await;
+    let duration = start.elapsed().as_secs_f64();
+    record_coprocessor_duration(name.as_deref(), PipelineStep::QueryPlannerRequest, duration);
+
+    tracing::debug!(?co_processor_result, "co-processor returned");
+    let co_processor_output = handle_coprocessor_output(
+        co_processor_result,
+        name.as_deref(),
+        PipelineStep::QueryPlannerRequest,
+        request_config.on_error,
+        |body, control| {
+            if let Some(Control::Break(_)) = control {
+                return Ok(ControlFlow::Break(break_errors(body)));
+            }
+            // The coprocessor can rewrite the operation before it is planned
+            let operation = body
+                .map(|value| {
+                    let body: graphql::Request = serde_json::from_value(value)?;
+                    let query = body
+                        .query
+                        .ok_or("the coprocessor output body is missing the query")?;
+                    Ok::<_, BoxError>((query, body.operation_name))
+                })
+                .transpose()?;
+            Ok(ControlFlow::Continue(operation))
+        },
+    )?;
+
+    let operation = match co_processor_output.body {
+        ControlFlow::Break(errors) => {
+            return Ok(ControlFlow::Break(rejection(request.context, errors)));
+        }
+        ControlFlow::Continue(operation) => operation,
+    };
+
+    if let Some((query, operation_name)) = operation {
+        request.query = query;
+        request.operation_name = operation_name;
+    }
+    co_processor_output.context.merge_into(&request.context);
+
+    Ok(ControlFlow::Continue(request))
+}
+
+async fn process_query_planner_response_stage<C>(
+    http_client: C,
+    coprocessor_url: Url,
+    transport: Transport,
+    name: Option<String>,
+    sdl: Arc<String>,
+    response: QueryPlannerResponse,
+    response_config: QueryPlannerResponseConf,
+) -> Result<QueryPlannerResponse, BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
+{
+    // Only produced query plans go through the coprocessor
+    let query_plan = match &response.content {
+        Some(QueryPlannerContent::Plan { plan }) => plan.clone(),
+        _ => return Ok(response),
+    };
+
+    let context_to_send = response_config.context.then(|| response.context.clone());
+    let sdl_to_send = response_config.sdl.then(|| sdl.clone().to_string());
+    let query_plan_to_send = response_config.query_plan.then_some(query_plan);
+
+    let payload = Externalizable::<serde_json::Value>::query_planner_builder()
+        .stage(PipelineStep::QueryPlannerResponse)
+        .id(response.context.id.clone())
+        .and_context(context_to_send)
+        .and_sdl(sdl_to_send)
+        .and_query_plan(query_plan_to_send)
+        .build();
+
+    if response_config.detached {
+        tokio::task::spawn(async move {
+            tracing::debug!(?payload, "externalized output");
+            let start = Instant::now();
+            let _ = payload.call(http_client, &coprocessor_url, transport).await;
+            let duration = start.elapsed().as_secs_f64();
+            record_coprocessor_duration(
+                name.as_deref(),
+                PipelineStep::QueryPlannerResponse,
+                duration,
+            );
+        });
+
+        return Ok(response);
+    }
+
+    tracing::debug!(?payload, "externalized output");
+    let start = Instant::now();
+    let co_processor_result = payload.call(http_client, &coprocessor_url, transport).await;
+    let duration = start.elapsed().as_secs_f64();
+    record_coprocessor_duration(
+        name.as_deref(),
+        PipelineStep::QueryPlannerResponse,
+        duration,
+    );
+
+    tracing::debug!(?co_processor_result, "co-processor returned");
+    let co_processor_output = handle_coprocessor_output(
+        co_processor_result,
+        name.as_deref(),
+        PipelineStep::QueryPlannerResponse,
+        response_config.on_error,
+        |body, control| Ok(matches!(control, Some(Control::Break(_))).then(|| break_errors(body))),
+    )?;
+
+    co_processor_output.context.merge_into(&response.context);
+
+    // The coprocessor can veto the query plan
+    if let Some(errors) = co_processor_output.body {
+        return Ok(rejection(response.context, errors));
+    }
+
+    Ok(response)
+}
@@ -0,0 +1,340 @@
+//! Retries and circuit breaking for coprocessor calls
+
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::future::BoxFuture;
+use http_body::Body as _;
+use hyper::Body;
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::Service;
+use tower::ServiceExt;
+
+/// Retries of failed coprocessor calls
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct RetryConf {
+    /// The number of retries after a failed call (default: 0)
+    pub(super) attempts: u32,
+    /// The delay before the first retry, doubled for each following retry (default: 100ms)
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    pub(super) min_backoff: Duration,
+    /// The maximum delay between two retries (default: 1s)
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    pub(super) max_backoff: Duration,
+}
+
+impl Default for RetryConf {
+    fn default() -> Self {
+        Self {
+            attempts: 0,
+            min_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Skips calls to the coprocessor while it is unhealthy
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct CircuitBreakerConf {
+    /// Enable the circuit breaker
+    pub(super) enabled: bool,
+    /// The number of consecutive failed calls that opens the circuit (default: 5)
+    pub(super) failure_threshold: u32,
+    /// How long calls are skipped once the circuit is open, before a call is tried again (default: 30s)
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    pub(super) open_duration: Duration,
+}
+
+impl Default for CircuitBreakerConf {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Error returned instead of calling the coprocessor while the circuit is open
+#[derive(Debug, thiserror::Error)]
+#[error("the coprocessor circuit breaker is open")]
+pub(super) struct CircuitOpen;
+
+#[derive(Debug)]
+struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<CircuitState>,
+}
+
+#[derive(Debug, Default)]
+struct CircuitState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn new(conf: &CircuitBreakerConf) -> Self {
+        Self {
+            failure_threshold: conf.failure_threshold.max(1),
+            open_duration: conf.open_duration,
+            state: Default::default(),
+        }
+    }
+
+    /// Whether a call can go through.
+    ///
+    /// Once the open duration has elapsed, a single call is let through to probe the
+    /// coprocessor, and the circuit stays open for another duration unless that call succeeds.
+    fn allow(&self) -> bool {
+        let mut state = self.state.lock();
+        match state.open_until {
+            None => true,
+            Some(open_until) => {
+                let now = Instant::now();
+                if now < open_until {
+                    return false;
+                }
+                state.open_until = Some(now + self.open_duration);
+                true
+            }
+        }
+    }
+
+    fn record(&self, succeeded: bool) {
+        let mut state = self.state.lock();
+        if succeeded {
+            if state.open_until.is_some() {
+                tracing::info!("external extensibility: coprocessor circuit breaker closed");
+            }
+            *state = Default::default();
+            return;
+        }
+
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        if state.open_until.is_none() && state.consecutive_failures >= self.failure_threshold {
+            tracing::warn!(
+                "external extensibility: coprocessor circuit breaker opened after {} failed calls",
+                state.consecutive_failures
+            );
+            state.open_until = Some(Instant::now() + self.open_duration);
+        }
+    }
+}
+
+/// HTTP client for a coprocessor that retries failed calls and stops calling the coprocessor
+/// while its circuit breaker is open.
+///
+/// A call fails on a connection error, a timeout or a 5xx or 429 HTTP status. Requests with a
+/// streaming body, such as the gRPC streams of deferred responses, are never retried.
+#[derive(Clone, Debug)]
+pub(super) struct ResilientClient<C> {
+    inner: C,
+    retry: RetryConf,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+}
+
+impl<C> ResilientClient<C> {
+    pub(super) fn new(inner: C, retry: RetryConf, circuit_breaker: &CircuitBreakerConf) -> Self {
+        Self {
+            inner,
+            retry,
+            circuit_breaker: circuit_breaker
+                .enabled
+                .then(|| Arc::new(CircuitBreaker::new(circuit_breaker))),
+        }
+    }
+}
+
+impl<C> Service<hyper::Request<Body>> for ResilientClient<C>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    <C as Service<hyper::Request<Body>>>::Future: Send + 'static,
+{
+    type Response = hyper::Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: hyper::Request<Body>) -> Self::Future {
+        // Use the client that was polled ready, and leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let inner = std::mem::replace(&mut self.inner, clone);
+        let retry = self.retry.clone();
+        let circuit_breaker = self.circuit_breaker.clone();
+
+        Box::pin(async move {
+            if let Some(circuit_breaker) = &circuit_breaker {
+                if !circuit_breaker.allow() {
+                    return Err(CircuitOpen.into());
+                }
+            }
+
+            let result = call_with_retries(inner, request, &retry).await;
+
+            if let Some(circuit_breaker) = &circuit_breaker {
+                circuit_breaker.record(matches!(&result, Ok(response) if !is_failure(response)));
+            }
+            result
+        })
+    }
+}
+
+fn is_failure(response: &hyper::Response<Body>) -> bool {
+    response.status().is_server_error() || response.status() == http::StatusCode::TOO_MANY_REQUESTS
+}
+
+async fn call_with_retries<C>(
+    client: C,
+    request: hyper::Request<Body>,
+    retry: &RetryConf,
+) -> Result<hyper::Response<Body>, BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError> + Clone,
+{
+    if retry.attempts == 0 || request.body().size_hint().exact().is_none() {
+        return client.oneshot(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    let mut backoff = retry.min_backoff;
+    let mut attempt = 0;
+
+    loop {
+        let mut request = hyper::Request::builder()
+            .method(parts.method.clone())
+            .uri(parts.uri.clone())
+            .version(parts.version)
+            .body(Body::from(body.clone()))?;
+        *request.headers_mut() = parts.headers.clone();
+
+        let result = client.clone().oneshot(request).await;
+        let failed = match &result {
+            Ok(response) => is_failure(response),
+            Err(_) => true,
+        };
+        if !failed || attempt >= retry.attempts {
+            return result;
+        }
+
+        attempt += 1;
+        u64_counter!(
+            "apollo.router.operations.coprocessor.retry",
+            "Total co-processor calls retried after a failure",
+            1
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(retry.max_backoff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    fn counting_client(
+        statuses: Vec<u16>,
+    ) -> (
+        Arc<AtomicUsize>,
+        tower::util::BoxCloneService<hyper::Request<Body>, hyper::Response<Body>, BoxError>,
+    ) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let client = tower::service_fn(move |_request: hyper::Request<Body>| {
+            let call = counter.fetch_add(1, Ordering::SeqCst);
+            let status = statuses[call.min(statuses.len() - 1)];
+            async move {
+                Ok::<_, BoxError>(
+                    hyper::Response::builder()
+                        .status(status)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+            }
+        });
+        (calls, tower::util::BoxCloneService::new(client))
+    }
+
+    fn request() -> hyper::Request<Body> {
+        hyper::Request::builder()
+            .uri("http://coprocessor")
+            .body(Body::from("{}"))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn retries_failed_calls() {
+        let (calls, client) = counting_client(vec![503, 500, 200]);
+        let mut client = ResilientClient::new(
+            client,
+            RetryConf {
+                attempts: 2,
+                min_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+            },
+            &Default::default(),
+        );
+
+        let response = client.ready().await.unwrap().call(request()).await.unwrap();
+        assert_eq!(200, response.status());
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn circuit_breaker_skips_calls() {
+        let (calls, client) = counting_client(vec![500, 500, 200]);
+        let mut client = ResilientClient::new(
+            client,
+            Default::default(),
+            &CircuitBreakerConf {
+                enabled: true,
+                failure_threshold: 2,
+                open_duration: Duration::from_millis(50),
+            },
+        );
+
+        for _ in 0..2 {
+            let response = client.ready().await.unwrap().call(request()).await.unwrap();
+            assert_eq!(500, response.status());
+        }
+
+        // The circuit is open
+        let error = client
+            .ready()
+            .await
+            .unwrap()
+            .call(request())
+            .await
+            .unwrap_err();
+        assert!(error.is::<CircuitOpen>());
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+
+        // A call goes through once the open duration has elapsed, and closes the circuit
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        for _ in 0..2 {
+            let response = client.ready().await.unwrap().call(request()).await.unwrap();
+            assert_eq!(200, response.status());
+        }
+        assert_eq!(4, calls.load(Ordering::SeqCst));
+    }
+}
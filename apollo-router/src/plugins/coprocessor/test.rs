@@ -2,6 +2,7 @@
 mod tests {
     use std::collections::HashMap;
     use std::sync::Arc;
+    use std::time::Duration;
 
     use futures::future::BoxFuture;
     use http::header::ACCEPT;
@@ -19,6 +20,7 @@ mod tests {
     use url::Url;
 
     use super::super::*;
+    use crate::metrics::FutureMetricsExt;
     use crate::plugin::test::MockHttpClientService;
     use crate::plugin::test::MockRouterService;
     use crate::plugin::test::MockSubgraphService;
@@ -133,6 +135,9 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -191,6 +196,9 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -248,6 +256,9 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -302,6 +313,8 @@ mod tests {
             mock_http_client,
             mock_subgraph_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            None,
             "my_subgraph_service_name".to_string(),
         );
 
@@ -428,6 +441,8 @@ mod tests {
             mock_http_client,
             mock_subgraph_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            None,
             "my_subgraph_service_name".to_string(),
         );
 
@@ -446,6 +461,401 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn external_plugin_subgraph_request_with_condition() {
+        let subgraph_stage: SubgraphStage = serde_json::from_value(json!({
+            "request": {
+                "body": true,
+                "condition": {
+                    "exists": {
+                        "subgraph_request_header": "x-call-coprocessor"
+                    }
+                }
+            }
+        }))
+        .unwrap();
+
+        for call_coprocessor in [true, false] {
+            let mut mock_subgraph_service = MockSubgraphService::new();
+
+            mock_subgraph_service
+                .expect_call()
+                .returning(|req: subgraph::Request| {
+                    Ok(subgraph::Response::builder()
+                        .data(json!({ "test": 1234_u32 }))
+                        .errors(Vec::new())
+                        .extensions(crate::json_ext::Object::new())
+                        .context(req.context)
+                        .build())
+                });
+
+            let mock_http_client = mock_with_callback(move |_: hyper::Request<Body>| {
+                Box::pin(async {
+                    Ok(hyper::Response::builder()
+                        .body(Body::from(
+                            r#"{
+                                "version": 1,
+                                "stage": "SubgraphRequest",
+                                "control": "continue",
+                                "context": {
+                                    "entries": {
+                                        "called-coprocessor": true
+                                    }
+                                }
+                            }"#,
+                        ))
+                        .unwrap())
+                })
+            });
+
+            let service = subgraph_stage.as_service(
+                mock_http_client,
+                mock_subgraph_service.boxed(),
+                Url::parse("http://test").unwrap(),
+                Transport::Http,
+                None,
+                "my_subgraph_service_name".to_string(),
+            );
+
+            let mut subgraph_request = http::Request::builder();
+            if call_coprocessor {
+                subgraph_request = subgraph_request.header("x-call-coprocessor", "true");
+            }
+            let request = subgraph::Request::fake_builder()
+                .subgraph_request(
+                    subgraph_request
+                        .body(crate::graphql::Request::default())
+                        .unwrap(),
+                )
+                .build();
+
+            // The coprocessor is only called when the header is present
+            let response = service.oneshot(request).await.unwrap();
+            assert_eq!(
+                call_coprocessor,
+                response.context.contains_key("called-coprocessor")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn external_plugin_subgraph_response_with_request_condition() {
+        // the subgraph name only exists on the request
+        let subgraph_stage: SubgraphStage = serde_json::from_value(json!({
+            "response": {
+                "body": true,
+                "condition": {
+                    "eq": [
+                        { "subgraph_name": true },
+                        "accounts"
+                    ]
+                }
+            }
+        }))
+        .unwrap();
+
+        for (subgraph_name, call_coprocessor) in [("accounts", true), ("products", false)] {
+            let mut mock_subgraph_service = MockSubgraphService::new();
+
+            mock_subgraph_service
+                .expect_call()
+                .returning(|req: subgraph::Request| {
+                    Ok(subgraph::Response::builder()
+                        .data(json!({ "test": 1234_u32 }))
+                        .errors(Vec::new())
+                        .extensions(crate::json_ext::Object::new())
+                        .context(req.context)
+                        .build())
+                });
+
+            let mock_http_client = mock_with_callback(move |_: hyper::Request<Body>| {
+                Box::pin(async {
+                    Ok(hyper::Response::builder()
+                        .body(Body::from(
+                            r#"{
+                                "version": 1,
+                                "stage": "SubgraphResponse",
+                                "control": "continue",
+                                "context": {
+                                    "entries": {
+                                        "called-coprocessor": true
+                                    }
+                                }
+                            }"#,
+                        ))
+                        .unwrap())
+                })
+            });
+
+            let service = subgraph_stage.as_service(
+                mock_http_client,
+                mock_subgraph_service.boxed(),
+                Url::parse("http://test").unwrap(),
+                Transport::Http,
+                None,
+                subgraph_name.to_string(),
+            );
+
+            let request = subgraph::Request::fake_builder()
+                .subgraph_name(subgraph_name.to_string())
+                .build();
+
+            let response = service.oneshot(request).await.unwrap();
+            assert_eq!(
+                call_coprocessor,
+                response.context.contains_key("called-coprocessor")
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn external_plugin_subgraph_request_over_unix_socket() {
+        use std::convert::Infallible;
+
+        use hyper::service::make_service_fn;
+        use hyper::service::service_fn;
+        use hyperlocal::UnixServerExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("coprocessor.sock");
+
+        let make_service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|request: hyper::Request<Body>| async move {
+                let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                let mut payload: Externalizable<serde_json::Value> =
+                    serde_json::from_slice(&body).unwrap();
+                payload.control = Some(Control::Continue);
+                payload.body = Some(json!({ "query": "query Long {\n  me {\n  name\n}\n}" }));
+                Ok::<_, Infallible>(hyper::Response::new(Body::from(
+                    serde_json::to_vec(&payload).unwrap(),
+                )))
+            }))
+        });
+        tokio::spawn(hyper::Server::bind_unix(&path).unwrap().serve(make_service));
+
+        let config: CoprocessorConf = serde_json::from_value(json!({
+            "url": format!("unix://{}", path.display()),
+        }))
+        .unwrap();
+
+        let subgraph_stage = SubgraphStage {
+            request: SubgraphRequestConf {
+                body: true,
+                ..Default::default()
+            },
+            response: Default::default(),
+        };
+
+        let mut mock_subgraph_service = MockSubgraphService::new();
+        mock_subgraph_service
+            .expect_call()
+            .returning(|req: subgraph::Request| {
+                // The query was changed by the coprocessor
+                assert_eq!(
+                    "query Long {\n  me {\n  name\n}\n}",
+                    req.subgraph_request.into_body().query.unwrap()
+                );
+
+                Ok(subgraph::Response::builder()
+                    .data(json!({ "test": 1234_u32 }))
+                    .errors(Vec::new())
+                    .extensions(crate::json_ext::Object::new())
+                    .context(req.context)
+                    .build())
+            });
+
+        let service = subgraph_stage.as_service(
+            new_http_client(&config).unwrap(),
+            mock_subgraph_service.boxed(),
+            config.url.clone(),
+            config.transport,
+            config.name.clone(),
+            "my_subgraph_service_name".to_string(),
+        );
+
+        let request = subgraph::Request::fake_builder().build();
+
+        assert_eq!(
+            serde_json_bytes::json!({ "test": 1234_u32 }),
+            service
+                .oneshot(request)
+                .await
+                .unwrap()
+                .response
+                .into_body()
+                .data
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn external_plugin_subgraph_request_on_error_continue() {
+        let subgraph_stage = SubgraphStage {
+            request: SubgraphRequestConf {
+                body: true,
+                on_error: OnError::Continue,
+                ..Default::default()
+            },
+            response: SubgraphResponseConf {
+                body: true,
+                on_error: OnError::Continue,
+                ..Default::default()
+            },
+        };
+
+        let mut mock_subgraph_service = MockSubgraphService::new();
+        mock_subgraph_service
+            .expect_call()
+            .returning(|req: subgraph::Request| {
+                // The request is unchanged
+                assert_eq!(
+                    "query Long {\n  me {\n  name\n}\n}",
+                    req.subgraph_request.body().query.as_deref().unwrap()
+                );
+
+                Ok(subgraph::Response::builder()
+                    .data(json!({ "test": 1234_u32 }))
+                    .errors(Vec::new())
+                    .extensions(crate::json_ext::Object::new())
+                    .context(req.context)
+                    .build())
+            });
+
+        let mock_http_client = mock_with_callback(move |_: hyper::Request<Body>| {
+            Box::pin(async {
+                Ok(hyper::Response::builder()
+                    .body(Body::from("not a coprocessor response"))
+                    .unwrap())
+            })
+        });
+
+        let service = subgraph_stage.as_service(
+            mock_http_client,
+            mock_subgraph_service.boxed(),
+            Url::parse("http://test").unwrap(),
+            Transport::Http,
+            None,
+            "my_subgraph_service_name".to_string(),
+        );
+
+        let request = subgraph::Request::fake_builder()
+            .subgraph_request(
+                http::Request::builder()
+                    .body(
+                        crate::graphql::Request::builder()
+                            .query("query Long {\n  me {\n  name\n}\n}")
+                            .build(),
+                    )
+                    .unwrap(),
+            )
+            .build();
+
+        let response = service.oneshot(request).await.unwrap().response;
+
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            serde_json_bytes::json!({ "test": 1234_u32 }),
+            response.into_body().data.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn external_plugin_subgraph_on_error_continue_with_invalid_output() {
+        let subgraph_stage = SubgraphStage {
+            request: SubgraphRequestConf {
+                body: true,
+                on_error: OnError::Continue,
+                ..Default::default()
+            },
+            response: SubgraphResponseConf {
+                body: true,
+                on_error: OnError::Continue,
+                ..Default::default()
+            },
+        };
+
+        let mut mock_subgraph_service = MockSubgraphService::new();
+        mock_subgraph_service
+            .expect_call()
+            .returning(|req: subgraph::Request| {
+                // The request is unchanged, including its context
+                assert_eq!(
+                    "query Long {\n  me {\n  name\n}\n}",
+                    req.subgraph_request.body().query.as_deref().unwrap()
+                );
+                assert!(!req.context.contains_key("from_request"));
+
+                Ok(subgraph::Response::builder()
+                    .data(json!({ "test": 1234_u32 }))
+                    .errors(Vec::new())
+                    .extensions(crate::json_ext::Object::new())
+                    .context(req.context)
+                    .build())
+            });
+
+        // The outputs have the right shape, but the request body is not a GraphQL request and
+        // the response headers are invalid
+        let mock_http_client = mock_with_callback(move |req: hyper::Request<Body>| {
+            Box::pin(async {
+                let input: serde_json::Value =
+                    serde_json::from_slice(&hyper::body::to_bytes(req.into_body()).await.unwrap())
+                        .unwrap();
+                let output = if input["stage"] == "SubgraphRequest" {
+                    json!({
+                        "version": 1,
+                        "stage": "SubgraphRequest",
+                        "control": "continue",
+                        "body": { "query": 42 },
+                        "context": { "entries": { "from_request": true } }
+                    })
+                } else {
+                    json!({
+                        "version": 1,
+                        "stage": "SubgraphResponse",
+                        "headers": { "invalid header": ["value"] },
+                        "body": { "data": { "test": 5678 } },
+                        "context": { "entries": { "from_response": true } }
+                    })
+                };
+                Ok(hyper::Response::builder()
+                    .body(Body::from(serde_json::to_vec(&output).unwrap()))
+                    .unwrap())
+            })
+        });
+
+        let service = subgraph_stage.as_service(
+            mock_http_client,
+            mock_subgraph_service.boxed(),
+            Url::parse("http://test").unwrap(),
+            Transport::Http,
+            None,
+            "my_subgraph_service_name".to_string(),
+        );
+
+        let request = subgraph::Request::fake_builder()
+            .subgraph_request(
+                http::Request::builder()
+                    .body(
+                        crate::graphql::Request::builder()
+                            .query("query Long {\n  me {\n  name\n}\n}")
+                            .build(),
+                    )
+                    .unwrap(),
+            )
+            .build();
+
+        let response = service.oneshot(request).await.unwrap();
+
+        // The response is unchanged, including its context
+        assert!(!response.context.contains_key("from_response"));
+        assert_eq!(StatusCode::OK, response.response.status());
+        assert_eq!(
+            serde_json_bytes::json!({ "test": 1234_u32 }),
+            response.response.into_body().data.unwrap()
+        );
+    }
+
     #[tokio::test]
     async fn external_plugin_subgraph_request_controlflow_break() {
         let subgraph_stage = SubgraphStage {
@@ -490,6 +900,8 @@ mod tests {
             mock_http_client,
             mock_subgraph_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            None,
             "my_subgraph_service_name".to_string(),
         );
 
@@ -510,6 +922,279 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn external_plugin_subgraph_request_chain_controlflow_break() {
+        async {
+            let config: Conf = serde_json::from_value(json!({
+                "coprocessors": [
+                    {
+                        "name": "auth",
+                        "url": "http://auth",
+                        "subgraph": { "all": { "request": { "body": true } } }
+                    },
+                    {
+                        "name": "masking",
+                        "url": "http://masking",
+                        "subgraph": { "all": { "request": { "body": true } } }
+                    }
+                ]
+            }))
+            .unwrap();
+            let mut coprocessors = config.into_coprocessors().unwrap().into_iter();
+
+            // The plugin clones the client once more than the stage does
+            let mut auth_http_client = MockHttpClientService::new();
+            auth_http_client.expect_clone().returning(|| {
+                mock_with_callback(|req: hyper::Request<Body>| {
+                    Box::pin(async move {
+                        assert_eq!("http://auth/", req.uri().to_string());
+                        Ok(hyper::Response::builder()
+                            .body(Body::from(
+                                r#"{
+                                    "version": 1,
+                                    "stage": "SubgraphRequest",
+                                    "control": {
+                                        "break": 401
+                                    },
+                                    "body": {
+                                        "errors": [{ "message": "unauthorized" }]
+                                    }
+                                }"#,
+                            ))
+                            .unwrap())
+                    })
+                })
+            });
+
+            // The second coprocessor is never called because the first one breaks
+            let mut masking_http_client = MockHttpClientService::new();
+            masking_http_client.expect_clone().returning(|| {
+                let mut mock_http_client = MockHttpClientService::new();
+                mock_http_client.expect_clone().returning(|| {
+                    let mut mock_http_client = MockHttpClientService::new();
+                    mock_http_client
+                        .expect_clone()
+                        .returning(MockHttpClientService::new);
+                    mock_http_client
+                });
+                mock_http_client
+            });
+
+            let plugin = CoprocessorPlugin::new(
+                vec![
+                    (auth_http_client, coprocessors.next().unwrap()),
+                    (masking_http_client, coprocessors.next().unwrap()),
+                ],
+                Default::default(),
+            )
+            .unwrap();
+
+            // This will never be called because we will break at the coprocessor.
+            let mock_subgraph_service = MockSubgraphService::new();
+            let service =
+                plugin.subgraph_service("my_subgraph_service_name", mock_subgraph_service.boxed());
+
+            let request = subgraph::Request::fake_builder().build();
+
+            let response = service.oneshot(request).await.unwrap().response;
+
+            assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+            assert_eq!(
+                "unauthorized",
+                response.into_body().errors[0].message.as_str()
+            );
+            // calls are counted per coprocessor of the chain
+            assert_counter!(
+                "apollo.router.operations.coprocessor",
+                1,
+                "coprocessor.stage" = PipelineStep::SubgraphRequest,
+                "coprocessor.succeeded" = true,
+                "coprocessor.name" = "auth"
+            );
+            assert_histogram_exists!(
+                "apollo.router.operations.coprocessor.duration",
+                f64,
+                "coprocessor.stage" = PipelineStep::SubgraphRequest,
+                "coprocessor.name" = "auth"
+            );
+        }
+        .with_metrics()
+        .await;
+    }
+
+    #[test]
+    fn chained_coprocessors_must_have_unique_names() {
+        for coprocessors in [
+            json!([{ "url": "http://auth" }]),
+            json!([
+                { "name": "auth", "url": "http://auth" },
+                { "name": "auth", "url": "http://masking" }
+            ]),
+        ] {
+            let config: Conf =
+                serde_json::from_value(json!({ "coprocessors": coprocessors })).unwrap();
+            assert!(config.into_coprocessors().is_err());
+        }
+    }
+
+    #[test]
+    fn configuration_errors_point_at_the_invalid_field() {
+        let error = serde_json::from_value::<Conf>(json!({
+            "url": "http://auth",
+            "timout": "1s"
+        }))
+        .unwrap_err();
+        assert!(
+            error.to_string().contains("unknown field `timout`"),
+            "{error}"
+        );
+
+        let error = serde_json::from_value::<Conf>(json!({
+            "coprocessors": [{ "name": "auth" }]
+        }))
+        .unwrap_err();
+        assert!(error.to_string().contains("missing field `url`"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn external_plugin_query_planner_request_rewrites_query() {
+        let query_planner_stage = query_planner::QueryPlannerStage {
+            request: query_planner::QueryPlannerRequestConf {
+                body: true,
+                ..Default::default()
+            },
+            response: Default::default(),
+        };
+
+        let planner =
+            tower::service_fn(|request: crate::services::QueryPlannerRequest| async move {
+                assert_eq!("{ me { id __typename } }", request.query);
+                assert_eq!(Some("Me".to_string()), request.operation_name);
+                Ok::<_, BoxError>(
+                    crate::services::QueryPlannerResponse::builder()
+                        .content(crate::services::QueryPlannerContent::Plan {
+                            plan: Arc::new(crate::query_planner::QueryPlan::fake_builder().build()),
+                        })
+                        .context(request.context)
+                        .build(),
+                )
+            });
+
+        let mock_http_client = mock_with_callback(move |req: hyper::Request<Body>| {
+            Box::pin(async {
+                let deserialized_request: Externalizable<serde_json::Value> =
+                    serde_json::from_slice(&hyper::body::to_bytes(req.into_body()).await.unwrap())
+                        .unwrap();
+                assert_eq!(
+                    PipelineStep::QueryPlannerRequest.to_string(),
+                    deserialized_request.stage
+                );
+                assert_eq!(
+                    json!({ "query": "{ me { id } }" }),
+                    deserialized_request.body.unwrap()
+                );
+
+                Ok(hyper::Response::builder()
+                    .body(Body::from(
+                        r#"{
+                                "version": 1,
+                                "stage": "QueryPlannerRequest",
+                                "control": "continue",
+                                "body": {
+                                    "query": "{ me { id __typename } }",
+                                    "operationName": "Me"
+                                }
+                            }"#,
+                    ))
+                    .unwrap())
+            })
+        });
+
+        let service = query_planner_stage.as_service(
+            mock_http_client,
+            planner.boxed(),
+            Url::parse("http://test").unwrap(),
+            Transport::Http,
+            None,
+            Arc::new("".to_string()),
+        );
+
+        let request = crate::services::QueryPlannerRequest::builder()
+            .query("{ me { id } }".to_string())
+            .context(crate::Context::new())
+            .build();
+
+        let response = service.oneshot(request).await.unwrap();
+        assert!(response.errors.is_empty());
+        assert!(response.content.is_some());
+    }
+
+    #[tokio::test]
+    async fn external_plugin_query_planner_response_controlflow_break() {
+        let query_planner_stage = query_planner::QueryPlannerStage {
+            request: Default::default(),
+            response: query_planner::QueryPlannerResponseConf {
+                query_plan: true,
+                ..Default::default()
+            },
+        };
+
+        let planner =
+            tower::service_fn(|request: crate::services::QueryPlannerRequest| async move {
+                Ok::<_, BoxError>(
+                    crate::services::QueryPlannerResponse::builder()
+                        .content(crate::services::QueryPlannerContent::Plan {
+                            plan: Arc::new(crate::query_planner::QueryPlan::fake_builder().build()),
+                        })
+                        .context(request.context)
+                        .build(),
+                )
+            });
+
+        let mock_http_client = mock_with_callback(move |req: hyper::Request<Body>| {
+            Box::pin(async {
+                let deserialized_request: serde_json::Value =
+                    serde_json::from_slice(&hyper::body::to_bytes(req.into_body()).await.unwrap())
+                        .unwrap();
+                assert_eq!("QueryPlannerResponse", deserialized_request["stage"]);
+                assert!(deserialized_request["queryPlan"].is_object());
+
+                Ok(hyper::Response::builder()
+                    .body(Body::from(
+                        r#"{
+                                "version": 1,
+                                "stage": "QueryPlannerResponse",
+                                "control": {
+                                    "break": 400
+                                },
+                                "body": {
+                                    "errors": [{ "message": "too many subgraph fetches" }]
+                                }
+                            }"#,
+                    ))
+                    .unwrap())
+            })
+        });
+
+        let service = query_planner_stage.as_service(
+            mock_http_client,
+            planner.boxed(),
+            Url::parse("http://test").unwrap(),
+            Transport::Http,
+            None,
+            Arc::new("".to_string()),
+        );
+
+        let request = crate::services::QueryPlannerRequest::builder()
+            .query("{ me { id } }".to_string())
+            .context(crate::Context::new())
+            .build();
+
+        let response = service.oneshot(request).await.unwrap();
+        assert!(response.content.is_none());
+        assert_eq!("too many subgraph fetches", response.errors[0].message);
+    }
+
     #[tokio::test]
     async fn external_plugin_subgraph_request_controlflow_break_with_message_string() {
         let subgraph_stage = SubgraphStage {
@@ -544,6 +1229,8 @@ mod tests {
             mock_http_client,
             mock_subgraph_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            None,
             "my_subgraph_service_name".to_string(),
         );
 
@@ -601,6 +1288,8 @@ mod tests {
             mock_http_client,
             mock_subgraph_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            None,
             "my_subgraph_service_name".to_string(),
         );
 
@@ -699,6 +1388,8 @@ mod tests {
             mock_http_client,
             mock_subgraph_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            None,
             "my_subgraph_service_name".to_string(),
         );
 
@@ -761,6 +1452,8 @@ mod tests {
             mock_http_client,
             mock_subgraph_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            None,
             "my_subgraph_service_name".to_string(),
         );
 
@@ -885,6 +1578,9 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -1011,6 +1707,9 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -1082,6 +1781,9 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -1161,6 +1863,9 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -1304,6 +2009,9 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -1408,6 +2116,9 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -1468,6 +2179,9 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
@@ -1504,6 +2218,9 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             Url::parse("http://test").unwrap(),
+            Transport::Http,
+            Duration::from_secs(5),
+            None,
             Arc::new("".to_string()),
         );
 
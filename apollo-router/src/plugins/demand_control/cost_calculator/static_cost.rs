@@ -424,7 +424,12 @@ mod tests {
         ctx.extensions().lock().insert::<ParsedDocument>(query);
 
         let planner_res = planner
-            .call(QueryPlannerRequest::new(query_str.to_string(), None, ctx))
+            .call(
+                QueryPlannerRequest::builder()
+                    .query(query_str)
+                    .context(ctx)
+                    .build(),
+            )
             .await
             .unwrap();
         let query_plan = match planner_res.content.unwrap() {
@@ -117,6 +117,13 @@ pub(crate) struct DemandControlConfig {
     strategy: StrategyConfig,
 }
 
+impl DemandControlConfig {
+    /// Whether operations are given a static cost estimate, which batch cost limits rely on
+    pub(crate) fn estimates_static_cost(&self) -> bool {
+        self.enabled && matches!(self.strategy, StrategyConfig::StaticEstimated { .. })
+    }
+}
+
 #[derive(Debug, Display, Error)]
 pub(crate) enum DemandControlError {
     /// query estimated cost {estimated_cost} exceeded configured maximum {max_cost}
@@ -126,6 +133,13 @@ pub(crate) enum DemandControlError {
         /// The maximum cost of the query
         max_cost: f64,
     },
+    /// batch estimated cost {estimated_cost} exceeded configured maximum {max_cost}
+    BatchCostTooExpensive {
+        /// The estimated cost of the batch, including the rejected query
+        estimated_cost: f64,
+        /// The maximum cost of the batch
+        max_cost: f64,
+    },
     /// auery actual cost {actual_cost} exceeded configured maximum {max_cost}
     #[allow(dead_code)]
     ActualCostTooExpensive {
@@ -148,6 +162,10 @@ impl IntoGraphQLErrors for DemandControlError {
             DemandControlError::EstimatedCostTooExpensive {
                 estimated_cost,
                 max_cost,
+            }
+            | DemandControlError::BatchCostTooExpensive {
+                estimated_cost,
+                max_cost,
             } => {
                 let mut extensions = Object::new();
                 extensions.insert("cost.estimated", estimated_cost.into());
@@ -190,6 +208,7 @@ impl DemandControlError {
     fn code(&self) -> &'static str {
         match self {
             DemandControlError::EstimatedCostTooExpensive { .. } => "COST_ESTIMATED_TOO_EXPENSIVE",
+            DemandControlError::BatchCostTooExpensive { .. } => "COST_BATCH_TOO_EXPENSIVE",
             DemandControlError::ActualCostTooExpensive { .. } => "COST_ACTUAL_TOO_EXPENSIVE",
             DemandControlError::QueryParseFailure(_) => "COST_QUERY_PARSE_FAILURE",
             DemandControlError::ResponseTypingFailure(_) => "COST_RESPONSE_TYPING_FAILURE",
@@ -362,6 +381,8 @@ impl Plugin for DemandControl {
     }
 }
 
+pub(crate) const APOLLO_DEMAND_CONTROL_PLUGIN_NAME: &str = "experimental_demand_control";
+
 register_plugin!("apollo", "experimental_demand_control", DemandControl);
 
 #[cfg(test)]
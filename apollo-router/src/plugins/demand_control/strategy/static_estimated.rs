@@ -1,5 +1,8 @@
+use std::sync::Arc;
+
 use apollo_compiler::ExecutableDocument;
 
+use crate::batching::BatchCost;
 use crate::graphql;
 use crate::plugins::demand_control::cost_calculator::static_cost::StaticCostCalculator;
 use crate::plugins::demand_control::strategy::StrategyImpl;
@@ -24,15 +27,32 @@ impl StrategyImpl for StaticEstimated {
                 let cost_result = extensions.get_or_default_mut::<CostContext>();
                 cost_result.estimated = cost;
                 if cost > self.max {
-                    Err(
-                        cost_result.result(DemandControlError::EstimatedCostTooExpensive {
+                    return Err(cost_result.result(
+                        DemandControlError::EstimatedCostTooExpensive {
                             estimated_cost: cost,
                             max_cost: self.max,
-                        }),
-                    )
-                } else {
-                    Ok(())
+                        },
+                    ));
+                }
+                // the operations of a client batch share the `batching.max_cost` budget
+                if let Some(batch_cost) = extensions.get::<Arc<BatchCost>>().cloned() {
+                    if let Err(estimated_cost) = batch_cost.add(cost) {
+                        u64_counter!(
+                            "apollo.router.operations.batching.rejected",
+                            "Number of batched operations rejected because they exceeded a batching limit",
+                            1,
+                            "reason" = "max_cost"
+                        );
+                        let cost_result = extensions.get_or_default_mut::<CostContext>();
+                        return Err(cost_result.result(
+                            DemandControlError::BatchCostTooExpensive {
+                                estimated_cost,
+                                max_cost: batch_cost.max(),
+                            },
+                        ));
+                    }
                 }
+                Ok(())
             })
     }
 
@@ -836,6 +836,7 @@ mod test {
             context: Context::new(),
             subgraph_name: String::from("test").into(),
             subscription_stream: None,
+            deferred_stream: None,
             connection_closed_signal: None,
             query_hash: Default::default(),
             authorization: Default::default(),
@@ -908,6 +909,7 @@ mod test {
             context: Context::new(),
             subgraph_name: String::from("test").into(),
             subscription_stream: None,
+            deferred_stream: None,
             connection_closed_signal: None,
             query_hash: Default::default(),
             authorization: Default::default(),
@@ -970,6 +972,7 @@ mod test {
             context: ctx,
             subgraph_name: String::from("test").into(),
             subscription_stream: None,
+            deferred_stream: None,
             connection_closed_signal: None,
             query_hash: Default::default(),
             authorization: Default::default(),
@@ -10,6 +10,9 @@ use base64::prelude::BASE64_URL_SAFE;
 use base64::prelude::BASE64_URL_SAFE_NO_PAD;
 use base64::Engine as _;
 use bytes::Bytes;
+use hmac::digest::KeyInit;
+use hmac::Hmac;
+use hmac::Mac;
 use http::header::InvalidHeaderName;
 use http::uri::Authority;
 use http::uri::Parts;
@@ -34,7 +37,13 @@ use tower::BoxError;
 use uuid::Uuid;
 
 use super::execution;
+use super::jwt;
+use super::jwt::SharedJwksManager;
+use super::metrics::RhaiMetrics;
+use super::operation;
+use super::query_planner;
 use super::router;
+use super::state::RhaiState;
 use super::subgraph;
 use super::supergraph;
 use super::Rhai;
@@ -184,6 +193,76 @@ mod router_sha256 {
     }
 }
 
+fn hmac_digest<M: Mac + KeyInit>(key: &str, message: &str) -> Vec<u8> {
+    let mut mac =
+        <M as KeyInit>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(message.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[export_module]
+mod router_crypto {
+    use rand::RngCore;
+    use subtle::ConstantTimeEq;
+
+    // Keep random values small enough to be generated on every request
+    const MAX_RANDOM_BYTES: i64 = 1024;
+
+    pub(crate) fn hmac_sha256(key: &str, message: &str) -> String {
+        hex::encode(hmac_digest::<Hmac<sha2::Sha256>>(key, message))
+    }
+
+    #[rhai_fn(name = "hmac_sha256")]
+    pub(crate) fn hmac_sha256_alphabet(
+        key: &str,
+        message: &str,
+        alphabet: Base64Alphabet,
+    ) -> String {
+        get_engine(&alphabet).encode(hmac_digest::<Hmac<sha2::Sha256>>(key, message))
+    }
+
+    pub(crate) fn hmac_sha512(key: &str, message: &str) -> String {
+        hex::encode(hmac_digest::<Hmac<sha2::Sha512>>(key, message))
+    }
+
+    #[rhai_fn(name = "hmac_sha512")]
+    pub(crate) fn hmac_sha512_alphabet(
+        key: &str,
+        message: &str,
+        alphabet: Base64Alphabet,
+    ) -> String {
+        get_engine(&alphabet).encode(hmac_digest::<Hmac<sha2::Sha512>>(key, message))
+    }
+
+    pub(crate) fn constant_time_eq(left: &str, right: &str) -> bool {
+        left.as_bytes().ct_eq(right.as_bytes()).into()
+    }
+
+    #[rhai_fn(return_raw)]
+    pub(crate) fn random_bytes(length: i64) -> Result<String, Box<EvalAltResult>> {
+        Ok(hex::encode(random(length)?))
+    }
+
+    #[rhai_fn(name = "random_bytes", return_raw)]
+    pub(crate) fn random_bytes_alphabet(
+        length: i64,
+        alphabet: Base64Alphabet,
+    ) -> Result<String, Box<EvalAltResult>> {
+        Ok(get_engine(&alphabet).encode(random(length)?))
+    }
+
+    fn random(length: i64) -> Result<Vec<u8>, Box<EvalAltResult>> {
+        if !(0..=MAX_RANDOM_BYTES).contains(&length) {
+            return Err(
+                format!("random_bytes length must be between 0 and {MAX_RANDOM_BYTES}").into(),
+            );
+        }
+        let mut bytes = vec![0; length as usize];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Ok(bytes)
+    }
+}
+
 #[export_module]
 mod router_expansion {
     pub(crate) type Expansion = expansion::Expansion;
@@ -1197,6 +1276,95 @@ mod router_plugin {
             .map(|x| x.as_secs() as i64)
     }
 
+    // Query planner request and response
+    #[rhai_fn(get = "context", pure, return_raw)]
+    pub(crate) fn query_planner_request_context_get(
+        obj: &mut SharedMut<query_planner::Request>,
+    ) -> Result<Context, Box<EvalAltResult>> {
+        Ok(obj.with_mut(|request| request.context.clone()))
+    }
+    #[rhai_fn(get = "id", pure)]
+    pub(crate) fn query_planner_request_id_get(
+        obj: &mut SharedMut<query_planner::Request>,
+    ) -> String {
+        obj.with_mut(|request| request.context.id.clone())
+    }
+    #[rhai_fn(set = "context", return_raw)]
+    pub(crate) fn query_planner_request_context_set(
+        obj: &mut SharedMut<query_planner::Request>,
+        context: Context,
+    ) -> Result<(), Box<EvalAltResult>> {
+        obj.with_mut(|request| request.context = context);
+        Ok(())
+    }
+
+    #[rhai_fn(get = "query", pure)]
+    pub(crate) fn query_planner_request_query_get(
+        obj: &mut SharedMut<query_planner::Request>,
+    ) -> String {
+        obj.with_mut(|request| request.query.clone())
+    }
+    #[rhai_fn(set = "query")]
+    pub(crate) fn query_planner_request_query_set(
+        obj: &mut SharedMut<query_planner::Request>,
+        value: &str,
+    ) {
+        obj.with_mut(|request| request.query = value.to_string())
+    }
+
+    #[rhai_fn(get = "operation_name", pure)]
+    pub(crate) fn query_planner_request_operation_name_get(
+        obj: &mut SharedMut<query_planner::Request>,
+    ) -> Dynamic {
+        obj.with_mut(|request| {
+            request
+                .operation_name
+                .clone()
+                .map_or(Dynamic::UNIT, Dynamic::from)
+        })
+    }
+    #[rhai_fn(set = "operation_name")]
+    pub(crate) fn query_planner_request_operation_name_set(
+        obj: &mut SharedMut<query_planner::Request>,
+        value: &str,
+    ) {
+        obj.with_mut(|request| request.operation_name = Some(value.to_string()))
+    }
+
+    #[rhai_fn(get = "context", pure, return_raw)]
+    pub(crate) fn query_planner_response_context_get(
+        obj: &mut SharedMut<query_planner::Response>,
+    ) -> Result<Context, Box<EvalAltResult>> {
+        Ok(obj.with_mut(|response| response.context.clone()))
+    }
+    #[rhai_fn(get = "id", pure)]
+    pub(crate) fn query_planner_response_id_get(
+        obj: &mut SharedMut<query_planner::Response>,
+    ) -> String {
+        obj.with_mut(|response| response.context.id.clone())
+    }
+    #[rhai_fn(set = "context", return_raw)]
+    pub(crate) fn query_planner_response_context_set(
+        obj: &mut SharedMut<query_planner::Response>,
+        context: Context,
+    ) -> Result<(), Box<EvalAltResult>> {
+        obj.with_mut(|response| response.context = context);
+        Ok(())
+    }
+
+    // The query plan is unit when the query was answered without planning, as for introspection
+    #[rhai_fn(get = "query_plan", pure)]
+    pub(crate) fn query_planner_response_query_plan_get(
+        obj: &mut SharedMut<query_planner::Response>,
+    ) -> Dynamic {
+        obj.with_mut(|response| match &response.content {
+            Some(query_planner::QueryPlannerContent::Plan { plan }) => {
+                plan.formatted_query_plan.clone().unwrap_or_default().into()
+            }
+            _ => Dynamic::UNIT,
+        })
+    }
+
     // Add query plan getter to execution request
     #[rhai_fn(get = "query_plan")]
     pub(crate) fn execution_request_query_plan_get(
@@ -1210,6 +1378,50 @@ mod router_plugin {
                 .unwrap_or_default()
         })
     }
+
+    // Add query plan structure getter to execution request
+    #[rhai_fn(get = "plan", pure, return_raw)]
+    pub(crate) fn execution_request_plan_get(
+        obj: &mut SharedMut<execution::Request>,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        obj.with_mut(|request| operation::plan(&request.query_plan))
+    }
+
+    // Add query plan structure getter to query planner response
+    #[rhai_fn(get = "plan", pure, return_raw)]
+    pub(crate) fn query_planner_response_plan_get(
+        obj: &mut SharedMut<query_planner::Response>,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        obj.with_mut(|response| match &response.content {
+            Some(query_planner::QueryPlannerContent::Plan { plan }) => operation::plan(plan),
+            _ => Ok(Dynamic::UNIT),
+        })
+    }
+
+    // Add operation getters to supergraph and execution requests
+    #[rhai_fn(get = "operation", pure)]
+    pub(crate) fn supergraph_request_operation_get(
+        obj: &mut SharedMut<supergraph::Request>,
+    ) -> Dynamic {
+        obj.with_mut(|request| {
+            operation::operation(
+                &request.context,
+                request.supergraph_request.body().operation_name.as_deref(),
+            )
+        })
+    }
+
+    #[rhai_fn(get = "operation", pure)]
+    pub(crate) fn execution_request_operation_get(
+        obj: &mut SharedMut<execution::Request>,
+    ) -> Dynamic {
+        obj.with_mut(|request| {
+            operation::operation(
+                &request.context,
+                request.supergraph_request.body().operation_name.as_deref(),
+            )
+        })
+    }
 }
 
 #[derive(Default)]
@@ -1618,7 +1830,14 @@ impl Rhai {
         Ok(())
     }
 
-    pub(super) fn new_rhai_engine(path: Option<PathBuf>, sdl: String, main: PathBuf) -> Engine {
+    pub(super) fn new_rhai_engine(
+        path: Option<PathBuf>,
+        sdl: String,
+        main: PathBuf,
+        metrics: Arc<RhaiMetrics>,
+        state: Arc<RhaiState>,
+        jwks_manager: SharedJwksManager,
+    ) -> Engine {
         let mut engine = Engine::new();
         // If we pass in a path, use it to configure our engine
         // with a FileModuleResolver which allows import to work
@@ -1637,9 +1856,14 @@ impl Rhai {
         let base64_module = exported_module!(router_base64);
         let json_module = exported_module!(router_json);
         let sha256_module = exported_module!(router_sha256);
+        let crypto_module = exported_module!(router_crypto);
+        let jwt_module = jwt::module(jwks_manager);
 
         let expansion_module = exported_module!(router_expansion);
 
+        let metrics_module = metrics.module();
+        let state_module = state.module();
+
         // Share main so we can move copies into each closure as required for logging
         let shared_main = Arc::new(main.display().to_string());
 
@@ -1665,9 +1889,17 @@ impl Rhai {
             .register_static_module("json", json_module.into())
             // Register our SHA256 module (not global)
             .register_static_module("sha256", sha256_module.into())
+            // Register our crypto module (not global)
+            .register_static_module("crypto", crypto_module.into())
+            // Register our JWT module (not global)
+            .register_static_module("jwt", jwt_module.into())
             // Register our expansion module (not global)
             // Hide the fact that it is an expansion module by calling it "env"
             .register_static_module("env", expansion_module.into())
+            // Register our metrics module (not global)
+            .register_static_module("metrics", metrics_module.into())
+            // Register our state module (not global)
+            .register_static_module("state", state_module.into())
             // Register HeaderMap as an iterator so we can loop over contents
             .register_iterator::<HeaderMap>()
             // Register a series of logging functions
@@ -0,0 +1,54 @@
+//! jwt module
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwapOption;
+use jsonwebtoken::DecodingKey;
+use jsonwebtoken::Validation;
+use rhai::serde::to_dynamic;
+use rhai::EvalAltResult;
+use rhai::ImmutableString;
+use rhai::Map;
+use rhai::Module;
+
+use crate::plugins::authentication::jwks::JwksManager;
+use crate::plugins::authentication::verify_jwt;
+
+/// The JWKS of the authentication plugin, set once all the plugins are created
+pub(crate) type SharedJwksManager = Arc<ArcSwapOption<JwksManager>>;
+
+/// Creates the `jwt` Rhai module, with the `decode` and `verify` functions
+pub(crate) fn module(jwks_manager: SharedJwksManager) -> Module {
+    let mut module = Module::new();
+    module.set_native_fn("decode", |token: ImmutableString| decode(&token));
+    module.set_native_fn("verify", move |token: ImmutableString| {
+        let guard = jwks_manager.load();
+        let jwks_manager = guard
+            .as_ref()
+            .ok_or("JWT verification requires JWT authentication to be configured")?;
+        let claims = verify_jwt(jwks_manager, &token).map_err(|(error, _)| error.to_string())?;
+        to_dynamic(claims)
+    });
+    module
+}
+
+// Decodes the header and claims of a JWT, without verifying its signature or its expiration
+fn decode(token: &str) -> Result<Map, Box<EvalAltResult>> {
+    let header = jsonwebtoken::decode_header(token).map_err(|e| e.to_string())?;
+    let mut validation = Validation::new(header.alg);
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    validation.required_spec_claims.clear();
+    let data = jsonwebtoken::decode::<serde_json::Value>(
+        token,
+        &DecodingKey::from_secret(&[]),
+        &validation,
+    )
+    .map_err(|e| e.to_string())?;
+
+    let mut decoded = Map::new();
+    decoded.insert("header".into(), to_dynamic(data.header)?);
+    decoded.insert("claims".into(), to_dynamic(data.claims)?);
+    Ok(decoded)
+}
@@ -0,0 +1,296 @@
+//! metrics module
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+
+use opentelemetry::metrics::Counter;
+use opentelemetry::metrics::Histogram;
+use opentelemetry::metrics::ObservableGauge;
+use opentelemetry::KeyValue;
+use opentelemetry::Value;
+use rhai::Dynamic;
+use rhai::EvalAltResult;
+use rhai::ImmutableString;
+use rhai::Map;
+use rhai::Module;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::metrics::meter_provider;
+
+/// Limits on the metrics recorded by Rhai scripts
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct Conf {
+    /// The maximum number of distinct metrics that scripts can create (default: 100).
+    /// Measurements for new metrics beyond this limit are dropped.
+    max_metrics: usize,
+    /// The maximum number of distinct attribute sets recorded for each metric (default: 1000).
+    /// Measurements with new attribute sets beyond this limit are dropped.
+    max_attribute_sets: usize,
+}
+
+impl Default for Conf {
+    fn default() -> Self {
+        Self {
+            max_metrics: 100,
+            max_attribute_sets: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Counter,
+    Histogram,
+    Gauge,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Counter => write!(f, "counter"),
+            Kind::Histogram => write!(f, "histogram"),
+            Kind::Gauge => write!(f, "gauge"),
+        }
+    }
+}
+
+// Attribute sets are compared on their keys and formatted values, since attribute values
+// can be floats.
+type AttributeSet = Vec<(String, String)>;
+
+type GaugeValues = Arc<Mutex<HashMap<AttributeSet, (f64, Vec<KeyValue>)>>>;
+
+// Instruments are registered with the meter provider, which drops them when the telemetry
+// configuration changes. We only keep weak references, and create them again when needed.
+enum Instrument {
+    Counter(Weak<Counter<f64>>),
+    Histogram(Weak<Histogram<f64>>),
+    Gauge {
+        instrument: Weak<ObservableGauge<f64>>,
+        values: GaugeValues,
+    },
+}
+
+struct Metric {
+    instrument: Instrument,
+    attribute_sets: HashSet<AttributeSet>,
+    overflowed: bool,
+}
+
+/// Metrics recorded by Rhai scripts through the `metrics` module
+///
+/// This is shared by all the engines of a Rhai plugin, so metrics survive script reloads.
+#[derive(Default)]
+pub(crate) struct RhaiMetrics {
+    conf: Conf,
+    metrics: Mutex<HashMap<String, Metric>>,
+    overflowed: AtomicBool,
+}
+
+impl RhaiMetrics {
+    pub(crate) fn new(conf: Conf) -> Self {
+        Self {
+            conf,
+            ..Default::default()
+        }
+    }
+
+    /// Creates the `metrics` Rhai module, with the `counter`, `histogram` and `gauge` functions
+    pub(crate) fn module(self: &Arc<Self>) -> Module {
+        let mut module = Module::new();
+        for kind in [Kind::Counter, Kind::Histogram, Kind::Gauge] {
+            let metrics = self.clone();
+            module.set_native_fn(
+                kind.to_string(),
+                move |name: ImmutableString, value: Dynamic| {
+                    metrics.record(kind, &name, value, Map::new())
+                },
+            );
+            let metrics = self.clone();
+            module.set_native_fn(
+                kind.to_string(),
+                move |name: ImmutableString, value: Dynamic, attributes: Map| {
+                    metrics.record(kind, &name, value, attributes)
+                },
+            );
+        }
+        module
+    }
+
+    fn record(
+        &self,
+        kind: Kind,
+        name: &str,
+        value: Dynamic,
+        attributes: Map,
+    ) -> Result<(), Box<EvalAltResult>> {
+        validate_name(name)?;
+        let value = value
+            .as_float()
+            .or_else(|_| value.as_int().map(|value| value as f64))
+            .map_err(|type_name| format!("metric {name}: expected a number, got {type_name}"))?;
+        if !value.is_finite() {
+            return Err(format!("metric {name}: the value must be finite").into());
+        }
+        if kind == Kind::Counter && value < 0.0 {
+            return Err(format!("metric {name}: a counter cannot decrease").into());
+        }
+        let (attribute_set, attributes) = convert_attributes(name, attributes)?;
+
+        let mut metrics = self.metrics.lock().expect("lock poisoned");
+        if !metrics.contains_key(name) {
+            if metrics.len() >= self.conf.max_metrics {
+                if !self.overflowed.swap(true, Ordering::Relaxed) {
+                    tracing::warn!(
+                        "rhai scripts created more than {} metrics, measurements for new metrics are dropped",
+                        self.conf.max_metrics
+                    );
+                }
+                return Ok(());
+            }
+            let instrument = match kind {
+                Kind::Counter => Instrument::Counter(Weak::new()),
+                Kind::Histogram => Instrument::Histogram(Weak::new()),
+                Kind::Gauge => Instrument::Gauge {
+                    instrument: Weak::new(),
+                    values: Default::default(),
+                },
+            };
+            metrics.insert(
+                name.to_string(),
+                Metric {
+                    instrument,
+                    attribute_sets: HashSet::new(),
+                    overflowed: false,
+                },
+            );
+        }
+        let metric = metrics.get_mut(name).expect("inserted above; qed");
+
+        let existing = match metric.instrument {
+            Instrument::Counter(_) => Kind::Counter,
+            Instrument::Histogram(_) => Kind::Histogram,
+            Instrument::Gauge { .. } => Kind::Gauge,
+        };
+        if existing != kind {
+            return Err(format!("metric {name} is a {existing}, not a {kind}").into());
+        }
+
+        if !metric.attribute_sets.contains(&attribute_set) {
+            if metric.attribute_sets.len() >= self.conf.max_attribute_sets {
+                if !metric.overflowed {
+                    metric.overflowed = true;
+                    tracing::warn!(
+                        "rhai metric {name} has more than {} attribute sets, measurements with new attributes are dropped",
+                        self.conf.max_attribute_sets
+                    );
+                }
+                return Ok(());
+            }
+            metric.attribute_sets.insert(attribute_set.clone());
+        }
+
+        match &mut metric.instrument {
+            Instrument::Counter(weak) => {
+                let counter = weak.upgrade().unwrap_or_else(|| {
+                    let counter = meter_provider().create_registered_instrument(|p| {
+                        p.meter("apollo/router")
+                            .f64_counter(name.to_string())
+                            .init()
+                    });
+                    *weak = Arc::downgrade(&counter);
+                    counter
+                });
+                counter.add(value, &attributes);
+            }
+            Instrument::Histogram(weak) => {
+                let histogram = weak.upgrade().unwrap_or_else(|| {
+                    let histogram = meter_provider().create_registered_instrument(|p| {
+                        p.meter("apollo/router")
+                            .f64_histogram(name.to_string())
+                            .init()
+                    });
+                    *weak = Arc::downgrade(&histogram);
+                    histogram
+                });
+                histogram.record(value, &attributes);
+            }
+            Instrument::Gauge { instrument, values } => {
+                values
+                    .lock()
+                    .expect("lock poisoned")
+                    .insert(attribute_set, (value, attributes));
+                if instrument.upgrade().is_none() {
+                    let observed = values.clone();
+                    let gauge = meter_provider().create_registered_instrument(|p| {
+                        let observed = observed.clone();
+                        p.meter("apollo/router")
+                            .f64_observable_gauge(name.to_string())
+                            .with_callback(move |observer| {
+                                for (value, attributes) in
+                                    observed.lock().expect("lock poisoned").values()
+                                {
+                                    observer.observe(*value, attributes);
+                                }
+                            })
+                            .init()
+                    });
+                    *instrument = Arc::downgrade(&gauge);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), Box<EvalAltResult>> {
+    let valid = name.len() <= 255
+        && name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '/'));
+    if !valid {
+        return Err(format!("invalid metric name: {name}").into());
+    }
+    // The router's own metrics must not be overwritten by scripts
+    if name.starts_with("apollo.") || name.starts_with("apollo_") {
+        return Err(format!("metric {name}: the apollo prefix is reserved").into());
+    }
+    Ok(())
+}
+
+fn convert_attributes(
+    name: &str,
+    attributes: Map,
+) -> Result<(AttributeSet, Vec<KeyValue>), Box<EvalAltResult>> {
+    let mut attribute_set = Vec::with_capacity(attributes.len());
+    let mut key_values = Vec::with_capacity(attributes.len());
+    for (key, value) in attributes {
+        let value = if value.is_string() {
+            Value::from(value.into_string()?)
+        } else if let Ok(value) = value.as_int() {
+            Value::from(value)
+        } else if let Ok(value) = value.as_float() {
+            Value::from(value)
+        } else if let Ok(value) = value.as_bool() {
+            Value::from(value)
+        } else {
+            return Err(format!(
+                "metric {name}: unsupported value for attribute {key}: {}",
+                value.type_name()
+            )
+            .into());
+        };
+        attribute_set.push((key.to_string(), value.to_string()));
+        key_values.push(KeyValue::new(key.to_string(), value));
+    }
+    Ok((attribute_set, key_values))
+}
@@ -40,19 +40,28 @@ use tower::ServiceExt;
 
 use self::engine::RhaiService;
 use self::engine::SharedMut;
+use self::jwt::SharedJwksManager;
+use self::metrics::RhaiMetrics;
+use self::state::RhaiState;
 use crate::error::Error;
 use crate::layers::ServiceBuilderExt;
-use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
+use crate::plugin::PluginPrivate;
+use crate::plugins::authentication::jwks::JwksManager;
 use crate::plugins::rhai::engine::OptionDance;
-use crate::register_plugin;
+use crate::register_private_plugin;
 
 mod engine;
 
 pub(crate) const RHAI_SPAN_NAME: &str = "rhai_plugin";
 
 mod execution;
+mod jwt;
+mod metrics;
+mod operation;
+mod query_planner;
 mod router;
+mod state;
 mod subgraph;
 mod supergraph;
 
@@ -67,11 +76,17 @@ impl EngineBlock {
         scripts: Option<PathBuf>,
         main: PathBuf,
         sdl: Arc<String>,
+        metrics: Arc<RhaiMetrics>,
+        state: Arc<RhaiState>,
+        jwks_manager: SharedJwksManager,
     ) -> Result<Self, BoxError> {
         let engine = Arc::new(Rhai::new_rhai_engine(
             scripts,
             sdl.to_string(),
             main.clone(),
+            metrics,
+            state,
+            jwks_manager,
         ));
         let ast = engine
             .compile_file(main.clone())
@@ -101,8 +116,9 @@ impl EngineBlock {
 /// the engine block will be infrequent in relation to the accesses of it.
 /// We'd love to use AtomicArc if such a thing existed, but since it doesn't
 /// we'll use ArcSwap to accomplish our goal.
-struct Rhai {
+pub(crate) struct Rhai {
     block: Arc<ArcSwap<EngineBlock>>,
+    jwks_manager: SharedJwksManager,
     park_flag: Arc<AtomicBool>,
     watcher_handle: Option<std::thread::JoinHandle<()>>,
 }
@@ -115,10 +131,16 @@ pub(crate) struct Conf {
     scripts: Option<PathBuf>,
     /// The main entry point for Rhai script evaluation
     main: Option<String>,
+    /// Limits on the metrics recorded by scripts
+    #[serde(default)]
+    metrics: metrics::Conf,
+    /// State shared by all the invocations of the scripts
+    #[serde(default)]
+    state: state::Conf,
 }
 
 #[async_trait::async_trait]
-impl Plugin for Rhai {
+impl PluginPrivate for Rhai {
     type Config = Conf;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
@@ -139,10 +161,20 @@ impl Plugin for Rhai {
         let watched_main = main.clone();
         let watched_sdl = sdl.clone();
 
+        let metrics = Arc::new(RhaiMetrics::new(init.config.metrics));
+        let watched_metrics = metrics.clone();
+        let state = Arc::new(RhaiState::new(init.config.state, &main, &sdl).await?);
+        let watched_state = state.clone();
+        let jwks_manager = SharedJwksManager::default();
+        let watched_jwks_manager = jwks_manager.clone();
+
         let block = Arc::new(ArcSwap::from_pointee(EngineBlock::try_new(
             Some(scripts_path),
             main,
             sdl,
+            metrics,
+            state,
+            jwks_manager.clone(),
         )?));
         let watched_block = block.clone();
 
@@ -182,6 +214,9 @@ impl Plugin for Rhai {
                                         Some(watching_path.clone()),
                                         watched_main.clone(),
                                         watched_sdl.clone(),
+                                        watched_metrics.clone(),
+                                        watched_state.clone(),
+                                        watched_jwks_manager.clone(),
                                     ) {
                                         Ok(eb) => {
                                             tracing::info!("updating rhai execution engine");
@@ -217,6 +252,7 @@ impl Plugin for Rhai {
 
         Ok(Self {
             block,
+            jwks_manager,
             park_flag,
             watcher_handle: Some(watcher_handle),
         })
@@ -293,6 +329,34 @@ impl Plugin for Rhai {
         }
         shared_service.take_unwrap()
     }
+
+    fn query_planner_service(
+        &self,
+        service: query_planner::BoxService,
+    ) -> query_planner::BoxService {
+        const FUNCTION_NAME_SERVICE: &str = "query_planner_service";
+        if !self.ast_has_function(FUNCTION_NAME_SERVICE) {
+            return service;
+        }
+        tracing::debug!("query_planner_service function found");
+        let shared_service = Arc::new(Mutex::new(Some(service)));
+        if let Err(error) = self.run_rhai_service(
+            FUNCTION_NAME_SERVICE,
+            None,
+            ServiceStep::QueryPlanner(shared_service.clone()),
+            self.block.load().scope.clone(),
+        ) {
+            tracing::error!("service callback failed: {error}");
+        }
+        shared_service.take_unwrap()
+    }
+}
+
+impl Rhai {
+    /// Shares the JWKS of the authentication plugin with the `jwt` module of the scripts
+    pub(crate) fn set_jwks_manager(&self, jwks_manager: Option<JwksManager>) {
+        self.jwks_manager.store(jwks_manager.map(Arc::new));
+    }
 }
 
 impl Drop for Rhai {
@@ -311,6 +375,7 @@ pub(crate) enum ServiceStep {
     Supergraph(SharedMut<supergraph::BoxService>),
     Execution(SharedMut<execution::BoxService>),
     Subgraph(SharedMut<subgraph::BoxService>),
+    QueryPlanner(SharedMut<query_planner::BoxService>),
 }
 
 // Actually use the checkpoint function so that we can shortcut requests which fail
@@ -717,6 +782,9 @@ impl ServiceStep {
             ServiceStep::Subgraph(service) => {
                 gen_map_request!(subgraph, service, rhai_service, callback);
             }
+            ServiceStep::QueryPlanner(service) => {
+                gen_map_request!(query_planner, service, rhai_service, callback);
+            }
         }
     }
 
@@ -734,6 +802,9 @@ impl ServiceStep {
             ServiceStep::Subgraph(service) => {
                 gen_map_response!(subgraph, service, rhai_service, callback);
             }
+            ServiceStep::QueryPlanner(service) => {
+                gen_map_response!(query_planner, service, rhai_service, callback);
+            }
         }
     }
 }
@@ -825,7 +896,7 @@ fn execute(
     }
 }
 
-register_plugin!("apollo", "rhai", Rhai);
+register_private_plugin!("apollo", "rhai", Rhai);
 
 #[cfg(test)]
 mod tests;
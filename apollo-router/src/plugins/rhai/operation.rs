@@ -0,0 +1,233 @@
+//! Read-only views of the operation and of the query plan
+
+use std::collections::BTreeSet;
+
+use apollo_compiler::executable;
+use apollo_compiler::executable::ExecutableDocument;
+use apollo_compiler::executable::Selection;
+use apollo_compiler::executable::SelectionSet;
+use apollo_compiler::Node;
+use rhai::serde::to_dynamic;
+use rhai::Array;
+use rhai::Dynamic;
+use rhai::EvalAltResult;
+use rhai::Map;
+
+use crate::query_planner::QueryPlan;
+use crate::services::layers::query_analysis::ParsedDocument;
+use crate::Context;
+
+/// Maximum number of fields listed in the view of an operation
+const MAX_FIELDS: usize = 1000;
+
+/// View of the operation computed by the first getter call of a request, reused by later calls
+struct OperationView {
+    operation_name: Option<String>,
+    view: Dynamic,
+}
+
+/// Returns a view of the operation of the request, or unit if the request was not parsed yet
+///
+/// Fields are listed in document order, with their response path. Fragment spreads and inline
+/// fragments are expanded in place, and the fragments are also listed separately. The view is
+/// computed once per request.
+pub(super) fn operation(context: &Context, operation_name: Option<&str>) -> Dynamic {
+    let mut extensions = context.extensions().lock();
+    if let Some(cached) = extensions.get::<OperationView>() {
+        if cached.operation_name.as_deref() == operation_name {
+            return cached.view.clone();
+        }
+    }
+    let Some(document) = extensions.get::<ParsedDocument>().cloned() else {
+        return Dynamic::UNIT;
+    };
+    let view = operation_view(&document, operation_name);
+    extensions.insert(OperationView {
+        operation_name: operation_name.map(str::to_string),
+        view: view.clone(),
+    });
+    view
+}
+
+fn operation_view(document: &ParsedDocument, operation_name: Option<&str>) -> Dynamic {
+    let executable: &ExecutableDocument = &document.executable;
+    let Ok(operation) = executable.get_operation(operation_name) else {
+        return Dynamic::UNIT;
+    };
+
+    let mut fields = Array::new();
+    let complete = collect_fields(executable, &operation.selection_set, "", &mut fields);
+
+    let variables: Array = operation
+        .variables
+        .iter()
+        .map(|variable| {
+            let mut view = Map::new();
+            view.insert("name".into(), variable.name.to_string().into());
+            view.insert("type".into(), variable.ty.to_string().into());
+            view.into()
+        })
+        .collect();
+
+    let fragments: Array = executable
+        .fragments
+        .values()
+        .map(|fragment| {
+            let mut view = Map::new();
+            view.insert("name".into(), fragment.name.to_string().into());
+            view.insert(
+                "type_condition".into(),
+                fragment.type_condition().to_string().into(),
+            );
+            view.insert("directives".into(), directives(&fragment.directives));
+            view.into()
+        })
+        .collect();
+
+    let mut view = Map::new();
+    view.insert(
+        "name".into(),
+        operation
+            .name
+            .as_ref()
+            .map_or(Dynamic::UNIT, |name| name.to_string().into()),
+    );
+    view.insert(
+        "kind".into(),
+        operation.operation_type.name().to_string().into(),
+    );
+    view.insert("variables".into(), variables.into());
+    view.insert("directives".into(), directives(&operation.directives));
+    view.insert("fields".into(), fields.into());
+    view.insert("fields_truncated".into(), (!complete).into());
+    view.insert("fragments".into(), fragments.into());
+    view.into()
+}
+
+/// Returns `false` if the fields were truncated to [`MAX_FIELDS`]
+fn collect_fields(
+    document: &ExecutableDocument,
+    selection_set: &SelectionSet,
+    parent_path: &str,
+    fields: &mut Array,
+) -> bool {
+    for selection in &selection_set.selections {
+        let complete = match selection {
+            Selection::Field(field) => {
+                if fields.len() >= MAX_FIELDS {
+                    return false;
+                }
+                let path = if parent_path.is_empty() {
+                    field.response_key().to_string()
+                } else {
+                    format!("{parent_path}.{}", field.response_key())
+                };
+                let mut view = Map::new();
+                view.insert("name".into(), field.name.to_string().into());
+                view.insert(
+                    "alias".into(),
+                    field
+                        .alias
+                        .as_ref()
+                        .map_or(Dynamic::UNIT, |alias| alias.to_string().into()),
+                );
+                view.insert("path".into(), path.clone().into());
+                view.insert("parent_type".into(), selection_set.ty.to_string().into());
+                view.insert("type".into(), field.ty().to_string().into());
+                view.insert("arguments".into(), arguments(&field.arguments));
+                view.insert("directives".into(), directives(&field.directives));
+                fields.push(view.into());
+
+                collect_fields(document, &field.selection_set, &path, fields)
+            }
+            Selection::FragmentSpread(spread) => {
+                match document.fragments.get(&spread.fragment_name) {
+                    Some(fragment) => {
+                        collect_fields(document, &fragment.selection_set, parent_path, fields)
+                    }
+                    None => true,
+                }
+            }
+            Selection::InlineFragment(inline) => {
+                collect_fields(document, &inline.selection_set, parent_path, fields)
+            }
+        };
+        if !complete {
+            return false;
+        }
+    }
+    true
+}
+
+fn arguments(arguments: &[Node<executable::Argument>]) -> Dynamic {
+    arguments
+        .iter()
+        .map(|argument| (argument.name.as_str().into(), value(&argument.value)))
+        .collect::<Map>()
+        .into()
+}
+
+fn directives(directives: &executable::DirectiveList) -> Dynamic {
+    directives
+        .iter()
+        .map(|directive| {
+            let mut view = Map::new();
+            view.insert("name".into(), directive.name.to_string().into());
+            view.insert("arguments".into(), arguments(&directive.arguments));
+            Dynamic::from(view)
+        })
+        .collect::<Array>()
+        .into()
+}
+
+// Variables are represented as a map with a single `variable` key, to distinguish them from
+// strings.
+fn value(value: &executable::Value) -> Dynamic {
+    match value {
+        executable::Value::Null => Dynamic::UNIT,
+        executable::Value::Enum(value) => value.to_string().into(),
+        executable::Value::Variable(name) => {
+            let mut variable = Map::new();
+            variable.insert("variable".into(), name.to_string().into());
+            variable.into()
+        }
+        executable::Value::String(value) => value.as_str().to_string().into(),
+        executable::Value::Float(value) => value
+            .try_to_f64()
+            .map_or_else(|_| value.as_str().to_string().into(), Dynamic::from),
+        executable::Value::Int(value) => value
+            .as_str()
+            .parse::<i64>()
+            .map_or_else(|_| value.as_str().to_string().into(), Dynamic::from),
+        executable::Value::Boolean(value) => (*value).into(),
+        executable::Value::List(values) => values
+            .iter()
+            .map(|item| self::value(item))
+            .collect::<Array>()
+            .into(),
+        executable::Value::Object(fields) => fields
+            .iter()
+            .map(|(name, item)| (name.as_str().into(), self::value(item)))
+            .collect::<Map>()
+            .into(),
+    }
+}
+
+/// Returns a view of the query plan, with its tree of nodes and the subgraphs it fetches from
+///
+/// The nodes have the same structure as the query plan sent to coprocessors.
+pub(super) fn plan(plan: &QueryPlan) -> Result<Dynamic, Box<EvalAltResult>> {
+    let subgraphs: BTreeSet<&str> = plan.root.service_usage().collect();
+
+    let mut view = Map::new();
+    view.insert("root".into(), to_dynamic(&plan.root)?);
+    view.insert(
+        "subgraphs".into(),
+        subgraphs
+            .into_iter()
+            .map(|subgraph| Dynamic::from(subgraph.to_string()))
+            .collect::<Array>()
+            .into(),
+    );
+    Ok(view.into())
+}
@@ -0,0 +1,34 @@
+//! query planner module
+
+use std::ops::ControlFlow;
+
+use tower::BoxError;
+
+use super::ErrorDetails;
+use crate::graphql::Error;
+pub(crate) use crate::services::query_planner::*;
+use crate::Context;
+
+// The query plan is not produced, and the client receives the errors instead. The status code
+// of the error details is not used.
+fn rejection(context: Context, error_details: ErrorDetails) -> Response {
+    let errors = match error_details.body {
+        Some(body) if !body.errors.is_empty() => body.errors,
+        _ => vec![Error {
+            message: error_details.message.unwrap_or_default(),
+            ..Default::default()
+        }],
+    };
+    Response::builder().context(context).errors(errors).build()
+}
+
+pub(super) fn request_failure(
+    context: Context,
+    error_details: ErrorDetails,
+) -> Result<ControlFlow<Response, Request>, BoxError> {
+    Ok(ControlFlow::Break(rejection(context, error_details)))
+}
+
+pub(super) fn response_failure(context: Context, error_details: ErrorDetails) -> Response {
+    rejection(context, error_details)
+}
@@ -0,0 +1,332 @@
+//! state module
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::sync::Weak;
+use std::time::Duration;
+use std::time::Instant;
+
+use lru::LruCache;
+use rhai::serde::from_dynamic;
+use rhai::serde::to_dynamic;
+use rhai::Dynamic;
+use rhai::EvalAltResult;
+use rhai::ImmutableString;
+use rhai::Module;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Digest;
+use sha2::Sha256;
+use tokio::runtime::Handle;
+use tokio::runtime::RuntimeFlavor;
+use tower::BoxError;
+
+use crate::cache::redis::RedisCacheStorage;
+use crate::cache::redis::RedisKey;
+use crate::cache::redis::RedisValue;
+use crate::configuration::RedisCache;
+
+/// Configuration of the state shared by Rhai scripts
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct Conf {
+    /// The maximum number of entries kept in memory (default: 10000).
+    /// The least recently used entries are evicted first.
+    max_entries: NonZeroUsize,
+    /// Clear the state when the schema changes (default: false)
+    clear_on_schema_change: bool,
+    /// Store the state in Redis instead of in memory, to share it between router instances
+    redis: Option<RedisCache>,
+}
+
+impl Default for Conf {
+    fn default() -> Self {
+        Self {
+            max_entries: NonZeroUsize::new(10_000).expect("not zero; qed"),
+            clear_on_schema_change: false,
+            redis: None,
+        }
+    }
+}
+
+struct Entry {
+    value: Value,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| expires_at <= Instant::now())
+    }
+}
+
+struct MemoryStore {
+    schema_hash: Mutex<String>,
+    entries: Mutex<LruCache<String, Entry>>,
+}
+
+impl MemoryStore {
+    fn new(max_entries: NonZeroUsize) -> Self {
+        Self {
+            schema_hash: Default::default(),
+            entries: Mutex::new(LruCache::new(max_entries)),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<Value> {
+        let mut entries = self.entries.lock().expect("lock poisoned");
+        match entries.get(key) {
+            Some(entry) if entry.is_expired() => {
+                entries.pop(key);
+                None
+            }
+            Some(entry) => Some(entry.value.clone()),
+            None => None,
+        }
+    }
+
+    fn set(&self, key: String, value: Value, ttl: Option<Duration>) {
+        let expires_at = ttl.map(|ttl| Instant::now() + ttl);
+        self.entries
+            .lock()
+            .expect("lock poisoned")
+            .put(key, Entry { value, expires_at });
+    }
+
+    /// Like the Redis backend, the `ttl` is applied whenever the key has no expiration yet
+    fn incr(&self, key: &str, by: i64, ttl: Option<Duration>) -> Result<i64, String> {
+        let expires_at = ttl.map(|ttl| Instant::now() + ttl);
+        let mut entries = self.entries.lock().expect("lock poisoned");
+        match entries.get_mut(key) {
+            Some(entry) if !entry.is_expired() => {
+                let value = entry
+                    .value
+                    .as_i64()
+                    .ok_or_else(|| format!("the value of {key} is not an integer"))?
+                    .checked_add(by)
+                    .ok_or_else(|| format!("the value of {key} overflowed"))?;
+                entry.value = value.into();
+                entry.expires_at = entry.expires_at.or(expires_at);
+                Ok(value)
+            }
+            _ => {
+                entries.put(
+                    key.to_string(),
+                    Entry {
+                        value: by.into(),
+                        expires_at,
+                    },
+                );
+                Ok(by)
+            }
+        }
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        self.entries
+            .lock()
+            .expect("lock poisoned")
+            .pop(key)
+            .map_or(false, |entry| !entry.is_expired())
+    }
+}
+
+// In memory stores are kept by main script, so that they survive the creation of a new Rhai
+// plugin when the schema or the configuration changes. The new plugin is created while the
+// previous one is still alive, so weak references are enough.
+static MEMORY_STORES: OnceLock<Mutex<HashMap<PathBuf, Weak<MemoryStore>>>> = OnceLock::new();
+
+enum Backend {
+    Memory(Arc<MemoryStore>),
+    Redis(RedisCacheStorage),
+}
+
+/// State shared by all the invocations of a Rhai script, through the `state` module
+///
+/// This is shared by all the engines of a Rhai plugin, so the state survives script reloads.
+pub(crate) struct RhaiState {
+    prefix: String,
+    backend: Backend,
+}
+
+impl Default for RhaiState {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            backend: Backend::Memory(Arc::new(MemoryStore::new(Conf::default().max_entries))),
+        }
+    }
+}
+
+impl RhaiState {
+    pub(crate) async fn new(conf: Conf, main: &Path, sdl: &str) -> Result<Self, BoxError> {
+        let schema_hash = hex::encode(Sha256::digest(sdl.as_bytes()));
+
+        if let Some(redis) = conf.redis {
+            // Keys are isolated per script. Changing the prefix when the schema changes makes
+            // the previous entries unreachable, and they expire with their TTL.
+            let mut prefix = format!("rhai:{}:", main.display());
+            if conf.clear_on_schema_change {
+                prefix.push_str(&schema_hash);
+                prefix.push(':');
+            }
+            let required_to_start = redis.required_to_start;
+            match RedisCacheStorage::new(redis).await {
+                Ok(storage) => {
+                    return Ok(Self {
+                        prefix,
+                        backend: Backend::Redis(storage),
+                    })
+                }
+                Err(e) => {
+                    tracing::error!(
+                        e,
+                        "could not open connection to Redis for the Rhai state, keeping it in memory",
+                    );
+                    if required_to_start {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        let mut stores = MEMORY_STORES
+            .get_or_init(Default::default)
+            .lock()
+            .expect("lock poisoned");
+        stores.retain(|_, store| store.strong_count() > 0);
+        let store = match stores.get(main).and_then(Weak::upgrade) {
+            Some(store)
+                if store.entries.lock().expect("lock poisoned").cap() == conf.max_entries =>
+            {
+                store
+            }
+            _ => {
+                let store = Arc::new(MemoryStore::new(conf.max_entries));
+                stores.insert(main.to_path_buf(), Arc::downgrade(&store));
+                store
+            }
+        };
+        let mut previous_hash = store.schema_hash.lock().expect("lock poisoned");
+        if conf.clear_on_schema_change && !previous_hash.is_empty() && *previous_hash != schema_hash
+        {
+            store.entries.lock().expect("lock poisoned").clear();
+        }
+        *previous_hash = schema_hash;
+        drop(previous_hash);
+
+        Ok(Self {
+            prefix: String::new(),
+            backend: Backend::Memory(store),
+        })
+    }
+
+    /// Creates the `state` Rhai module, with the `get`, `set`, `incr` and `delete` functions
+    pub(crate) fn module(self: &Arc<Self>) -> Module {
+        let mut module = Module::new();
+        let state = self.clone();
+        module.set_native_fn("get", move |key: ImmutableString| state.get(&key));
+        let state = self.clone();
+        module.set_native_fn("set", move |key: ImmutableString, value: Dynamic| {
+            state.set(&key, value, None)
+        });
+        let state = self.clone();
+        module.set_native_fn(
+            "set",
+            move |key: ImmutableString, value: Dynamic, ttl: i64| {
+                state.set(&key, value, Some(ttl_from_secs(ttl)?))
+            },
+        );
+        let state = self.clone();
+        module.set_native_fn("incr", move |key: ImmutableString| {
+            state.incr(&key, 1, None)
+        });
+        let state = self.clone();
+        module.set_native_fn("incr", move |key: ImmutableString, by: i64| {
+            state.incr(&key, by, None)
+        });
+        let state = self.clone();
+        module.set_native_fn("incr", move |key: ImmutableString, by: i64, ttl: i64| {
+            state.incr(&key, by, Some(ttl_from_secs(ttl)?))
+        });
+        let state = self.clone();
+        module.set_native_fn("delete", move |key: ImmutableString| state.delete(&key));
+        module
+    }
+
+    fn get(&self, key: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+        let value = match &self.backend {
+            Backend::Memory(store) => store.get(key),
+            Backend::Redis(storage) => {
+                block_on(storage.get::<String, Value>(RedisKey(format!("{}{key}", self.prefix))))?
+                    .map(|value| value.0)
+            }
+        };
+        value.map_or(Ok(Dynamic::UNIT), to_dynamic)
+    }
+
+    fn set(
+        &self,
+        key: &str,
+        value: Dynamic,
+        ttl: Option<Duration>,
+    ) -> Result<(), Box<EvalAltResult>> {
+        let value: Value = from_dynamic(&value)?;
+        match &self.backend {
+            Backend::Memory(store) => store.set(key.to_string(), value, ttl),
+            Backend::Redis(storage) => block_on(storage.insert(
+                RedisKey(format!("{}{key}", self.prefix)),
+                RedisValue(value),
+                ttl,
+            ))?,
+        }
+        Ok(())
+    }
+
+    fn incr(&self, key: &str, by: i64, ttl: Option<Duration>) -> Result<i64, Box<EvalAltResult>> {
+        match &self.backend {
+            Backend::Memory(store) => Ok(store.incr(key, by, ttl)?),
+            Backend::Redis(storage) => {
+                block_on(storage.incr(RedisKey(format!("{}{key}", self.prefix)), by, ttl))?
+                    .ok_or_else(|| format!("could not increment {key} in Redis").into())
+            }
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<bool, Box<EvalAltResult>> {
+        match &self.backend {
+            Backend::Memory(store) => Ok(store.delete(key)),
+            Backend::Redis(storage) => Ok(block_on(
+                storage.delete(RedisKey(format!("{}{key}", self.prefix))),
+            )?
+            .map_or(false, |count| count > 0)),
+        }
+    }
+}
+
+fn ttl_from_secs(ttl: i64) -> Result<Duration, Box<EvalAltResult>> {
+    u64::try_from(ttl)
+        .ok()
+        .filter(|ttl| *ttl > 0)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("the TTL must be a positive number of seconds, got {ttl}").into())
+}
+
+// Rhai functions are synchronous, so Redis calls block the worker thread until they complete,
+// within the Redis timeout.
+fn block_on<F: Future>(future: F) -> Result<F::Output, Box<EvalAltResult>> {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            Ok(tokio::task::block_in_place(|| handle.block_on(future)))
+        }
+        _ => Err("the Redis state is not available outside of request processing".into()),
+    }
+}
@@ -1,8 +1,11 @@
 //! Rhai module tests.
 
+use std::collections::HashMap;
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 use std::time::SystemTime;
 
 use http::HeaderMap;
@@ -18,21 +21,32 @@ use tower::Service;
 use tower::ServiceExt;
 use uuid::Uuid;
 
+use super::jwt::SharedJwksManager;
+use super::metrics::RhaiMetrics;
 use super::process_error;
+use super::state::RhaiState;
 use super::subgraph;
 use super::PathBuf;
 use super::Rhai;
 use crate::graphql::Error;
 use crate::graphql::Request;
 use crate::http_ext;
+use crate::metrics::FutureMetricsExt;
 use crate::plugin::test::MockExecutionService;
 use crate::plugin::test::MockSupergraphService;
 use crate::plugin::DynPlugin;
+use crate::plugins::authentication::jwks::parse_jwks;
+use crate::plugins::authentication::jwks::JwksConfig;
+use crate::plugins::authentication::jwks::JwksManager;
 use crate::plugins::rhai::engine::RhaiExecutionDeferredResponse;
 use crate::plugins::rhai::engine::RhaiExecutionResponse;
 use crate::plugins::rhai::engine::RhaiSupergraphDeferredResponse;
 use crate::plugins::rhai::engine::RhaiSupergraphResponse;
+use crate::query_planner::QueryPlan;
 use crate::services::ExecutionRequest;
+use crate::services::QueryPlannerContent;
+use crate::services::QueryPlannerRequest;
+use crate::services::QueryPlannerResponse;
 use crate::services::SubgraphRequest;
 use crate::services::SupergraphRequest;
 use crate::services::SupergraphResponse;
@@ -154,10 +168,87 @@ async fn rhai_plugin_execution_service_error() -> Result<(), BoxError> {
     Ok(())
 }
 
+#[tokio::test]
+async fn rhai_plugin_query_planner_service() -> Result<(), BoxError> {
+    let planner = tower::service_fn(|request: QueryPlannerRequest| async move {
+        assert_eq!("query Me { me { id __typename } }", request.query);
+        let mut plan = QueryPlan::fake_builder().build();
+        plan.formatted_query_plan = Some("QueryPlan {}".to_string());
+        Ok::<_, BoxError>(
+            QueryPlannerResponse::builder()
+                .content(QueryPlannerContent::Plan {
+                    plan: Arc::new(plan),
+                })
+                .context(request.context)
+                .build(),
+        )
+    });
+
+    let dyn_plugin: Box<dyn DynPlugin> = crate::plugin::plugins()
+        .find(|factory| factory.name == "apollo.rhai")
+        .expect("Plugin not found")
+        .create_instance_without_schema(
+            &Value::from_str(r#"{"scripts":"tests/fixtures", "main":"test.rhai"}"#).unwrap(),
+        )
+        .await
+        .unwrap();
+
+    let response = dyn_plugin
+        .query_planner_service(BoxService::new(planner))
+        .oneshot(
+            QueryPlannerRequest::builder()
+                .query("query Me { me { id } }".to_string())
+                .operation_name("Me".to_string())
+                .context(Context::new())
+                .build(),
+        )
+        .await?;
+    assert!(response.errors.is_empty());
+    assert_eq!(
+        response
+            .context
+            .get::<_, String>("query_plan")
+            .unwrap()
+            .unwrap(),
+        "QueryPlan {}"
+    );
+
+    // Throwing an error rejects the operation before it is planned
+    let response = dyn_plugin
+        .query_planner_service(BoxService::new(tower::service_fn(
+            |_request: QueryPlannerRequest| async {
+                Err::<QueryPlannerResponse, BoxError>(
+                    crate::error::QueryPlannerError::UnhandledPlannerResult.into(),
+                )
+            },
+        )))
+        .oneshot(
+            QueryPlannerRequest::builder()
+                .query("query Forbidden { me { id } }".to_string())
+                .operation_name("Forbidden".to_string())
+                .context(Context::new())
+                .build(),
+        )
+        .await?;
+    assert!(response.content.is_none());
+    assert_eq!(
+        response.errors[0].message,
+        "rhai execution error: 'Runtime error: this operation is not allowed (line 48, position 9)\nin call to function 'query_planner_request''"
+    );
+    Ok(())
+}
+
 // A Rhai engine suitable for minimal testing. There are no scripts and the SDL is an empty
 // string.
 fn new_rhai_test_engine() -> Engine {
-    Rhai::new_rhai_engine(None, "".to_string(), PathBuf::new())
+    Rhai::new_rhai_engine(
+        None,
+        "".to_string(),
+        PathBuf::new(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+    )
 }
 
 // Some of these tests rely extensively on internal implementation details of the tracing_test crate.
@@ -223,6 +314,226 @@ fn it_prints_messages_to_log() {
     ));
 }
 
+#[tokio::test]
+async fn it_records_metrics() {
+    async {
+        let engine = new_rhai_test_engine();
+        let scripts = vec![
+            r#"metrics::counter("rhai.requests", 1, #{ "client": "web" })"#,
+            r#"metrics::counter("rhai.requests", 2.5, #{ "client": "web" })"#,
+            r#"metrics::histogram("rhai.latency", 10)"#,
+            r#"metrics::gauge("rhai.queue", 3)"#,
+            r#"metrics::gauge("rhai.queue", 5)"#,
+        ];
+        for script in scripts {
+            engine.eval::<()>(script).expect("it recorded a metric");
+        }
+        assert_counter!("rhai.requests", 3.5, "client" = "web");
+        assert_histogram_sum!("rhai.latency", 10.0);
+        assert_gauge!("rhai.queue", 5.0);
+    }
+    .with_metrics()
+    .await;
+}
+
+#[tokio::test]
+async fn it_rejects_invalid_metrics() {
+    async {
+        let engine = new_rhai_test_engine();
+        let scripts = vec![
+            r#"metrics::counter("apollo.router.requests", 1)"#,
+            r#"metrics::counter("rhai requests", 1)"#,
+            r#"metrics::counter("rhai.requests", -1)"#,
+            r#"metrics::counter("rhai.requests", "one")"#,
+            r#"metrics::counter("rhai.requests", 1, #{ "client": [] })"#,
+        ];
+        for script in scripts {
+            assert!(engine.eval::<()>(script).is_err(), "{script} should fail");
+        }
+
+        engine
+            .eval::<()>(r#"metrics::counter("rhai.requests", 1)"#)
+            .expect("it recorded a counter");
+        let error = engine
+            .eval::<()>(r#"metrics::gauge("rhai.requests", 1)"#)
+            .expect_err("a counter is not a gauge");
+        assert!(error
+            .to_string()
+            .contains("metric rhai.requests is a counter, not a gauge"));
+    }
+    .with_metrics()
+    .await;
+}
+
+#[tokio::test]
+async fn it_limits_metrics_cardinality() {
+    async {
+        let conf = serde_json::from_value(serde_json::json!({
+            "max_metrics": 1,
+            "max_attribute_sets": 1,
+        }))
+        .unwrap();
+        let engine = Rhai::new_rhai_engine(
+            None,
+            "".to_string(),
+            PathBuf::new(),
+            Arc::new(RhaiMetrics::new(conf)),
+            Default::default(),
+            Default::default(),
+        );
+        let scripts = vec![
+            r#"metrics::counter("rhai.requests", 1, #{ "client": "web" })"#,
+            // dropped: too many attribute sets
+            r#"metrics::counter("rhai.requests", 1, #{ "client": "ios" })"#,
+            // dropped: too many metrics
+            r#"metrics::counter("rhai.other", 1)"#,
+            r#"metrics::counter("rhai.requests", 1, #{ "client": "web" })"#,
+        ];
+        for script in scripts {
+            engine.eval::<()>(script).expect("it accepted the metric");
+        }
+        assert_counter!("rhai.requests", 2.0, "client" = "web");
+        let metrics = crate::metrics::collect_metrics();
+        assert!(metrics.find("rhai.other").is_none());
+    }
+    .with_metrics()
+    .await;
+}
+
+#[test]
+fn it_shares_state() {
+    let engine = new_rhai_test_engine();
+    let mut scope = rhai::Scope::new();
+    let get = |scope: &mut rhai::Scope, script: &str| {
+        engine
+            .eval_with_scope::<rhai::Dynamic>(scope, script)
+            .expect("can use the state")
+    };
+
+    assert!(get(&mut scope, r#"state::get("flags")"#).is_unit());
+    let _ = get(&mut scope, r#"state::set("flags", #{ "beta": true })"#);
+    assert!(get(&mut scope, r#"state::get("flags").beta"#).cast::<bool>());
+
+    assert_eq!(get(&mut scope, r#"state::incr("hits")"#).cast::<i64>(), 1);
+    assert_eq!(
+        get(&mut scope, r#"state::incr("hits", 2)"#).cast::<i64>(),
+        3
+    );
+    assert!(engine
+        .eval_with_scope::<i64>(&mut scope, r#"state::incr("flags")"#)
+        .is_err());
+
+    assert!(get(&mut scope, r#"state::delete("hits")"#).cast::<bool>());
+    assert!(!get(&mut scope, r#"state::delete("hits")"#).cast::<bool>());
+    assert!(engine
+        .eval_with_scope::<()>(&mut scope, r#"state::set("expired", 1, 0)"#)
+        .is_err());
+}
+
+async fn check_incr_expiration(state: RhaiState) {
+    let engine = Rhai::new_rhai_engine(
+        None,
+        "".to_string(),
+        PathBuf::new(),
+        Default::default(),
+        Arc::new(state),
+        Default::default(),
+    );
+    let run = |script: &str| {
+        engine
+            .eval::<rhai::Dynamic>(script)
+            .expect("can use the state")
+    };
+    let _ = run(r#"state::delete("no_ttl"); state::delete("ttl")"#);
+
+    // A key created without expiration is given one by the next increment with a TTL
+    assert_eq!(run(r#"state::incr("no_ttl")"#).cast::<i64>(), 1);
+    assert_eq!(run(r#"state::incr("no_ttl", 1, 1)"#).cast::<i64>(), 2);
+    // An existing expiration is kept
+    assert_eq!(run(r#"state::incr("ttl", 1, 1)"#).cast::<i64>(), 1);
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(run(r#"state::incr("ttl", 1, 60)"#).cast::<i64>(), 2);
+    tokio::time::sleep(Duration::from_millis(600)).await;
+
+    assert!(run(r#"state::get("no_ttl")"#).is_unit());
+    assert!(run(r#"state::get("ttl")"#).is_unit());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn it_expires_incremented_keys_in_memory() {
+    check_incr_expiration(RhaiState::default()).await;
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[tokio::test(flavor = "multi_thread")]
+async fn it_expires_incremented_keys_in_redis() {
+    let conf = serde_json::from_value(serde_json::json!({
+        "redis": { "urls": ["redis://127.0.0.1:6379"], "required_to_start": true }
+    }))
+    .unwrap();
+    let state = RhaiState::new(
+        conf,
+        Path::new("it_expires_incremented_keys_in_redis.rhai"),
+        "type Query { a: Int }",
+    )
+    .await
+    .expect("can connect to Redis");
+    check_incr_expiration(state).await;
+}
+
+#[tokio::test]
+async fn it_keeps_state_across_schema_changes() {
+    let main = PathBuf::from("it_keeps_state_across_schema_changes.rhai");
+    let run = |state: &Arc<RhaiState>, script: &str| {
+        let engine = Rhai::new_rhai_engine(
+            None,
+            "".to_string(),
+            PathBuf::new(),
+            Default::default(),
+            state.clone(),
+            Default::default(),
+        );
+        engine
+            .eval::<rhai::Dynamic>(script)
+            .expect("can use the state")
+    };
+
+    let conf = || serde_json::from_value(serde_json::json!({})).unwrap();
+    let first = Arc::new(
+        RhaiState::new(conf(), &main, "type Query { a: Int }")
+            .await
+            .unwrap(),
+    );
+    let _ = run(&first, r#"state::set("key", "value")"#);
+    let second = Arc::new(
+        RhaiState::new(conf(), &main, "type Query { b: Int }")
+            .await
+            .unwrap(),
+    );
+    assert_eq!(
+        run(&second, r#"state::get("key")"#).cast::<String>(),
+        "value"
+    );
+
+    let conf =
+        || serde_json::from_value(serde_json::json!({ "clear_on_schema_change": true })).unwrap();
+    let third = Arc::new(
+        RhaiState::new(conf(), &main, "type Query { b: Int }")
+            .await
+            .unwrap(),
+    );
+    assert_eq!(
+        run(&third, r#"state::get("key")"#).cast::<String>(),
+        "value"
+    );
+    let fourth = Arc::new(
+        RhaiState::new(conf(), &main, "type Query { c: Int }")
+            .await
+            .unwrap(),
+    );
+    assert!(run(&fourth, r#"state::get("key")"#).is_unit());
+}
+
 #[tokio::test]
 async fn it_can_access_sdl_constant() {
     let dyn_plugin: Box<dyn DynPlugin> = crate::plugin::plugins()
@@ -595,6 +906,284 @@ fn it_can_sha256_string() {
     assert_eq!(hash_rhai, hex::encode(hash));
 }
 
+#[test]
+fn it_can_hmac_strings() {
+    let engine = new_rhai_test_engine();
+    let sha256: String = engine
+        .eval(r#"crypto::hmac_sha256("key", "The quick brown fox jumps over the lazy dog")"#)
+        .expect("can compute a HMAC");
+    assert_eq!(
+        sha256,
+        "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+    );
+    let sha256_base64: String = engine
+        .eval(r#"crypto::hmac_sha256("key", "The quick brown fox jumps over the lazy dog", base64::STANDARD)"#)
+        .expect("can compute a HMAC");
+    assert_eq!(
+        sha256_base64,
+        "97yD9DBThCSxMpjmqm+xQ+9NWaFJRhdZl0edvC0aPNg="
+    );
+    let sha512: String = engine
+        .eval(r#"crypto::hmac_sha512("key", "The quick brown fox jumps over the lazy dog")"#)
+        .expect("can compute a HMAC");
+    assert_eq!(
+        sha512,
+        "b42af09057bac1e2d41708e48a902e09b5ff7f12ab428a4fe86653c73dd248fb82f948a549f7b791a5b41915ee4d1ec3935357e4e2317250d0372afa2ebeeb3a"
+    );
+}
+
+#[test]
+fn it_can_compare_strings_in_constant_time() {
+    let engine = new_rhai_test_engine();
+    assert!(engine
+        .eval::<bool>(r#"crypto::constant_time_eq("signature", "signature")"#)
+        .expect("can compare strings"));
+    assert!(!engine
+        .eval::<bool>(r#"crypto::constant_time_eq("signature", "signaturf")"#)
+        .expect("can compare strings"));
+    assert!(!engine
+        .eval::<bool>(r#"crypto::constant_time_eq("signature", "sig")"#)
+        .expect("can compare strings"));
+}
+
+#[test]
+fn it_can_generate_random_bytes() {
+    let engine = new_rhai_test_engine();
+    let first: String = engine
+        .eval(r#"crypto::random_bytes(16)"#)
+        .expect("can generate random bytes");
+    let second: String = engine
+        .eval(r#"crypto::random_bytes(16)"#)
+        .expect("can generate random bytes");
+    assert_eq!(hex::decode(&first).expect("it is hex encoded").len(), 16);
+    assert_ne!(first, second);
+    let encoded: String = engine
+        .eval(r#"crypto::random_bytes(16, base64::URL_SAFE_NO_PAD)"#)
+        .expect("can generate random bytes");
+    assert_eq!(encoded.len(), 22);
+    assert!(engine
+        .eval::<String>(r#"crypto::random_bytes(4096)"#)
+        .is_err());
+}
+
+fn hs256_token(claims: serde_json::Value) -> String {
+    let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS256);
+    header.kid = Some("key1".to_string());
+    jsonwebtoken::encode(
+        &header,
+        &claims,
+        &jsonwebtoken::EncodingKey::from_secret(b"secret\n"),
+    )
+    .unwrap()
+}
+
+#[test]
+fn it_can_decode_jwt() {
+    let engine = new_rhai_test_engine();
+    let token = hs256_token(serde_json::json!({ "sub": "user", "exp": 0 }));
+    let mut scope = rhai::Scope::new();
+    scope.push("token", token);
+    let decoded: rhai::Map = engine
+        .eval_with_scope(&mut scope, "jwt::decode(token)")
+        .expect("can decode a JWT");
+    let claims = decoded["claims"].clone_cast::<rhai::Map>();
+    assert_eq!(claims["sub"].clone_cast::<String>(), "user");
+    let header = decoded["header"].clone_cast::<rhai::Map>();
+    assert_eq!(header["kid"].clone_cast::<String>(), "key1");
+}
+
+#[test]
+fn it_can_verify_jwt() {
+    let url = url::Url::parse("file:///jwks.json").unwrap();
+    let jwks = parse_jwks(include_str!("../authentication/testdata/jwks.json")).unwrap();
+    let jwks_manager = JwksManager::new_test(
+        vec![JwksConfig {
+            url: url.clone(),
+            issuer: None,
+            algorithms: None,
+            poll_interval: Duration::from_secs(60),
+            headers: Vec::new(),
+        }],
+        HashMap::from([(url, jwks)]),
+    );
+    let shared_jwks_manager = SharedJwksManager::default();
+    let engine = Rhai::new_rhai_engine(
+        None,
+        "".to_string(),
+        PathBuf::new(),
+        Default::default(),
+        Default::default(),
+        shared_jwks_manager.clone(),
+    );
+    let valid = hs256_token(serde_json::json!({ "sub": "user", "exp": u32::MAX }));
+    let expired = hs256_token(serde_json::json!({ "sub": "user", "exp": 0 }));
+    let mut scope = rhai::Scope::new();
+    scope.push("valid", valid);
+    scope.push("expired", expired);
+
+    // JWT authentication is not configured
+    assert!(engine
+        .eval_with_scope::<rhai::Map>(&mut scope, "jwt::verify(valid)")
+        .is_err());
+
+    shared_jwks_manager.store(Some(Arc::new(jwks_manager)));
+    let claims: rhai::Map = engine
+        .eval_with_scope(&mut scope, "jwt::verify(valid)")
+        .expect("can verify a JWT");
+    assert_eq!(claims["sub"].clone_cast::<String>(), "user");
+    let error = engine
+        .eval_with_scope::<rhai::Map>(&mut scope, "jwt::verify(expired)")
+        .expect_err("the JWT is expired");
+    assert!(error.to_string().contains("ExpiredSignature"));
+}
+
+#[test]
+fn it_can_read_the_operation() {
+    let configuration = crate::Configuration::default();
+    let schema = crate::spec::Schema::parse_test(
+        include_str!("../../testdata/supergraph.graphql"),
+        &configuration,
+    )
+    .unwrap();
+    let query = "query TopProducts($first: Int) {
+        products: topProducts(first: $first) { ...ProductFields reviews { body } }
+    }
+    fragment ProductFields on Product { upc name @include(if: true) }";
+    let document =
+        crate::spec::Query::parse_document(query, Some("TopProducts"), &schema, &configuration)
+            .unwrap();
+    let request = SupergraphRequest::fake_builder()
+        .query(query)
+        .operation_name("TopProducts")
+        .build()
+        .unwrap();
+    request
+        .context
+        .extensions()
+        .lock()
+        .insert::<crate::services::layers::query_analysis::ParsedDocument>(document);
+
+    let engine = new_rhai_test_engine();
+    let mut scope = rhai::Scope::new();
+    scope.push("request", Arc::new(Mutex::new(Some(request))));
+    let operation: rhai::Map = engine
+        .eval_with_scope(&mut scope, "request.operation")
+        .expect("can read the operation");
+    assert_eq!(operation["name"].clone_cast::<String>(), "TopProducts");
+    assert_eq!(operation["kind"].clone_cast::<String>(), "query");
+    let variables = operation["variables"].clone_cast::<rhai::Array>();
+    let variable = variables[0].clone_cast::<rhai::Map>();
+    assert_eq!(variable["name"].clone_cast::<String>(), "first");
+    assert_eq!(variable["type"].clone_cast::<String>(), "Int");
+
+    let paths: Vec<String> = engine
+        .eval_with_scope::<rhai::Array>(
+            &mut scope,
+            "request.operation.fields.map(|field| field.path)",
+        )
+        .unwrap()
+        .into_iter()
+        .map(|path| path.cast::<String>())
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            "products",
+            "products.upc",
+            "products.name",
+            "products.reviews",
+            "products.reviews.body"
+        ]
+    );
+    let fields = operation["fields"].clone_cast::<rhai::Array>();
+    let products = fields[0].clone_cast::<rhai::Map>();
+    assert_eq!(products["name"].clone_cast::<String>(), "topProducts");
+    assert_eq!(products["alias"].clone_cast::<String>(), "products");
+    assert_eq!(products["parent_type"].clone_cast::<String>(), "Query");
+    assert_eq!(products["type"].clone_cast::<String>(), "[Product]");
+    let first = products["arguments"].clone_cast::<rhai::Map>()["first"].clone_cast::<rhai::Map>();
+    assert_eq!(first["variable"].clone_cast::<String>(), "first");
+    let name = fields[2].clone_cast::<rhai::Map>();
+    let directive = name["directives"].clone_cast::<rhai::Array>()[0].clone_cast::<rhai::Map>();
+    assert_eq!(directive["name"].clone_cast::<String>(), "include");
+    assert!(directive["arguments"].clone_cast::<rhai::Map>()["if"].clone_cast::<bool>());
+
+    let fragments = operation["fragments"].clone_cast::<rhai::Array>();
+    let fragment = fragments[0].clone_cast::<rhai::Map>();
+    assert_eq!(fragment["name"].clone_cast::<String>(), "ProductFields");
+    assert_eq!(fragment["type_condition"].clone_cast::<String>(), "Product");
+    assert!(!operation["fields_truncated"].clone_cast::<bool>());
+}
+
+#[test]
+fn it_caps_the_fields_of_the_operation() {
+    let configuration = crate::Configuration::default();
+    let schema = crate::spec::Schema::parse_test(
+        include_str!("../../testdata/supergraph.graphql"),
+        &configuration,
+    )
+    .unwrap();
+    // Each fragment spreads the next one twice, so the operation expands to 2^12 fields
+    let mut query = String::from("{ me { ...F0 } }");
+    for i in 0..12 {
+        query.push_str(&format!(
+            " fragment F{i} on User {{ a{i}: id ...F{next} b{i}: id ...F{next} }}",
+            next = i + 1
+        ));
+    }
+    query.push_str(" fragment F12 on User { id }");
+    let document =
+        crate::spec::Query::parse_document(&query, None, &schema, &configuration).unwrap();
+    let request = SupergraphRequest::fake_builder()
+        .query(query)
+        .build()
+        .unwrap();
+    request
+        .context
+        .extensions()
+        .lock()
+        .insert::<crate::services::layers::query_analysis::ParsedDocument>(document);
+
+    let engine = new_rhai_test_engine();
+    let mut scope = rhai::Scope::new();
+    scope.push("request", Arc::new(Mutex::new(Some(request))));
+    let operation: rhai::Map = engine
+        .eval_with_scope(&mut scope, "request.operation")
+        .expect("can read the operation");
+    assert_eq!(operation["fields"].clone_cast::<rhai::Array>().len(), 1000);
+    assert!(operation["fields_truncated"].clone_cast::<bool>());
+}
+
+#[test]
+fn it_can_read_the_query_plan() {
+    let root: crate::query_planner::PlanNode =
+        serde_json::from_str(include_str!("../../query_planner/testdata/query_plan.json")).unwrap();
+    let request = ExecutionRequest::fake_builder()
+        .query_plan(QueryPlan::fake_builder().root(root).build())
+        .build();
+
+    let engine = new_rhai_test_engine();
+    let mut scope = rhai::Scope::new();
+    scope.push("request", Arc::new(Mutex::new(Some(request))));
+    let plan: rhai::Map = engine
+        .eval_with_scope(&mut scope, "request.plan")
+        .expect("can read the query plan");
+    let subgraphs: Vec<String> = plan["subgraphs"]
+        .clone_cast::<rhai::Array>()
+        .into_iter()
+        .map(|subgraph| subgraph.cast::<String>())
+        .collect();
+    assert_eq!(subgraphs, ["books", "product"]);
+    let root = plan["root"].clone_cast::<rhai::Map>();
+    assert_eq!(root["kind"].clone_cast::<String>(), "Sequence");
+
+    // Without a parsed document, there is no operation
+    assert!(engine
+        .eval_with_scope::<rhai::Dynamic>(&mut scope, "request.operation")
+        .unwrap()
+        .is_unit());
+}
+
 async fn base_globals_function(fn_name: &str) -> Result<bool, Box<rhai::EvalAltResult>> {
     let dyn_plugin: Box<dyn DynPlugin> = crate::plugin::plugins()
         .find(|factory| factory.name == "apollo.rhai")
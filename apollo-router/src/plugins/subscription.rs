@@ -26,15 +26,20 @@ use tower::ServiceExt;
 use tracing_futures::Instrument;
 use uuid::Uuid;
 
+use crate::configuration::RedisCache;
 use crate::context::Context;
 use crate::graphql;
 use crate::graphql::Response;
 use crate::json_ext::Object;
 use crate::layers::ServiceBuilderExt;
+use crate::notification::redis::Event;
+use crate::notification::redis::RedisNotify;
 use crate::notification::Notify;
 use crate::notification::NotifyError;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
+use crate::plugins::authentication::APOLLO_AUTHENTICATION_JWT_CLAIMS;
+use crate::plugins::telemetry::CLIENT_NAME;
 use crate::protocols::websocket::WebSocketProtocol;
 use crate::query_planner::OperationKind;
 use crate::register_plugin;
@@ -57,6 +62,7 @@ pub(crate) struct Subscription {
     notify: Notify<String, graphql::Response>,
     callback_hmac_key: Option<String>,
     pub(crate) config: SubscriptionConfig,
+    pub(crate) redis_notify: Option<RedisNotify>,
 }
 
 /// Subscriptions configuration
@@ -74,6 +80,65 @@ pub(crate) struct SubscriptionConfig {
     pub(crate) max_opened_subscriptions: Option<usize>,
     /// It represent the capacity of the in memory queue to know how many events we can keep in a buffer
     pub(crate) queue_capacity: Option<usize>,
+    /// Deduplicate subscriptions between router instances with Redis: a single instance opens each subgraph subscription and shares its events with the other instances.
+    /// The TTL is how long an instance keeps a subscription without renewing its ownership (default: 10s)
+    pub(crate) redis: Option<RedisCache>,
+    /// Limit the number of subscriptions opened at the same time by a single client
+    pub(crate) per_client: Option<ClientLimitsConfig>,
+    /// Close subscriptions that have been opened for longer than this duration, e.g. '1h' (default: no limit)
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "Option<String>")]
+    pub(crate) max_lifetime: Option<Duration>,
+    /// Close subscriptions that did not receive any event during this duration, e.g. '10m' (default: no limit)
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "Option<String>")]
+    pub(crate) idle_timeout: Option<Duration>,
+}
+
+/// Limits applied to each client
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ClientLimitsConfig {
+    /// How clients are identified. Requests without an identifier are only subject to the global limit
+    pub(crate) key: ClientKey,
+    /// Maximum number of subscriptions opened at the same time by a client
+    pub(crate) max_opened_subscriptions: usize,
+}
+
+/// Identifies the client of a subscription
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub(crate) enum ClientKey {
+    /// Value of a request header
+    Header(String),
+    /// Value of a claim of the JWT validated by the authentication plugin
+    Claim(String),
+    /// Client name, as used for telemetry
+    ClientName,
+}
+
+impl ClientKey {
+    /// Returns the identifier of the client which sent this request, if any
+    pub(crate) fn client_id(&self, context: &Context, headers: &http::HeaderMap) -> Option<String> {
+        match self {
+            ClientKey::Header(name) => headers
+                .get(name.as_str())
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            ClientKey::Claim(name) => {
+                let claims: serde_json_bytes::Value = context
+                    .get(APOLLO_AUTHENTICATION_JWT_CLAIMS)
+                    .ok()
+                    .flatten()?;
+                match claims.as_object()?.get(name.as_str())? {
+                    serde_json_bytes::Value::String(value) => Some(value.as_str().to_string()),
+                    serde_json_bytes::Value::Number(value) => Some(value.to_string()),
+                    _ => None,
+                }
+            }
+            ClientKey::ClientName => context.get(CLIENT_NAME).ok().flatten(),
+        }
+    }
 }
 
 impl Default for SubscriptionConfig {
@@ -84,6 +149,10 @@ impl Default for SubscriptionConfig {
             enable_deduplication: true,
             max_opened_subscriptions: None,
             queue_capacity: None,
+            redis: None,
+            per_client: None,
+            max_lifetime: None,
+            idle_timeout: None,
         }
     }
 }
@@ -219,19 +288,47 @@ pub(crate) struct PassthroughMode {
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
-/// WebSocket configuration for a specific subgraph
+/// Passthrough configuration for a specific subgraph
 pub(crate) struct WebSocketConfiguration {
-    /// Path on which WebSockets are listening
+    /// Path on which subscriptions are served, WebSockets or HTTP streams
     #[serde(default)]
     pub(crate) path: Option<String>,
-    /// Which WebSocket GraphQL protocol to use for this subgraph possible values are: 'graphql_ws' | 'graphql_transport_ws' (default: graphql_ws)
+    /// Which GraphQL protocol to use for this subgraph possible values are: 'graphql_ws' | 'graphql_transport_ws' | 'sse' | 'multipart' (default: graphql_ws)
     #[serde(default)]
-    pub(crate) protocol: WebSocketProtocol,
-    /// Heartbeat interval for graphql-ws protocol (default: disabled)
+    pub(crate) protocol: PassthroughProtocol,
+    /// Heartbeat interval for graphql-ws protocol (default: disabled).
+    /// With the 'sse' and 'multipart' protocols, the subscription ends with an error when the subgraph sends nothing, not even a heartbeat, during this interval
     #[serde(default = "HeartbeatInterval::new_disabled")]
     pub(crate) heartbeat_interval: HeartbeatInterval,
 }
 
+/// Protocol used to receive subscription events from a subgraph in passthrough mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PassthroughProtocol {
+    /// WebSocket with the graphql-transport-ws protocol
+    #[default]
+    GraphqlWs,
+    /// WebSocket with the legacy subscriptions-transport-ws protocol
+    #[serde(rename = "graphql_transport_ws")]
+    SubscriptionsTransportWs,
+    /// HTTP with Server-Sent Events, in the distinct connections mode
+    Sse,
+    /// HTTP with multipart subscription responses
+    Multipart,
+}
+
+impl PassthroughProtocol {
+    /// Returns the WebSocket protocol, or None for the protocols over HTTP
+    pub(crate) fn websocket(self) -> Option<WebSocketProtocol> {
+        match self {
+            Self::GraphqlWs => Some(WebSocketProtocol::GraphqlWs),
+            Self::SubscriptionsTransportWs => Some(WebSocketProtocol::SubscriptionsTransportWs),
+            Self::Sse | Self::Multipart => None,
+        }
+    }
+}
+
 fn default_path() -> String {
     String::from("/callback")
 }
@@ -266,10 +363,28 @@ impl Plugin for Subscription {
                 .await?;
         }
 
+        let mut redis_notify = None;
+        if let (Some(redis), true) = (&init.config.redis, init.config.enable_deduplication) {
+            let required_to_start = redis.required_to_start;
+            match RedisNotify::new(redis.clone()).await {
+                Ok(notify) => redis_notify = Some(notify),
+                Err(e) => {
+                    tracing::error!(
+                        e,
+                        "could not open connection to Redis for the deduplication of subscriptions, they are only deduplicated in each router instance",
+                    );
+                    if required_to_start {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
         Ok(Subscription {
             notify: init.notify,
             callback_hmac_key,
             config: init.config,
+            redis_notify,
         })
     }
 
@@ -303,8 +418,13 @@ impl Plugin for Subscription {
                 .expect("cannot run subscription in callback mode without a hmac key");
             let endpoint = Endpoint::from_router_service(
                 format!("{path}/:callback"),
-                CallbackService::new(self.notify.clone(), path.to_string(), callback_hmac_key)
-                    .boxed(),
+                CallbackService::new(
+                    self.notify.clone(),
+                    self.redis_notify.clone(),
+                    path.to_string(),
+                    callback_hmac_key,
+                )
+                .boxed(),
             );
             map.insert(listen.clone().unwrap_or_else(default_listen_addr), endpoint);
         }
@@ -395,6 +515,8 @@ impl SubscriptionPayload {
 #[derive(Clone)]
 pub(crate) struct CallbackService {
     notify: Notify<String, graphql::Response>,
+    /// Relays the callbacks of the subscriptions owned by other router instances
+    redis_notify: Option<RedisNotify>,
     path: String,
     callback_hmac_key: String,
 }
@@ -402,17 +524,27 @@ pub(crate) struct CallbackService {
 impl CallbackService {
     pub(crate) fn new(
         notify: Notify<String, graphql::Response>,
+        redis_notify: Option<RedisNotify>,
         path: String,
         callback_hmac_key: String,
     ) -> Self {
         Self {
             notify,
+            redis_notify,
             path,
             callback_hmac_key,
         }
     }
 }
 
+/// Relays a callback to the router instance owning the subscription, if it is another one
+async fn relay(redis_notify: &Option<RedisNotify>, topic: &str, event: Event) -> bool {
+    match redis_notify {
+        Some(redis_notify) => redis_notify.relay(topic, event).await,
+        None => false,
+    }
+}
+
 impl Service<router::Request> for CallbackService {
     type Response = router::Response;
     type Error = BoxError;
@@ -424,6 +556,7 @@ impl Service<router::Request> for CallbackService {
 
     fn call(&mut self, req: router::Request) -> Self::Future {
         let mut notify = self.notify.clone();
+        let redis_notify = self.redis_notify.clone();
         let path = self.path.clone();
         let callback_hmac_key = self.callback_hmac_key.clone();
         Box::pin(
@@ -496,6 +629,17 @@ impl Service<router::Request> for CallbackService {
                                 mut payload,
                                 ..
                             }) => {
+                                // Keep the subscription to the client opened
+                                payload.subscribed = Some(true);
+                                if relay(&redis_notify, &id, Event::Next { payload: payload.clone() }).await {
+                                    return Ok(router::Response {
+                                        response: http::Response::builder()
+                                            .status(StatusCode::OK)
+                                            .body::<hyper::Body>("".into())
+                                            .map_err(BoxError::from)?,
+                                        context: req.context,
+                                    });
+                                }
                                 let mut handle = match notify.subscribe_if_exist(id).await? {
                                     Some(handle) => handle.into_sink(),
                                     None => {
@@ -508,8 +652,6 @@ impl Service<router::Request> for CallbackService {
                                         });
                                     }
                                 };
-                                // Keep the subscription to the client opened
-                                payload.subscribed = Some(true);
                                 tracing::info!(
                                         monotonic_counter.apollo.router.operations.subscriptions.events = 1u64,
                                         subscriptions.mode="callback"
@@ -527,7 +669,9 @@ impl Service<router::Request> for CallbackService {
                             CallbackPayload::Subscription(SubscriptionPayload::Check {
                                 ..
                             }) => {
-                                if notify.exist(id).await? {
+                                if notify.exist(id.clone()).await?
+                                    || relay(&redis_notify, &id, Event::Heartbeat).await
+                                {
                                     Ok(router::Response {
                                         response: http::Response::builder()
                                             .status(StatusCode::NO_CONTENT)
@@ -562,7 +706,16 @@ impl Service<router::Request> for CallbackService {
                                     });
                                 }
 
-                                let (mut valid_ids, invalid_ids) = notify.invalid_ids(ids).await?;
+                                let (mut valid_ids, unknown_ids) = notify.invalid_ids(ids).await?;
+                                // The subscriptions owned by other router instances are valid too
+                                let mut invalid_ids = Vec::new();
+                                for unknown_id in unknown_ids {
+                                    if relay(&redis_notify, &unknown_id, Event::Heartbeat).await {
+                                        valid_ids.push(unknown_id);
+                                    } else {
+                                        invalid_ids.push(unknown_id);
+                                    }
+                                }
                                 if invalid_ids.is_empty() {
                                     Ok(router::Response {
                                         response: http::Response::builder()
@@ -611,6 +764,30 @@ impl Service<router::Request> for CallbackService {
                                 errors,
                                 ..
                             }) => {
+                                let relayed = match &errors {
+                                    Some(errors) => {
+                                        relay(
+                                            &redis_notify,
+                                            &id,
+                                            Event::Next {
+                                                payload: graphql::Response::builder()
+                                                    .errors(errors.clone())
+                                                    .build(),
+                                            },
+                                        )
+                                        .await
+                                    }
+                                    None => true,
+                                };
+                                if relayed && relay(&redis_notify, &id, Event::Complete).await {
+                                    return Ok(router::Response {
+                                        response: http::Response::builder()
+                                            .status(StatusCode::ACCEPTED)
+                                            .body::<hyper::Body>("".into())
+                                            .map_err(BoxError::from)?,
+                                        context: req.context,
+                                    });
+                                }
                                 if let Some(errors) = errors {
                                     let mut handle = match notify.subscribe(id.clone()).await {
                                          Ok(handle) => handle.into_sink(),
@@ -1404,6 +1581,53 @@ mod tests {
         assert!(sub_config.max_opened_subscriptions.is_none());
         assert!(sub_config.queue_capacity.is_none());
     }
+
+    #[test]
+    fn it_identifies_clients() {
+        let sub_config: SubscriptionConfig = serde_json::from_value(serde_json::json!({
+            "per_client": {
+                "key": { "claim": "sub" },
+                "max_opened_subscriptions": 3
+            },
+            "max_lifetime": "1h",
+            "idle_timeout": "10m"
+        }))
+        .unwrap();
+        assert_eq!(sub_config.max_lifetime, Some(Duration::from_secs(3600)));
+        assert_eq!(sub_config.idle_timeout, Some(Duration::from_secs(600)));
+        let per_client = sub_config.per_client.unwrap();
+        assert_eq!(per_client.key, ClientKey::Claim("sub".to_string()));
+        assert_eq!(per_client.max_opened_subscriptions, 3);
+
+        let context = Context::new();
+        let mut headers = http::HeaderMap::new();
+        assert_eq!(per_client.key.client_id(&context, &headers), None);
+        context
+            .insert(
+                APOLLO_AUTHENTICATION_JWT_CLAIMS,
+                serde_json::json!({ "sub": "user-1", "exp": 12 }),
+            )
+            .unwrap();
+        context.insert(CLIENT_NAME, "web".to_string()).unwrap();
+        headers.insert("x-client-id", HeaderValue::from_static("client-1"));
+
+        assert_eq!(
+            per_client.key.client_id(&context, &headers),
+            Some("user-1".to_string())
+        );
+        assert_eq!(
+            ClientKey::Claim("exp".to_string()).client_id(&context, &headers),
+            Some("12".to_string())
+        );
+        assert_eq!(
+            ClientKey::Header("x-client-id".to_string()).client_id(&context, &headers),
+            Some("client-1".to_string())
+        );
+        assert_eq!(
+            ClientKey::ClientName.client_id(&context, &headers),
+            Some("web".to_string())
+        );
+    }
 }
 
 register_plugin!("apollo", "subscription", Subscription);
@@ -1487,6 +1487,7 @@ mod test {
         req.router_request.extensions_mut().insert(ConnectionInfo {
             peer_address: Some(SocketAddr::from_str("192.168.0.8:6060").unwrap()),
             server_address: Some(SocketAddr::from_str("192.168.0.1:8080").unwrap()),
+            client_certificate: None,
         });
         let attributes = common.on_request(&req);
         assert_eq!(
@@ -1509,6 +1510,7 @@ mod test {
         req.router_request.extensions_mut().insert(ConnectionInfo {
             peer_address: Some(SocketAddr::from_str("192.168.0.8:6060").unwrap()),
             server_address: Some(SocketAddr::from_str("192.168.0.1:8080").unwrap()),
+            client_certificate: None,
         });
         let attributes = server.on_request(&req);
         assert_eq!(
@@ -1526,6 +1528,7 @@ mod test {
         req.router_request.extensions_mut().insert(ConnectionInfo {
             peer_address: Some(SocketAddr::from_str("192.168.0.8:6060").unwrap()),
             server_address: Some(SocketAddr::from_str("192.168.0.1:8080").unwrap()),
+            client_certificate: None,
         });
         let attributes = server.on_request(&req);
         assert_eq!(
@@ -1548,6 +1551,7 @@ mod test {
         req.router_request.extensions_mut().insert(ConnectionInfo {
             peer_address: Some(SocketAddr::from_str("192.168.0.8:6060").unwrap()),
             server_address: Some(SocketAddr::from_str("192.168.0.1:8080").unwrap()),
+            client_certificate: None,
         });
         let attributes = server.on_request(&req);
         assert_eq!(
@@ -1565,6 +1569,7 @@ mod test {
         req.router_request.extensions_mut().insert(ConnectionInfo {
             peer_address: Some(SocketAddr::from_str("192.168.0.8:6060").unwrap()),
             server_address: Some(SocketAddr::from_str("192.168.0.1:8080").unwrap()),
+            client_certificate: None,
         });
         let attributes = server.on_request(&req);
         assert_eq!(
@@ -1590,6 +1595,7 @@ mod test {
         req.router_request.extensions_mut().insert(ConnectionInfo {
             peer_address: Some(SocketAddr::from_str("192.168.0.8:6060").unwrap()),
             server_address: Some(SocketAddr::from_str("192.168.0.1:8080").unwrap()),
+            client_certificate: None,
         });
         let attributes = server.on_request(&req);
         assert_eq!(
@@ -1615,6 +1621,7 @@ mod test {
         req.router_request.extensions_mut().insert(ConnectionInfo {
             peer_address: Some(SocketAddr::from_str("192.168.0.8:6060").unwrap()),
             server_address: Some(SocketAddr::from_str("192.168.0.1:8080").unwrap()),
+            client_certificate: None,
         });
         let attributes = server.on_request(&req);
         assert_eq!(
@@ -1640,6 +1647,7 @@ mod test {
         req.router_request.extensions_mut().insert(ConnectionInfo {
             peer_address: Some(SocketAddr::from_str("192.168.0.8:6060").unwrap()),
             server_address: Some(SocketAddr::from_str("192.168.0.1:8080").unwrap()),
+            client_certificate: None,
         });
         let attributes = server.on_request(&req);
         assert_eq!(
@@ -1665,6 +1673,7 @@ mod test {
         req.router_request.extensions_mut().insert(ConnectionInfo {
             peer_address: Some(SocketAddr::from_str("192.168.0.8:6060").unwrap()),
             server_address: Some(SocketAddr::from_str("192.168.0.1:8080").unwrap()),
+            client_certificate: None,
         });
         let attributes = server.on_request(&req);
         assert_eq!(
@@ -1690,6 +1699,7 @@ mod test {
         req.router_request.extensions_mut().insert(ConnectionInfo {
             peer_address: Some(SocketAddr::from_str("192.168.0.8:6060").unwrap()),
             server_address: Some(SocketAddr::from_str("192.168.0.1:8080").unwrap()),
+            client_certificate: None,
         });
         let attributes = server.on_request(&req);
         assert_eq!(
@@ -1712,6 +1722,7 @@ mod test {
         req.router_request.extensions_mut().insert(ConnectionInfo {
             peer_address: Some(SocketAddr::from_str("192.168.0.8:6060").unwrap()),
             server_address: Some(SocketAddr::from_str("192.168.0.1:8080").unwrap()),
+            client_certificate: None,
         });
         let attributes = server.on_request(&req);
         assert_eq!(
@@ -1729,6 +1740,7 @@ mod test {
         req.router_request.extensions_mut().insert(ConnectionInfo {
             peer_address: Some(SocketAddr::from_str("192.168.0.8:6060").unwrap()),
             server_address: Some(SocketAddr::from_str("192.168.0.1:8080").unwrap()),
+            client_certificate: None,
         });
         let attributes = server.on_request(&req);
         assert_eq!(
@@ -1751,6 +1763,7 @@ mod test {
         req.router_request.extensions_mut().insert(ConnectionInfo {
             peer_address: Some(SocketAddr::from_str("192.168.0.8:6060").unwrap()),
             server_address: Some(SocketAddr::from_str("192.168.0.1:8080").unwrap()),
+            client_certificate: None,
         });
         let attributes = server.on_request(&req);
         assert_eq!(
@@ -1768,6 +1781,7 @@ mod test {
         req.router_request.extensions_mut().insert(ConnectionInfo {
             peer_address: Some(SocketAddr::from_str("192.168.0.8:6060").unwrap()),
             server_address: Some(SocketAddr::from_str("192.168.0.1:8080").unwrap()),
+            client_certificate: None,
         });
         let attributes = server.on_request(&req);
         assert_eq!(
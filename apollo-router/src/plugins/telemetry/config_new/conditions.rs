@@ -5,8 +5,7 @@ use serde::Deserialize;
 use crate::plugins::telemetry::config::AttributeValue;
 use crate::plugins::telemetry::config_new::Selector;
 
-#[derive(Deserialize, JsonSchema, Clone, Debug)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 #[serde(deny_unknown_fields, rename_all = "snake_case")]
 pub(crate) enum Condition<T> {
     /// A condition to check a selection against a value.
@@ -41,8 +40,7 @@ impl Condition<()> {
     }
 }
 
-#[derive(Deserialize, JsonSchema, Clone, Debug)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 #[serde(deny_unknown_fields, rename_all = "snake_case", untagged)]
 pub(crate) enum SelectorOrValue<T> {
     /// A constant value.
@@ -72,6 +70,7 @@ where
                         *self = Condition::True;
                         Some(true)
                     } else {
+                        *self = Condition::False;
                         Some(false)
                     }
                 }
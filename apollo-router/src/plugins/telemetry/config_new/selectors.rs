@@ -24,8 +24,7 @@ use crate::services::router;
 use crate::services::subgraph;
 use crate::services::supergraph;
 
-#[derive(Deserialize, JsonSchema, Clone, Debug)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 #[serde(deny_unknown_fields, rename_all = "snake_case")]
 pub(crate) enum TraceIdFormat {
     /// Open Telemetry trace ID, a hex string.
@@ -34,8 +33,7 @@ pub(crate) enum TraceIdFormat {
     Datadog,
 }
 
-#[derive(Deserialize, JsonSchema, Clone, Debug)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 #[serde(deny_unknown_fields, rename_all = "snake_case")]
 pub(crate) enum OperationName {
     /// The raw operation name.
@@ -44,16 +42,14 @@ pub(crate) enum OperationName {
     Hash,
 }
 
-#[derive(Deserialize, JsonSchema, Clone, Debug)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 #[serde(deny_unknown_fields, rename_all = "snake_case")]
 pub(crate) enum Query {
     /// The raw query kind.
     String,
 }
 
-#[derive(Deserialize, JsonSchema, Clone, Debug)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 #[serde(deny_unknown_fields, rename_all = "snake_case")]
 pub(crate) enum ResponseStatus {
     /// The http status code.
@@ -62,16 +58,14 @@ pub(crate) enum ResponseStatus {
     Reason,
 }
 
-#[derive(Deserialize, JsonSchema, Clone, Debug)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 #[serde(deny_unknown_fields, rename_all = "snake_case")]
 pub(crate) enum OperationKind {
     /// The raw operation kind.
     String,
 }
 
-#[derive(Deserialize, JsonSchema, Clone, Debug)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 #[serde(deny_unknown_fields, untagged)]
 pub(crate) enum RouterSelector {
     /// A header from the request
@@ -155,8 +149,7 @@ pub(crate) enum RouterSelector {
     },
 }
 
-#[derive(Deserialize, JsonSchema, Clone, Debug)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 #[serde(deny_unknown_fields, untagged)]
 pub(crate) enum SupergraphSelector {
     OperationName {
@@ -276,9 +269,8 @@ pub(crate) enum SupergraphSelector {
 }
 
 #[derive(Deserialize, JsonSchema, Clone, Derivative)]
-#[cfg_attr(test, derivative(PartialEq))]
 #[serde(deny_unknown_fields, rename_all = "snake_case", untagged)]
-#[derivative(Debug)]
+#[derivative(Debug, PartialEq)]
 pub(crate) enum SubgraphSelector {
     SubgraphOperationName {
         /// The operation name from the subgraph query.
@@ -296,6 +288,10 @@ pub(crate) enum SubgraphSelector {
         #[allow(dead_code)]
         subgraph_operation_kind: OperationKind,
     },
+    SubgraphName {
+        /// The subgraph name
+        subgraph_name: bool,
+    },
     SubgraphQuery {
         /// The graphql query to the subgraph.
         // Allow dead code is required because there is only one variant in Query and we need to avoid the dead code warning.
@@ -757,6 +753,10 @@ impl Selector for SubgraphSelector {
                 .ok()
                 .flatten()
                 .map(opentelemetry::Value::from),
+            SubgraphSelector::SubgraphName { subgraph_name } if *subgraph_name => request
+                .subgraph_name
+                .clone()
+                .map(opentelemetry::Value::from),
 
             SubgraphSelector::SupergraphQuery { default, .. } => request
                 .supergraph_request
@@ -1787,6 +1787,25 @@ mod test {
         );
     }
 
+    #[test]
+    fn subgraph_name() {
+        let selector = SubgraphSelector::SubgraphName {
+            subgraph_name: true,
+        };
+        assert_eq!(
+            selector.on_request(
+                &crate::services::SubgraphRequest::fake_builder()
+                    .subgraph_name("products".to_string())
+                    .build(),
+            ),
+            Some("products".into())
+        );
+        assert_eq!(
+            selector.on_request(&crate::services::SubgraphRequest::fake_builder().build()),
+            None
+        );
+    }
+
     #[test]
     fn subgraph_operation_kind() {
         let selector = SubgraphSelector::SupergraphOperationKind {
@@ -0,0 +1,683 @@
+//! Merge entity fetches from independent client requests. Implemented as a tower Layer.
+//!
+//! `_entities` fetches sent to a subgraph during a short window are merged into one subgraph
+//! request when they are compatible: same URI, headers, operation, variables (except
+//! `representations`) and authorization metadata. The representations are concatenated (and
+//! deduplicated), and the entities and errors of the merged response are split back to each
+//! request. The merged fetch is sent with the context of the first request of the batch, and the
+//! context entries written while sending it are copied to every request.
+//!
+//! See [`Layer`] and [`tower::Service`] for more details.
+
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use indexmap::IndexSet;
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::Context as otelContext;
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json_bytes::Value;
+use tokio::sync::oneshot;
+use tower::BoxError;
+use tower::Layer;
+use tower::ServiceExt;
+use tracing::Instrument;
+use tracing::Span;
+
+use crate::batching::BatchQuery;
+use crate::error::FetchError;
+use crate::graphql;
+use crate::http_ext;
+use crate::json_ext::Object;
+use crate::json_ext::Path;
+use crate::json_ext::PathElement;
+use crate::plugins::authorization::CacheKeyMetadata;
+use crate::plugins::telemetry::otel::span_ext::OpenTelemetrySpanExt;
+use crate::query_planner::fetch::OperationKind;
+use crate::services::SubgraphRequest;
+use crate::services::SubgraphResponse;
+use crate::Context;
+
+const REPRESENTATIONS: &str = "representations";
+const ENTITIES: &str = "_entities";
+
+/// Entity fetches micro-batching configuration
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct EntityBatchingConfig {
+    #[serde(
+        deserialize_with = "humantime_serde::deserialize",
+        default = "default_max_wait"
+    )]
+    #[schemars(with = "String", default = "default_max_wait")]
+    /// How long entity fetches wait for other compatible fetches before being sent (default: 2ms)
+    max_wait: Duration,
+    /// Maximum number of representations in a merged fetch. Fetches are sent as soon as this
+    /// number is reached (default: 50)
+    #[serde(default = "default_max_size")]
+    max_size: usize,
+}
+
+fn default_max_wait() -> Duration {
+    Duration::from_millis(2)
+}
+
+fn default_max_size() -> usize {
+    50
+}
+
+pub(crate) struct EntityBatchingLayer {
+    subgraph_name: String,
+    config: EntityBatchingConfig,
+}
+
+impl EntityBatchingLayer {
+    pub(crate) fn new(subgraph_name: String, config: EntityBatchingConfig) -> Self {
+        Self {
+            subgraph_name,
+            config,
+        }
+    }
+}
+
+impl<S> Layer<S> for EntityBatchingLayer
+where
+    S: tower::Service<SubgraphRequest, Response = SubgraphResponse, Error = BoxError> + Clone,
+{
+    type Service = EntityBatchingService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        EntityBatchingService {
+            service,
+            subgraph_name: Arc::new(self.subgraph_name.clone()),
+            config: self.config.clone(),
+            pending: Default::default(),
+            next_id: Default::default(),
+        }
+    }
+}
+
+/// Fetches are compatible if they only differ by their representations
+type BatchKey = (http_ext::Request<graphql::Request>, Arc<CacheKeyMetadata>);
+
+type BatchResult = Result<http::Response<graphql::Response>, Arc<BoxError>>;
+
+struct Waiter {
+    representations: Vec<Value>,
+    context: Context,
+    /// Trace context of the fetch, linked to the span of the merged fetch
+    span_context: otelContext,
+    sender: oneshot::Sender<BatchResult>,
+}
+
+struct PendingBatch {
+    id: u64,
+    /// The first request of the batch, used to send the merged fetch. Its context is the only one
+    /// seen by the services after this layer, and the entries they write are copied to the other
+    /// waiters
+    request: SubgraphRequest,
+    waiters: Vec<Waiter>,
+    size: usize,
+}
+
+#[derive(Clone)]
+pub(crate) struct EntityBatchingService<S: Clone> {
+    service: S,
+    subgraph_name: Arc<String>,
+    config: EntityBatchingConfig,
+    pending: Arc<Mutex<HashMap<BatchKey, PendingBatch>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl<S> EntityBatchingService<S>
+where
+    S: tower::Service<SubgraphRequest, Response = SubgraphResponse, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    <S as tower::Service<SubgraphRequest>>::Future: Send + 'static,
+{
+    fn is_batchable(&self, request: &SubgraphRequest) -> bool {
+        // Requests of a client batch are already part of a subgraph batch, and subscriptions or
+        // deferred responses cannot be shared
+        request.operation_kind == OperationKind::Query
+            && request.subscription_stream.is_none()
+            && request.deferred_stream.is_none()
+            && !request
+                .context
+                .extensions()
+                .lock()
+                .contains_key::<BatchQuery>()
+            && matches!(
+                request.subgraph_request.body().variables.get(REPRESENTATIONS),
+                Some(Value::Array(representations)) if representations.len() < self.config.max_size
+            )
+    }
+
+    fn batch(
+        &self,
+        mut request: SubgraphRequest,
+    ) -> BoxFuture<'static, Result<SubgraphResponse, BoxError>> {
+        let representations = match request
+            .subgraph_request
+            .body_mut()
+            .variables
+            .remove(REPRESENTATIONS)
+        {
+            Some(Value::Array(representations)) => representations,
+            _ => unreachable!("checked by is_batchable; qed"),
+        };
+        let key: BatchKey = (
+            (&request.subgraph_request).into(),
+            request.authorization.clone(),
+        );
+        let context = request.context.clone();
+        let (sender, receiver) = oneshot::channel();
+        let size = representations.len();
+        let waiter = Waiter {
+            representations,
+            context: context.clone(),
+            span_context: Span::current().context(),
+            sender,
+        };
+
+        let mut pending = self.pending.lock();
+        // a fetch that does not fit in the pending batch is merged in a new one
+        if matches!(pending.get(&key), Some(batch) if batch.size + size > self.config.max_size) {
+            let batch = pending.remove(&key).expect("the batch was just found; qed");
+            tokio::task::spawn(Self::send(
+                self.service.clone(),
+                self.subgraph_name.clone(),
+                batch,
+            ));
+        }
+        match pending.get_mut(&key) {
+            Some(batch) => {
+                batch.waiters.push(waiter);
+                batch.size += size;
+                if batch.size >= self.config.max_size {
+                    let batch = pending.remove(&key).expect("the batch was just found; qed");
+                    tokio::task::spawn(Self::send(
+                        self.service.clone(),
+                        self.subgraph_name.clone(),
+                        batch,
+                    ));
+                }
+            }
+            None => {
+                let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                pending.insert(
+                    key.clone(),
+                    PendingBatch {
+                        id,
+                        request,
+                        waiters: vec![waiter],
+                        size,
+                    },
+                );
+
+                let service = self.service.clone();
+                let subgraph_name = self.subgraph_name.clone();
+                let pending = self.pending.clone();
+                let max_wait = self.config.max_wait;
+                tokio::task::spawn(async move {
+                    tokio::time::sleep(max_wait).await;
+                    let batch = {
+                        let mut pending = pending.lock();
+                        // the batch may have been sent already because it was full
+                        match pending.get(&key) {
+                            Some(batch) if batch.id == id => pending.remove(&key),
+                            _ => None,
+                        }
+                    };
+                    if let Some(batch) = batch {
+                        Self::send(service, subgraph_name, batch).await;
+                    }
+                });
+            }
+        }
+        drop(pending);
+
+        let subgraph_name = self.subgraph_name.clone();
+        Box::pin(async move {
+            match receiver.await {
+                Ok(Ok(response)) => Ok(SubgraphResponse::new_from_response(response, context)),
+                // each fetch gets its own copy of the error, keeping the details of fetch errors
+                Ok(Err(error)) => Err(match error.downcast_ref::<FetchError>() {
+                    Some(error) => error.clone().into(),
+                    None => FetchError::SubrequestHttpError {
+                        status_code: None,
+                        service: subgraph_name.to_string(),
+                        reason: error.to_string(),
+                    }
+                    .into(),
+                }),
+                Err(_) => Err("the merged entity fetch was cancelled".into()),
+            }
+        })
+    }
+
+    /// Sends the merged fetch, then splits its response
+    async fn send(service: S, subgraph_name: Arc<String>, batch: PendingBatch) {
+        let PendingBatch {
+            mut request,
+            waiters,
+            ..
+        } = batch;
+        u64_histogram!(
+            "apollo.router.operations.entity_batching.size",
+            "Number of entity fetches merged in a subgraph request",
+            waiters.len() as u64,
+            "subgraph" = subgraph_name.to_string()
+        );
+
+        let mut representations: IndexSet<Value> = IndexSet::new();
+        let indexes: Vec<Vec<usize>> = waiters
+            .iter()
+            .map(|waiter| {
+                waiter
+                    .representations
+                    .iter()
+                    .map(|representation| representations.insert_full(representation.clone()).0)
+                    .collect()
+            })
+            .collect();
+        request.subgraph_request.body_mut().variables.insert(
+            REPRESENTATIONS,
+            Value::Array(representations.into_iter().collect()),
+        );
+
+        let context = request.context.clone();
+        let entries_before: HashMap<String, Value> = context
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+
+        let span = tracing::info_span!(
+            "entity_batch_request",
+            "subgraph.name" = %subgraph_name,
+            size = waiters.len()
+        );
+        for waiter in &waiters {
+            span.add_link(waiter.span_context.span().span_context().clone());
+        }
+        let result = async move {
+            match service.ready_oneshot().await {
+                Ok(mut service) => service.call(request).await,
+                Err(error) => Err(error),
+            }
+        }
+        .instrument(span)
+        .await;
+
+        // the entries written by the services after this layer are only in the context of the
+        // first request
+        let written: Vec<(String, Value)> = context
+            .iter()
+            .filter(|entry| entries_before.get(entry.key()) != Some(entry.value()))
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        for waiter in &waiters {
+            for (key, value) in &written {
+                waiter.context.insert_json_value(key.clone(), value.clone());
+            }
+        }
+
+        match result {
+            Ok(response) => {
+                let response = response.response;
+                for (waiter, indexes) in waiters.into_iter().zip(indexes) {
+                    let (parts, body) = http_ext::Response::from(&response).inner.into_parts();
+                    let _ = waiter.sender.send(Ok(http::Response::from_parts(
+                        parts,
+                        split_response(body, &indexes),
+                    )));
+                }
+            }
+            Err(error) => {
+                let error = Arc::new(error);
+                for waiter in waiters {
+                    let _ = waiter.sender.send(Err(error.clone()));
+                }
+            }
+        }
+    }
+}
+
+/// Extracts the response of a fetch from a merged response. `indexes` contains the position of
+/// each representation of the fetch in the merged fetch
+fn split_response(mut response: graphql::Response, indexes: &[usize]) -> graphql::Response {
+    let entities = response
+        .data
+        .as_mut()
+        .and_then(|data| data.as_object_mut())
+        .and_then(|data| data.get_mut(ENTITIES))
+        .and_then(|entities| entities.as_array_mut());
+    if let Some(entities) = entities {
+        let split: Vec<Value> = indexes
+            .iter()
+            .map(|index| entities.get(*index).cloned().unwrap_or_default())
+            .collect();
+        let mut data = Object::new();
+        data.insert(ENTITIES, Value::Array(split));
+        response.data = Some(Value::Object(data));
+    }
+
+    let errors = std::mem::take(&mut response.errors);
+    for error in errors {
+        match error.path.as_ref().map(|path| path.0.as_slice()) {
+            Some([PathElement::Key(key, _), PathElement::Index(merged_index), rest @ ..])
+                if key == ENTITIES =>
+            {
+                // an error on an entity is reported to each position this entity was requested at
+                for (index, _) in indexes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, index)| *index == merged_index)
+                {
+                    let mut error = error.clone();
+                    let mut path = vec![
+                        PathElement::Key(ENTITIES.to_string(), None),
+                        PathElement::Index(index),
+                    ];
+                    path.extend_from_slice(rest);
+                    error.path = Some(Path(path));
+                    response.errors.push(error);
+                }
+            }
+            _ => response.errors.push(error),
+        }
+    }
+    response
+}
+
+impl<S> tower::Service<SubgraphRequest> for EntityBatchingService<S>
+where
+    S: tower::Service<SubgraphRequest, Response = SubgraphResponse, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    <S as tower::Service<SubgraphRequest>>::Future: Send + 'static,
+{
+    type Response = SubgraphResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: SubgraphRequest) -> Self::Future {
+        if self.is_batchable(&request) {
+            self.batch(request)
+        } else {
+            let service = self.service.clone();
+            Box::pin(async move { service.oneshot(request).await })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use http::HeaderValue;
+    use serde_json_bytes::json;
+    use tower::Service;
+
+    use super::*;
+    use crate::Context;
+
+    const ENTITIES_QUERY: &str =
+        "query($representations:[_Any!]!){_entities(representations:$representations){...on User{name}}}";
+
+    fn request(ids: &[&str], authorization: &str) -> SubgraphRequest {
+        let representations: Vec<Value> = ids
+            .iter()
+            .map(|id| json!({"__typename": "User", "id": id}))
+            .collect();
+        SubgraphRequest::fake_builder()
+            .subgraph_request(
+                http::Request::builder()
+                    .header(
+                        "authorization",
+                        HeaderValue::from_str(authorization).unwrap(),
+                    )
+                    .body(
+                        graphql::Request::fake_builder()
+                            .query(ENTITIES_QUERY)
+                            .variable(REPRESENTATIONS, representations)
+                            .build(),
+                    )
+                    .unwrap(),
+            )
+            .operation_kind(OperationKind::Query)
+            .context(Context::new())
+            .build()
+    }
+
+    fn names(response: &SubgraphResponse) -> Value {
+        response.response.body().data.clone().unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn it_merges_compatible_entity_fetches() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let subgraph = tower::service_fn(move |request: SubgraphRequest| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let entities: Vec<Value> = request.subgraph_request.body().variables[REPRESENTATIONS]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|representation| json!({"name": representation["id"]}))
+                .collect();
+            let mut errors = Vec::new();
+            if let Some(index) = entities.iter().position(|e| e["name"] == "b") {
+                errors.push(
+                    graphql::Error::builder()
+                        .message("cannot fetch b")
+                        .path(Path(vec![
+                            PathElement::Key(ENTITIES.to_string(), None),
+                            PathElement::Index(index),
+                            PathElement::Key("name".to_string(), None),
+                        ]))
+                        .extension_code("ERROR")
+                        .build(),
+                );
+            }
+            async move {
+                Ok(SubgraphResponse::fake_builder()
+                    .data(json!({ "_entities": entities }))
+                    .errors(errors)
+                    .build())
+            }
+        });
+        let mut service = EntityBatchingLayer::new(
+            "accounts".to_string(),
+            EntityBatchingConfig {
+                max_wait: Duration::from_millis(50),
+                max_size: 50,
+            },
+        )
+        .layer(subgraph);
+
+        let first = service.call(request(&["a", "b"], "user1"));
+        let second = service.call(request(&["b", "c"], "user1"));
+        let other_user = service.call(request(&["a"], "user2"));
+        let (first, second, other_user) = tokio::join!(first, second, other_user);
+        let (first, second, other_user) = (first.unwrap(), second.unwrap(), other_user.unwrap());
+
+        // requests with different headers are not merged
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            names(&first),
+            json!({"_entities": [{"name": "a"}, {"name": "b"}]})
+        );
+        assert_eq!(
+            names(&second),
+            json!({"_entities": [{"name": "b"}, {"name": "c"}]})
+        );
+        assert_eq!(names(&other_user), json!({"_entities": [{"name": "a"}]}));
+
+        assert_eq!(
+            first.response.body().errors[0].path,
+            Some(Path::from("_entities/1/name"))
+        );
+        assert_eq!(
+            second.response.body().errors[0].path,
+            Some(Path::from("_entities/0/name"))
+        );
+        assert!(other_user.response.body().errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_sends_full_batches_immediately() {
+        let subgraph = tower::service_fn(|request: SubgraphRequest| async move {
+            let entities = request.subgraph_request.body().variables[REPRESENTATIONS].clone();
+            Ok(SubgraphResponse::fake_builder()
+                .data(json!({ "_entities": entities }))
+                .build())
+        });
+        let mut service = EntityBatchingLayer::new(
+            "accounts".to_string(),
+            EntityBatchingConfig {
+                max_wait: Duration::from_secs(3600),
+                max_size: 3,
+            },
+        )
+        .layer(subgraph);
+
+        let first = service.call(request(&["a", "b"], "user1"));
+        let second = service.call(request(&["c"], "user1"));
+        let (first, second) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(first, second)
+        })
+        .await
+        .expect("the full batch should be sent without waiting for max_wait");
+        assert_eq!(
+            names(&first.unwrap()),
+            json!({"_entities": [{"__typename": "User", "id": "a"}, {"__typename": "User", "id": "b"}]})
+        );
+        assert_eq!(
+            names(&second.unwrap()),
+            json!({"_entities": [{"__typename": "User", "id": "c"}]})
+        );
+    }
+
+    #[tokio::test]
+    async fn it_never_exceeds_max_size() {
+        let sizes = Arc::new(Mutex::new(Vec::new()));
+        let recorded = sizes.clone();
+        let subgraph = tower::service_fn(move |request: SubgraphRequest| {
+            let entities = request.subgraph_request.body().variables[REPRESENTATIONS].clone();
+            recorded.lock().push(entities.as_array().unwrap().len());
+            async move {
+                Ok(SubgraphResponse::fake_builder()
+                    .data(json!({ "_entities": entities }))
+                    .build())
+            }
+        });
+        let mut service = EntityBatchingLayer::new(
+            "accounts".to_string(),
+            EntityBatchingConfig {
+                max_wait: Duration::from_millis(50),
+                max_size: 3,
+            },
+        )
+        .layer(subgraph);
+
+        let first = service.call(request(&["a", "b"], "user1"));
+        let second = service.call(request(&["c", "d"], "user1"));
+        let (first, second) = tokio::join!(first, second);
+        assert_eq!(
+            names(&first.unwrap()),
+            json!({"_entities": [{"__typename": "User", "id": "a"}, {"__typename": "User", "id": "b"}]})
+        );
+        assert_eq!(
+            names(&second.unwrap()),
+            json!({"_entities": [{"__typename": "User", "id": "c"}, {"__typename": "User", "id": "d"}]})
+        );
+        // the second fetch did not fit in the first batch
+        assert_eq!(*sizes.lock(), vec![2, 2]);
+    }
+
+    #[tokio::test]
+    async fn it_gives_the_error_of_the_merged_fetch_to_each_fetch() {
+        let subgraph = tower::service_fn(|_request: SubgraphRequest| async move {
+            Err::<SubgraphResponse, BoxError>(
+                FetchError::SubrequestHttpError {
+                    status_code: Some(503),
+                    service: "accounts".to_string(),
+                    reason: "service unavailable".to_string(),
+                }
+                .into(),
+            )
+        });
+        let mut service = EntityBatchingLayer::new(
+            "accounts".to_string(),
+            EntityBatchingConfig {
+                max_wait: Duration::from_millis(50),
+                max_size: 50,
+            },
+        )
+        .layer(subgraph);
+
+        let first = service.call(request(&["a"], "user1"));
+        let second = service.call(request(&["b"], "user1"));
+        let (first, second) = tokio::join!(first, second);
+        for result in [first, second] {
+            let error = result.unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<FetchError>(),
+                Some(&FetchError::SubrequestHttpError {
+                    status_code: Some(503),
+                    service: "accounts".to_string(),
+                    reason: "service unavailable".to_string(),
+                })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn it_copies_the_context_written_by_the_merged_fetch_to_each_fetch() {
+        let subgraph = tower::service_fn(|request: SubgraphRequest| async move {
+            request
+                .context
+                .insert("subgraph_status", "ok".to_string())
+                .unwrap();
+            let entities = request.subgraph_request.body().variables[REPRESENTATIONS].clone();
+            Ok(SubgraphResponse::fake_builder()
+                .data(json!({ "_entities": entities }))
+                .build())
+        });
+        let mut service = EntityBatchingLayer::new(
+            "accounts".to_string(),
+            EntityBatchingConfig {
+                max_wait: Duration::from_millis(50),
+                max_size: 50,
+            },
+        )
+        .layer(subgraph);
+
+        let first = request(&["a"], "user1");
+        let second = request(&["b"], "user1");
+        let (first_context, second_context) = (first.context.clone(), second.context.clone());
+        let (first, second) = tokio::join!(service.call(first), service.call(second));
+        first.unwrap();
+        second.unwrap();
+        for context in [first_context, second_context] {
+            assert_eq!(
+                context.get::<_, String>("subgraph_status").unwrap(),
+                Some("ok".to_string())
+            );
+        }
+    }
+}
@@ -5,8 +5,10 @@
 //! * Timeout
 //! * Compression
 //! * Rate limiting
+//! * Entity fetches micro-batching
 //!
 mod deduplication;
+mod entity_batching;
 pub(crate) mod rate;
 mod retry;
 pub(crate) mod timeout;
@@ -30,6 +32,8 @@ use tower::ServiceBuilder;
 use tower::ServiceExt;
 
 use self::deduplication::QueryDeduplicationLayer;
+use self::entity_batching::EntityBatchingConfig;
+use self::entity_batching::EntityBatchingLayer;
 use self::rate::RateLimitLayer;
 pub(crate) use self::rate::RateLimited;
 pub(crate) use self::retry::RetryPolicy;
@@ -70,6 +74,8 @@ struct Shaping {
     experimental_retry: Option<RetryConfig>,
     /// Enable HTTP2 for subgraphs
     experimental_http2: Option<Http2Config>,
+    /// Merge compatible entity fetches from concurrent client requests
+    experimental_entity_batching: Option<EntityBatchingConfig>,
 }
 
 #[derive(PartialEq, Default, Debug, Clone, Deserialize, JsonSchema)]
@@ -107,6 +113,11 @@ impl Merge for Shaping {
                     .as_ref()
                     .or(fallback.experimental_http2.as_ref())
                     .cloned(),
+                experimental_entity_batching: self
+                    .experimental_entity_batching
+                    .as_ref()
+                    .or(fallback.experimental_entity_batching.as_ref())
+                    .cloned(),
             },
         }
     }
@@ -266,13 +277,16 @@ impl Plugin for TrafficShaping {
 pub(crate) type TrafficShapingSubgraphFuture<S> = Either<
     Either<
         BoxFuture<'static, Result<subgraph::Response, BoxError>>,
-        timeout::future::ResponseFuture<
-            Oneshot<
-                Either<
-                    Retry<RetryPolicy, Either<rate::service::RateLimit<S>, S>>,
-                    Either<rate::service::RateLimit<S>, S>,
+        Either<
+            BoxFuture<'static, Result<subgraph::Response, BoxError>>,
+            timeout::future::ResponseFuture<
+                Oneshot<
+                    Either<
+                        Retry<RetryPolicy, Either<rate::service::RateLimit<S>, S>>,
+                        Either<rate::service::RateLimit<S>, S>,
+                    >,
+                    subgraph::Request,
                 >,
-                subgraph::Request,
             >,
         >,
     >,
@@ -375,16 +389,17 @@ impl TrafficShaping {
                 tower::retry::RetryLayer::new(retry_policy)
             });
 
+            let timeout = config.shaping.timeout.unwrap_or(DEFAULT_TIMEOUT);
+            let service_name = name.to_string();
             Either::A(ServiceBuilder::new()
 
                 .option_layer(config.shaping.deduplicate_query.unwrap_or_default().then(
                   QueryDeduplicationLayer::default
                 ))
-                    .layer(TimeoutLayer::new(
-                        config.shaping
-                        .timeout
-                        .unwrap_or(DEFAULT_TIMEOUT),
-                    ))
+                .option_layer(config.shaping.experimental_entity_batching.clone().map(|entity_batching_config| {
+                    EntityBatchingLayer::new(name.to_string(), entity_batching_config)
+                }))
+                    .layer(TimeoutLayer::new(timeout))
                     .option_layer(retry)
                     .option_layer(rate_limit)
                 .service(service)
@@ -393,6 +408,8 @@ impl TrafficShaping {
                         let compression_header_val = HeaderValue::from_str(&compression.to_string()).expect("compression is manually implemented and already have the right values; qed");
                         req.subgraph_request.headers_mut().insert(CONTENT_ENCODING, compression_header_val);
                     }
+                    // the subsequent payloads of an incremental response share the request timeout
+                    timeout::apply_to_deferred_stream(&mut req, timeout, &service_name);
 
                     req
                 }))
@@ -845,4 +862,27 @@ mod test {
             .await
             .unwrap();
     }
+
+    #[tokio::test]
+    async fn it_times_out_the_incremental_response_of_a_subgraph() {
+        use futures::StreamExt;
+
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        let mut request = SubgraphRequest::fake_builder().build();
+        request.deferred_stream = Some(sender);
+        timeout::apply_to_deferred_stream(&mut request, Duration::from_millis(100), "test");
+
+        let first = crate::graphql::Response::builder().has_next(true).build();
+        let stream = futures::stream::iter([first])
+            .chain(futures::stream::pending())
+            .boxed();
+        request.deferred_stream.unwrap().send(stream).await.unwrap();
+        let responses: Vec<_> = receiver.recv().await.unwrap().collect().await;
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[1].has_next, Some(false));
+        assert_eq!(
+            responses[1].errors[0].message,
+            "HTTP fetch failed from 'test': request timed out"
+        );
+    }
 }
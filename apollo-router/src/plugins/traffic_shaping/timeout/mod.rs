@@ -13,13 +13,73 @@ use std::task::Context;
 use std::task::Poll;
 use std::time::Duration;
 
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
 use tower::util::Oneshot;
 use tower::Service;
 use tower::ServiceExt;
 
 use self::future::ResponseFuture;
 pub(crate) use self::layer::TimeoutLayer;
+use crate::error::FetchError;
+use crate::graphql;
 pub(crate) use crate::plugins::traffic_shaping::timeout::error::Elapsed;
+use crate::services::SubgraphRequest;
+
+/// Applies the `timeout` of a subgraph request to the subsequent payloads of its incremental
+/// response, which are read after the subgraph service returned
+pub(crate) fn apply_to_deferred_stream(
+    request: &mut SubgraphRequest,
+    timeout: Duration,
+    service_name: &str,
+) {
+    let Some(deferred_stream) = request.deferred_stream.take() else {
+        return;
+    };
+    let deadline = Instant::now() + timeout;
+    let (sender, mut receiver) = mpsc::channel::<BoxStream<'static, graphql::Response>>(1);
+    request.deferred_stream = Some(sender);
+    let service_name = service_name.to_string();
+    tokio::task::spawn(async move {
+        if let Some(stream) = receiver.recv().await {
+            let stream = with_deadline(stream, deadline, service_name).boxed();
+            let _ = deferred_stream.send(stream).await;
+        }
+    });
+}
+
+/// Ends the stream with an error response if it is not over at the `deadline`
+fn with_deadline(
+    stream: BoxStream<'static, graphql::Response>,
+    deadline: Instant,
+    service_name: String,
+) -> impl futures::Stream<Item = graphql::Response> {
+    futures::stream::unfold((stream, false), move |(mut stream, elapsed)| {
+        let service_name = service_name.clone();
+        async move {
+            if elapsed {
+                return None;
+            }
+            match tokio::time::timeout_at(deadline, stream.next()).await {
+                Ok(response) => response.map(|response| (response, (stream, false))),
+                Err(_) => {
+                    let error = FetchError::SubrequestHttpError {
+                        status_code: None,
+                        service: service_name,
+                        reason: Elapsed::new().to_string(),
+                    };
+                    let response = graphql::Response::builder()
+                        .error(error.to_graphql_error(None))
+                        .has_next(false)
+                        .build();
+                    Some((response, (stream, true)))
+                }
+            }
+        }
+    })
+}
 
 /// Applies a timeout to requests.
 #[derive(Debug, Clone)]
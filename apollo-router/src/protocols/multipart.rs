@@ -1,3 +1,4 @@
+use std::fmt::Display;
 use std::pin::Pin;
 use std::task::Poll;
 use std::time::Duration;
@@ -6,17 +7,22 @@ use bytes::Bytes;
 use futures::stream::select;
 use futures::stream::StreamExt;
 use futures::Stream;
+use serde::Deserialize;
 use serde::Serialize;
 use serde_json_bytes::Value;
 use tokio_stream::once;
 use tokio_stream::wrappers::IntervalStream;
+use tower::BoxError;
 
 use crate::graphql;
 
 #[cfg(test)]
-const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(10);
+pub(super) const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(10);
 #[cfg(not(test))]
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+pub(super) const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Maximum size of a part or an event received from a subgraph, before its end is found
+pub(crate) const MAX_EVENT_SIZE: usize = 64 * 1024 * 1024;
 
 #[derive(thiserror::Error, Debug)]
 pub(crate) enum Error {
@@ -30,10 +36,11 @@ pub(crate) enum ProtocolMode {
     Defer,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct SubscriptionPayload {
+    #[serde(default)]
     payload: Option<graphql::Response>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
     errors: Vec<graphql::Error>,
 }
 
@@ -177,6 +184,236 @@ impl Stream for Multipart {
     }
 }
 
+/// Splits a multipart response received from a subgraph into the bodies of its parts
+///
+/// The headers of each part are removed, and empty parts are skipped. A part larger than
+/// [`MAX_EVENT_SIZE`] ends the stream with an error.
+pub(crate) fn decode_parts<S, E>(
+    boundary: String,
+    body: S,
+) -> impl Stream<Item = Result<Bytes, BoxError>> + Send
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+    E: Into<BoxError> + Send + 'static,
+{
+    let delimiter = format!("--{boundary}").into_bytes();
+    futures::stream::unfold(
+        (body, Vec::new(), 0, false, false),
+        move |(mut body, mut buffer, mut scanned, mut started, terminated)| {
+            let delimiter = delimiter.clone();
+            async move {
+                if terminated {
+                    return None;
+                }
+                loop {
+                    // A part is complete when the next delimiter is received, and we need the two
+                    // bytes after the delimiter to know if it is the last one. The bytes before
+                    // `scanned` were already searched for a delimiter.
+                    match find(&buffer, &delimiter, scanned) {
+                        Some(position) if buffer.len() >= position + delimiter.len() + 2 => {
+                            let part: Vec<u8> =
+                                buffer.drain(..position + delimiter.len()).collect();
+                            scanned = 0;
+                            let is_last = buffer.starts_with(b"--");
+                            if started {
+                                if let Some(part) = part_body(&part[..position]) {
+                                    return Some((
+                                        Ok(part),
+                                        (body, buffer, scanned, started, is_last),
+                                    ));
+                                }
+                            }
+                            started = true;
+                            if is_last {
+                                return None;
+                            }
+                            continue;
+                        }
+                        Some(position) => scanned = position,
+                        None => scanned = (buffer.len() + 1).saturating_sub(delimiter.len()),
+                    }
+                    if buffer.len() > MAX_EVENT_SIZE {
+                        let error = format!("a part is larger than {MAX_EVENT_SIZE} bytes");
+                        return Some((Err(error.into()), (body, buffer, scanned, started, true)));
+                    }
+                    match body.next().await {
+                        Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                        Some(Err(err)) => {
+                            return Some((Err(err.into()), (body, buffer, scanned, started, true)))
+                        }
+                        None => return None,
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// Returns the position of the first occurrence of `needle` in `buffer`, starting at `from`
+pub(super) fn find(buffer: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    buffer
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| from + position)
+}
+
+fn part_body(part: &[u8]) -> Option<Bytes> {
+    // the headers of the part are separated from its body by an empty line
+    let body = match part.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(position) => &part[position + 4..],
+        None => part.strip_prefix(b"\r\n").unwrap_or(part),
+    };
+    let body = body.strip_suffix(b"\r\n").unwrap_or(body);
+    if body.iter().all(u8::is_ascii_whitespace) {
+        None
+    } else {
+        Some(Bytes::copy_from_slice(body))
+    }
+}
+
+/// Decodes the parts of a multipart subscription received from a subgraph into GraphQL responses
+///
+/// Heartbeats are skipped. Transport errors sent by the subgraph end the stream with an error
+/// response.
+pub(crate) fn decode_subscription<S, E>(
+    service_name: String,
+    boundary: String,
+    body: S,
+) -> impl Stream<Item = graphql::Response> + Send
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+    E: Into<BoxError> + Send + 'static,
+{
+    futures::stream::unfold(
+        (decode_parts(boundary, body).boxed(), false),
+        move |(mut parts, terminated)| {
+            let service_name = service_name.clone();
+            async move {
+                if terminated {
+                    return None;
+                }
+                loop {
+                    match parts.next().await {
+                        Some(Ok(part)) => {
+                            if let Some(response) = parse_subscription_part(&service_name, &part) {
+                                let terminated = !response.subscribed.unwrap_or(false);
+                                return Some((response, (parts, terminated)));
+                            }
+                        }
+                        Some(Err(err)) => {
+                            let response = graphql::Response::builder()
+                                .error(
+                                    graphql::Error::builder()
+                                        .message(format!(
+                                            "cannot read the subscription events from subgraph {service_name}: {err}"
+                                        ))
+                                        .extension_code("SUBSCRIPTION_STREAM_ERROR")
+                                        .build(),
+                                )
+                                .subscribed(false)
+                                .build();
+                            return Some((response, (parts, true)));
+                        }
+                        None => return None,
+                    }
+                }
+            }
+        },
+    )
+}
+
+fn parse_subscription_part(service_name: &str, body: &[u8]) -> Option<graphql::Response> {
+    match serde_json::from_slice::<SubscriptionPayload>(body) {
+        Ok(SubscriptionPayload {
+            payload: Some(mut response),
+            errors,
+        }) if errors.is_empty() => {
+            response.subscribed = Some(true);
+            Some(response)
+        }
+        // heartbeat
+        Ok(SubscriptionPayload {
+            payload: None,
+            errors,
+        }) if errors.is_empty() => None,
+        // transport errors end the subscription
+        Ok(SubscriptionPayload { payload, errors }) => {
+            let mut response = payload.unwrap_or_default();
+            response.errors.extend(errors);
+            response.subscribed = Some(false);
+            Some(response)
+        }
+        Err(error) => Some(
+            graphql::Response::builder()
+                .error(
+                    graphql::Error::builder()
+                        .message(format!(
+                            "service '{service_name}' response was malformed: {error}"
+                        ))
+                        .extension_code("SUBREQUEST_MALFORMED_RESPONSE")
+                        .build(),
+                )
+                .subscribed(true)
+                .build(),
+        ),
+    }
+}
+
+/// Decodes the subsequent payloads of an incremental delivery (`@defer`) multipart response
+/// received from a subgraph, once its first part was read
+///
+/// The stream ends after the payload with `hasNext: false`. Transport errors end the stream with
+/// an error response.
+pub(crate) fn decode_incremental<S, E>(
+    service_name: String,
+    parts: S,
+) -> impl Stream<Item = graphql::Response> + Send
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+    E: Display + Send + 'static,
+{
+    futures::stream::unfold((parts, false), move |(mut parts, terminated)| {
+        let service_name = service_name.clone();
+        async move {
+            if terminated {
+                return None;
+            }
+            let response = match parts.next().await? {
+                // subsequent payloads may have neither data nor errors
+                Ok(part) => serde_json::from_slice::<graphql::Response>(&part).unwrap_or_else(
+                    |error| {
+                        graphql::Response::builder()
+                            .error(
+                                graphql::Error::builder()
+                                    .message(format!(
+                                        "service '{service_name}' response was malformed: {error}"
+                                    ))
+                                    .extension_code("SUBREQUEST_MALFORMED_RESPONSE")
+                                    .build(),
+                            )
+                            .has_next(false)
+                            .build()
+                    },
+                ),
+                Err(err) => graphql::Response::builder()
+                    .error(
+                        graphql::Error::builder()
+                            .message(format!(
+                                "cannot read the incremental response from subgraph {service_name}: {err}"
+                            ))
+                            .extension_code("SUBREQUEST_HTTP_ERROR")
+                            .build(),
+                    )
+                    .has_next(false)
+                    .build(),
+            };
+            let terminated = !response.has_next.unwrap_or(false);
+            Some((response, (parts, terminated)))
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use futures::stream;
@@ -290,4 +527,92 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn it_decodes_subgraph_subscriptions() {
+        let chunks = vec![
+            "\r\n--graphql\r\ncontent-type: application/json\r\n\r\n{}\r\n--gra",
+            "phql\r\ncontent-type: application/json\r\n\r\n{\"payload\":{\"data\":{\"foo\":1}}}\r\n--graphql",
+            "\r\ncontent-type: application/json\r\n\r\n{\"payload\":null,\"errors\":[{\"message\":\"closed\"}]}\r\n--graphql--\r\n",
+        ];
+        let body = stream::iter(
+            chunks
+                .into_iter()
+                .map(|chunk| Ok::<_, std::io::Error>(Bytes::from(chunk))),
+        );
+
+        let responses: Vec<graphql::Response> =
+            decode_subscription("test".to_string(), "graphql".to_string(), body)
+                .collect()
+                .await;
+        assert_eq!(
+            responses,
+            vec![
+                graphql::Response::builder()
+                    .data(serde_json_bytes::json!({"foo": 1}))
+                    .subscribed(true)
+                    .build(),
+                graphql::Response::builder()
+                    .error(
+                        serde_json::from_str::<graphql::Error>(r#"{"message":"closed"}"#).unwrap()
+                    )
+                    .subscribed(false)
+                    .build(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rejects_oversized_subgraph_parts() {
+        let start = Bytes::from_static(b"\r\n--graphql\r\ncontent-type: application/json\r\n\r\n");
+        let chunk = Bytes::from(vec![b'a'; 1024 * 1024]);
+        let body = stream::iter([start])
+            .chain(stream::repeat(chunk))
+            .map(Ok::<_, std::io::Error>);
+
+        let responses: Vec<graphql::Response> =
+            decode_subscription("test".to_string(), "graphql".to_string(), body)
+                .collect()
+                .await;
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].subscribed, Some(false));
+        assert_eq!(
+            responses[0].errors[0].extensions["code"],
+            "SUBSCRIPTION_STREAM_ERROR"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_decodes_subgraph_incremental_responses() {
+        let chunks = vec![
+            "\r\n---\r\ncontent-type: application/json\r\n\r\n{\"data\":{\"me\":{\"id\":1}},\"hasNext\":true}\r\n---",
+            "\r\ncontent-type: application/json\r\n\r\n{\"incremental\":[{\"data\":{\"name\":\"Ada\"},\"path\":[\"me\"],\"label\":\"name\"}],\"hasNext\":false}\r\n-----\r\n",
+        ];
+        let body = stream::iter(
+            chunks
+                .into_iter()
+                .map(|chunk| Ok::<_, std::io::Error>(Bytes::from(chunk))),
+        );
+
+        let mut parts = decode_parts("-".to_string(), body).boxed();
+        let first = parts.next().await.unwrap().unwrap();
+        assert_eq!(
+            first,
+            Bytes::from_static(b"{\"data\":{\"me\":{\"id\":1}},\"hasNext\":true}")
+        );
+        let responses: Vec<graphql::Response> = decode_incremental("test".to_string(), parts)
+            .collect()
+            .await;
+        assert_eq!(
+            responses,
+            vec![graphql::Response::builder()
+                .incremental(vec![graphql::IncrementalResponse::builder()
+                    .data(serde_json_bytes::json!({"name": "Ada"}))
+                    .path(crate::json_ext::Path::from("me"))
+                    .label("name".to_string())
+                    .build()])
+                .has_next(false)
+                .build()]
+        );
+    }
 }
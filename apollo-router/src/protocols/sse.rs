@@ -0,0 +1,353 @@
+//! GraphQL over Server-Sent Events, in the "distinct connections" mode
+//!
+//! Reference: <https://github.com/enisdenjo/graphql-sse/blob/master/PROTOCOL.md#distinct-connections-mode>
+
+use std::fmt::Display;
+use std::pin::Pin;
+use std::task::Poll;
+
+use bytes::Bytes;
+use futures::stream::select;
+use futures::stream::StreamExt;
+use futures::Stream;
+use serde_json_bytes::Value;
+use tokio_stream::once;
+use tokio_stream::wrappers::IntervalStream;
+
+use super::multipart::find;
+use super::multipart::Error;
+use super::multipart::ProtocolMode;
+use super::multipart::HEARTBEAT_INTERVAL;
+use super::multipart::MAX_EVENT_SIZE;
+use crate::graphql;
+
+// Comments are ignored by clients, they only keep the connection open through proxies
+const HEARTBEAT: &[u8] = b":\n\n";
+const COMPLETE: &[u8] = b"event: complete\ndata:\n\n";
+
+enum MessageKind {
+    Heartbeat,
+    Message(graphql::Response),
+    Eof,
+}
+
+/// Serializes a stream of GraphQL responses as `next` events, followed by a `complete` event
+pub(crate) struct EventStream {
+    stream: Pin<Box<dyn Stream<Item = MessageKind> + Send>>,
+    is_terminated: bool,
+}
+
+impl EventStream {
+    pub(crate) fn new<S>(stream: S, mode: ProtocolMode) -> Self
+    where
+        S: Stream<Item = graphql::Response> + Send + 'static,
+    {
+        let stream = stream
+            .map(MessageKind::Message)
+            .chain(once(MessageKind::Eof));
+        let stream = match mode {
+            ProtocolMode::Subscription => select(
+                stream,
+                IntervalStream::new(tokio::time::interval(HEARTBEAT_INTERVAL))
+                    .map(|_| MessageKind::Heartbeat),
+            )
+            .boxed(),
+            ProtocolMode::Defer => stream.boxed(),
+        };
+
+        Self {
+            stream,
+            is_terminated: false,
+        }
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        if self.is_terminated {
+            return Poll::Ready(None);
+        }
+        match self.stream.as_mut().poll_next(cx) {
+            Poll::Ready(Some(MessageKind::Heartbeat)) => {
+                Poll::Ready(Some(Ok(Bytes::from_static(HEARTBEAT))))
+            }
+            Poll::Ready(Some(MessageKind::Message(response))) => {
+                let is_still_open =
+                    response.has_next.unwrap_or(false) || response.subscribed.unwrap_or(false);
+                // The last message of a subscription closed at the server side has no content
+                let is_empty = matches!(response.data, None | Some(Value::Null))
+                    && response.errors.is_empty()
+                    && response.extensions.is_empty()
+                    && response.incremental.is_empty();
+
+                let mut buf = Vec::new();
+                if is_still_open || !is_empty {
+                    buf.extend_from_slice(b"event: next\ndata: ");
+                    serde_json::to_writer(&mut buf, &response)?;
+                    buf.extend_from_slice(b"\n\n");
+                }
+                if !is_still_open {
+                    self.is_terminated = true;
+                    buf.extend_from_slice(COMPLETE);
+                }
+
+                Poll::Ready(Some(Ok(buf.into())))
+            }
+            Poll::Ready(Some(MessageKind::Eof)) => {
+                self.is_terminated = true;
+                Poll::Ready(Some(Ok(Bytes::from_static(COMPLETE))))
+            }
+            Poll::Ready(None) => {
+                self.is_terminated = true;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Decodes the events of a subscription received from a subgraph into GraphQL responses
+///
+/// The stream ends with the `complete` event. A transport error, or an event larger than
+/// [`MAX_EVENT_SIZE`], ends it with an error response.
+pub(crate) fn decode<S, E>(
+    service_name: String,
+    body: S,
+) -> impl Stream<Item = graphql::Response> + Send
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+    E: Display,
+{
+    futures::stream::unfold(
+        (body, Vec::new(), 0, false),
+        move |(mut body, mut buffer, mut scanned, terminated)| {
+            let service_name = service_name.clone();
+            async move {
+                if terminated {
+                    return None;
+                }
+                loop {
+                    // The bytes before `scanned` were already searched for the end of an event
+                    if let Some(position) = find(&buffer, b"\n\n", scanned) {
+                        let event: Vec<u8> = buffer.drain(..position + 2).collect();
+                        scanned = 0;
+                        match parse_event(&service_name, &event) {
+                            Event::Ignored => continue,
+                            Event::Complete => return None,
+                            Event::Next(response) => {
+                                return Some((response, (body, buffer, scanned, false)))
+                            }
+                        }
+                    }
+                    scanned = buffer.len().saturating_sub(1);
+                    if buffer.len() > MAX_EVENT_SIZE {
+                        let response = stream_error(
+                            &service_name,
+                            format!("an event is larger than {MAX_EVENT_SIZE} bytes"),
+                        );
+                        return Some((response, (body, buffer, scanned, true)));
+                    }
+                    match body.next().await {
+                        // Line endings can also be CRLF, and carriage returns are always escaped in JSON
+                        Some(Ok(chunk)) => {
+                            buffer.extend(chunk.iter().filter(|byte| **byte != b'\r'))
+                        }
+                        Some(Err(err)) => {
+                            let response = stream_error(&service_name, err);
+                            return Some((response, (body, buffer, scanned, true)));
+                        }
+                        None => return None,
+                    }
+                }
+            }
+        },
+    )
+}
+
+fn stream_error(service_name: &str, error: impl Display) -> graphql::Response {
+    graphql::Response::builder()
+        .error(
+            graphql::Error::builder()
+                .message(format!(
+                    "cannot read the subscription events from subgraph {service_name}: {error}"
+                ))
+                .extension_code("SUBSCRIPTION_STREAM_ERROR")
+                .build(),
+        )
+        .subscribed(false)
+        .build()
+}
+
+enum Event {
+    Next(graphql::Response),
+    Complete,
+    Ignored,
+}
+
+fn parse_event(service_name: &str, event: &[u8]) -> Event {
+    let mut name = None;
+    let mut data = Vec::new();
+    for line in event.split(|byte| *byte == b'\n') {
+        let (field, value) = match line.iter().position(|byte| *byte == b':') {
+            // comments are sent as heartbeats
+            Some(0) => continue,
+            Some(position) => {
+                let value = &line[position + 1..];
+                (&line[..position], value.strip_prefix(b" ").unwrap_or(value))
+            }
+            None => (line, &b""[..]),
+        };
+        match field {
+            b"event" => name = Some(value),
+            b"data" => {
+                if !data.is_empty() {
+                    data.push(b'\n');
+                }
+                data.extend_from_slice(value);
+            }
+            _ => {}
+        }
+    }
+
+    match name {
+        Some(b"complete") => Event::Complete,
+        Some(b"next") | None if !data.is_empty() => {
+            let mut response = graphql::Response::from_bytes(service_name, data.into())
+                .unwrap_or_else(|error| {
+                    graphql::Response::builder()
+                        .error(error.to_graphql_error(None))
+                        .build()
+                });
+            response.subscribed = Some(true);
+            Event::Next(response)
+        }
+        _ => Event::Ignored,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn it_sends_next_and_complete_events() {
+        let responses = vec![
+            graphql::Response::builder()
+                .data(serde_json_bytes::json!({"foo": 1}))
+                .subscribed(true)
+                .build(),
+            graphql::Response::builder()
+                .data(serde_json_bytes::json!({"foo": 2}))
+                .subscribed(true)
+                .build(),
+            graphql::Response::builder().build(),
+        ];
+
+        let events: Vec<String> =
+            EventStream::new(stream::iter(responses), ProtocolMode::Subscription)
+                .map(|chunk| String::from_utf8(chunk.unwrap().to_vec()).unwrap())
+                .filter(|chunk| futures::future::ready(chunk != ":\n\n"))
+                .collect()
+                .await;
+        assert_eq!(
+            events,
+            vec![
+                "event: next\ndata: {\"data\":{\"foo\":1}}\n\n",
+                "event: next\ndata: {\"data\":{\"foo\":2}}\n\n",
+                "event: complete\ndata:\n\n",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_completes_deferred_responses() {
+        let responses = vec![
+            graphql::Response::builder()
+                .data(serde_json_bytes::json!({"foo": 1}))
+                .has_next(true)
+                .build(),
+            graphql::Response::builder()
+                .incremental(vec![graphql::IncrementalResponse::builder()
+                    .data(serde_json_bytes::json!({"bar": 2}))
+                    .path(crate::json_ext::Path::default())
+                    .build()])
+                .has_next(false)
+                .build(),
+        ];
+
+        let events: Vec<String> = EventStream::new(stream::iter(responses), ProtocolMode::Defer)
+            .map(|chunk| String::from_utf8(chunk.unwrap().to_vec()).unwrap())
+            .collect()
+            .await;
+        assert_eq!(
+            events,
+            vec![
+                "event: next\ndata: {\"data\":{\"foo\":1},\"hasNext\":true}\n\n",
+                "event: next\ndata: {\"hasNext\":false,\"incremental\":[{\"data\":{\"bar\":2},\"path\":[]}]}\n\nevent: complete\ndata:\n\n",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_completes_empty_streams() {
+        let events: Vec<String> = EventStream::new(stream::empty(), ProtocolMode::Subscription)
+            .map(|chunk| String::from_utf8(chunk.unwrap().to_vec()).unwrap())
+            .filter(|chunk| futures::future::ready(chunk != ":\n\n"))
+            .collect()
+            .await;
+        assert_eq!(events, vec!["event: complete\ndata:\n\n"]);
+    }
+
+    #[tokio::test]
+    async fn it_decodes_subgraph_events() {
+        let chunks = vec![
+            ":\n\n",
+            "event: next\r\ndata: {\"data\":{\"foo\"",
+            ":1}}\r\n",
+            "\r\nevent: next\ndata: {\"data\":{\"foo\":2}}\n\n",
+            "event: complete\ndata:\n\n",
+            "event: next\ndata: {\"data\":{\"foo\":3}}\n\n",
+        ];
+        let body = stream::iter(
+            chunks
+                .into_iter()
+                .map(|chunk| Ok::<_, std::io::Error>(Bytes::from(chunk))),
+        );
+
+        let responses: Vec<graphql::Response> = decode("test".to_string(), body).collect().await;
+        assert_eq!(
+            responses,
+            vec![
+                graphql::Response::builder()
+                    .data(serde_json_bytes::json!({"foo": 1}))
+                    .subscribed(true)
+                    .build(),
+                graphql::Response::builder()
+                    .data(serde_json_bytes::json!({"foo": 2}))
+                    .subscribed(true)
+                    .build(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rejects_oversized_subgraph_events() {
+        let chunk = Bytes::from(vec![b'a'; 1024 * 1024]);
+        let body = stream::repeat(chunk).map(Ok::<_, std::io::Error>);
+
+        let responses: Vec<graphql::Response> = decode("test".to_string(), body).collect().await;
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].subscribed, Some(false));
+        assert_eq!(
+            responses[0].errors[0].extensions["code"],
+            "SUBSCRIPTION_STREAM_ERROR"
+        );
+    }
+}
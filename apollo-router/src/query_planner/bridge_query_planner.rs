@@ -42,12 +42,13 @@ use crate::executable::USING_CATCH_UNWIND;
 use crate::graphql;
 use crate::introspection::Introspection;
 use crate::json_ext::Object;
-use crate::json_ext::Path;
 use crate::metrics::meter_provider;
 use crate::plugins::authorization::AuthorizationPlugin;
 use crate::plugins::authorization::CacheKeyMetadata;
+use crate::plugins::authorization::UnauthorizedPath;
 use crate::plugins::authorization::UnauthorizedPaths;
 use crate::plugins::progressive_override::LABELS_TO_OVERRIDE_KEY;
+use crate::query_planner::defer_passthrough::with_defer_directive;
 use crate::query_planner::fetch::QueryHash;
 use crate::query_planner::fetch::SubgraphSchemas;
 use crate::query_planner::labeler::add_defer_labels;
@@ -437,7 +438,17 @@ impl BridgeQueryPlanner {
 
         let schema = Arc::new(schema.with_api_schema(api_schema));
 
-        let subgraph_schemas = Arc::new(planner.subgraphs().await?);
+        let mut subgraph_schemas = planner.subgraphs().await?;
+        for name in &configuration
+            .supergraph
+            .experimental_defer_passthrough
+            .subgraphs
+        {
+            if let Some(schema) = subgraph_schemas.get_mut(name) {
+                *schema = with_defer_directive(schema.clone())?;
+            }
+        }
+        let subgraph_schemas = Arc::new(subgraph_schemas);
 
         let introspection = if configuration.supergraph.introspection {
             Some(Arc::new(
@@ -513,6 +524,7 @@ impl BridgeQueryPlanner {
             unauthorized: UnauthorizedPaths {
                 paths: vec![],
                 errors: AuthorizationPlugin::log_errors(&self.configuration),
+                audited_types: vec![],
             },
             subselections,
             defer_stats,
@@ -557,6 +569,13 @@ impl BridgeQueryPlanner {
                 plan_options,
             )
             .await?;
+        let defer_passthrough = &self.configuration.supergraph.experimental_defer_passthrough;
+        if self.configuration.supergraph.defer_support && !defer_passthrough.subgraphs.is_empty() {
+            plan_success
+                .data
+                .query_plan
+                .pass_through_defer(&defer_passthrough.subgraphs, &self.subgraph_schemas);
+        }
         plan_success
             .data
             .query_plan
@@ -844,7 +863,7 @@ impl Service<QueryPlannerRequest> for BridgeQueryPlanner {
 }
 
 // Appease clippy::type_complexity
-pub(crate) type FilteredQuery = (Vec<Path>, ast::Document);
+pub(crate) type FilteredQuery = (Vec<UnauthorizedPath>, ast::Document);
 
 impl BridgeQueryPlanner {
     async fn get(
@@ -859,19 +878,24 @@ impl BridgeQueryPlanner {
                         .data(Object::new())
                         .errors(
                             unauthorized_paths
-                                .into_iter()
-                                .map(|path| {
+                                .iter()
+                                .map(|unauthorized| {
                                     graphql::Error::builder()
                                         .message("Unauthorized field or type")
-                                        .path(path)
+                                        .path(unauthorized.path.clone())
                                         .extension_code("UNAUTHORIZED_FIELD_OR_TYPE")
                                         .build()
                                 })
                                 .collect(),
                         )
                         .build();
-                    return Ok(QueryPlannerContent::Response {
+                    return Ok(QueryPlannerContent::Unauthorized {
                         response: Box::new(response),
+                        unauthorized: UnauthorizedPaths {
+                            paths: unauthorized_paths,
+                            errors: AuthorizationPlugin::log_errors(&self.configuration),
+                            audited_types: vec![],
+                        },
                     });
                 }
                 other => other?,
@@ -908,6 +932,14 @@ impl BridgeQueryPlanner {
             selections.unauthorized.paths = unauthorized_paths;
         }
 
+        if self.enable_authorization_directives {
+            selections.unauthorized.audited_types = AuthorizationPlugin::audited_types(
+                &self.configuration,
+                &doc.executable,
+                key.operation_name.as_deref(),
+            );
+        }
+
         if selections.contains_introspection() {
             // It can happen if you have a statically skipped query like { get @skip(if: true) { id name }} because it will be statically filtered with {}
             if selections
@@ -999,6 +1031,12 @@ impl QueryPlan {
         Ok(())
     }
 
+    fn pass_through_defer(&mut self, subgraphs: &[String], subgraph_schemas: &SubgraphSchemas) {
+        if let Some(node) = self.node.as_mut() {
+            node.pass_through_defer(subgraphs, subgraph_schemas);
+        }
+    }
+
     fn extract_authorization_metadata(
         &mut self,
         schema: &Valid<apollo_compiler::Schema>,
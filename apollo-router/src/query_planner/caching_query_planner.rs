@@ -1,13 +1,12 @@
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::ops::ControlFlow;
 use std::ops::Deref;
 use std::sync::Arc;
 use std::task;
 
 use apollo_compiler::validation::Valid;
 use futures::future::BoxFuture;
-use indexmap::IndexMap;
-use query_planner::QueryPlannerPlugin;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use router_bridge::planner::PlanOptions;
@@ -15,6 +14,7 @@ use router_bridge::planner::Planner;
 use router_bridge::planner::UsageReporting;
 use sha2::Digest;
 use sha2::Sha256;
+use tower::buffer::Buffer;
 use tower::BoxError;
 use tower::ServiceBuilder;
 use tower::ServiceExt;
@@ -26,8 +26,10 @@ use crate::cache::storage::InMemoryCache;
 use crate::cache::DeduplicatingCache;
 use crate::error::CacheResolverError;
 use crate::error::QueryPlannerError;
+use crate::layers::ServiceBuilderExt;
 use crate::plugins::authorization::AuthorizationPlugin;
 use crate::plugins::authorization::CacheKeyMetadata;
+use crate::plugins::authorization::ScopesConfig;
 use crate::plugins::progressive_override::LABELS_TO_OVERRIDE_KEY;
 use crate::plugins::telemetry::utils::Timer;
 use crate::query_planner::labeler::add_defer_labels;
@@ -37,16 +39,16 @@ use crate::services::layers::persisted_queries::PersistedQueryLayer;
 use crate::services::layers::query_analysis::ParsedDocument;
 use crate::services::layers::query_analysis::QueryAnalysisLayer;
 use crate::services::query_planner;
+use crate::services::Plugins;
 use crate::services::QueryPlannerContent;
 use crate::services::QueryPlannerRequest;
 use crate::services::QueryPlannerResponse;
+use crate::spec::Query;
 use crate::spec::Schema;
 use crate::spec::SpecError;
 use crate::Configuration;
 use crate::Context;
 
-/// An [`IndexMap`] of available plugins.
-pub(crate) type Plugins = IndexMap<String, Box<dyn QueryPlannerPlugin>>;
 pub(crate) type InMemoryCachePlanner =
     InMemoryCache<CachingQueryKey, Result<QueryPlannerContent, Arc<QueryPlannerError>>>;
 
@@ -59,9 +61,11 @@ pub(crate) struct CachingQueryPlanner<T: Clone> {
         DeduplicatingCache<CachingQueryKey, Result<QueryPlannerContent, Arc<QueryPlannerError>>>,
     >,
     delegate: T,
+    /// The delegate planner wrapped by the `query_planner_service` hooks of the plugins
+    plugins_service: Buffer<query_planner::BoxService, QueryPlannerRequest>,
     schema: Arc<Schema>,
-    plugins: Arc<Plugins>,
     enable_authorization_directives: bool,
+    scopes_config: Arc<ScopesConfig>,
 }
 
 impl<T: Clone + 'static> CachingQueryPlanner<T>
@@ -77,8 +81,8 @@ where
     pub(crate) async fn new(
         delegate: T,
         schema: Arc<Schema>,
-        configuration: &Configuration,
-        plugins: Plugins,
+        configuration: Arc<Configuration>,
+        plugins: Arc<Plugins>,
     ) -> Result<CachingQueryPlanner<T>, BoxError> {
         let cache = Arc::new(
             DeduplicatingCache::from_configuration(
@@ -89,16 +93,62 @@ where
         );
 
         let enable_authorization_directives =
-            AuthorizationPlugin::enable_directives(configuration, &schema).unwrap_or(false);
+            AuthorizationPlugin::enable_directives(&configuration, &schema).unwrap_or(false);
+        let scopes_config = Arc::new(AuthorizationPlugin::scopes_config(&configuration));
+        let plugins_service =
+            Self::plugins_service(delegate.clone(), schema.clone(), configuration, &plugins);
         Ok(Self {
             cache,
             delegate,
+            plugins_service,
             schema,
-            plugins: Arc::new(plugins),
             enable_authorization_directives,
+            scopes_config,
         })
     }
 
+    /// Applies the `query_planner_service` hooks of the plugins around the delegate planner.
+    ///
+    /// If a plugin rewrites the query, the new query is parsed and validated again before it is planned.
+    fn plugins_service(
+        delegate: T,
+        schema: Arc<Schema>,
+        configuration: Arc<Configuration>,
+        plugins: &Plugins,
+    ) -> Buffer<query_planner::BoxService, QueryPlannerRequest> {
+        let service = ServiceBuilder::new()
+            .map_err(BoxError::from)
+            .checkpoint(move |request: QueryPlannerRequest| {
+                let rewritten = request
+                    .context
+                    .extensions()
+                    .lock()
+                    .get::<PlannedQuery>()
+                    .map_or(true, |planned| planned.0 != request.query);
+                if rewritten {
+                    let doc = Query::parse_document(
+                        &request.query,
+                        request.operation_name.as_deref(),
+                        &schema,
+                        &configuration,
+                    )?;
+                    request
+                        .context
+                        .extensions()
+                        .lock()
+                        .insert::<ParsedDocument>(doc);
+                }
+                Ok(ControlFlow::Continue(request))
+            })
+            .service(delegate)
+            .boxed();
+
+        let service = plugins.iter().rev().fold(service, |service, (_, plugin)| {
+            plugin.query_planner_service(service)
+        });
+        ServiceBuilder::new().buffered().service(service)
+    }
+
     pub(crate) fn previous_cache(&self) -> InMemoryCachePlanner {
         self.cache.in_memory_cache()
     }
@@ -117,15 +167,6 @@ where
             );
         });
 
-        let mut service = ServiceBuilder::new().service(
-            self.plugins
-                .iter()
-                .rev()
-                .fold(self.delegate.clone().boxed(), |acc, (_, e)| {
-                    e.query_planner_service(acc)
-                }),
-        );
-
         let mut cache_keys = {
             let cache = previous_cache.lock().await;
 
@@ -248,7 +289,12 @@ where
                 context.extensions().lock().insert::<ParsedDocument>(doc);
 
                 context.extensions().lock().insert(caching_key.metadata);
+                context
+                    .extensions()
+                    .lock()
+                    .insert(PlannedQuery(query.clone()));
 
+                let mut service = self.plugins_service.clone();
                 let request = QueryPlannerRequest {
                     query,
                     operation_name: operation,
@@ -261,17 +307,25 @@ where
                 };
 
                 match res {
-                    Ok(QueryPlannerResponse { content, .. }) => {
-                        if let Some(content) = content.clone() {
+                    Ok(QueryPlannerResponse {
+                        content, errors, ..
+                    }) => match content {
+                        Some(content) => {
                             count += 1;
                             tokio::spawn(async move {
-                                entry.insert(Ok(content.clone())).await;
+                                entry.insert(Ok(content)).await;
                             });
                         }
-                    }
+                        None => {
+                            let e = Arc::new(QueryPlannerError::Rejected(errors));
+                            tokio::spawn(async move {
+                                entry.send(Err(e)).await;
+                            });
+                        }
+                    },
                     Err(error) => {
                         count += 1;
-                        let e = Arc::new(error);
+                        let e = Arc::new(into_query_planner_error(error));
                         tokio::spawn(async move {
                             entry.insert(Err(e)).await;
                         });
@@ -351,11 +405,11 @@ where
     <T as tower::Service<QueryPlannerRequest>>::Future: Send,
 {
     async fn plan(
-        mut self,
+        self,
         request: query_planner::CachingRequest,
     ) -> Result<<T as tower::Service<QueryPlannerRequest>>::Response, CacheResolverError> {
         if self.enable_authorization_directives {
-            AuthorizationPlugin::update_cache_key(&request.context);
+            AuthorizationPlugin::update_cache_key(&request.context, &self.scopes_config);
         }
 
         let plan_options = PlanOptions {
@@ -407,6 +461,11 @@ where
                 query = modified_query.to_string();
             }
 
+            context
+                .extensions()
+                .lock()
+                .insert(PlannedQuery(query.clone()));
+            let mut service = self.plugins_service.clone();
             let request = QueryPlannerRequest::builder()
                 .query(query)
                 .and_operation_name(operation_name)
@@ -419,7 +478,13 @@ where
             // of restarting the query planner until another timeout
             tokio::task::spawn(
                 async move {
-                    let res = self.delegate.ready().await?.call(request).await;
+                    let res = service
+                        .ready()
+                        .await
+                        .map_err(into_query_planner_error)?
+                        .call(request)
+                        .await
+                        .map_err(into_query_planner_error);
 
                     match res {
                         Ok(QueryPlannerResponse {
@@ -427,10 +492,20 @@ where
                             context,
                             errors,
                         }) => {
-                            if let Some(content) = content.clone() {
-                                tokio::spawn(async move {
-                                    entry.insert(Ok(content)).await;
-                                });
+                            match content.clone() {
+                                Some(content) => {
+                                    tokio::spawn(async move {
+                                        entry.insert(Ok(content)).await;
+                                    });
+                                }
+                                // rejections are not cached, but the requests waiting for
+                                // the same query plan receive them too
+                                None => {
+                                    let e = Arc::new(QueryPlannerError::Rejected(errors.clone()));
+                                    tokio::spawn(async move {
+                                        entry.send(Err(e)).await;
+                                    });
+                                }
                             }
 
                             // This will be overridden when running in ApolloMetricsGenerationMode::New mode
@@ -515,6 +590,17 @@ where
     }
 }
 
+/// The query sent to the `query_planner_service` hooks, used to detect rewrites by the plugins
+struct PlannedQuery(String);
+
+/// Recovers the query planner errors that went through the `query_planner_service` hooks
+fn into_query_planner_error(error: BoxError) -> QueryPlannerError {
+    match error.downcast::<QueryPlannerError>() {
+        Ok(error) => *error,
+        Err(error) => QueryPlannerError::SpecError(SpecError::TransformError(error.to_string())),
+    }
+}
+
 fn stats_report_key_hash(stats_report_key: &str) -> String {
     let mut hasher = sha1::Sha1::new();
     hasher.update(stats_report_key.as_bytes());
@@ -582,8 +668,17 @@ mod tests {
     use test_log::test;
     use tower::Service;
 
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
     use super::*;
     use crate::error::PlanErrors;
+    use crate::graphql;
+    use crate::graphql::IntoGraphQLErrors;
+    use crate::plugin::DynPlugin;
+    use crate::plugin::PluginInit;
+    use crate::plugin::PluginUnstable;
     use crate::query_planner::QueryPlan;
     use crate::spec::Query;
     use crate::spec::Schema;
@@ -628,7 +723,7 @@ mod tests {
         let mut delegate = MockMyQueryPlanner::new();
         delegate.expect_clone().returning(|| {
             let mut planner = MockMyQueryPlanner::new();
-            planner.expect_sync_call().times(0..2).returning(|_| {
+            planner.expect_sync_call().times(0..3).returning(|_| {
                 Err(QueryPlannerError::from(PlanErrors {
                     errors: Default::default(),
                     usage_reporting: UsageReporting {
@@ -645,7 +740,7 @@ mod tests {
         let schema = Arc::new(Schema::parse_test(schema, &configuration).unwrap());
 
         let mut planner =
-            CachingQueryPlanner::new(delegate, schema.clone(), &configuration, IndexMap::new())
+            CachingQueryPlanner::new(delegate, schema.clone(), configuration, Default::default())
                 .await
                 .unwrap();
 
@@ -740,10 +835,14 @@ mod tests {
         )
         .unwrap();
 
-        let mut planner =
-            CachingQueryPlanner::new(delegate, Arc::new(schema), &configuration, IndexMap::new())
-                .await
-                .unwrap();
+        let mut planner = CachingQueryPlanner::new(
+            delegate,
+            Arc::new(schema),
+            Arc::new(configuration),
+            Default::default(),
+        )
+        .await
+        .unwrap();
 
         let context = Context::new();
         context.extensions().lock().insert::<ParsedDocument>(doc);
@@ -764,6 +863,118 @@ mod tests {
         }
     }
 
+    struct RejectingPlugin {
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl PluginUnstable for RejectingPlugin {
+        type Config = ();
+
+        async fn new(_init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+            Ok(RejectingPlugin {
+                calls: Default::default(),
+            })
+        }
+
+        fn query_planner_service(
+            &self,
+            _service: query_planner::BoxService,
+        ) -> query_planner::BoxService {
+            let calls = self.calls.clone();
+            tower::service_fn(move |request: QueryPlannerRequest| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    Ok(QueryPlannerResponse::builder()
+                        .context(request.context)
+                        .errors(vec![graphql::Error::builder()
+                            .message("rejected")
+                            .extension_code("REJECTED")
+                            .build()])
+                        .build())
+                }
+            })
+            .boxed()
+        }
+
+        fn unstable_method(&self) {}
+    }
+
+    // the plugin answers instead of the planner
+    fn unused_planner() -> MockMyQueryPlanner {
+        let mut planner = MockMyQueryPlanner::new();
+        planner.expect_clone().returning(unused_planner);
+        planner.expect_sync_call().times(0);
+        planner
+    }
+
+    #[test(tokio::test)]
+    async fn test_plugin_rejection_is_sent_to_waiting_requests() {
+        let delegate = unused_planner();
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut plugins = Plugins::new();
+        plugins.insert(
+            "rejecting".to_string(),
+            Box::new(RejectingPlugin {
+                calls: calls.clone(),
+            }) as Box<dyn DynPlugin>,
+        );
+
+        let configuration = Configuration::default();
+        let schema =
+            Schema::parse_test(include_str!("testdata/schema.graphql"), &configuration).unwrap();
+        let doc = Query::parse_document(
+            "query Me { me { username } }",
+            None,
+            &schema,
+            &configuration,
+        )
+        .unwrap();
+
+        let planner = CachingQueryPlanner::new(
+            delegate,
+            Arc::new(schema),
+            Arc::new(configuration),
+            Arc::new(plugins),
+        )
+        .await
+        .unwrap();
+
+        let request = || {
+            let context = Context::new();
+            context
+                .extensions()
+                .lock()
+                .insert::<ParsedDocument>(doc.clone());
+            query_planner::CachingRequest::new(
+                "query Me { me { username } }".to_string(),
+                Some("".into()),
+                context,
+            )
+        };
+
+        let (first, second) = futures::join!(
+            planner.clone().oneshot(request()),
+            planner.clone().oneshot(request())
+        );
+        // the first request plans the query, the second one waits for its result
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let first = first.unwrap();
+        assert!(first.content.is_none());
+        assert_eq!(first.errors[0].message, "rejected");
+        let second = second.unwrap_err().into_graphql_errors().unwrap();
+        assert_eq!(second, first.errors);
+
+        // the rejection is not cached
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let third = planner.clone().oneshot(request()).await.unwrap();
+        assert_eq!(third.errors, first.errors);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
     #[test]
     fn apollo_operation_id_hash() {
         assert_eq!(
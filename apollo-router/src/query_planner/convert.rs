@@ -88,6 +88,7 @@ impl From<&'_ Box<next::FetchNode>> for plan::PlanNode {
             output_rewrites: option_vec(output_rewrites),
             schema_aware_hash: Default::default(),
             authorization: Default::default(),
+            deferred: Default::default(),
         })
     }
 }
@@ -0,0 +1,359 @@
+//! `@defer` passthrough to subgraphs supporting incremental delivery.
+//!
+//! The query planner implements `@defer` in the router: a deferred fragment is fetched with an
+//! additional fetch, started once the primary fetches it depends on return. When that fetch goes
+//! to the same subgraph as the root fetch it depends on, and that subgraph supports `@defer`, the
+//! deferred fragment is added to the root fetch with a `@defer` directive instead, and the
+//! subgraph sends its data incrementally, without an additional round trip.
+//!
+//! The deferred fetch stays in the plan: it is only executed if the subgraph did not send the
+//! data of the fragment (if the subgraph response was deduplicated or cached, for example).
+
+use std::sync::Arc;
+
+use apollo_compiler::ast;
+use apollo_compiler::executable::Directive;
+use apollo_compiler::executable::InlineFragment;
+use apollo_compiler::executable::Selection;
+use apollo_compiler::executable::SelectionSet;
+use apollo_compiler::name;
+use apollo_compiler::validation::Valid;
+use apollo_compiler::Node;
+use apollo_compiler::NodeStr;
+use apollo_compiler::Schema;
+
+use super::fetch::FetchNode;
+use super::fetch::SubgraphOperation;
+use super::fetch::SubgraphSchemas;
+use super::plan::Depends;
+use super::plan::PlanNode;
+use super::FlattenNode;
+use crate::error::SchemaError;
+use crate::json_ext::Path;
+use crate::json_ext::PathElement;
+
+const DEFER_DIRECTIVE_DEFINITION: &str =
+    "directive @defer(label: String, if: Boolean! = true) on FRAGMENT_SPREAD | INLINE_FRAGMENT";
+
+/// Adds the `@defer` directive definition to a subgraph schema, if it is not already defined
+pub(crate) fn with_defer_directive(
+    schema: Arc<Valid<Schema>>,
+) -> Result<Arc<Valid<Schema>>, SchemaError> {
+    if schema.directive_definitions.contains_key(&name!("defer")) {
+        return Ok(schema);
+    }
+    let document = ast::Document::parse(DEFER_DIRECTIVE_DEFINITION, "defer.graphql")
+        .map_err(|errors| SchemaError::Validate(errors.into()))?;
+    let mut schema = schema.as_ref().clone().into_inner();
+    for definition in document.definitions {
+        if let ast::Definition::DirectiveDefinition(definition) = definition {
+            schema
+                .directive_definitions
+                .insert(definition.name.clone(), definition);
+        }
+    }
+    Ok(Arc::new(
+        schema
+            .validate()
+            .map_err(|errors| SchemaError::Validate(errors.into()))?,
+    ))
+}
+
+impl PlanNode {
+    /// Moves the deferred fragments fetched from the listed subgraphs to the root fetch they
+    /// depend on, when possible
+    pub(crate) fn pass_through_defer(
+        &mut self,
+        subgraphs: &[String],
+        subgraph_schemas: &SubgraphSchemas,
+    ) {
+        match self {
+            PlanNode::Sequence { nodes } | PlanNode::Parallel { nodes } => {
+                for node in nodes {
+                    node.pass_through_defer(subgraphs, subgraph_schemas);
+                }
+            }
+            PlanNode::Flatten(flatten) => {
+                flatten.node.pass_through_defer(subgraphs, subgraph_schemas)
+            }
+            PlanNode::Condition {
+                if_clause,
+                else_clause,
+                ..
+            } => {
+                if let Some(node) = if_clause.as_mut() {
+                    node.pass_through_defer(subgraphs, subgraph_schemas);
+                }
+                if let Some(node) = else_clause.as_mut() {
+                    node.pass_through_defer(subgraphs, subgraph_schemas);
+                }
+            }
+            PlanNode::Defer { primary, deferred } => {
+                if let Some(primary_node) = primary.node.as_mut() {
+                    for deferred_node in deferred.iter() {
+                        let ([Depends { id }], Some(label), Some(node)) = (
+                            deferred_node.depends.as_slice(),
+                            &deferred_node.label,
+                            &deferred_node.node,
+                        ) else {
+                            continue;
+                        };
+                        let PlanNode::Flatten(FlattenNode { path, node }) = node.as_ref() else {
+                            continue;
+                        };
+                        let PlanNode::Fetch(entity_fetch) = node.as_ref() else {
+                            continue;
+                        };
+                        let Some(root_fetch) = primary_node.root_fetch_mut(id) else {
+                            continue;
+                        };
+                        if root_fetch.service_name == entity_fetch.service_name
+                            && subgraphs
+                                .iter()
+                                .any(|name| name.as_str() == root_fetch.service_name.as_str())
+                            && !entity_fetch.requires.is_empty()
+                            && entity_fetch.output_rewrites.is_none()
+                        {
+                            if let Some(schema) =
+                                subgraph_schemas.get(root_fetch.service_name.as_str())
+                            {
+                                root_fetch.merge_deferred_fetch(entity_fetch, path, label, schema);
+                            }
+                        }
+                    }
+                }
+                for deferred_node in deferred.iter_mut() {
+                    if let Some(node) = deferred_node.node.as_mut() {
+                        Arc::make_mut(node).pass_through_defer(subgraphs, subgraph_schemas);
+                    }
+                }
+            }
+            PlanNode::Fetch(_) | PlanNode::Subscription { .. } => {}
+        }
+    }
+
+    /// Finds the fetch at the root of the response with this id
+    fn root_fetch_mut(&mut self, id: &NodeStr) -> Option<&mut FetchNode> {
+        match self {
+            PlanNode::Fetch(fetch) => (fetch.id.as_ref() == Some(id)
+                && fetch.requires.is_empty()
+                && fetch.output_rewrites.is_none())
+            .then_some(fetch),
+            PlanNode::Sequence { nodes } | PlanNode::Parallel { nodes } => {
+                nodes.iter_mut().find_map(|node| node.root_fetch_mut(id))
+            }
+            PlanNode::Condition {
+                if_clause,
+                else_clause,
+                ..
+            } => {
+                if let Some(fetch) = if_clause.as_mut().and_then(|node| node.root_fetch_mut(id)) {
+                    return Some(fetch);
+                }
+                else_clause
+                    .as_mut()
+                    .and_then(|node| node.root_fetch_mut(id))
+            }
+            PlanNode::Flatten(_) | PlanNode::Defer { .. } | PlanNode::Subscription { .. } => None,
+        }
+    }
+}
+
+impl FetchNode {
+    /// Adds the entities selected by `entity_fetch` at `path` to this fetch, in fragments deferred
+    /// with `label`. This fetch is left unchanged if the resulting operation is not valid for the
+    /// subgraph.
+    fn merge_deferred_fetch(
+        &mut self,
+        entity_fetch: &FetchNode,
+        path: &Path,
+        label: &NodeStr,
+        schema: &Valid<Schema>,
+    ) {
+        let (Ok(document), Ok(entity_document)) = (
+            self.operation.as_parsed(schema),
+            entity_fetch.operation.as_parsed(schema),
+        ) else {
+            return;
+        };
+        let Ok(entity_operation) = entity_document.get_operation(
+            entity_fetch
+                .operation_name
+                .as_ref()
+                .map(|name| name.as_str()),
+        ) else {
+            return;
+        };
+        // the entity fetch has the shape `{ _entities(representations: $representations) { ... on T { ... } } }`
+        let [Selection::Field(entities)] = entity_operation.selection_set.selections.as_slice()
+        else {
+            return;
+        };
+        if entities.name.as_str() != "_entities" {
+            return;
+        }
+        let mut fragments: Vec<InlineFragment> = Vec::new();
+        for selection in &entities.selection_set.selections {
+            match selection {
+                Selection::InlineFragment(fragment)
+                    if fragment.type_condition.is_some() && fragment.directives.is_empty() =>
+                {
+                    fragments.push(fragment.as_ref().clone())
+                }
+                Selection::Field(field) if field.name.as_str() == "__typename" => {}
+                _ => return,
+            }
+        }
+
+        let mut document = document.as_ref().clone().into_inner();
+        for (name, fragment) in &entity_document.fragments {
+            match document.fragments.get(name) {
+                Some(existing) if existing != fragment => return,
+                Some(_) => {}
+                None => {
+                    document.fragments.insert(name.clone(), fragment.clone());
+                }
+            }
+        }
+        let Ok(operation) =
+            document.get_operation_mut(self.operation_name.as_ref().map(|name| name.as_str()))
+        else {
+            return;
+        };
+        let mut variable_usages = Vec::new();
+        for variable in &entity_operation.variables {
+            if variable.name.as_str() == "representations" {
+                continue;
+            }
+            if !operation.variables.iter().any(|v| v.name == variable.name) {
+                operation.variables.push(variable.clone());
+            }
+            variable_usages.push(NodeStr::new(variable.name.as_str()));
+        }
+        let Some(selection_set) = selection_set_at_path(&mut operation.selection_set, path) else {
+            return;
+        };
+        let defer = Node::new(Directive {
+            name: name!("defer"),
+            arguments: vec![Node::new(ast::Argument {
+                name: name!("label"),
+                value: Node::new(ast::Value::String(label.clone())),
+            })],
+        });
+        for mut fragment in fragments {
+            fragment.directives.push(defer.clone());
+            selection_set.push(fragment);
+        }
+        let Ok(document) = document.validate(schema) else {
+            return;
+        };
+
+        self.operation = SubgraphOperation::from_parsed(document);
+        for variable in variable_usages {
+            if !self.variable_usages.contains(&variable) {
+                self.variable_usages.push(variable);
+            }
+        }
+        self.deferred.push(label.clone());
+    }
+}
+
+fn selection_set_at_path<'a>(
+    mut selection_set: &'a mut SelectionSet,
+    path: &Path,
+) -> Option<&'a mut SelectionSet> {
+    for element in path.iter() {
+        match element {
+            PathElement::Flatten(None) => {}
+            PathElement::Key(key, None) => {
+                selection_set =
+                    selection_set
+                        .selections
+                        .iter_mut()
+                        .find_map(|selection| match selection {
+                            Selection::Field(field) if field.response_key().as_str() == key => {
+                                Some(&mut field.make_mut().selection_set)
+                            }
+                            _ => None,
+                        })?;
+            }
+            _ => return None,
+        }
+    }
+    Some(selection_set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUBGRAPH_SCHEMA: &str = r#"
+        type Query {
+          me: User
+          _entities(representations: [_Any!]!): [_Entity]!
+        }
+        type User {
+          id: ID!
+          name: String
+          username: String
+        }
+        scalar _Any
+        union _Entity = User
+    "#;
+
+    fn operation(node: &PlanNode) -> &str {
+        match node {
+            PlanNode::Fetch(fetch) => fetch.operation.as_serialized(),
+            _ => panic!("expected a fetch node"),
+        }
+    }
+
+    #[test]
+    fn it_passes_defer_through_to_the_subgraph() {
+        let schema = Schema::parse_and_validate(SUBGRAPH_SCHEMA, "accounts.graphql").unwrap();
+        let schema = with_defer_directive(Arc::new(schema)).unwrap();
+        let subgraph_schemas: SubgraphSchemas = [("accounts".to_string(), schema)].into();
+
+        let mut plan: PlanNode =
+            serde_json::from_str(include_str!("testdata/defer_clause_plan.json")).unwrap();
+        let PlanNode::Condition { if_clause, .. } = &mut plan else {
+            panic!("expected a condition node");
+        };
+        let Some(PlanNode::Defer { deferred, .. }) = if_clause.as_deref_mut() else {
+            panic!("expected a defer node");
+        };
+        deferred[0].label = Some("name".into());
+
+        // subgraphs not configured for passthrough are unchanged
+        let unchanged = plan.clone();
+        plan.pass_through_defer(&["reviews".to_string()], &subgraph_schemas);
+        assert_eq!(plan, unchanged);
+
+        plan.pass_through_defer(&["accounts".to_string()], &subgraph_schemas);
+        let PlanNode::Condition { if_clause, .. } = &plan else {
+            panic!("expected a condition node");
+        };
+        let Some(PlanNode::Defer { primary, deferred }) = if_clause.as_deref() else {
+            panic!("expected a defer node");
+        };
+        let primary = primary.node.as_deref().unwrap();
+        insta::assert_snapshot!(operation(primary), @r###"
+        query Me__accounts__0 {
+          me {
+            __typename
+            id
+            ... on User @defer(label: "name") {
+              name
+              username
+            }
+          }
+        }
+        "###);
+        let PlanNode::Fetch(fetch) = primary else {
+            panic!("expected a fetch node");
+        };
+        assert_eq!(fetch.deferred, vec![NodeStr::new("name")]);
+        // the deferred fetch is kept in case the subgraph does not send the deferred data
+        assert!(deferred[0].node.is_some());
+    }
+}
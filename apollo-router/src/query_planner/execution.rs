@@ -6,7 +6,6 @@ use futures::future::join_all;
 use futures::prelude::*;
 use tokio::sync::broadcast;
 use tokio::sync::mpsc;
-use tokio_stream::wrappers::BroadcastStream;
 use tracing::Instrument;
 
 use super::log;
@@ -85,13 +84,47 @@ impl QueryPlan {
 
         Response::builder().data(value).errors(errors).build()
     }
+}
 
-    pub fn contains_mutations(&self) -> bool {
-        self.root.contains_mutations()
-    }
+/// Result of a fetch that deferred nodes depend on
+///
+/// A fetch answered incrementally sends one result per payload, each one holding everything
+/// received so far, so a receiver that lags behind only needs the latest one.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DeferredFetchResult {
+    pub(crate) value: Value,
+    pub(crate) errors: Vec<Error>,
+    /// Labels of the deferred fragments whose data the subgraph already sent incrementally
+    pub(crate) labels: Vec<String>,
+    /// Whether the subgraph will not send anything more
+    pub(crate) complete: bool,
+}
 
-    pub fn subgraph_fetches(&self) -> usize {
-        self.root.subgraph_fetches()
+impl DeferredFetchResult {
+    /// Waits until the fetch is complete, or until the subgraph sent the data of the deferred
+    /// fragment with this `label`, and returns the latest result
+    async fn wait_for(
+        mut receiver: broadcast::Receiver<Self>,
+        label: Option<String>,
+    ) -> Option<Self> {
+        let mut latest = None;
+        loop {
+            match receiver.recv().await {
+                Ok(result) => {
+                    let done = result.complete
+                        || label
+                            .as_ref()
+                            .is_some_and(|label| result.labels.contains(label));
+                    latest = Some(result);
+                    if done {
+                        return latest;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                // the fetch was not performed, possibly because there was no need to do it
+                Err(broadcast::error::RecvError::Closed) => return latest,
+            }
+        }
     }
 }
 
@@ -101,7 +134,7 @@ pub(crate) struct ExecutionParameters<'a> {
     pub(crate) service_factory: &'a Arc<SubgraphServiceFactory>,
     pub(crate) schema: &'a Arc<Schema>,
     pub(crate) supergraph_request: &'a Arc<http::Request<Request>>,
-    pub(crate) deferred_fetches: &'a HashMap<NodeStr, broadcast::Sender<(Value, Vec<Error>)>>,
+    pub(crate) deferred_fetches: &'a HashMap<NodeStr, broadcast::Sender<DeferredFetchResult>>,
     pub(crate) query: &'a Arc<Query>,
     pub(crate) root_node: &'a PlanNode,
     pub(crate) subscription_handle: &'a Option<SubscriptionHandle>,
@@ -255,7 +288,7 @@ impl PlanNode {
                     async {
                         let mut deferred_fetches: HashMap<
                             NodeStr,
-                            broadcast::Sender<(Value, Vec<Error>)>,
+                            broadcast::Sender<DeferredFetchResult>,
                         > = HashMap::new();
                         let mut futures = Vec::new();
 
@@ -403,22 +436,21 @@ impl DeferredNode {
         parent_value: &Value,
         sender: mpsc::Sender<Response>,
         primary_sender: &broadcast::Sender<(Value, Vec<Error>)>,
-        deferred_fetches: &mut HashMap<NodeStr, broadcast::Sender<(Value, Vec<Error>)>>,
+        deferred_fetches: &mut HashMap<NodeStr, broadcast::Sender<DeferredFetchResult>>,
     ) -> impl Future<Output = ()> {
         let mut deferred_receivers = Vec::new();
+        let label = self.label.as_ref().map(|l| l.to_string());
 
         for d in self.depends.iter() {
-            match deferred_fetches.get(&d.id) {
+            let receiver = match deferred_fetches.get(&d.id) {
                 None => {
                     let (sender, receiver) = tokio::sync::broadcast::channel(1);
-                    deferred_fetches.insert(d.id.clone(), sender.clone());
-                    deferred_receivers.push(BroadcastStream::new(receiver).into_future());
-                }
-                Some(sender) => {
-                    let receiver = sender.subscribe();
-                    deferred_receivers.push(BroadcastStream::new(receiver).into_future());
+                    deferred_fetches.insert(d.id.clone(), sender);
+                    receiver
                 }
-            }
+                Some(sender) => sender.subscribe(),
+            };
+            deferred_receivers.push(DeferredFetchResult::wait_for(receiver, label.clone()));
         }
 
         // if a deferred node has no depends (ie not waiting for data from fetches) then it has to
@@ -430,9 +462,8 @@ impl DeferredNode {
 
         let mut stream: stream::FuturesUnordered<_> = deferred_receivers.into_iter().collect();
         //FIXME/ is there a solution without cloning the entire node? Maybe it could be moved instead?
-        let deferred_inner = self.node.clone();
+        let mut deferred_inner = self.node.clone();
         let deferred_path = self.query_path.clone();
-        let label = self.label.as_ref().map(|l| l.to_string());
         let tx = sender;
         let sc = parameters.schema.clone();
         let orig = parameters.supergraph_request.clone();
@@ -454,14 +485,17 @@ impl DeferredNode {
                 value.deep_merge(primary_value);
                 errors.extend(primary_errors)
             } else {
-                while let Some((v, _remaining)) = stream.next().await {
-                    // a Err(RecvError) means either that the fetch was not performed and the
-                    // sender was dropped, possibly because there was no need to do it,
-                    // or because it is lagging, but here we only send one message so it
-                    // will not happen
-                    if let Some(Ok((deferred_value, err))) = v {
-                        value.deep_merge(deferred_value);
-                        errors.extend(err.into_iter())
+                while let Some(result) = stream.next().await {
+                    if let Some(result) = result {
+                        value.deep_merge(result.value);
+                        errors.extend(result.errors);
+                        // the subgraph already sent the data of this deferred fragment
+                        if label
+                            .as_ref()
+                            .is_some_and(|label| result.labels.contains(label))
+                        {
+                            deferred_inner = None;
+                        }
                     }
                 }
             }
@@ -5,14 +5,19 @@ use std::sync::Arc;
 use apollo_compiler::validation::Valid;
 use apollo_compiler::ExecutableDocument;
 use apollo_compiler::NodeStr;
+use futures::stream::BoxStream;
+use futures::StreamExt;
 use indexmap::IndexSet;
 use once_cell::sync::OnceCell as OnceLock;
 use serde::Deserialize;
 use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc;
 use tower::ServiceExt;
 use tracing::instrument;
 use tracing::Instrument;
 
+use super::execution::DeferredFetchResult;
 use super::execution::ExecutionParameters;
 use super::rewrites;
 use super::selection::execute_selection_set;
@@ -133,6 +138,11 @@ pub(crate) struct FetchNode {
     // authorization metadata for the subgraph query
     #[serde(default)]
     pub(crate) authorization: Arc<CacheKeyMetadata>,
+
+    // labels of the `@defer` fragments passed through to the subgraph in this fetch, their data
+    // is received incrementally
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) deferred: Vec<NodeStr>,
 }
 
 #[derive(Clone)]
@@ -403,6 +413,13 @@ impl FetchNode {
             .build();
         subgraph_request.query_hash = self.schema_aware_hash.clone();
         subgraph_request.authorization = self.authorization.clone();
+        let deferred_receiver = if self.deferred.is_empty() {
+            None
+        } else {
+            let (sender, receiver) = mpsc::channel(1);
+            subgraph_request.deferred_stream = Some(sender);
+            Some(receiver)
+        };
 
         let service = parameters
             .service_factory
@@ -463,8 +480,30 @@ impl FetchNode {
         if let Some(id) = &self.id {
             if let Some(sender) = parameters.deferred_fetches.get(id.as_str()) {
                 tracing::info!(monotonic_counter.apollo.router.operations.defer.fetch = 1u64);
-                if let Err(e) = sender.clone().send((value.clone(), errors.clone())) {
-                    tracing::error!("error sending fetch result at path {} and id {:?} for deferred response building: {}", current_dir, self.id, e);
+                match deferred_receiver {
+                    // the deferred nodes wait for the data that the subgraph sends incrementally
+                    Some(receiver) => {
+                        tokio::task::spawn(
+                            receive_deferred(
+                                receiver,
+                                sender.clone(),
+                                value.clone(),
+                                errors.clone(),
+                                current_dir.clone(),
+                            )
+                            .in_current_span(),
+                        );
+                    }
+                    None => {
+                        if let Err(e) = sender.clone().send(DeferredFetchResult {
+                            value: value.clone(),
+                            errors: errors.clone(),
+                            labels: Vec::new(),
+                            complete: true,
+                        }) {
+                            tracing::error!("error sending fetch result at path {} and id {:?} for deferred response building: {}", current_dir, self.id, e);
+                        }
+                    }
                 }
             }
         }
@@ -598,7 +637,6 @@ impl FetchNode {
         }
     }
 
-    #[cfg(test)]
     pub(crate) fn service_name(&self) -> &str {
         &self.service_name
     }
@@ -653,3 +691,73 @@ impl FetchNode {
         ));
     }
 }
+
+/// Merges the subsequent payloads of an incremental subgraph response into the fetch result,
+/// and sends the result to the deferred nodes after each payload, so that a deferred fragment
+/// sent by the subgraph is released as soon as it is received
+async fn receive_deferred(
+    mut receiver: mpsc::Receiver<BoxStream<'static, graphql::Response>>,
+    sender: broadcast::Sender<DeferredFetchResult>,
+    value: Value,
+    errors: Vec<Error>,
+    current_dir: Path,
+) {
+    let mut result = DeferredFetchResult {
+        value,
+        errors,
+        labels: Vec::new(),
+        complete: false,
+    };
+    // no stream is sent if the subgraph answered with a single response
+    if let Some(mut stream) = receiver.recv().await {
+        while let Some(response) = stream.next().await {
+            result.errors.extend(response.errors);
+            for incremental in response.incremental {
+                let path = Path::from_iter(
+                    current_dir
+                        .iter()
+                        .chain(incremental.path.iter().flat_map(|path| path.iter()))
+                        .cloned(),
+                );
+                if let Some(data) = incremental.data {
+                    merge_at_path(&mut result.value, &path, data);
+                }
+                result.errors.extend(incremental.errors);
+                result.labels.extend(incremental.label);
+            }
+            if !response.has_next.unwrap_or(false) {
+                break;
+            }
+            // every deferred node already has what it waits for
+            if sender.send(result.clone()).is_err() {
+                return;
+            }
+        }
+    }
+    result.complete = true;
+    if let Err(e) = sender.send(result) {
+        tracing::error!(
+            "error sending incremental fetch result at path {} for deferred response building: {}",
+            current_dir,
+            e
+        );
+    }
+}
+
+fn merge_at_path(value: &mut Value, path: &Path, data: Value) {
+    let mut current = value;
+    for element in path.iter() {
+        let next = match (element, current) {
+            (json_ext::PathElement::Key(key, _), Value::Object(object)) => {
+                object.get_mut(key.as_str())
+            }
+            (json_ext::PathElement::Index(index), Value::Array(array)) => array.get_mut(*index),
+            _ => None,
+        };
+        match next {
+            Some(next) => current = next,
+            None => return,
+        }
+    }
+    current.deep_merge(data);
+}
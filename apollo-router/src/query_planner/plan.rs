@@ -67,6 +67,27 @@ impl QueryPlan {
 }
 
 impl QueryPlan {
+    /// The query plan in the text format used by the `Apollo-Expose-Query-Plan` header, if it
+    /// was generated
+    pub fn formatted(&self) -> Option<&str> {
+        self.formatted_query_plan.as_deref()
+    }
+
+    /// The number of fetches to subgraphs in the plan
+    pub fn subgraph_fetches(&self) -> usize {
+        self.root.subgraph_fetches()
+    }
+
+    /// The names of the subgraphs fetched by the plan, once per fetch
+    pub fn subgraphs(&self) -> impl Iterator<Item = &str> + '_ {
+        self.root.service_usage()
+    }
+
+    /// Whether the plan fetches mutations
+    pub fn contains_mutations(&self) -> bool {
+        self.root.contains_mutations()
+    }
+
     pub(crate) fn is_deferred(&self, operation: Option<&str>, variables: &Object) -> bool {
         self.root.is_deferred(operation, variables, &self.query)
     }
@@ -376,7 +397,6 @@ impl PlanNode {
         Ok(())
     }
 
-    #[cfg(test)]
     /// Retrieves all the services used across all plan nodes.
     ///
     /// Note that duplicates are not filtered.
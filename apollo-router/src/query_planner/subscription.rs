@@ -1,8 +1,11 @@
+use std::collections::HashMap;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 
 use apollo_compiler::NodeStr;
 use futures::future;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json_bytes::Value;
@@ -27,6 +30,48 @@ use crate::services::SubscriptionTaskParams;
 
 pub(crate) const SUBSCRIPTION_EVENT_SPAN_NAME: &str = "subscription_event";
 pub(crate) static OPENED_SUBSCRIPTIONS: AtomicUsize = AtomicUsize::new(0);
+/// Number of opened subscriptions for each client, when per client limits are configured
+static OPENED_CLIENT_SUBSCRIPTIONS: Lazy<Mutex<HashMap<String, usize>>> =
+    Lazy::new(Default::default);
+
+/// Counts a subscription opened by a client until it is dropped
+pub(crate) struct OpenedClientSubscription {
+    client: String,
+}
+
+impl OpenedClientSubscription {
+    /// Counts a new subscription for `client`, unless it already has `max` opened subscriptions
+    ///
+    /// The check and the increment happen under the same lock, so concurrent subscriptions of a
+    /// client cannot exceed the limit.
+    pub(crate) fn try_acquire(client: &str, max: usize) -> Option<Self> {
+        let mut opened = OPENED_CLIENT_SUBSCRIPTIONS.lock();
+        let count = opened.entry(client.to_string()).or_default();
+        if *count >= max {
+            if *count == 0 {
+                opened.remove(client);
+            }
+            return None;
+        }
+        *count += 1;
+        Some(Self {
+            client: client.to_string(),
+        })
+    }
+}
+
+impl Drop for OpenedClientSubscription {
+    fn drop(&mut self) {
+        let mut opened = OPENED_CLIENT_SUBSCRIPTIONS.lock();
+        if let Some(count) = opened.get_mut(&self.client) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                opened.remove(&self.client);
+            }
+        }
+    }
+}
+
 pub(crate) struct SubscriptionHandle {
     pub(crate) closed_signal: broadcast::Receiver<()>,
     pub(crate) subscription_conf_tx: Option<tokio::sync::mpsc::Sender<SubscriptionTaskParams>>,
@@ -109,6 +154,36 @@ impl SubscriptionNode {
                 });
             }
         }
+        // The subscription counts toward the client's limit from now on, and until the
+        // subscription task drops it
+        let mut opened_client_subscription = None;
+        if let Some(per_client) = parameters
+            .subscription_config
+            .as_ref()
+            .and_then(|s| s.per_client.as_ref())
+        {
+            if let Some(client) = per_client
+                .key
+                .client_id(parameters.context, parameters.supergraph_request.headers())
+            {
+                match OpenedClientSubscription::try_acquire(
+                    &client,
+                    per_client.max_opened_subscriptions,
+                ) {
+                    Some(opened) => opened_client_subscription = Some(opened),
+                    None => {
+                        return Box::pin(async {
+                            vec![Error::builder()
+                                .message(
+                                    "can't open new subscription, limit reached for this client",
+                                )
+                                .extension_code("SUBSCRIPTION_CLIENT_MAX_LIMIT")
+                                .build()]
+                        });
+                    }
+                }
+            }
+        }
         let subscription_handle = parameters
             .subscription_handle
             .as_ref()
@@ -152,6 +227,7 @@ impl SubscriptionNode {
                         subscription_config,
                         stream_rx: rx_handle.into(),
                         service_name: self.service_name.to_string(),
+                        opened_client_subscription,
                     };
 
                     if let Err(err) = subscription_conf_tx.send(subs_params).await {
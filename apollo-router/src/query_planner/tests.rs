@@ -67,6 +67,20 @@ fn service_usage() {
     );
 }
 
+#[test]
+fn query_plan_accessors() {
+    let query_plan = QueryPlan::fake_builder()
+        .root(serde_json::from_str::<PlanNode>(test_query_plan!()).unwrap())
+        .build();
+    assert_eq!(query_plan.formatted(), None);
+    assert_eq!(query_plan.subgraph_fetches(), 5);
+    assert_eq!(
+        query_plan.subgraphs().collect::<Vec<_>>(),
+        vec!["product", "books", "product", "books", "product"]
+    );
+    assert!(!query_plan.contains_mutations());
+}
+
 /// This test panics in the product subgraph. HOWEVER, this does not result in a panic in the
 /// test, since the buffer() functionality in the tower stack "loses" the panic and we end up
 /// with a closed service.
@@ -268,6 +282,7 @@ async fn defer() {
                         output_rewrites: None,
                         schema_aware_hash: Default::default(),
                         authorization: Default::default(),
+                        deferred: Default::default(),
                     }))),
                 },
                 deferred: vec![DeferredNode {
@@ -313,6 +328,7 @@ async fn defer() {
                             output_rewrites: None,
                             schema_aware_hash: Default::default(),
                             authorization: Default::default(),
+                            deferred: Default::default(),
                         })),
                     }))),
                 }],
@@ -409,6 +425,134 @@ async fn defer() {
     );
 }
 
+#[tokio::test]
+async fn deferred_fragment_sent_by_the_subgraph_is_released_as_soon_as_it_arrives() {
+    // plan for { t { x ... @defer(label: "y") { y } }}, with @defer passed through to X
+    let fetch_y = PlanNode::Fetch(FetchNode {
+        service_name: "Y".into(),
+        requires: vec![],
+        variable_usages: vec![],
+        operation: SubgraphOperation::from_string("{ t { y } }"),
+        operation_name: None,
+        operation_kind: OperationKind::Query,
+        id: None,
+        input_rewrites: None,
+        output_rewrites: None,
+        schema_aware_hash: Default::default(),
+        authorization: Default::default(),
+        deferred: Default::default(),
+    });
+    let query_plan = QueryPlan::fake_builder()
+        .root(PlanNode::Defer {
+            primary: Primary {
+                subselection: Some("{ t { x } }".to_string()),
+                node: Some(Box::new(PlanNode::Fetch(FetchNode {
+                    service_name: "X".into(),
+                    requires: vec![],
+                    variable_usages: vec![],
+                    operation: SubgraphOperation::from_string(
+                        "{ t { x ... @defer(label: \"y\") { y } } }",
+                    ),
+                    operation_name: None,
+                    operation_kind: OperationKind::Query,
+                    id: Some("fetch1".into()),
+                    input_rewrites: None,
+                    output_rewrites: None,
+                    schema_aware_hash: Default::default(),
+                    authorization: Default::default(),
+                    deferred: vec!["y".into()],
+                }))),
+            },
+            deferred: vec![DeferredNode {
+                depends: vec![Depends {
+                    id: "fetch1".into(),
+                }],
+                label: Some("y".into()),
+                query_path: Path(vec![PathElement::Key("t".to_string(), None)]),
+                subselection: Some("{ y }".to_string()),
+                node: Some(Arc::new(fetch_y)),
+            }],
+        })
+        .build();
+
+    let mut mock_x_service = plugin::test::MockSubgraphService::new();
+    mock_x_service.expect_clone().return_once(|| {
+        let mut mock_x_service = plugin::test::MockSubgraphService::new();
+        mock_x_service.expect_call().times(1).returning(|request| {
+            // the subgraph sends the deferred fragment, then keeps the response open
+            let incremental = crate::graphql::Response::builder()
+                .incremental(vec![crate::graphql::IncrementalResponse::builder()
+                    .data(serde_json_bytes::json!({"y": "Y"}))
+                    .path(Path::from("t"))
+                    .label("y".to_string())
+                    .build()])
+                .has_next(true)
+                .build();
+            let stream = futures::stream::iter([incremental])
+                .chain(futures::stream::pending())
+                .boxed();
+            request
+                .deferred_stream
+                .expect("the incremental response is requested")
+                .try_send(stream)
+                .unwrap();
+            Ok(SubgraphResponse::fake_builder()
+                .data(serde_json::json! {{ "t": { "x": "X" } }})
+                .build())
+        });
+        mock_x_service
+    });
+    // the subgraph sent the deferred fragment, so it is not fetched again
+    let mock_y_service = plugin::test::MockSubgraphService::new();
+
+    let (sender, receiver) = tokio::sync::mpsc::channel(10);
+    let schema = include_str!("testdata/defer_schema.graphql");
+    let schema = Arc::new(Schema::parse_test(schema, &Default::default()).unwrap());
+    let sf = Arc::new(SubgraphServiceFactory {
+        services: Arc::new(HashMap::from([
+            (
+                "X".into(),
+                Arc::new(mock_x_service) as Arc<dyn MakeSubgraphService>,
+            ),
+            (
+                "Y".into(),
+                Arc::new(mock_y_service) as Arc<dyn MakeSubgraphService>,
+            ),
+        ])),
+        plugins: Default::default(),
+    });
+
+    let response = query_plan
+        .execute(
+            &Context::new(),
+            &sf,
+            &Default::default(),
+            &schema,
+            sender,
+            None,
+            &None,
+            None,
+        )
+        .await;
+    assert_eq!(
+        serde_json::to_value(&response).unwrap(),
+        serde_json::json! {{"data":{"t":{"x":"X"}}}}
+    );
+
+    let deferred = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        ReceiverStream::new(receiver).next(),
+    )
+    .await
+    .expect("the deferred response is sent before the subgraph response ends")
+    .unwrap();
+    assert_eq!(deferred.label.as_deref(), Some("y"));
+    assert_eq!(
+        deferred.data,
+        Some(serde_json_bytes::json!({"t": {"x": "X", "y": "Y"}}))
+    );
+}
+
 #[tokio::test]
 async fn defer_if_condition() {
     let query = r#"
@@ -1801,6 +1945,7 @@ fn broken_plan_does_not_panic() {
             output_rewrites: None,
             schema_aware_hash: Default::default(),
             authorization: Default::default(),
+            deferred: Default::default(),
         }),
         formatted_query_plan: Default::default(),
         usage_reporting: UsageReporting {
@@ -1819,3 +1964,37 @@ fn broken_plan_does_not_panic() {
         r#"[1:3] Cannot query field "invalid" on type "Query"."#
     );
 }
+
+#[test]
+fn client_subscription_limit_holds_under_concurrency() {
+    use super::subscription::OpenedClientSubscription;
+
+    const MAX: usize = 3;
+    let barrier = std::sync::Barrier::new(MAX + 1);
+    let acquired: Vec<Option<OpenedClientSubscription>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..=MAX)
+            .map(|_| {
+                scope.spawn(|| {
+                    barrier.wait();
+                    OpenedClientSubscription::try_acquire("concurrent_client", MAX)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    assert_eq!(
+        acquired.iter().filter(|opened| opened.is_some()).count(),
+        MAX
+    );
+
+    // Closing a subscription lets the client open another one
+    drop(acquired);
+    let opened: Vec<_> = (0..MAX)
+        .map(|_| OpenedClientSubscription::try_acquire("concurrent_client", MAX))
+        .collect();
+    assert!(opened.iter().all(Option::is_some));
+    assert!(OpenedClientSubscription::try_acquire("concurrent_client", MAX).is_none());
+}
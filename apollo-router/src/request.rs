@@ -11,6 +11,17 @@ use serde_json_bytes::Value;
 use crate::configuration::BatchingMode;
 use crate::json_ext::Object;
 
+/// Errors produced when reading a batch of requests
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum BatchError {
+    /// The batch could not be deserialized
+    #[error(transparent)]
+    Invalid(#[from] serde_json::Error),
+    /// The batch holds more requests than allowed
+    #[error("the batch contains {size} operations, above the configured maximum of {max_size}")]
+    TooLarge { size: usize, max_size: usize },
+}
+
 /// A GraphQL `Request` used to represent both supergraph and subgraph requests.
 #[derive(Clone, Derivative, Serialize, Deserialize, Default)]
 // Note: if adding #[serde(deny_unknown_fields)],
@@ -165,25 +176,47 @@ impl Request {
     /// params") into a GraphQL [`Request`].
     ///
     /// An error will be produced in the event that the query string parameters
-    /// cannot be turned into a valid GraphQL `Request`.
+    /// cannot be turned into a valid GraphQL `Request`, or if they hold more than
+    /// `max_size` requests.
     pub(crate) fn batch_from_urlencoded_query(
         url_encoded_query: String,
-    ) -> Result<Vec<Request>, serde_json::Error> {
+        max_size: Option<usize>,
+    ) -> Result<Vec<Request>, BatchError> {
         let value: serde_json::Value = serde_urlencoded::from_bytes(url_encoded_query.as_bytes())
             .map_err(serde_json::Error::custom)?;
+        Request::check_batch_size(&value, max_size)?;
 
-        Request::process_query_values(&value)
+        Ok(Request::process_query_values(&value)?)
     }
 
     /// Convert Bytes into a GraphQL [`Request`].
     ///
     /// An error will be produced in the event that the bytes array cannot be
-    /// turned into a valid GraphQL `Request`.
-    pub(crate) fn batch_from_bytes(bytes: &[u8]) -> Result<Vec<Request>, serde_json::Error> {
+    /// turned into a valid GraphQL `Request`, or if it holds more than `max_size`
+    /// requests.
+    pub(crate) fn batch_from_bytes(
+        bytes: &[u8],
+        max_size: Option<usize>,
+    ) -> Result<Vec<Request>, BatchError> {
         let value: serde_json::Value =
             serde_json::from_slice(bytes).map_err(serde_json::Error::custom)?;
+        Request::check_batch_size(&value, max_size)?;
 
-        Request::process_batch_values(&value)
+        Ok(Request::process_batch_values(&value)?)
+    }
+
+    /// Rejects oversized batches before any of their requests are deserialized
+    fn check_batch_size(
+        value: &serde_json::Value,
+        max_size: Option<usize>,
+    ) -> Result<(), BatchError> {
+        match (value.as_array(), max_size) {
+            (Some(array), Some(max_size)) if array.len() > max_size => Err(BatchError::TooLarge {
+                size: array.len(),
+                max_size,
+            }),
+            _ => Ok(()),
+        }
     }
 
     fn allocate_result_array(value: &serde_json::Value) -> Vec<Request> {
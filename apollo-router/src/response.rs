@@ -51,6 +51,14 @@ pub struct Response {
 
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub incremental: Vec<IncrementalResponse>,
+
+    /// The deferred fragments announced in the incremental delivery format.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub pending: Vec<PendingResponse>,
+
+    /// The deferred fragments completed in the incremental delivery format.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub completed: Vec<CompletedResponse>,
 }
 
 #[buildstructor::buildstructor]
@@ -68,6 +76,8 @@ impl Response {
         has_next: Option<bool>,
         subscribed: Option<bool>,
         incremental: Vec<IncrementalResponse>,
+        pending: Vec<PendingResponse>,
+        completed: Vec<CompletedResponse>,
         created_at: Option<Instant>,
     ) -> Self {
         Self {
@@ -79,6 +89,8 @@ impl Response {
             has_next,
             subscribed,
             incremental,
+            pending,
+            completed,
             created_at,
         }
     }
@@ -167,6 +179,22 @@ impl Response {
                 })?,
             None => vec![],
         };
+        let pending = extract_key_value_from_object!(object, "pending")
+            .map(serde_json_bytes::from_value)
+            .transpose()
+            .map_err(|err| FetchError::SubrequestMalformedResponse {
+                service: service_name.to_string(),
+                reason: err.to_string(),
+            })?
+            .unwrap_or_default();
+        let completed = extract_key_value_from_object!(object, "completed")
+            .map(serde_json_bytes::from_value)
+            .transpose()
+            .map_err(|err| FetchError::SubrequestMalformedResponse {
+                service: service_name.to_string(),
+                reason: err.to_string(),
+            })?
+            .unwrap_or_default();
         // Graphql spec says:
         // If the data entry in the response is not present, the errors entry in the response must not be empty.
         // It must contain at least one error. The errors it contains should indicate why no data was able to be returned.
@@ -186,6 +214,8 @@ impl Response {
             has_next,
             subscribed: None,
             incremental,
+            pending,
+            completed,
             created_at: None,
         })
     }
@@ -201,6 +231,10 @@ pub struct IncrementalResponse {
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub label: Option<String>,
 
+    /// The id of the pending fragment, in the incremental delivery format.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub id: Option<String>,
+
     /// The response data.
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub data: Option<Value>,
@@ -222,8 +256,10 @@ pub struct IncrementalResponse {
 impl IncrementalResponse {
     /// Constructor
     #[builder(visibility = "pub")]
+    #[allow(clippy::too_many_arguments)]
     fn new(
         label: Option<String>,
+        id: Option<String>,
         data: Option<Value>,
         path: Option<Path>,
         errors: Vec<Error>,
@@ -231,6 +267,7 @@ impl IncrementalResponse {
     ) -> Self {
         Self {
             label,
+            id,
             data,
             path,
             errors,
@@ -244,6 +281,55 @@ impl IncrementalResponse {
     }
 }
 
+/// A deferred fragment that will be sent in subsequent payloads.
+/// Used with the incremental delivery format
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct PendingResponse {
+    /// The id referenced by the incremental and completed entries of this fragment.
+    pub id: String,
+
+    /// The path of the deferred fragment.
+    pub path: Path,
+
+    /// The label that was passed to the defer directive.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub label: Option<String>,
+}
+
+#[buildstructor::buildstructor]
+impl PendingResponse {
+    /// Constructor
+    #[builder(visibility = "pub")]
+    fn new(id: String, path: Path, label: Option<String>) -> Self {
+        Self { id, path, label }
+    }
+}
+
+/// A deferred fragment for which all the data was sent.
+/// Used with the incremental delivery format
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub struct CompletedResponse {
+    /// The id of the pending fragment.
+    pub id: String,
+
+    /// The graphql errors that prevented the fragment from being sent.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub errors: Vec<Error>,
+}
+
+#[buildstructor::buildstructor]
+impl CompletedResponse {
+    /// Constructor
+    #[builder(visibility = "pub")]
+    fn new(id: String, errors: Vec<Error>) -> Self {
+        Self { id, errors }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use router_bridge::planner::Location;
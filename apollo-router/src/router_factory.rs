@@ -25,6 +25,8 @@ use crate::plugin::DynPlugin;
 use crate::plugin::Handler;
 use crate::plugin::PluginFactory;
 use crate::plugin::PluginInit;
+use crate::plugins::authentication::AuthenticationPlugin;
+use crate::plugins::rhai::Rhai;
 use crate::plugins::subscription::Subscription;
 use crate::plugins::subscription::APOLLO_SUBSCRIPTION_PLUGIN;
 use crate::plugins::telemetry::reload::apollo_opentelemetry_initialized;
@@ -399,8 +401,9 @@ pub(crate) async fn create_subgraph_services(
     let subscription_plugin_conf = plugins
         .iter()
         .find(|i| i.0.as_str() == APOLLO_SUBSCRIPTION_PLUGIN)
-        .and_then(|plugin| (*plugin.1).as_any().downcast_ref::<Subscription>())
-        .map(|p| p.config.clone());
+        .and_then(|plugin| (*plugin.1).as_any().downcast_ref::<Subscription>());
+    let redis_notify = subscription_plugin_conf.and_then(|p| p.redis_notify.clone());
+    let subscription_plugin_conf = subscription_plugin_conf.map(|p| p.config.clone());
 
     let shaping = plugins
         .iter()
@@ -426,6 +429,7 @@ pub(crate) async fn create_subgraph_services(
                 name,
                 configuration,
                 subscription_plugin_conf.clone(),
+                redis_notify.clone(),
                 http_service_factory,
             )?,
         );
@@ -667,6 +671,19 @@ pub(crate) async fn create_plugins(
     add_optional_apollo_plugin!("experimental_demand_control");
     add_user_plugins!();
 
+    // Rhai scripts verify JWTs with the keys loaded by the authentication plugin
+    if let Some(rhai) = plugin_instances
+        .get("apollo.rhai")
+        .and_then(|plugin| plugin.as_any().downcast_ref::<Rhai>())
+    {
+        rhai.set_jwks_manager(
+            plugin_instances
+                .get("apollo.authentication")
+                .and_then(|plugin| plugin.as_any().downcast_ref::<AuthenticationPlugin>())
+                .and_then(AuthenticationPlugin::jwks_manager),
+        );
+    }
+
     // Macros above remove from `apollo_plugin_factories`, so anything left at the end
     // indicates a missing macro call.
     let unused_apollo_plugin_names = apollo_plugin_factories.keys().copied().collect::<Vec<_>>();
@@ -10,6 +10,7 @@ use tower::BoxError;
 use crate::graphql;
 use crate::Context;
 
+mod incremental;
 pub(crate) mod service;
 
 pub type BoxService = tower::util::BoxService<Request, Response, BoxError>;
@@ -0,0 +1,615 @@
+//! Incremental delivery format, with `pending` and `completed` entries
+//!
+//! The deferred fragments of the query plan are announced in the payload containing the data of
+//! their parent, and completed in the payload carrying their own data.
+
+use std::collections::HashMap;
+use std::mem;
+
+use serde_json_bytes::Value;
+
+use crate::graphql::CompletedResponse;
+use crate::graphql::Error;
+use crate::graphql::PendingResponse;
+use crate::graphql::Response;
+use crate::json_ext::Object;
+use crate::json_ext::Path;
+use crate::json_ext::PathElement;
+use crate::json_ext::ValueExt;
+use crate::query_planner::DeferredNode;
+use crate::query_planner::PlanNode;
+use crate::query_planner::QueryPlan;
+use crate::spec::Query;
+use crate::spec::Schema;
+
+/// Tracks the deferred fragments sent to a client
+pub(crate) struct IncrementalDelivery {
+    /// Deferred fragments of the query plan, by label of the fragment they are nested in (`None`
+    /// for the primary response)
+    deferred: HashMap<Option<String>, Vec<(String, Path)>>,
+    /// Announced fragments, by label, with their path and id
+    pending: HashMap<String, Vec<(Path, String)>>,
+    /// Paths nullified in the response, with the errors under them
+    nullified: Vec<(Path, Vec<Error>)>,
+    next_id: usize,
+}
+
+impl IncrementalDelivery {
+    pub(crate) fn new(
+        query_plan: &QueryPlan,
+        operation_name: Option<&str>,
+        variables: &Object,
+    ) -> Self {
+        let mut deferred = HashMap::new();
+        collect_deferred(
+            &query_plan.root,
+            None,
+            &query_plan.query,
+            operation_name,
+            variables,
+            &mut deferred,
+        );
+        Self {
+            deferred,
+            pending: HashMap::new(),
+            nullified: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    fn next_id(&mut self) -> String {
+        let id = self.next_id.to_string();
+        self.next_id += 1;
+        id
+    }
+
+    /// Announces the deferred fragments nested in the fragment with this label (or in the primary
+    /// response), found in `data`
+    pub(crate) fn announce(
+        &mut self,
+        parent: Option<&str>,
+        schema: &Schema,
+        data: &Value,
+    ) -> Vec<PendingResponse> {
+        let children = self
+            .deferred
+            .get(&parent.map(str::to_owned))
+            .cloned()
+            .unwrap_or_default();
+        let mut pending = Vec::new();
+        for (label, query_path) in children {
+            let mut paths = Vec::new();
+            select_deferred_values(schema, data, &query_path, |path, _| paths.push(path));
+            for path in paths {
+                let id = self.next_id();
+                self.pending
+                    .entry(label.clone())
+                    .or_default()
+                    .push((path.clone(), id.clone()));
+                pending.push(
+                    PendingResponse::builder()
+                        .id(id)
+                        .path(path)
+                        .and_label(user_label(&label))
+                        .build(),
+                );
+            }
+        }
+        pending
+    }
+
+    /// References the announced fragments from the incremental entries of the fragment with this
+    /// label, and completes them
+    ///
+    /// The fragments without data are completed with the `errors` under their path.
+    pub(crate) fn complete(&mut self, label: &str, response: &mut Response, errors: &[Error]) {
+        let mut announced = self.pending.remove(label).unwrap_or_default();
+        for incremental in response.incremental.iter_mut() {
+            let path = incremental.path.clone().unwrap_or_default();
+            let id = match announced
+                .iter()
+                .position(|(announced, _)| *announced == path)
+            {
+                Some(index) => announced.swap_remove(index).1,
+                // The fragment was sent before its parent
+                None => {
+                    let id = self.next_id();
+                    response.pending.push(
+                        PendingResponse::builder()
+                            .id(id.clone())
+                            .path(path)
+                            .and_label(incremental.label.clone())
+                            .build(),
+                    );
+                    id
+                }
+            };
+            let errors = if has_data(&incremental.data) {
+                Vec::new()
+            } else {
+                mem::take(&mut incremental.errors)
+            };
+            incremental.id = Some(id.clone());
+            response
+                .completed
+                .push(CompletedResponse::builder().id(id).errors(errors).build());
+        }
+        response
+            .incremental
+            .retain(|incremental| has_data(&incremental.data));
+
+        for (path, id) in announced {
+            let errors = errors
+                .iter()
+                .filter(|error| {
+                    error
+                        .path
+                        .as_ref()
+                        .map_or(true, |error_path| error_path.starts_with(&path))
+                })
+                .cloned()
+                .collect();
+            response
+                .completed
+                .push(CompletedResponse::builder().id(id).errors(errors).build());
+        }
+    }
+
+    /// Records the paths nullified by a response, with the errors that nullified them
+    pub(crate) fn nullify(&mut self, paths: &[Path], errors: &[Error]) {
+        for path in paths {
+            let errors = errors
+                .iter()
+                .filter(|error| {
+                    error
+                        .path
+                        .as_ref()
+                        .is_some_and(|error_path| error_path.starts_with(path))
+                })
+                .cloned()
+                .collect();
+            self.nullified.push((path.clone(), errors));
+        }
+    }
+
+    /// Completes the announced fragments under a nullified path, with the errors that nullified
+    /// it
+    ///
+    /// Their data will never be sent, since the responses under a nullified path are dropped.
+    pub(crate) fn complete_nullified(&mut self, response: &mut Response) {
+        let mut completed = Vec::new();
+        for announced in self.pending.values_mut() {
+            announced.retain(|(path, id)| {
+                let Some((_, errors)) = self
+                    .nullified
+                    .iter()
+                    .find(|(nullified, _)| path.starts_with(nullified))
+                else {
+                    return true;
+                };
+                completed.push((id.clone(), errors.clone()));
+                false
+            });
+        }
+        self.pending.retain(|_, announced| !announced.is_empty());
+        completed.sort_by_key(|(id, _)| id.parse::<usize>().unwrap_or_default());
+        response.completed.extend(
+            completed
+                .into_iter()
+                .map(|(id, errors)| CompletedResponse::builder().id(id).errors(errors).build()),
+        );
+    }
+
+    /// Removes the paths and labels replaced by ids
+    ///
+    /// A fragment announced in the payload carrying its data is announced in an additional payload
+    /// sent before. The fragments still pending when the last payload is sent are completed with
+    /// an error.
+    pub(crate) fn finish(&mut self, mut response: Response) -> Vec<Response> {
+        self.complete_nullified(&mut response);
+        if response.has_next == Some(false) {
+            let mut remaining: Vec<_> = self.pending.drain().flat_map(|(_, v)| v).collect();
+            remaining.sort_by_key(|(_, id)| id.parse::<usize>().unwrap_or_default());
+            for (path, id) in remaining {
+                let error = Error::builder()
+                    .message("the deferred fragment was not delivered")
+                    .path(path)
+                    .extension_code("DEFERRED_FRAGMENT_NOT_DELIVERED")
+                    .build();
+                response
+                    .completed
+                    .push(CompletedResponse::builder().id(id).error(error).build());
+            }
+        }
+
+        for incremental in response.incremental.iter_mut() {
+            if incremental.id.is_some() {
+                incremental.path = None;
+                incremental.label = None;
+            }
+        }
+
+        let (early, pending): (Vec<_>, Vec<_>) = mem::take(&mut response.pending)
+            .into_iter()
+            .partition(|pending| {
+                response
+                    .completed
+                    .iter()
+                    .any(|completed| completed.id == pending.id)
+            });
+        response.pending = pending;
+        let announcement =
+            (!early.is_empty()).then(|| Response::builder().pending(early).has_next(true).build());
+        announcement
+            .into_iter()
+            .chain(std::iter::once(response))
+            .collect()
+    }
+}
+
+fn has_data(data: &Option<Value>) -> bool {
+    data.as_ref().is_some_and(|data| !data.is_null())
+}
+
+/// Returns the label given by the client, without the prefix added in labeler.rs
+///
+/// The labels generated for the fragments without one are removed.
+pub(super) fn user_label(label: &str) -> Option<String> {
+    label.strip_prefix('_').map(str::to_owned)
+}
+
+/// Calls `f` with the values found at the path of a deferred fragment, and their own path
+///
+/// If the path points to an array, `f` is called with each of its items, because the data of a
+/// deferred fragment must be an object.
+pub(super) fn select_deferred_values<'a>(
+    schema: &Schema,
+    data: &'a Value,
+    path: &'a Path,
+    mut f: impl FnMut(Path, &'a Value),
+) {
+    data.select_values_and_paths(schema, path, |path, value| {
+        if let Value::Array(array) = value {
+            let mut parent = path.clone();
+            for (i, value) in array.iter().enumerate() {
+                parent.push(PathElement::Index(i));
+                f(parent.clone(), value);
+                parent.pop();
+            }
+        } else {
+            f(path.clone(), value);
+        }
+    });
+}
+
+fn collect_deferred(
+    node: &PlanNode,
+    parent: Option<&str>,
+    query: &Query,
+    operation_name: Option<&str>,
+    variables: &Object,
+    output: &mut HashMap<Option<String>, Vec<(String, Path)>>,
+) {
+    match node {
+        PlanNode::Sequence { nodes } | PlanNode::Parallel { nodes } => {
+            for node in nodes {
+                collect_deferred(node, parent, query, operation_name, variables, output);
+            }
+        }
+        PlanNode::Flatten(flatten) => collect_deferred(
+            &flatten.node,
+            parent,
+            query,
+            operation_name,
+            variables,
+            output,
+        ),
+        PlanNode::Defer { primary, deferred } => {
+            if let Some(node) = &primary.node {
+                collect_deferred(node, parent, query, operation_name, variables, output);
+            }
+            for DeferredNode {
+                label,
+                query_path,
+                node,
+                ..
+            } in deferred
+            {
+                // labeler.rs gives a label to every deferred fragment
+                let Some(label) = label else { continue };
+                output
+                    .entry(parent.map(str::to_owned))
+                    .or_default()
+                    .push((label.to_string(), query_path.clone()));
+                if let Some(node) = node {
+                    let parent = Some(label.as_str());
+                    collect_deferred(node, parent, query, operation_name, variables, output);
+                }
+            }
+        }
+        PlanNode::Condition {
+            condition,
+            if_clause,
+            else_clause,
+        } => {
+            // the defer if clause is mandatory, and defaults to true
+            let clause = if query
+                .variable_value(operation_name, condition.as_str(), variables)
+                .map_or(true, |value| *value == Value::Bool(true))
+            {
+                if_clause
+            } else {
+                else_clause
+            };
+            if let Some(node) = clause {
+                collect_deferred(node, parent, query, operation_name, variables, output);
+            }
+        }
+        PlanNode::Fetch(_) | PlanNode::Subscription { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json_bytes::json;
+
+    use super::*;
+    use crate::graphql::IncrementalResponse;
+
+    fn to_json(responses: &[Response]) -> serde_json::Value {
+        serde_json::to_value(responses).unwrap()
+    }
+
+    #[test]
+    fn it_announces_the_deferred_fragments_with_their_parent() {
+        let schema = Schema::parse_test(
+            include_str!("../../testdata/minimal_supergraph.graphql"),
+            &Default::default(),
+        )
+        .unwrap();
+        let root: PlanNode = serde_json::from_value(serde_json::json!({
+            "kind": "Defer",
+            "primary": {},
+            "deferred": [
+                {
+                    "depends": [],
+                    "label": "_name",
+                    "queryPath": ["me"],
+                    "node": {
+                        "kind": "Defer",
+                        "primary": {},
+                        "deferred": [{ "depends": [], "label": "1", "queryPath": ["me", "friends"] }]
+                    }
+                },
+                { "depends": [], "label": "_failing", "queryPath": ["me"] }
+            ]
+        }))
+        .unwrap();
+        let query_plan = QueryPlan::fake_builder().root(root).build();
+        let mut incremental = IncrementalDelivery::new(&query_plan, None, &Object::new());
+
+        // the primary response announces the fragments on `me`
+        let mut primary = Response::builder()
+            .data(json!({ "me": { "id": "1" } }))
+            .has_next(true)
+            .build();
+        primary.pending = incremental.announce(None, &schema, primary.data.as_ref().unwrap());
+        assert_eq!(
+            to_json(&incremental.finish(primary)),
+            serde_json::json!([{
+                "data": { "me": { "id": "1" } },
+                "hasNext": true,
+                "pending": [
+                    { "id": "0", "path": ["me"], "label": "name" },
+                    { "id": "1", "path": ["me"], "label": "failing" }
+                ]
+            }])
+        );
+
+        // the `name` fragment announces the fragment nested on each friend
+        let data = json!({ "me": { "name": "Ada", "friends": [{ "id": "2" }, { "id": "3" }] } });
+        let mut response = Response::builder()
+            .incremental(vec![IncrementalResponse::builder()
+                .data(json!({ "name": "Ada", "friends": [{ "id": "2" }, { "id": "3" }] }))
+                .path(Path::from("me"))
+                .label("name".to_string())
+                .build()])
+            .has_next(true)
+            .build();
+        response.pending = incremental.announce(Some("_name"), &schema, &data);
+        incremental.complete("_name", &mut response, &[]);
+        assert_eq!(
+            to_json(&incremental.finish(response)),
+            serde_json::json!([{
+                "hasNext": true,
+                "incremental": [{
+                    "id": "0",
+                    "data": { "name": "Ada", "friends": [{ "id": "2" }, { "id": "3" }] }
+                }],
+                "pending": [
+                    { "id": "2", "path": ["me", "friends", 0] },
+                    { "id": "3", "path": ["me", "friends", 1] }
+                ],
+                "completed": [{ "id": "0" }]
+            }])
+        );
+
+        // the `failing` fragment has no data
+        let error = Error::builder()
+            .message("cannot fetch the fragment")
+            .path(Path::from("me/email"))
+            .extension_code("FETCH_ERROR")
+            .build();
+        let mut response = Response::builder().has_next(true).build();
+        incremental.complete("_failing", &mut response, &[error]);
+        assert_eq!(
+            to_json(&incremental.finish(response)),
+            serde_json::json!([{
+                "hasNext": true,
+                "completed": [{
+                    "id": "1",
+                    "errors": [{
+                        "message": "cannot fetch the fragment",
+                        "path": ["me", "email"],
+                        "extensions": { "code": "FETCH_ERROR" }
+                    }]
+                }]
+            }])
+        );
+
+        // the last fragment completes the fragments announced without data
+        let mut response = Response::builder()
+            .incremental(vec![IncrementalResponse::builder()
+                .data(json!({ "name": "Bob" }))
+                .path(Path::from("me/friends/0"))
+                .build()])
+            .has_next(false)
+            .build();
+        incremental.complete("1", &mut response, &[]);
+        assert_eq!(
+            to_json(&incremental.finish(response)),
+            serde_json::json!([{
+                "hasNext": false,
+                "incremental": [{ "id": "2", "data": { "name": "Bob" } }],
+                "completed": [{ "id": "2" }, { "id": "3" }]
+            }])
+        );
+    }
+
+    #[test]
+    fn it_completes_the_fragments_that_will_not_be_sent() {
+        let schema = Schema::parse_test(
+            include_str!("../../testdata/minimal_supergraph.graphql"),
+            &Default::default(),
+        )
+        .unwrap();
+        let root: PlanNode = serde_json::from_value(serde_json::json!({
+            "kind": "Defer",
+            "primary": {},
+            "deferred": [
+                { "depends": [], "label": "_name", "queryPath": ["me"] },
+                { "depends": [], "label": "_email", "queryPath": ["me"] },
+                { "depends": [], "label": "_friends", "queryPath": ["me"] }
+            ]
+        }))
+        .unwrap();
+        let query_plan = QueryPlan::fake_builder().root(root).build();
+        let mut incremental = IncrementalDelivery::new(&query_plan, None, &Object::new());
+
+        let mut primary = Response::builder()
+            .data(json!({ "me": { "id": "1" } }))
+            .has_next(true)
+            .build();
+        primary.pending = incremental.announce(None, &schema, primary.data.as_ref().unwrap());
+        assert_eq!(incremental.finish(primary)[0].pending.len(), 3);
+
+        // the `name` fragment nullifies `me`, so the other fragments will never be sent
+        let error = Error::builder()
+            .message("Cannot return null for non-nullable field User.name")
+            .path(Path::from("me/name"))
+            .extension_code("FETCH_ERROR")
+            .build();
+        incremental.nullify(&[Path::from("me")], &[error]);
+        let mut response = Response::builder().has_next(true).build();
+        incremental.complete("_name", &mut response, &[]);
+        assert_eq!(
+            to_json(&incremental.finish(response)),
+            serde_json::json!([{
+                "hasNext": true,
+                "completed": [
+                    { "id": "0" },
+                    {
+                        "id": "1",
+                        "errors": [{
+                            "message": "Cannot return null for non-nullable field User.name",
+                            "path": ["me", "name"],
+                            "extensions": { "code": "FETCH_ERROR" }
+                        }]
+                    },
+                    {
+                        "id": "2",
+                        "errors": [{
+                            "message": "Cannot return null for non-nullable field User.name",
+                            "path": ["me", "name"],
+                            "extensions": { "code": "FETCH_ERROR" }
+                        }]
+                    }
+                ]
+            }])
+        );
+
+        // the fragments still pending at the end of the response are completed with an error
+        let mut incremental = IncrementalDelivery::new(&query_plan, None, &Object::new());
+        let mut primary = Response::builder()
+            .data(json!({ "me": { "id": "1" } }))
+            .has_next(true)
+            .build();
+        primary.pending = incremental.announce(None, &schema, primary.data.as_ref().unwrap());
+        incremental.finish(primary);
+        let mut response = Response::builder()
+            .incremental(vec![IncrementalResponse::builder()
+                .data(json!({ "name": "Ada" }))
+                .path(Path::from("me"))
+                .label("name".to_string())
+                .build()])
+            .has_next(false)
+            .build();
+        incremental.complete("_name", &mut response, &[]);
+        assert_eq!(
+            to_json(&incremental.finish(response)),
+            serde_json::json!([{
+                "hasNext": false,
+                "incremental": [{ "id": "0", "data": { "name": "Ada" } }],
+                "completed": [
+                    { "id": "0" },
+                    {
+                        "id": "1",
+                        "errors": [{
+                            "message": "the deferred fragment was not delivered",
+                            "path": ["me"],
+                            "extensions": { "code": "DEFERRED_FRAGMENT_NOT_DELIVERED" }
+                        }]
+                    },
+                    {
+                        "id": "2",
+                        "errors": [{
+                            "message": "the deferred fragment was not delivered",
+                            "path": ["me"],
+                            "extensions": { "code": "DEFERRED_FRAGMENT_NOT_DELIVERED" }
+                        }]
+                    }
+                ]
+            }])
+        );
+    }
+
+    #[test]
+    fn it_announces_fragments_sent_before_their_parent() {
+        let query_plan = QueryPlan::fake_builder().build();
+        let mut incremental = IncrementalDelivery::new(&query_plan, None, &Object::new());
+
+        let mut response = Response::builder()
+            .incremental(vec![IncrementalResponse::builder()
+                .data(json!({ "name": "Ada" }))
+                .path(Path::from("me"))
+                .label("name".to_string())
+                .build()])
+            .has_next(false)
+            .build();
+        incremental.complete("_name", &mut response, &[]);
+        assert_eq!(
+            to_json(&incremental.finish(response)),
+            serde_json::json!([
+                {
+                    "hasNext": true,
+                    "pending": [{ "id": "0", "path": ["me"], "label": "name" }]
+                },
+                {
+                    "hasNext": false,
+                    "incremental": [{ "id": "0", "data": { "name": "Ada" } }],
+                    "completed": [{ "id": "0" }]
+                }
+            ])
+        );
+    }
+}
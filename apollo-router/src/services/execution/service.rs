@@ -31,13 +31,14 @@ use tracing::Instrument;
 use tracing::Span;
 use tracing_core::Level;
 
+use super::incremental::select_deferred_values;
+use super::incremental::user_label;
+use super::incremental::IncrementalDelivery;
 use crate::graphql::Error;
 use crate::graphql::IncrementalResponse;
 use crate::graphql::Response;
 use crate::json_ext::Object;
 use crate::json_ext::Path;
-use crate::json_ext::PathElement;
-use crate::json_ext::ValueExt;
 use crate::plugins::authentication::APOLLO_AUTHENTICATION_JWT_CLAIMS;
 use crate::plugins::subscription::Subscription;
 use crate::plugins::subscription::SubscriptionConfig;
@@ -45,6 +46,7 @@ use crate::plugins::subscription::APOLLO_SUBSCRIPTION_PLUGIN;
 use crate::query_planner::subscription::SubscriptionHandle;
 use crate::services::execution;
 use crate::services::new_service::ServiceFactory;
+use crate::services::router::ClientRequestAccepts;
 use crate::services::ExecutionRequest;
 use crate::services::ExecutionResponse;
 use crate::services::Plugins;
@@ -180,6 +182,14 @@ impl ExecutionService {
 
         let schema = self.schema.clone();
         let mut nullified_paths: Vec<Path> = vec![];
+        let accepts_incremental = ctx
+            .extensions()
+            .lock()
+            .get::<ClientRequestAccepts>()
+            .map_or(false, |accepts| accepts.multipart_incremental);
+        let mut incremental = (is_deferred && accepts_incremental).then(|| {
+            IncrementalDelivery::new(&req.query_plan, operation_name.as_deref(), &variables)
+        });
 
         let execution_span = Span::current();
 
@@ -223,8 +233,8 @@ impl ExecutionService {
                 }
                 response
             })
-            .filter_map(move |response: Response| {
-                ready(execution_span.in_scope(|| {
+            .flat_map(move |response: Response| {
+                let Some(response) = execution_span.in_scope(|| {
                     Self::process_graphql_response(
                         &query,
                         operation_name.as_deref(),
@@ -232,15 +242,23 @@ impl ExecutionService {
                         is_deferred,
                         &schema,
                         &mut nullified_paths,
+                        incremental.as_mut(),
                         response,
                     )
-                }))
+                }) else {
+                    return futures::stream::iter(Vec::new());
+                };
+                futures::stream::iter(match incremental.as_mut() {
+                    Some(incremental) => incremental.finish(response),
+                    None => vec![response],
+                })
             })
             .boxed();
 
         ExecutionResponse::new_from_response(http::Response::new(stream as _), ctx)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn process_graphql_response(
         query: &Arc<Query>,
         operation_name: Option<&str>,
@@ -248,6 +266,7 @@ impl ExecutionService {
         is_deferred: bool,
         schema: &Arc<Schema>,
         nullified_paths: &mut Vec<Path>,
+        mut incremental: Option<&mut IncrementalDelivery>,
         mut response: Response,
     ) -> Option<Response> {
         // responses that would fall under a path that was previously nullified are not sent
@@ -261,10 +280,16 @@ impl ExecutionService {
             })
             .unwrap_or(false)
         {
-            if response.has_next == Some(false) {
-                return Some(Response::builder().has_next(false).build());
-            } else {
+            let has_next = response.has_next != Some(false);
+            let mut response = Response::builder().has_next(has_next).build();
+            // the fragments announced under that path are completed with the errors that nullified it
+            if let Some(incremental) = incremental {
+                incremental.complete_nullified(&mut response);
+            }
+            if has_next && response.completed.is_empty() {
                 return None;
+            } else {
+                return Some(response);
             }
         }
 
@@ -283,25 +308,25 @@ impl ExecutionService {
             let mut paths = Vec::new();
             if !query.unauthorized.paths.is_empty() {
                 if query.unauthorized.errors.log {
-                    let unauthorized_paths = query.unauthorized.paths.iter().map(|path| path.to_string()).collect::<Vec<_>>();
+                    let unauthorized_paths = query.unauthorized.paths.iter().map(|unauthorized| unauthorized.path.to_string()).collect::<Vec<_>>();
 
                     event!(Level::ERROR, unauthorized_query_paths = ?unauthorized_paths, "Authorization error",);
                 }
 
                 match query.unauthorized.errors.response {
-                    crate::plugins::authorization::ErrorLocation::Errors => for path in &query.unauthorized.paths {
+                    crate::plugins::authorization::ErrorLocation::Errors => for unauthorized in &query.unauthorized.paths {
                         response.errors.push(Error::builder()
                         .message("Unauthorized field or type")
-                        .path(path.clone())
+                        .path(unauthorized.path.clone())
                         .extension_code("UNAUTHORIZED_FIELD_OR_TYPE").build());
                     },
                     crate::plugins::authorization::ErrorLocation::Extensions =>{
                         if !query.unauthorized.paths.is_empty() {
                             let mut v = vec![];
-                            for path in &query.unauthorized.paths{
+                            for unauthorized in &query.unauthorized.paths{
                                 v.push(serde_json_bytes::to_value(Error::builder()
                                 .message("Unauthorized field or type")
-                                .path(path.clone())
+                                .path(unauthorized.path.clone())
                                 .extension_code("UNAUTHORIZED_FIELD_OR_TYPE").build()).expect("error serialization should not fail"));
                             }
                             response.extensions.insert("authorizationErrors", Value::Array(v));
@@ -332,6 +357,16 @@ impl ExecutionService {
                     )
                     ,
             );
+            if let Some(incremental) = incremental.as_deref_mut() {
+                let mut errors = response.errors.clone();
+                if let Some(value_completion) = response.extensions.get("valueCompletion") {
+                    errors.extend(
+                        serde_json_bytes::from_value::<Vec<Error>>(value_completion.clone())
+                            .unwrap_or_default(),
+                    );
+                }
+                incremental.nullify(&paths, &errors);
+            }
             nullified_paths.extend(paths);
         });
 
@@ -351,6 +386,27 @@ impl ExecutionService {
                     ),
                 });
 
+                if let Some(incremental) = incremental {
+                    match (response.path.take(), response.label.take()) {
+                        // the primary response
+                        (None, _) => {
+                            if let Some(data) = &response.data {
+                                response.pending = incremental.announce(None, schema, data);
+                            }
+                        }
+                        // a deferred response without data
+                        (Some(_), label) => {
+                            let errors = std::mem::take(&mut response.errors);
+                            incremental.complete(
+                                label.as_deref().unwrap_or_default(),
+                                &mut response,
+                                &errors,
+                            );
+                        }
+                    }
+                    return Some(response);
+                }
+
                 response.label = rewrite_defer_label(&response);
                 Some(response)
             }
@@ -379,29 +435,35 @@ impl ExecutionService {
                 // been returned (at least not in that particular response). And while this is probably only
                 // true in fairly contrived examples, this is not working as intended by the query planner,
                 // so it is dodgy and could create bigger problems in the future.
-                response_data.select_values_and_paths(schema, response_path, |path, value| {
-                    // if the deferred path points to an array, split it into multiple subresponses
-                    // because the root must be an object
-                    if let Value::Array(array) = value {
-                        let mut parent = path.clone();
-                        for (i, value) in array.iter().enumerate() {
-                            parent.push(PathElement::Index(i));
-                            sub_responses.push((parent.clone(), value.clone()));
-                            parent.pop();
-                        }
-                    } else {
-                        sub_responses.push((path.clone(), value.clone()));
-                    }
+                select_deferred_values(schema, response_data, response_path, |path, value| {
+                    sub_responses.push((path, value.clone()));
                 });
 
-                Self::split_incremental_response(
+                let Some(incremental) = incremental else {
+                    return Self::split_incremental_response(
+                        query,
+                        operation_name,
+                        has_next,
+                        variables_set,
+                        response,
+                        sub_responses,
+                    );
+                };
+                // the fragments nested in this one are announced with its data
+                let label = response.label.clone().unwrap_or_default();
+                let pending = incremental.announce(Some(&label), schema, response_data);
+                let errors = response.errors.clone();
+                let mut response = Self::split_incremental_response(
                     query,
                     operation_name,
                     has_next,
                     variables_set,
                     response,
                     sub_responses,
-                )
+                )?;
+                response.pending = pending;
+                incremental.complete(&label, &mut response, &errors);
+                Some(response)
             }
         }
     }
@@ -498,18 +560,7 @@ impl ExecutionService {
 }
 
 fn rewrite_defer_label(response: &Response) -> Option<String> {
-    if let Some(label) = &response.label {
-        #[allow(clippy::manual_map)] // use an explicit `if` to comment each case
-        if let Some(rest) = label.strip_prefix('_') {
-            // Drop the prefix added in labeler.rs
-            Some(rest.to_owned())
-        } else {
-            // Remove the synthetic lable generated in labeler.rs
-            None
-        }
-    } else {
-        None
-    }
+    response.label.as_deref().and_then(user_label)
 }
 
 #[derive(Clone, Copy)]
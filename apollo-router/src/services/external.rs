@@ -27,6 +27,10 @@ use crate::plugins::telemetry::reload::prepare_context;
 use crate::query_planner::QueryPlan;
 use crate::Context;
 
+mod grpc;
+
+pub(crate) use self::grpc::GrpcBody;
+
 pub(crate) const DEFAULT_EXTERNALIZATION_TIMEOUT: Duration = Duration::from_secs(1);
 
 /// Version of our externalised data. Rev this if it changes
@@ -42,6 +46,8 @@ pub(crate) enum PipelineStep {
     ExecutionResponse,
     SubgraphRequest,
     SubgraphResponse,
+    QueryPlannerRequest,
+    QueryPlannerResponse,
 }
 
 impl From<PipelineStep> for opentelemetry::Value {
@@ -50,6 +56,18 @@ impl From<PipelineStep> for opentelemetry::Value {
     }
 }
 
+/// Protocol used to call the coprocessor
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Transport {
+    /// JSON payloads sent with HTTP POST requests
+    #[default]
+    Http,
+    /// Protobuf payloads sent with gRPC, using the `Coprocessor` service defined in
+    /// `coprocessor.proto`. The coprocessor must support HTTP/2
+    Grpc,
+}
+
 #[derive(Clone, Debug, Default, Display, Deserialize, PartialEq, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) enum Control {
@@ -104,6 +122,40 @@ pub(crate) struct Externalizable<T> {
     query_plan: Option<Arc<QueryPlan>>,
 }
 
+impl<T> Externalizable<T> {
+    /// An output that leaves the request or response of the stage unchanged, as if the
+    /// coprocessor had answered without any modification.
+    pub(crate) fn unchanged(stage: PipelineStep) -> Self {
+        // Setting the control of a response stage would override the status code
+        let control = matches!(
+            stage,
+            PipelineStep::RouterRequest
+                | PipelineStep::SupergraphRequest
+                | PipelineStep::ExecutionRequest
+                | PipelineStep::SubgraphRequest
+                | PipelineStep::QueryPlannerRequest
+        )
+        .then(Control::default);
+        Externalizable {
+            version: EXTERNALIZABLE_VERSION,
+            stage: stage.to_string(),
+            control,
+            id: None,
+            headers: None,
+            body: None,
+            context: None,
+            status_code: None,
+            sdl: None,
+            uri: None,
+            path: None,
+            method: None,
+            service_name: None,
+            has_next: None,
+            query_plan: None,
+        }
+    }
+}
+
 #[buildstructor::buildstructor]
 impl<T> Externalizable<T>
 where
@@ -266,7 +318,63 @@ where
         }
     }
 
-    pub(crate) async fn call<C>(self, mut client: C, url: &Url) -> Result<Self, BoxError>
+    #[builder(visibility = "pub(crate)")]
+    /// This is the constructor (or builder) to use when constructing a Query Planner
+    /// `Externalizable`.
+    ///
+    fn query_planner_new(
+        stage: PipelineStep,
+        control: Option<Control>,
+        id: String,
+        body: Option<T>,
+        context: Option<Context>,
+        sdl: Option<String>,
+        query_plan: Option<Arc<QueryPlan>>,
+    ) -> Self {
+        assert!(matches!(
+            stage,
+            PipelineStep::QueryPlannerRequest | PipelineStep::QueryPlannerResponse
+        ));
+        Externalizable {
+            version: EXTERNALIZABLE_VERSION,
+            stage: stage.to_string(),
+            control,
+            id: Some(id),
+            headers: None,
+            body,
+            context,
+            status_code: None,
+            sdl,
+            uri: None,
+            path: None,
+            method: None,
+            service_name: None,
+            has_next: None,
+            query_plan,
+        }
+    }
+
+    pub(crate) async fn call<C>(
+        self,
+        client: C,
+        url: &Url,
+        transport: Transport,
+    ) -> Result<Self, BoxError>
+    where
+        C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T: GrpcBody,
+    {
+        match transport {
+            Transport::Http => self.call_http(client, url).await,
+            Transport::Grpc => grpc::call(self, client, url).await,
+        }
+    }
+
+    async fn call_http<C>(self, mut client: C, url: &Url) -> Result<Self, BoxError>
     where
         C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
             + Clone
@@ -277,18 +385,13 @@ where
         tracing::debug!("forwarding json: {}", serde_json::to_string(&self)?);
 
         let mut request = hyper::Request::builder()
-            .uri(&url.to_string())
+            .uri(request_uri(url, None)?)
             .method(Method::POST)
             .header(ACCEPT, "application/json")
             .header(CONTENT_TYPE, "application/json")
             .body(serde_json::to_vec(&self)?.into())?;
 
-        get_text_map_propagator(|propagator| {
-            propagator.inject_context(
-                &prepare_context(tracing::span::Span::current().context()),
-                &mut opentelemetry_http::HeaderInjector(request.headers_mut()),
-            );
-        });
+        inject_trace_context(request.headers_mut());
 
         let response = client.call(request).await?;
         hyper::body::to_bytes(response.into_body())
@@ -298,6 +401,97 @@ where
     }
 }
 
+/// Calls the coprocessor for each chunk of a deferred response.
+///
+/// With the gRPC transport, the chunks of a response are sent in order on a single stream.
+pub(crate) struct DeferredCalls<C> {
+    client: C,
+    url: Url,
+    transport: Transport,
+    timeout: Duration,
+    stream: tokio::sync::Mutex<Option<grpc::Stream>>,
+}
+
+impl<C> DeferredCalls<C>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+{
+    pub(crate) fn new(client: C, url: Url, transport: Transport, timeout: Duration) -> Self {
+        Self {
+            client,
+            url,
+            transport,
+            timeout,
+            stream: Default::default(),
+        }
+    }
+
+    pub(crate) async fn call<T>(
+        &self,
+        payload: Externalizable<T>,
+    ) -> Result<Externalizable<T>, BoxError>
+    where
+        T: Debug + DeserializeOwned + Serialize + Send + Sync + GrpcBody,
+    {
+        match self.transport {
+            Transport::Http => payload.call_http(self.client.clone(), &self.url).await,
+            Transport::Grpc => {
+                let mut stream = self.stream.lock().await;
+                // the timeout covers each exchange on the stream, not only opening it
+                let result = tokio::time::timeout(
+                    self.timeout,
+                    grpc::call_stream(&mut stream, self.client.clone(), &self.url, payload),
+                )
+                .await
+                .map_err(|_| BoxError::from("the coprocessor did not reply in time"))
+                .and_then(|result| result);
+                if result.is_err() {
+                    // the next chunk will open a new stream
+                    *stream = None;
+                }
+                result
+            }
+        }
+    }
+}
+
+/// URI of a request to the coprocessor, with `path` replacing the path of the URL if set.
+///
+/// There is no standard format for Unix socket URLs, so `unix:///path/to/socket` URLs are converted
+/// to hyperlocal URIs, which hide the socket path in a hex encoded authority that the Unix socket
+/// connector knows how to decode.
+fn request_uri(url: &Url, path: Option<&str>) -> Result<http::Uri, BoxError> {
+    if url.scheme() == "unix" {
+        #[cfg(unix)]
+        return Ok(hyperlocal::Uri::new(url.path(), path.unwrap_or("/")).into());
+        #[cfg(not(unix))]
+        return Err("Unix sockets are not supported on this platform".into());
+    }
+
+    match path {
+        Some(path) => {
+            let mut url = url.clone();
+            url.set_path(path);
+            url.set_query(None);
+            Ok(url.as_str().parse()?)
+        }
+        None => Ok(url.as_str().parse()?),
+    }
+}
+
+fn inject_trace_context(headers: &mut HeaderMap) {
+    get_text_map_propagator(|propagator| {
+        propagator.inject_context(
+            &prepare_context(tracing::span::Span::current().context()),
+            &mut opentelemetry_http::HeaderInjector(headers),
+        );
+    });
+}
+
 /// Convert a HeaderMap into a HashMap
 pub(crate) fn externalize_header_map(
     input: &HeaderMap<HeaderValue>,
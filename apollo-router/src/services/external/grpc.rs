@@ -0,0 +1,602 @@
+//! gRPC transport for coprocessors
+//!
+//! The messages and the service are defined in `plugins/coprocessor/proto/coprocessor.proto`. The
+//! gRPC framing is implemented on top of the coprocessor's HTTP client, which must use HTTP/2.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use bytes::Buf;
+use bytes::BufMut;
+use bytes::Bytes;
+use bytes::BytesMut;
+use http::header::CONTENT_TYPE;
+use http::header::TE;
+use http::HeaderMap;
+use http::Method;
+use http::StatusCode;
+use hyper::body::HttpBody;
+use hyper::Body;
+use prost::Message;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tower::BoxError;
+use tower::Service;
+use url::Url;
+
+use super::inject_trace_context;
+use super::request_uri;
+use super::Control;
+use super::Externalizable;
+use crate::Context;
+
+#[allow(unreachable_pub)]
+pub(crate) mod proto {
+    #![allow(clippy::derive_partial_eq_without_eq)]
+    tonic::include_proto!("apollo.router.coprocessor.v1");
+}
+
+const PROCESS_PATH: &str = "/apollo.router.coprocessor.v1.Coprocessor/Process";
+const PROCESS_STREAM_PATH: &str = "/apollo.router.coprocessor.v1.Coprocessor/ProcessStream";
+const GRPC_CONTENT_TYPE: &str = "application/grpc";
+const GRPC_STATUS: &str = "grpc-status";
+const GRPC_MESSAGE: &str = "grpc-message";
+const FRAME_HEADER_LENGTH: usize = 5;
+
+/// Encoding of the `body` field of the gRPC messages
+pub(crate) trait GrpcBody: Sized {
+    fn encode(self) -> Result<Vec<u8>, BoxError>;
+    fn decode(body: Vec<u8>) -> Result<Self, BoxError>;
+}
+
+/// The router stages send the body as it was received from the client
+impl GrpcBody for String {
+    fn encode(self) -> Result<Vec<u8>, BoxError> {
+        Ok(self.into_bytes())
+    }
+
+    fn decode(body: Vec<u8>) -> Result<Self, BoxError> {
+        Ok(String::from_utf8(body)?)
+    }
+}
+
+/// The other stages send GraphQL requests and responses encoded as JSON
+impl GrpcBody for serde_json::Value {
+    fn encode(self) -> Result<Vec<u8>, BoxError> {
+        Ok(serde_json::to_vec(&self)?)
+    }
+
+    fn decode(body: Vec<u8>) -> Result<Self, BoxError> {
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+pub(super) async fn call<T, C>(
+    payload: Externalizable<T>,
+    mut client: C,
+    url: &Url,
+) -> Result<Externalizable<T>, BoxError>
+where
+    T: Debug + DeserializeOwned + Serialize + Send + Sync + GrpcBody,
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+{
+    let message = proto::Externalizable::try_from(payload)?;
+    tracing::debug!(?message, "forwarding protobuf");
+
+    let request = request(url, PROCESS_PATH, Body::from(encode_frame(&message)))?;
+    let mut reader = FrameReader::new(client.call(request).await?)?;
+
+    let reply = reader
+        .next()
+        .await?
+        .ok_or("the coprocessor did not return a message")?;
+    // reading until the end of the response checks the gRPC status in the trailers
+    if reader.next().await?.is_some() {
+        return Err("the coprocessor returned more than one message".into());
+    }
+
+    reply.try_into()
+}
+
+/// Sends a chunk of a deferred response on the stream, opening it if needed
+pub(super) async fn call_stream<T, C>(
+    stream: &mut Option<Stream>,
+    client: C,
+    url: &Url,
+    payload: Externalizable<T>,
+) -> Result<Externalizable<T>, BoxError>
+where
+    T: Debug + DeserializeOwned + Serialize + Send + Sync + GrpcBody,
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+{
+    let message = proto::Externalizable::try_from(payload)?;
+    tracing::debug!(?message, "forwarding protobuf");
+    let frame = encode_frame(&message);
+
+    let stream = match stream {
+        Some(stream) => {
+            stream.sender.send_data(frame).await?;
+            stream
+        }
+        None => stream.insert(Stream::open(client, url, frame).await?),
+    };
+
+    stream
+        .reader
+        .next()
+        .await?
+        .ok_or("the coprocessor closed the stream")?
+        .try_into()
+}
+
+/// Bidirectional stream used to process the chunks of a deferred response
+pub(crate) struct Stream {
+    sender: hyper::body::Sender,
+    reader: FrameReader,
+}
+
+impl Stream {
+    async fn open<C>(mut client: C, url: &Url, first_frame: Bytes) -> Result<Self, BoxError>
+    where
+        C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>,
+    {
+        let (mut sender, body) = Body::channel();
+        let request = request(url, PROCESS_STREAM_PATH, body)?;
+
+        // the coprocessor might wait for the first message before sending the response headers
+        let (sent, response) = futures::join!(sender.send_data(first_frame), client.call(request));
+        let reader = FrameReader::new(response?)?;
+        sent?;
+
+        Ok(Self { sender, reader })
+    }
+}
+
+fn request(url: &Url, path: &str, body: Body) -> Result<hyper::Request<Body>, BoxError> {
+    let mut request = hyper::Request::builder()
+        .uri(request_uri(url, Some(path))?)
+        .method(Method::POST)
+        .header(CONTENT_TYPE, GRPC_CONTENT_TYPE)
+        .header(TE, "trailers")
+        .body(body)?;
+
+    inject_trace_context(request.headers_mut());
+
+    Ok(request)
+}
+
+fn encode_frame(message: &proto::Externalizable) -> Bytes {
+    let length = message.encoded_len();
+    let mut frame = BytesMut::with_capacity(FRAME_HEADER_LENGTH + length);
+    // messages are not compressed
+    frame.put_u8(0);
+    frame.put_u32(length as u32);
+    message
+        .encode(&mut frame)
+        .expect("the buffer was allocated with the message's length; qed");
+    frame.freeze()
+}
+
+/// Reads the length prefixed messages of a gRPC response
+struct FrameReader {
+    body: Body,
+    buffer: BytesMut,
+    /// gRPC status sent in the headers, for responses without a body
+    status: Option<Result<(), BoxError>>,
+}
+
+impl FrameReader {
+    fn new(response: hyper::Response<Body>) -> Result<Self, BoxError> {
+        if response.status() != StatusCode::OK {
+            return Err(format!(
+                "the coprocessor returned the HTTP status {} to a gRPC request",
+                response.status()
+            )
+            .into());
+        }
+        let (parts, body) = response.into_parts();
+
+        Ok(Self {
+            body,
+            buffer: BytesMut::new(),
+            status: status(&parts.headers),
+        })
+    }
+
+    /// Returns the next message, or `None` at the end of the response
+    async fn next(&mut self) -> Result<Option<proto::Externalizable>, BoxError> {
+        loop {
+            if let Some(message) = self.decode()? {
+                return Ok(Some(message));
+            }
+
+            match self.body.data().await {
+                Some(data) => self.buffer.extend_from_slice(&data?),
+                None => {
+                    if !self.buffer.is_empty() {
+                        return Err("the coprocessor returned a truncated gRPC message".into());
+                    }
+                    let trailers = self.body.trailers().await?;
+                    return trailers
+                        .as_ref()
+                        .and_then(status)
+                        .or_else(|| self.status.take())
+                        .unwrap_or_else(|| {
+                            Err("the coprocessor did not return a gRPC status".into())
+                        })
+                        .map(|()| None);
+                }
+            }
+        }
+    }
+
+    fn decode(&mut self) -> Result<Option<proto::Externalizable>, BoxError> {
+        if self.buffer.len() < FRAME_HEADER_LENGTH {
+            return Ok(None);
+        }
+        if self.buffer[0] != 0 {
+            return Err("compressed gRPC messages are not supported".into());
+        }
+        let length = u32::from_be_bytes([
+            self.buffer[1],
+            self.buffer[2],
+            self.buffer[3],
+            self.buffer[4],
+        ]) as usize;
+        if self.buffer.len() < FRAME_HEADER_LENGTH + length {
+            return Ok(None);
+        }
+
+        self.buffer.advance(FRAME_HEADER_LENGTH);
+        let message = self.buffer.split_to(length);
+        Ok(Some(proto::Externalizable::decode(message)?))
+    }
+}
+
+fn status(headers: &HeaderMap) -> Option<Result<(), BoxError>> {
+    let code = headers.get(GRPC_STATUS)?;
+    Some(
+        match code.to_str().ok().and_then(|code| code.parse().ok()) {
+            Some(0u32) => Ok(()),
+            _ => Err(format!(
+                "the coprocessor returned the gRPC status {}: {}",
+                String::from_utf8_lossy(code.as_bytes()),
+                headers
+                    .get(GRPC_MESSAGE)
+                    .map(|message| String::from_utf8_lossy(message.as_bytes()))
+                    .unwrap_or_default()
+            )
+            .into()),
+        },
+    )
+}
+
+impl<T: GrpcBody> TryFrom<Externalizable<T>> for proto::Externalizable {
+    type Error = BoxError;
+
+    fn try_from(payload: Externalizable<T>) -> Result<Self, Self::Error> {
+        Ok(proto::Externalizable {
+            version: payload.version.into(),
+            stage: payload.stage,
+            control: payload.control.map(|control| proto::Control {
+                kind: Some(match control {
+                    Control::Continue => {
+                        proto::control::Kind::Continue(proto::control::Continue {})
+                    }
+                    Control::Break(code) => proto::control::Kind::Break(code.into()),
+                }),
+            }),
+            id: payload.id,
+            headers: payload.headers.map(|headers| proto::Headers {
+                entries: headers
+                    .into_iter()
+                    .map(|(name, values)| (name, proto::HeaderValues { values }))
+                    .collect(),
+            }),
+            body: payload.body.map(GrpcBody::encode).transpose()?,
+            context: payload
+                .context
+                .map(|context| {
+                    context
+                        .iter()
+                        .map(|entry| Ok((entry.key().clone(), serde_json::to_vec(entry.value())?)))
+                        .collect::<Result<_, BoxError>>()
+                        .map(|entries| proto::Context { entries })
+                })
+                .transpose()?,
+            sdl: payload.sdl,
+            uri: payload.uri,
+            method: payload.method,
+            path: payload.path,
+            service_name: payload.service_name,
+            status_code: payload.status_code.map(u32::from),
+            has_next: payload.has_next,
+            query_plan: payload
+                .query_plan
+                .map(|query_plan| serde_json::to_vec(&query_plan))
+                .transpose()?,
+        })
+    }
+}
+
+impl<T: GrpcBody> TryFrom<proto::Externalizable> for Externalizable<T> {
+    type Error = BoxError;
+
+    fn try_from(message: proto::Externalizable) -> Result<Self, Self::Error> {
+        Ok(Externalizable {
+            version: message.version.try_into()?,
+            stage: message.stage,
+            control: message
+                .control
+                .map(|control| match control.kind {
+                    None | Some(proto::control::Kind::Continue(_)) => Ok(Control::Continue),
+                    Some(proto::control::Kind::Break(code)) => {
+                        Ok::<_, BoxError>(Control::Break(code.try_into()?))
+                    }
+                })
+                .transpose()?,
+            id: message.id,
+            headers: message.headers.map(|headers| {
+                headers
+                    .entries
+                    .into_iter()
+                    .map(|(name, values)| (name, values.values))
+                    .collect::<HashMap<_, _>>()
+            }),
+            body: message.body.map(T::decode).transpose()?,
+            context: message
+                .context
+                .map(|context| {
+                    let result = Context::new();
+                    for (key, value) in context.entries {
+                        result.insert_json_value(key, serde_json::from_slice(&value)?);
+                    }
+                    Ok::<_, BoxError>(result)
+                })
+                .transpose()?,
+            sdl: message.sdl,
+            uri: message.uri,
+            method: message.method,
+            path: message.path,
+            service_name: message.service_name,
+            status_code: message.status_code.map(u16::try_from).transpose()?,
+            has_next: message.has_next,
+            query_plan: message
+                .query_plan
+                .map(|query_plan| serde_json::from_slice(&query_plan))
+                .transpose()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use hyper::service::make_service_fn;
+    use hyper::service::service_fn;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::services::external::DeferredCalls;
+    use crate::services::external::PipelineStep;
+    use crate::services::external::Transport;
+
+    fn payload(id: &str) -> Externalizable<serde_json::Value> {
+        let context = Context::new();
+        context.insert("key", "value".to_string()).unwrap();
+
+        Externalizable::supergraph_builder()
+            .stage(PipelineStep::SupergraphResponse)
+            .control(Control::Break(401))
+            .id(id.to_string())
+            .headers(
+                [(
+                    "x-header".to_string(),
+                    vec!["a".to_string(), "b".to_string()],
+                )]
+                .into(),
+            )
+            .body(json!({ "data": { "me": { "name": "Ada" } } }))
+            .context(context)
+            .status_code(200)
+            .sdl("type Query { me: User }".to_string())
+            .has_next(true)
+            .build()
+    }
+
+    #[test]
+    fn convert_externalizable() {
+        let payload = payload("1");
+        let expected = serde_json::to_value(&payload).unwrap();
+
+        let message = proto::Externalizable::try_from(payload).unwrap();
+        assert_eq!(
+            message.body,
+            Some(br#"{"data":{"me":{"name":"Ada"}}}"#.to_vec())
+        );
+        assert_eq!(
+            message.context.as_ref().unwrap().entries["key"],
+            b"\"value\""
+        );
+
+        let payload = Externalizable::<serde_json::Value>::try_from(message).unwrap();
+        assert_eq!(serde_json::to_value(&payload).unwrap(), expected);
+
+        // the router stages send the body as is
+        let message = proto::Externalizable::try_from(
+            Externalizable::<String>::router_builder()
+                .stage(PipelineStep::RouterRequest)
+                .id("1".to_string())
+                .body("{}".to_string())
+                .build(),
+        )
+        .unwrap();
+        assert_eq!(message.body, Some(b"{}".to_vec()));
+    }
+
+    /// Coprocessor replying to each message with its `body` replaced by the number of messages
+    /// received on the stream, with an error status if the id of the message is "error", and not
+    /// at all if it is "stall"
+    async fn coprocessor(streams: Arc<AtomicUsize>) -> SocketAddr {
+        let make_service = make_service_fn(move |_| {
+            let streams = streams.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: hyper::Request<Body>| {
+                    let streams = streams.clone();
+                    async move {
+                        if request.uri().path() == PROCESS_STREAM_PATH {
+                            streams.fetch_add(1, Ordering::SeqCst);
+                        }
+                        let (mut sender, body) = Body::channel();
+                        let mut reader = FrameReader {
+                            body: request.into_body(),
+                            buffer: BytesMut::new(),
+                            status: Some(Ok(())),
+                        };
+
+                        tokio::spawn(async move {
+                            let mut count = 0;
+                            while let Some(mut message) = reader.next().await.unwrap() {
+                                if message.id.as_deref() == Some("error") {
+                                    let mut trailers = HeaderMap::new();
+                                    trailers.insert(GRPC_STATUS, "3".parse().unwrap());
+                                    trailers.insert(GRPC_MESSAGE, "invalid id".parse().unwrap());
+                                    sender.send_trailers(trailers).await.unwrap();
+                                    return;
+                                }
+                                if message.id.as_deref() == Some("stall") {
+                                    continue;
+                                }
+
+                                count += 1;
+                                message.control = Some(proto::Control {
+                                    kind: Some(proto::control::Kind::Continue(
+                                        proto::control::Continue {},
+                                    )),
+                                });
+                                message.body = Some(count.to_string().into_bytes());
+                                sender.send_data(encode_frame(&message)).await.unwrap();
+                            }
+                            let mut trailers = HeaderMap::new();
+                            trailers.insert(GRPC_STATUS, "0".parse().unwrap());
+                            sender.send_trailers(trailers).await.unwrap();
+                        });
+
+                        Ok::<_, Infallible>(
+                            hyper::Response::builder()
+                                .header(CONTENT_TYPE, GRPC_CONTENT_TYPE)
+                                .body(body)
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into())
+            .http2_only(true)
+            .serve(make_service);
+        let address = server.local_addr();
+        tokio::spawn(server);
+        address
+    }
+
+    fn client() -> impl Service<
+        hyper::Request<Body>,
+        Response = hyper::Response<Body>,
+        Error = BoxError,
+        Future = impl Send,
+    > + Clone
+           + Send
+           + Sync
+           + 'static {
+        hyper::Client::builder()
+            .http2_only(true)
+            .build_http()
+            .map_err(BoxError::from)
+    }
+
+    #[tokio::test]
+    async fn unary_call() {
+        let address = coprocessor(Default::default()).await;
+        let url = Url::parse(&format!("http://{address}")).unwrap();
+
+        let reply = payload("1")
+            .call(client(), &url, Transport::Grpc)
+            .await
+            .unwrap();
+        assert_eq!(reply.control, Some(Control::Continue));
+        assert_eq!(reply.body, Some(json!(1)));
+        assert_eq!(reply.id.as_deref(), Some("1"));
+        assert_eq!(
+            reply.context.unwrap().get::<_, String>("key").unwrap(),
+            Some("value".to_string())
+        );
+
+        let error = payload("error")
+            .call(client(), &url, Transport::Grpc)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "the coprocessor returned the gRPC status 3: invalid id"
+        );
+    }
+
+    #[tokio::test]
+    async fn deferred_calls_share_a_stream() {
+        let streams = Arc::new(AtomicUsize::new(0));
+        let address = coprocessor(streams.clone()).await;
+        let url = Url::parse(&format!("http://{address}")).unwrap();
+
+        let deferred_calls =
+            DeferredCalls::new(client(), url, Transport::Grpc, Duration::from_secs(5));
+        for expected in 1..=3 {
+            let reply = deferred_calls.call(payload("1")).await.unwrap();
+            assert_eq!(reply.body, Some(json!(expected)));
+        }
+        assert_eq!(streams.load(Ordering::SeqCst), 1);
+
+        // an error closes the stream, and the next chunk opens a new one
+        assert!(deferred_calls.call(payload("error")).await.is_err());
+        let reply = deferred_calls.call(payload("1")).await.unwrap();
+        assert_eq!(reply.body, Some(json!(1)));
+        assert_eq!(streams.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn deferred_calls_time_out_on_an_open_stream() {
+        let streams = Arc::new(AtomicUsize::new(0));
+        let address = coprocessor(streams.clone()).await;
+        let url = Url::parse(&format!("http://{address}")).unwrap();
+
+        let deferred_calls =
+            DeferredCalls::new(client(), url, Transport::Grpc, Duration::from_millis(200));
+        let reply = deferred_calls.call(payload("1")).await.unwrap();
+        assert_eq!(reply.body, Some(json!(1)));
+
+        // the coprocessor does not reply to the second chunk of the stream
+        let error = deferred_calls.call(payload("stall")).await.unwrap_err();
+        assert_eq!(error.to_string(), "the coprocessor did not reply in time");
+
+        // the stream that timed out is not reused
+        let reply = deferred_calls.call(payload("1")).await.unwrap();
+        assert_eq!(reply.body, Some(json!(1)));
+        assert_eq!(streams.load(Ordering::SeqCst), 2);
+    }
+}
@@ -6,9 +6,11 @@ use http::HeaderMap;
 use http::Method;
 use http::StatusCode;
 use mediatype::names::APPLICATION;
+use mediatype::names::EVENT_STREAM;
 use mediatype::names::JSON;
 use mediatype::names::MIXED;
 use mediatype::names::MULTIPART;
+use mediatype::names::TEXT;
 use mediatype::names::_STAR;
 use mediatype::MediaTypeList;
 use mediatype::ReadParams;
@@ -22,14 +24,20 @@ use crate::graphql;
 use crate::layers::sync_checkpoint::CheckpointService;
 use crate::layers::ServiceExt as _;
 use crate::services::router;
+use crate::services::router::service::EVENT_STREAM_CONTENT_TYPE_HEADER_VALUE;
 use crate::services::router::service::MULTIPART_DEFER_CONTENT_TYPE_HEADER_VALUE;
+use crate::services::router::service::MULTIPART_INCREMENTAL_CONTENT_TYPE_HEADER_VALUE;
 use crate::services::router::service::MULTIPART_SUBSCRIPTION_CONTENT_TYPE_HEADER_VALUE;
 use crate::services::router::ClientRequestAccepts;
 use crate::services::supergraph;
 use crate::services::APPLICATION_JSON_HEADER_VALUE;
+use crate::services::EVENT_STREAM_CONTENT_TYPE;
 use crate::services::MULTIPART_DEFER_ACCEPT;
 use crate::services::MULTIPART_DEFER_SPEC_PARAMETER;
 use crate::services::MULTIPART_DEFER_SPEC_VALUE;
+use crate::services::MULTIPART_INCREMENTAL_ACCEPT;
+use crate::services::MULTIPART_INCREMENTAL_SPEC_PARAMETER;
+use crate::services::MULTIPART_INCREMENTAL_SPEC_VALUE;
 use crate::services::MULTIPART_SUBSCRIPTION_ACCEPT;
 use crate::services::MULTIPART_SUBSCRIPTION_SPEC_PARAMETER;
 use crate::services::MULTIPART_SUBSCRIPTION_SPEC_VALUE;
@@ -91,6 +99,7 @@ where
                 if accepts.wildcard
                     || accepts.multipart_defer
                     || accepts.multipart_subscription
+                    || accepts.event_stream
                     || accepts.json
                 {
                     req.context.extensions().lock().insert(accepts);
@@ -103,11 +112,13 @@ where
                                 "errors": [
                                     graphql::Error::builder()
                                         .message(format!(
-                                            r#"'accept' header must be one of: \"*/*\", {:?}, {:?}, {:?} or {:?}"#,
+                                            r#"'accept' header must be one of: \"*/*\", {:?}, {:?}, {:?}, {:?}, {:?} or {:?}"#,
                                             APPLICATION_JSON.essence_str(),
                                             GRAPHQL_JSON_RESPONSE_HEADER_VALUE,
                                             MULTIPART_SUBSCRIPTION_ACCEPT,
-                                            MULTIPART_DEFER_ACCEPT
+                                            MULTIPART_DEFER_ACCEPT,
+                                            MULTIPART_INCREMENTAL_ACCEPT,
+                                            EVENT_STREAM_CONTENT_TYPE,
                                         ))
                                         .extension_code("INVALID_ACCEPT_HEADER")
                                         .build()
@@ -142,7 +153,9 @@ where
                     wildcard: accepts_wildcard,
                     json: accepts_json,
                     multipart_defer: accepts_multipart_defer,
+                    multipart_incremental: accepts_multipart_incremental,
                     multipart_subscription: accepts_multipart_subscription,
+                    event_stream: accepts_event_stream,
                 } = {
                     let lock = context.extensions().lock();
                     let cra = lock.get::<ClientRequestAccepts>();
@@ -153,6 +166,11 @@ where
                     parts
                         .headers
                         .insert(CONTENT_TYPE, APPLICATION_JSON_HEADER_VALUE.clone());
+                } else if accepts_multipart_incremental {
+                    parts.headers.insert(
+                        CONTENT_TYPE,
+                        MULTIPART_INCREMENTAL_CONTENT_TYPE_HEADER_VALUE.clone(),
+                    );
                 } else if accepts_multipart_defer {
                     parts.headers.insert(
                         CONTENT_TYPE,
@@ -163,6 +181,10 @@ where
                         CONTENT_TYPE,
                         MULTIPART_SUBSCRIPTION_CONTENT_TYPE_HEADER_VALUE.clone(),
                     );
+                } else if accepts_event_stream {
+                    parts
+                        .headers
+                        .insert(CONTENT_TYPE, EVENT_STREAM_CONTENT_TYPE_HEADER_VALUE.clone());
                 }
                 (parts, res)
             })
@@ -212,6 +234,9 @@ fn parse_accept(headers: &HeaderMap) -> ClientRequestAccepts {
                     {
                         accepts.json = true
                     }
+                    if !accepts.event_stream && (mime.ty == TEXT && mime.subty == EVENT_STREAM) {
+                        accepts.event_stream = true
+                    }
                     if !accepts.wildcard && (mime.ty == _STAR && mime.subty == _STAR) {
                         accepts.wildcard = true
                     }
@@ -224,6 +249,19 @@ fn parse_accept(headers: &HeaderMap) -> ClientRequestAccepts {
                             accepts.multipart_defer = true
                         }
                     }
+                    if !accepts.multipart_incremental
+                        && (mime.ty == MULTIPART && mime.subty == MIXED)
+                    {
+                        let parameter = mediatype::Name::new(MULTIPART_INCREMENTAL_SPEC_PARAMETER)
+                            .expect("valid name");
+                        let value = mediatype::Value::new(MULTIPART_INCREMENTAL_SPEC_VALUE)
+                            .expect("valid value");
+                        if mime.get_param(parameter) == Some(value) {
+                            // `@defer` responses are sent in the incremental delivery format
+                            accepts.multipart_defer = true;
+                            accepts.multipart_incremental = true
+                        }
+                    }
                     if !accepts.multipart_subscription
                         && (mime.ty == MULTIPART && mime.subty == MIXED)
                     {
@@ -291,5 +329,24 @@ mod tests {
         default_headers.append(ACCEPT, HeaderValue::from_static(MULTIPART_DEFER_ACCEPT));
         let accepts = parse_accept(&default_headers);
         assert!(accepts.multipart_defer);
+        assert!(!accepts.multipart_incremental);
+
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(
+            ACCEPT,
+            HeaderValue::from_static(MULTIPART_INCREMENTAL_ACCEPT),
+        );
+        let accepts = parse_accept(&default_headers);
+        assert!(accepts.multipart_defer);
+        assert!(accepts.multipart_incremental);
+
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("text/event-stream; charset=utf-8"),
+        );
+        let accepts = parse_accept(&default_headers);
+        assert!(accepts.event_stream);
+        assert!(!accepts.json);
     }
 }
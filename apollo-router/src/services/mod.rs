@@ -28,7 +28,7 @@ pub(crate) mod external;
 pub(crate) mod http;
 pub(crate) mod layers;
 pub(crate) mod new_service;
-pub(crate) mod query_planner;
+pub mod query_planner;
 pub mod router;
 pub mod subgraph;
 pub(crate) mod subgraph_service;
@@ -77,8 +77,18 @@ pub(crate) const MULTIPART_DEFER_ACCEPT: &str = "multipart/mixed;deferSpec=20220
 pub(crate) const MULTIPART_DEFER_CONTENT_TYPE: &str =
     "multipart/mixed;boundary=\"graphql\";deferSpec=20220824";
 
+// the incremental delivery format with `pending` and `completed` entries, negotiated instead of `deferSpec`
+pub(crate) const MULTIPART_INCREMENTAL_SPEC_PARAMETER: &str = "incrementalSpec";
+pub(crate) const MULTIPART_INCREMENTAL_SPEC_VALUE: &str = "v0.2";
+pub(crate) const MULTIPART_INCREMENTAL_ACCEPT: &str = "multipart/mixed;incrementalSpec=v0.2";
+pub(crate) const MULTIPART_INCREMENTAL_CONTENT_TYPE: &str =
+    "multipart/mixed;boundary=\"graphql\";incrementalSpec=v0.2";
+
 pub(crate) const MULTIPART_SUBSCRIPTION_ACCEPT: &str = "multipart/mixed;subscriptionSpec=1.0";
 pub(crate) const MULTIPART_SUBSCRIPTION_CONTENT_TYPE: &str =
     "multipart/mixed;boundary=\"graphql\";subscriptionSpec=1.0";
 pub(crate) const MULTIPART_SUBSCRIPTION_SPEC_PARAMETER: &str = "subscriptionSpec";
 pub(crate) const MULTIPART_SUBSCRIPTION_SPEC_VALUE: &str = "1.0";
+
+// GraphQL over Server-Sent Events, in the distinct connections mode
+pub(crate) const EVENT_STREAM_CONTENT_TYPE: &str = "text/event-stream";
@@ -386,10 +386,20 @@ impl Response {
     }
 }
 
+/// Inserted in the context extensions by transports that send the GraphQL responses
+/// themselves, like WebSocket: the router service hands over the stream of responses
+/// instead of serializing them in the HTTP response body
+pub(crate) struct ResponseStreamSender(
+    pub(crate) tokio::sync::oneshot::Sender<graphql::ResponseStream>,
+);
+
 #[derive(Clone, Default, Debug)]
 pub(crate) struct ClientRequestAccepts {
     pub(crate) multipart_defer: bool,
+    /// The client expects `@defer` responses in the incremental delivery format
+    pub(crate) multipart_incremental: bool,
     pub(crate) multipart_subscription: bool,
+    pub(crate) event_stream: bool,
     pub(crate) json: bool,
     pub(crate) wildcard: bool,
 }
@@ -15,6 +15,7 @@ use futures::future::BoxFuture;
 use futures::stream;
 use futures::stream::once;
 use futures::stream::StreamExt;
+use http::header::CACHE_CONTROL;
 use http::header::CONTENT_TYPE;
 use http::header::VARY;
 use http::request::Parts;
@@ -35,8 +36,10 @@ use tower_service::Service;
 use tracing::Instrument;
 
 use super::ClientRequestAccepts;
+use super::ResponseStreamSender;
 use crate::axum_factory::CanceledRequest;
 use crate::batching::Batch;
+use crate::batching::BatchCost;
 use crate::batching::BatchQuery;
 use crate::cache::DeduplicatingCache;
 use crate::configuration::Batching;
@@ -47,7 +50,9 @@ use crate::http_ext;
 use crate::plugin::test::MockSupergraphService;
 use crate::protocols::multipart::Multipart;
 use crate::protocols::multipart::ProtocolMode;
+use crate::protocols::sse::EventStream;
 use crate::query_planner::InMemoryCachePlanner;
+use crate::request::BatchError;
 use crate::router_factory::RouterFactory;
 use crate::services::layers::apq::APQLayer;
 use crate::services::layers::content_negotiation;
@@ -68,8 +73,11 @@ use crate::services::SupergraphCreator;
 use crate::services::SupergraphRequest;
 use crate::services::SupergraphResponse;
 use crate::services::APPLICATION_JSON_HEADER_VALUE;
+use crate::services::EVENT_STREAM_CONTENT_TYPE;
 use crate::services::MULTIPART_DEFER_ACCEPT;
 use crate::services::MULTIPART_DEFER_CONTENT_TYPE;
+use crate::services::MULTIPART_INCREMENTAL_ACCEPT;
+use crate::services::MULTIPART_INCREMENTAL_CONTENT_TYPE;
 use crate::services::MULTIPART_SUBSCRIPTION_ACCEPT;
 use crate::services::MULTIPART_SUBSCRIPTION_CONTENT_TYPE;
 use crate::Configuration;
@@ -79,8 +87,12 @@ use crate::ListenAddr;
 
 pub(crate) static MULTIPART_DEFER_CONTENT_TYPE_HEADER_VALUE: HeaderValue =
     HeaderValue::from_static(MULTIPART_DEFER_CONTENT_TYPE);
+pub(crate) static MULTIPART_INCREMENTAL_CONTENT_TYPE_HEADER_VALUE: HeaderValue =
+    HeaderValue::from_static(MULTIPART_INCREMENTAL_CONTENT_TYPE);
 pub(crate) static MULTIPART_SUBSCRIPTION_CONTENT_TYPE_HEADER_VALUE: HeaderValue =
     HeaderValue::from_static(MULTIPART_SUBSCRIPTION_CONTENT_TYPE);
+pub(crate) static EVENT_STREAM_CONTENT_TYPE_HEADER_VALUE: HeaderValue =
+    HeaderValue::from_static(EVENT_STREAM_CONTENT_TYPE);
 static ACCEL_BUFFERING_HEADER_NAME: HeaderName = HeaderName::from_static("x-accel-buffering");
 static ACCEL_BUFFERING_HEADER_VALUE: HeaderValue = HeaderValue::from_static("no");
 static ORIGIN_HEADER_VALUE: HeaderValue = HeaderValue::from_static("origin");
@@ -254,11 +266,30 @@ impl RouterService {
             },
         };
 
+        let response_stream_sender = context.extensions().lock().remove::<ResponseStreamSender>();
+        if let Some(ResponseStreamSender(sender)) = response_stream_sender {
+            let (parts, body) = response.into_parts();
+            let stream = body.inspect(|response| {
+                if !response.errors.is_empty() {
+                    Self::count_errors(&response.errors);
+                }
+            });
+            if sender.send(stream.boxed()).is_err() {
+                tracing::trace!("the client closed the connection before the response was sent");
+            }
+            return Ok(router::Response {
+                response: http::Response::from_parts(parts, Body::empty()),
+                context,
+            });
+        }
+
         let ClientRequestAccepts {
             wildcard: accepts_wildcard,
             json: accepts_json,
             multipart_defer: accepts_multipart_defer,
+            multipart_incremental: accepts_multipart_incremental,
             multipart_subscription: accepts_multipart_subscription,
+            event_stream: accepts_event_stream,
         } = context
             .extensions()
             .lock()
@@ -312,7 +343,12 @@ impl RouterService {
                         })
                     })
                 } else if accepts_multipart_defer || accepts_multipart_subscription {
-                    if accepts_multipart_defer {
+                    if accepts_multipart_incremental {
+                        parts.headers.insert(
+                            CONTENT_TYPE,
+                            MULTIPART_INCREMENTAL_CONTENT_TYPE_HEADER_VALUE.clone(),
+                        );
+                    } else if accepts_multipart_defer {
                         parts.headers.insert(
                             CONTENT_TYPE,
                             MULTIPART_DEFER_CONTENT_TYPE_HEADER_VALUE.clone(),
@@ -368,6 +404,42 @@ impl RouterService {
                     });
 
                     Ok(RouterResponse { response, context })
+                } else if accepts_event_stream {
+                    parts
+                        .headers
+                        .insert(CONTENT_TYPE, EVENT_STREAM_CONTENT_TYPE_HEADER_VALUE.clone());
+                    parts
+                        .headers
+                        .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+                    parts.headers.insert(
+                        ACCEL_BUFFERING_HEADER_NAME.clone(),
+                        ACCEL_BUFFERING_HEADER_VALUE.clone(),
+                    );
+
+                    if !response.errors.is_empty() {
+                        Self::count_errors(&response.errors);
+                    }
+
+                    let body = body.inspect(|response| {
+                        if !response.errors.is_empty() {
+                            Self::count_errors(&response.errors);
+                        }
+                    });
+                    let event_stream = match response.subscribed {
+                        // the first response only signals that the subscription was accepted
+                        Some(true) => EventStream::new(body, ProtocolMode::Subscription),
+                        _ => {
+                            EventStream::new(once(ready(response)).chain(body), ProtocolMode::Defer)
+                        }
+                    };
+
+                    Ok(RouterResponse {
+                        response: http::Response::from_parts(
+                            parts,
+                            Body::wrap_stream(event_stream),
+                        ),
+                        context,
+                    })
                 } else {
                     tracing::info!(
                         monotonic_counter.apollo.router.graphql_error = 1u64,
@@ -379,11 +451,13 @@ impl RouterService {
                             .error(
                                 graphql::Error::builder()
                                     .message(format!(
-                                        r#"'accept' header must be one of: \"*/*\", {:?}, {:?}, {:?} or {:?}"#,
+                                        r#"'accept' header must be one of: \"*/*\", {:?}, {:?}, {:?}, {:?}, {:?} or {:?}"#,
                                         APPLICATION_JSON.essence_str(),
                                         GRAPHQL_JSON_RESPONSE_HEADER_VALUE,
                                         MULTIPART_DEFER_ACCEPT,
+                                        MULTIPART_INCREMENTAL_ACCEPT,
                                         MULTIPART_SUBSCRIPTION_ACCEPT,
+                                        EVENT_STREAM_CONTENT_TYPE,
                                     ))
                                     .extension_code("INVALID_ACCEPT_HEADER")
                                     .build(),
@@ -402,7 +476,23 @@ impl RouterService {
 
         let (supergraph_requests, is_batch) = match self.translate_request(req).await {
             Ok(requests) => requests,
-            Err(err) => {
+            Err(RequestRejection::BatchTooLarge(size, max_size)) => {
+                u64_counter!(
+                    "apollo.router.operations.batching.rejected",
+                    "Number of batched operations rejected because they exceeded a batching limit",
+                    size as u64,
+                    "reason" = "max_size"
+                );
+                return Self::batch_error_response(
+                    context,
+                    size,
+                    graphql::Error::builder()
+                        .message(BatchError::TooLarge { size, max_size }.to_string())
+                        .extension_code("BATCH_LIMIT_EXCEEDED")
+                        .build(),
+                );
+            }
+            Err(RequestRejection::Invalid(err)) => {
                 u64_counter!(
                     "apollo_router_http_requests_total",
                     "Total number of HTTP requests made.",
@@ -425,6 +515,13 @@ impl RouterService {
                     .build();
             }
         };
+        if is_batch {
+            u64_histogram!(
+                "apollo.router.operations.batching.client_size",
+                "Number of operations in the batches accepted from clients",
+                supergraph_requests.len() as u64
+            );
+        }
 
         // We need to handle cases where a failure is part of a batch and thus must be cancelled.
         // Requests can be cancelled at any point of the router pipeline, but all failures bubble back
@@ -459,8 +556,17 @@ impl RouterService {
         // (Short circuit processing and propagate any errors in the batch)
         // Note: We use `join_all` here since it awaits all futures before returning, thus allowing us to
         // handle cancellation logic without fear of the other futures getting killed.
-        let mut results: Vec<router::Response> = join_all(futures)
-            .await
+        // `buffered` also preserves ordering, while limiting the number of operations executed concurrently
+        let results = match self.batching.max_in_flight {
+            Some(max_in_flight) if is_batch => {
+                stream::iter(futures)
+                    .buffered(max_in_flight.get())
+                    .collect::<Vec<_>>()
+                    .await
+            }
+            _ => join_all(futures).await,
+        };
+        let mut results: Vec<router::Response> = results
             .into_iter()
             .collect::<Result<Vec<router::Response>, BoxError>>()?;
 
@@ -491,12 +597,29 @@ impl RouterService {
         }
     }
 
+    /// Rejects all the operations of a batch with the same error
+    fn batch_error_response(
+        context: Context,
+        batch_size: usize,
+        error: graphql::Error,
+    ) -> Result<RouterResponse, BoxError> {
+        let response = graphql::Response::builder().error(error).build();
+        let body = serde_json::to_string(&vec![response; batch_size])?;
+        Ok(RouterResponse {
+            response: http::Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
+                .body(Body::from(body))?,
+            context,
+        })
+    }
+
     async fn translate_query_request(
         &self,
         parts: &Parts,
-    ) -> Result<(Vec<graphql::Request>, bool), TranslateError> {
+    ) -> Result<(Vec<graphql::Request>, bool), RequestRejection> {
         let mut is_batch = false;
-        parts.uri.query().map(|q| {
+        parts.uri.query().map(|q| -> Result<_, RequestRejection> {
             let mut result = vec![];
 
             match graphql::Request::from_urlencoded_query(q.to_string()) {
@@ -509,22 +632,31 @@ impl RouterService {
                     if self.batching.enabled
                         && matches!(self.batching.mode, BatchingMode::BatchHttpLink)
                     {
-                        result = graphql::Request::batch_from_urlencoded_query(q.to_string())
-                            .map_err(|e| TranslateError {
+                        result = graphql::Request::batch_from_urlencoded_query(
+                            q.to_string(),
+                            self.batching.max_size,
+                        )
+                        .map_err(|e| match e {
+                            BatchError::TooLarge { size, max_size } => {
+                                RequestRejection::BatchTooLarge(size, max_size)
+                            }
+                            BatchError::Invalid(e) => TranslateError {
                                 status: StatusCode::BAD_REQUEST,
                                 error: "failed to decode a valid GraphQL request from path",
                                 extension_code: "INVALID_GRAPHQL_REQUEST",
                                 extension_details: format!(
                                     "failed to decode a valid GraphQL request from path {e}"
                                 ),
-                            })?;
+                            }
+                            .into(),
+                        })?;
                         if result.is_empty() {
                             return Err(TranslateError {
                                 status: StatusCode::BAD_REQUEST,
                                 error: "failed to decode a valid GraphQL request from path",
                                 extension_code: "INVALID_GRAPHQL_REQUEST",
                                 extension_details: "failed to decode a valid GraphQL request from path: empty array ".to_string()
-                            });
+                            }.into());
                         }
                         is_batch = true;
                     } else if !q.is_empty() && q.as_bytes()[0] == b'[' {
@@ -539,7 +671,7 @@ impl RouterService {
                             error: "batching not enabled",
                             extension_code: "BATCHING_NOT_ENABLED",
                             extension_details,
-                        });
+                        }.into());
                     } else {
                         return Err(TranslateError {
                             status: StatusCode::BAD_REQUEST,
@@ -548,7 +680,7 @@ impl RouterService {
                             extension_details: format!(
                                 "failed to decode a valid GraphQL request from path {err}"
                             ),
-                        });
+                        }.into());
                     }
                 }
             };
@@ -559,14 +691,14 @@ impl RouterService {
                 error: "There was no GraphQL operation to execute. Use the `query` parameter to send an operation, using either GET or POST.",
                 extension_code: "INVALID_GRAPHQL_REQUEST",
                 extension_details: "There was no GraphQL operation to execute. Use the `query` parameter to send an operation, using either GET or POST.".to_string()
-            })
+            }.into())
         })
     }
 
     fn translate_bytes_request(
         &self,
         bytes: &Bytes,
-    ) -> Result<(Vec<graphql::Request>, bool), TranslateError> {
+    ) -> Result<(Vec<graphql::Request>, bool), RequestRejection> {
         let mut result = vec![];
         let mut is_batch = false;
 
@@ -578,14 +710,20 @@ impl RouterService {
                 if self.batching.enabled
                     && matches!(self.batching.mode, BatchingMode::BatchHttpLink)
                 {
-                    result =
-                        graphql::Request::batch_from_bytes(bytes).map_err(|e| TranslateError {
-                            status: StatusCode::BAD_REQUEST,
-                            error: "failed to deserialize the request body into JSON",
-                            extension_code: "INVALID_GRAPHQL_REQUEST",
-                            extension_details: format!(
-                                "failed to deserialize the request body into JSON: {e}"
-                            ),
+                    result = graphql::Request::batch_from_bytes(bytes, self.batching.max_size)
+                        .map_err(|e| match e {
+                            BatchError::TooLarge { size, max_size } => {
+                                RequestRejection::BatchTooLarge(size, max_size)
+                            }
+                            BatchError::Invalid(e) => TranslateError {
+                                status: StatusCode::BAD_REQUEST,
+                                error: "failed to deserialize the request body into JSON",
+                                extension_code: "INVALID_GRAPHQL_REQUEST",
+                                extension_details: format!(
+                                    "failed to deserialize the request body into JSON: {e}"
+                                ),
+                            }
+                            .into(),
                         })?;
                     if result.is_empty() {
                         return Err(TranslateError {
@@ -595,7 +733,8 @@ impl RouterService {
                             extension_details:
                                 "failed to decode a valid GraphQL request from path: empty array "
                                     .to_string(),
-                        });
+                        }
+                        .into());
                     }
                     is_batch = true;
                 } else if !bytes.is_empty() && bytes[0] == b'[' {
@@ -611,7 +750,8 @@ impl RouterService {
                         error: "batching not enabled",
                         extension_code: "BATCHING_NOT_ENABLED",
                         extension_details,
-                    });
+                    }
+                    .into());
                 } else {
                     return Err(TranslateError {
                         status: StatusCode::BAD_REQUEST,
@@ -620,7 +760,8 @@ impl RouterService {
                         extension_details: format!(
                             "failed to deserialize the request body into JSON: {err}"
                         ),
-                    });
+                    }
+                    .into());
                 }
             }
         };
@@ -630,7 +771,7 @@ impl RouterService {
     async fn translate_request(
         &self,
         req: RouterRequest,
-    ) -> Result<(Vec<SupergraphRequest>, bool), TranslateError> {
+    ) -> Result<(Vec<SupergraphRequest>, bool), RequestRejection> {
         let RouterRequest {
             router_request,
             context,
@@ -638,7 +779,7 @@ impl RouterService {
 
         let (parts, body) = router_request.into_parts();
 
-        let graphql_requests: Result<(Vec<graphql::Request>, bool), TranslateError> = if parts
+        let graphql_requests: Result<(Vec<graphql::Request>, bool), RequestRejection> = if parts
             .method
             == Method::GET
         {
@@ -660,7 +801,8 @@ impl RouterService {
                     error: "payload too large for the `http_max_request_bytes` configuration",
                     extension_code: "INVALID_GRAPHQL_REQUEST",
                     extension_details: "payload too large".to_string(),
-                })
+                }
+                .into())
             } else {
                 let body = http_body::Limited::new(body, self.http_max_request_bytes);
                 hyper::body::to_bytes(body)
@@ -683,6 +825,7 @@ impl RouterService {
                             }
                         }
                     })
+                    .map_err(RequestRejection::Invalid)
                     .and_then(|bytes| {
                         self.translate_bytes_request(&bytes)
                     })
@@ -700,17 +843,17 @@ impl RouterService {
             .then(|| {
                 context.extensions().lock().insert(self.batching.clone());
 
-                self.batching.subgraph.as_ref()
-            })
-            .flatten()
-            .map(|subgraph_batching_config| {
-                subgraph_batching_config.all.enabled
-                    || subgraph_batching_config
-                        .subgraphs
-                        .values()
-                        .any(|v| v.enabled)
+                self.batching.subgraph_batching_enabled()
             })
             .and_then(|a| a.then_some(Arc::new(Batch::spawn_handler(batch_size))));
+        // The operations of the batch share the same cost budget
+        let batch_cost = is_batch
+            .then_some(self.batching.max_cost)
+            .flatten()
+            .map(|max_cost| Arc::new(BatchCost::new(max_cost)));
+        if let Some(batch_cost) = &batch_cost {
+            context.extensions().lock().insert(batch_cost.clone());
+        }
 
         let mut ok_results_it = ok_results.into_iter();
         let first = ok_results_it
@@ -753,6 +896,9 @@ impl RouterService {
                     new_context_guard.insert(client_request_accepts);
                 }
                 new_context_guard.insert(self.batching.clone());
+                if let Some(batch_cost) = &batch_cost {
+                    new_context_guard.insert(batch_cost.clone());
+                }
                 // We are only going to insert a BatchQuery if Subgraph processing is enabled
                 if let Some(shared_batch_details) = &shared_batch_details {
                     new_context_guard.insert(
@@ -826,6 +972,18 @@ struct TranslateError<'a> {
     extension_details: String,
 }
 
+enum RequestRejection<'a> {
+    Invalid(TranslateError<'a>),
+    /// The batch size and the configured maximum, checked before the operations are deserialized
+    BatchTooLarge(usize, usize),
+}
+
+impl<'a> From<TranslateError<'a>> for RequestRejection<'a> {
+    fn from(error: TranslateError<'a>) -> Self {
+        RequestRejection::Invalid(error)
+    }
+}
+
 // Process the headers to make sure that `VARY` is set correctly
 pub(crate) fn process_vary_header(headers: &mut HeaderMap<HeaderValue>) {
     if headers.get(VARY).is_none() {
@@ -1,5 +1,8 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 
 use futures::stream::StreamExt;
 use http::header::CONTENT_TYPE;
@@ -14,14 +17,17 @@ use tower::ServiceExt;
 use tower_service::Service;
 
 use crate::graphql;
+use crate::metrics::FutureMetricsExt;
 use crate::services::router;
 use crate::services::router::service::from_supergraph_mock_callback;
+use crate::services::router::service::from_supergraph_mock_callback_and_configuration;
 use crate::services::router::service::process_vary_header;
 use crate::services::subgraph;
 use crate::services::supergraph;
 use crate::services::SupergraphRequest;
 use crate::services::SupergraphResponse;
 use crate::services::MULTIPART_DEFER_CONTENT_TYPE;
+use crate::Configuration;
 use crate::Context;
 
 // Test Vary processing
@@ -371,6 +377,213 @@ async fn it_will_not_process_a_query_batch_without_enablement() {
     assert_eq!(expected_response, data);
 }
 
+#[tokio::test]
+async fn it_will_not_process_a_query_batch_above_max_size() {
+    let expected_response: serde_json::Value = serde_json::from_str(include_str!(
+        "../query_batching/testdata/batch_max_size_response.json"
+    ))
+    .unwrap();
+
+    async fn with_config() -> router::Response {
+        let http_request = supergraph::Request::canned_builder()
+            .build()
+            .unwrap()
+            .supergraph_request
+            .map(|req: crate::request::Request| {
+                // Modify the request so that it is a valid array of requests.
+                let mut json_bytes = serde_json::to_vec(&req).unwrap();
+                let mut result = vec![b'['];
+                result.append(&mut json_bytes.clone());
+                result.push(b',');
+                result.append(&mut json_bytes);
+                result.push(b']');
+                hyper::Body::from(result)
+            });
+        let config = serde_json::json!({
+            "batching": {
+                "enabled": true,
+                "mode" : "batch_http_link",
+                "max_size": 1
+            }
+        });
+        crate::TestHarness::builder()
+            .configuration_json(config)
+            .unwrap()
+            .build_router()
+            .await
+            .unwrap()
+            .oneshot(router::Request::from(http_request))
+            .await
+            .unwrap()
+    }
+    // Send a request
+    let response = with_config().await.response;
+    assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+    let data: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    assert_eq!(expected_response, data);
+}
+
+fn batch_of(query: &str, size: usize) -> router::Request {
+    let request = graphql::Request::builder().query(query).build();
+    let batch = serde_json::to_vec(&vec![request; size]).unwrap();
+    http::Request::builder()
+        .method(Method::POST)
+        .uri("http://example.com/")
+        .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
+        .body(hyper::Body::from(batch))
+        .unwrap()
+        .into()
+}
+
+#[tokio::test]
+async fn it_limits_the_operations_of_a_query_batch_in_flight() {
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+    let configuration = Configuration::builder()
+        .batching(
+            serde_json::from_value::<crate::configuration::Batching>(serde_json::json!({
+                "enabled": true,
+                "mode" : "batch_http_link",
+                "max_in_flight": 2
+            }))
+            .unwrap(),
+        )
+        .build()
+        .unwrap();
+    let in_flight_in_callback = in_flight.clone();
+    let max_in_flight_in_callback = max_in_flight.clone();
+    let router_service = from_supergraph_mock_callback_and_configuration(
+        move |req| {
+            let current = in_flight_in_callback.fetch_add(1, Ordering::SeqCst) + 1;
+            max_in_flight_in_callback.fetch_max(current, Ordering::SeqCst);
+            let in_flight = in_flight_in_callback.clone();
+            // the operation stays in flight until its response is sent
+            let response = futures::stream::once(async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                graphql::Response::builder()
+                    .data(json!({"response": "yay"}))
+                    .build()
+            });
+            Ok(SupergraphResponse::new_from_response(
+                http::Response::new(response.boxed()),
+                req.context,
+            ))
+        },
+        Arc::new(configuration),
+    )
+    .await;
+
+    let response = router_service
+        .oneshot(batch_of("{ me { name } }", 5))
+        .await
+        .unwrap()
+        .response;
+    assert_eq!(response.status(), http::StatusCode::OK);
+    let data: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    assert_eq!(data.as_array().unwrap().len(), 5);
+    assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn it_records_the_size_of_accepted_query_batches() {
+    async {
+        let configuration = Configuration::builder()
+            .batching(
+                serde_json::from_value::<crate::configuration::Batching>(serde_json::json!({
+                    "enabled": true,
+                    "mode" : "batch_http_link",
+                    "max_size": 3
+                }))
+                .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let mut router_service = from_supergraph_mock_callback_and_configuration(
+            move |req| {
+                let response = graphql::Response::builder()
+                    .data(json!({"response": "yay"}))
+                    .build();
+                Ok(SupergraphResponse::new_from_graphql_response(
+                    response,
+                    req.context,
+                ))
+            },
+            Arc::new(configuration),
+        )
+        .await;
+
+        for size in [2, 3, 4] {
+            router_service
+                .ready()
+                .await
+                .unwrap()
+                .call(batch_of("{ me { name } }", size))
+                .await
+                .unwrap();
+        }
+
+        // the batch of 4 operations is rejected and not recorded
+        assert_histogram_sum!("apollo.router.operations.batching.client_size", 5);
+    }
+    .with_metrics()
+    .await;
+}
+
+#[tokio::test]
+async fn it_limits_the_estimated_cost_of_a_query_batch() {
+    let config = serde_json::json!({
+        "batching": {
+            "enabled": true,
+            "mode" : "batch_http_link",
+            "max_cost": 2.0
+        },
+        "experimental_demand_control": {
+            "enabled": true,
+            "mode": "enforce",
+            "strategy": {
+                "static_estimated": {
+                    "list_size": 10,
+                    "max": 10.0
+                }
+            }
+        }
+    });
+    let response = crate::TestHarness::builder()
+        .configuration_json(config)
+        .unwrap()
+        .build_router()
+        .await
+        .unwrap()
+        // each operation has an estimated cost of 1
+        .oneshot(batch_of("{ me { name } }", 3))
+        .await
+        .unwrap()
+        .response;
+
+    let data: serde_json::Value =
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+            .unwrap();
+    let responses = data.as_array().unwrap();
+    assert_eq!(responses.len(), 3);
+    let rejected = responses
+        .iter()
+        .filter(|response| {
+            response["errors"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .any(|error| error["extensions"]["code"] == "COST_BATCH_TOO_EXPENSIVE")
+        })
+        .count();
+    assert_eq!(rejected, 1);
+}
+
 #[tokio::test]
 async fn it_will_not_process_a_poorly_formatted_query_batch() {
     let expected_response: serde_json::Value = serde_json::from_str(include_str!(
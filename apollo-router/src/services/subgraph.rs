@@ -4,6 +4,7 @@ use std::pin::Pin;
 use std::sync::Arc;
 
 use apollo_compiler::validation::Valid;
+use futures::stream::BoxStream;
 use http::StatusCode;
 use http::Version;
 use multimap::MultiMap;
@@ -52,6 +53,8 @@ pub struct Request {
     pub(crate) subgraph_name: Option<String>,
     /// Channel to send the subscription stream to listen on events coming from subgraph in a task
     pub(crate) subscription_stream: Option<mpsc::Sender<BoxGqlStream>>,
+    /// Channel to send the subsequent payloads of an incremental response, when `@defer` is passed through to the subgraph
+    pub(crate) deferred_stream: Option<mpsc::Sender<BoxStream<'static, graphql::Response>>>,
     /// Channel triggered when the client connection has been dropped
     pub(crate) connection_closed_signal: Option<broadcast::Receiver<()>>,
 
@@ -85,6 +88,7 @@ impl Request {
             context,
             subgraph_name,
             subscription_stream,
+            deferred_stream: None,
             connection_closed_signal,
             query_hash: Default::default(),
             authorization: Default::default(),
@@ -146,6 +150,7 @@ impl Clone for Request {
             context: self.context.clone(),
             subgraph_name: self.subgraph_name.clone(),
             subscription_stream: self.subscription_stream.clone(),
+            deferred_stream: self.deferred_stream.clone(),
             connection_closed_signal: self
                 .connection_closed_signal
                 .as_ref()
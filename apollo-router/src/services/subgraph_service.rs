@@ -8,6 +8,8 @@ use std::task::Poll;
 
 use bytes::Bytes;
 use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::FutureExt;
 use futures::StreamExt;
 use futures::TryFutureExt;
 use http::header::ACCEPT;
@@ -20,8 +22,14 @@ use hyper::Body;
 use hyper_rustls::ConfigBuilderExt;
 use itertools::Itertools;
 use mediatype::names::APPLICATION;
+use mediatype::names::BOUNDARY;
+use mediatype::names::EVENT_STREAM;
 use mediatype::names::JSON;
+use mediatype::names::MIXED;
+use mediatype::names::MULTIPART;
+use mediatype::names::TEXT;
 use mediatype::MediaType;
+use mediatype::ReadParams;
 use mime::APPLICATION_JSON;
 use rustls::RootCertStore;
 use serde::Serialize;
@@ -51,10 +59,13 @@ use crate::error::FetchError;
 use crate::error::SubgraphBatchingError;
 use crate::graphql;
 use crate::json_ext::Object;
+use crate::notification::redis::OpenSubscription;
+use crate::notification::redis::RedisNotify;
 use crate::plugins::authentication::subgraph::SigningParamsConfig;
 use crate::plugins::file_uploads;
 use crate::plugins::subscription::create_verifier;
 use crate::plugins::subscription::CallbackMode;
+use crate::plugins::subscription::PassthroughProtocol;
 use crate::plugins::subscription::SubscriptionConfig;
 use crate::plugins::subscription::SubscriptionMode;
 use crate::plugins::subscription::WebSocketConfiguration;
@@ -64,12 +75,17 @@ use crate::plugins::telemetry::config_new::events::SubgraphEventRequestLevel;
 use crate::plugins::telemetry::config_new::events::SubgraphEventResponseLevel;
 use crate::plugins::telemetry::LOGGING_DISPLAY_BODY;
 use crate::plugins::telemetry::LOGGING_DISPLAY_HEADERS;
+use crate::protocols::multipart;
+use crate::protocols::sse;
 use crate::protocols::websocket::convert_websocket_stream;
 use crate::protocols::websocket::GraphqlWebSocket;
+use crate::protocols::websocket::WebSocketProtocol;
 use crate::query_planner::OperationKind;
 use crate::services::layers::apq;
 use crate::services::SubgraphRequest;
 use crate::services::SubgraphResponse;
+use crate::services::EVENT_STREAM_CONTENT_TYPE;
+use crate::services::MULTIPART_SUBSCRIPTION_ACCEPT;
 use crate::Configuration;
 use crate::Context;
 use crate::Notify;
@@ -92,6 +108,9 @@ pub(crate) static APPLICATION_JSON_HEADER_VALUE: HeaderValue =
     HeaderValue::from_static("application/json");
 static ACCEPT_GRAPHQL_JSON: HeaderValue =
     HeaderValue::from_static("application/json, application/graphql-response+json");
+static ACCEPT_INCREMENTAL_GRAPHQL_JSON: HeaderValue = HeaderValue::from_static(
+    "multipart/mixed;deferSpec=20220824, application/json, application/graphql-response+json",
+);
 
 enum APQError {
     PersistedQueryNotSupported,
@@ -127,6 +146,8 @@ pub(crate) struct SubgraphService {
     /// Subscription config if enabled
     subscription_config: Option<SubscriptionConfig>,
     notify: Notify<String, graphql::Response>,
+    /// Deduplicates subscriptions between router instances, if configured
+    redis_notify: Option<RedisNotify>,
 }
 
 impl SubgraphService {
@@ -134,6 +155,7 @@ impl SubgraphService {
         service: impl Into<String>,
         configuration: &Configuration,
         subscription_config: Option<SubscriptionConfig>,
+        redis_notify: Option<RedisNotify>,
         client_factory: HttpClientServiceFactory,
     ) -> Result<Self, BoxError> {
         let name: String = service.into();
@@ -146,13 +168,15 @@ impl SubgraphService {
             .map(|apq| apq.enabled)
             .unwrap_or(configuration.apq.subgraph.all.enabled);
 
-        SubgraphService::new(
+        let mut service = SubgraphService::new(
             name,
             enable_apq,
             subscription_config,
             configuration.notify.clone(),
             client_factory,
-        )
+        )?;
+        service.redis_notify = redis_notify;
+        Ok(service)
     }
 
     pub(crate) fn new(
@@ -168,6 +192,7 @@ impl SubgraphService {
             apq: Arc::new(<AtomicBool>::new(enable_apq)),
             subscription_config,
             notify,
+            redis_notify: None,
         })
     }
 }
@@ -245,6 +270,11 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
         let arc_apq_enabled = self.apq.clone();
 
         let mut notify = self.notify.clone();
+        // Subscriptions can only be shared with other router instances if they are deduplicated
+        let redis_notify = subscription_config
+            .as_ref()
+            .filter(|config| config.enable_deduplication)
+            .and(self.redis_notify.clone());
 
         let make_calls = async move {
             // Subscription handling
@@ -261,16 +291,37 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
 
                 match &mode {
                     Some(SubscriptionMode::Passthrough(ws_conf)) => {
-                        // call_websocket for passthrough mode
-                        return call_websocket(
-                            notify,
-                            request,
-                            context,
-                            service_name,
-                            ws_conf,
-                            hashed_request,
-                        )
-                        .await;
+                        return match ws_conf.protocol.websocket() {
+                            // call_websocket for passthrough mode
+                            Some(protocol) => {
+                                call_websocket(
+                                    notify,
+                                    redis_notify,
+                                    request,
+                                    context,
+                                    service_name,
+                                    ws_conf,
+                                    protocol,
+                                    hashed_request,
+                                )
+                                .await
+                            }
+                            // or stream the events over HTTP
+                            None => {
+                                call_http_subscription(
+                                    notify,
+                                    redis_notify,
+                                    request,
+                                    body,
+                                    context,
+                                    client_factory,
+                                    service_name,
+                                    ws_conf,
+                                    hashed_request,
+                                )
+                                .await
+                            }
+                        };
                     }
                     Some(SubscriptionMode::Callback(CallbackMode {
                         public_url,
@@ -292,7 +343,6 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
                                 reason: "cannot get the callback stream".to_string(),
                             }
                         })?;
-                        stream_tx.send(Box::pin(handle.into_stream())).await?;
 
                         tracing::info!(
                             monotonic_counter.apollo.router.operations.subscriptions = 1u64,
@@ -301,6 +351,7 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
                             subgraph.service.name = service_name,
                         );
                         if !created {
+                            stream_tx.send(Box::pin(handle.into_stream())).await?;
                             tracing::info!(
                                 monotonic_counter.apollo_router_deduplicated_subscriptions_total = 1u64,
                                 mode = %"callback",
@@ -338,7 +389,7 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
                             .append(ACCEPT, CALLBACK_PROTOCOL_ACCEPT.clone());
 
                         let subscription_extension = SubscriptionExtension {
-                            subscription_id,
+                            subscription_id: subscription_id.clone(),
                             callback_url,
                             verifier,
                             heartbeat_interval_ms: heartbeat_interval
@@ -358,6 +409,67 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
                                 }
                             })?,
                         );
+
+                        if let Some(redis_notify) = &redis_notify {
+                            if !redis_notify.acquire(&subscription_id).await {
+                                // Another router instance owns the subscription, this one
+                                // registers its callback again if it takes over
+                                let open: OpenSubscription = {
+                                    let request = request.clone();
+                                    let body = body.clone();
+                                    let context = context.clone();
+                                    let client_factory = client_factory.clone();
+                                    let service_name = service_name.clone();
+                                    Arc::new(move || {
+                                        let request = request.clone();
+                                        let body = body.clone();
+                                        let context = context.clone();
+                                        let client_factory = client_factory.clone();
+                                        let service_name = service_name.clone();
+                                        async move {
+                                            let response = call_http(
+                                                request,
+                                                body,
+                                                context,
+                                                client_factory,
+                                                &service_name,
+                                            )
+                                            .await?;
+                                            let errors = &response.response.body().errors;
+                                            if !errors.is_empty() {
+                                                return Err(BoxError::from(
+                                                    errors
+                                                        .iter()
+                                                        .map(|error| error.message.as_str())
+                                                        .collect::<Vec<_>>()
+                                                        .join(", "),
+                                                ));
+                                            }
+                                            Ok(None)
+                                        }
+                                        .boxed()
+                                    })
+                                };
+                                let (handle_sink, handle_stream) = handle.split();
+                                redis_notify.follow(notify, subscription_id, handle_sink, open);
+                                stream_tx.send(Box::pin(handle_stream)).await?;
+                                tracing::info!(
+                                    monotonic_counter.apollo_router_deduplicated_subscriptions_total = 1u64,
+                                    mode = %"callback",
+                                );
+
+                                return Ok(SubgraphResponse::builder()
+                                    .context(context)
+                                    .extensions(Object::default())
+                                    .build());
+                            }
+                            redis_notify.lead(
+                                subscription_id,
+                                handle.clone(),
+                                Some(notify.clone()),
+                            );
+                        }
+                        stream_tx.send(Box::pin(handle.into_stream())).await?;
                     }
                     _ => {
                         return Err(Box::new(FetchError::SubrequestWsError {
@@ -455,31 +567,25 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
 }
 
 /// call websocket makes websocket calls with modified graphql::Request (body)
+#[allow(clippy::too_many_arguments)]
 async fn call_websocket(
     mut notify: Notify<String, graphql::Response>,
+    redis_notify: Option<RedisNotify>,
     request: SubgraphRequest,
     context: Context,
     service_name: String,
     subgraph_cfg: &WebSocketConfiguration,
+    protocol: WebSocketProtocol,
     subscription_hash: String,
 ) -> Result<SubgraphResponse, BoxError> {
-    let operation_name = request
-        .subgraph_request
-        .body()
-        .operation_name
-        .clone()
-        .unwrap_or_default();
-
-    let SubgraphRequest {
-        subgraph_request,
-        subscription_stream,
-        ..
-    } = request;
     let subscription_stream_tx =
-        subscription_stream.ok_or_else(|| FetchError::SubrequestWsError {
-            service: service_name.clone(),
-            reason: "cannot get the websocket stream".to_string(),
-        })?;
+        request
+            .subscription_stream
+            .clone()
+            .ok_or_else(|| FetchError::SubrequestWsError {
+                service: service_name.clone(),
+                reason: "cannot get the websocket stream".to_string(),
+            })?;
 
     let (handle, created) = notify
         .create_or_subscribe(subscription_hash.clone(), false)
@@ -506,7 +612,112 @@ async fn call_websocket(
             .build());
     }
 
-    let (parts, body) = subgraph_request.into_parts();
+    if let Some(redis_notify) = &redis_notify {
+        if !redis_notify.acquire(&subscription_hash).await {
+            // Another router instance owns the subscription
+            let open: OpenSubscription = {
+                let request = request.clone();
+                let context = context.clone();
+                let service_name = service_name.clone();
+                let subgraph_cfg = subgraph_cfg.clone();
+                let subscription_hash = subscription_hash.clone();
+                Arc::new(move || {
+                    let request = request.clone();
+                    let context = context.clone();
+                    let service_name = service_name.clone();
+                    let subgraph_cfg = subgraph_cfg.clone();
+                    let subscription_hash = subscription_hash.clone();
+                    async move {
+                        let (_, gql_stream) = connect_websocket(
+                            request,
+                            &context,
+                            service_name,
+                            &subgraph_cfg,
+                            protocol,
+                            subscription_hash,
+                        )
+                        .await?;
+                        Ok(Some(gql_stream))
+                    }
+                    .boxed()
+                })
+            };
+            let (handle_sink, handle_stream) = handle.split();
+            redis_notify.follow(notify, subscription_hash, handle_sink, open);
+            subscription_stream_tx.send(Box::pin(handle_stream)).await?;
+            tracing::info!(
+                monotonic_counter.apollo_router_deduplicated_subscriptions_total = 1u64,
+                mode = %"passthrough",
+            );
+
+            return Ok(SubgraphResponse::builder()
+                .context(context)
+                .extensions(Object::default())
+                .build());
+        }
+    }
+
+    let (resp, gql_stream) = match connect_websocket(
+        request,
+        &context,
+        service_name,
+        subgraph_cfg,
+        protocol,
+        subscription_hash.clone(),
+    )
+    .await
+    {
+        Ok(connection) => connection,
+        Err(err) => {
+            if let Some(redis_notify) = &redis_notify {
+                redis_notify.release(&subscription_hash).await;
+            }
+            return Err(err);
+        }
+    };
+    if let Some(redis_notify) = &redis_notify {
+        redis_notify.lead(subscription_hash, handle.clone(), None);
+    }
+
+    let (handle_sink, handle_stream) = handle.split();
+
+    tokio::task::spawn(async move {
+        let _ = gql_stream
+            .map(Ok::<_, graphql::Error>)
+            .forward(handle_sink)
+            .await;
+    });
+
+    subscription_stream_tx.send(Box::pin(handle_stream)).await?;
+
+    Ok(SubgraphResponse::new_from_response(
+        resp.map(|_| graphql::Response::default()),
+        context,
+    ))
+}
+
+/// connect_websocket opens the subscription to the subgraph over a websocket
+async fn connect_websocket(
+    request: SubgraphRequest,
+    context: &Context,
+    service_name: String,
+    subgraph_cfg: &WebSocketConfiguration,
+    protocol: WebSocketProtocol,
+    subscription_hash: String,
+) -> Result<
+    (
+        http::Response<Option<Vec<u8>>>,
+        BoxStream<'static, graphql::Response>,
+    ),
+    BoxError,
+> {
+    let operation_name = request
+        .subgraph_request
+        .body()
+        .operation_name
+        .clone()
+        .unwrap_or_default();
+    let (parts, body) = request.subgraph_request.into_parts();
 
     // Check context key and Authorization header (context key takes precedence) to set connection params if needed
     let connection_params = match (
@@ -521,7 +732,7 @@ async fn call_websocket(
         _ => None,
     };
 
-    let request = get_websocket_request(service_name.clone(), parts, subgraph_cfg)?;
+    let request = get_websocket_request(service_name.clone(), parts, subgraph_cfg, protocol)?;
 
     let display_headers = context.contains_key(LOGGING_DISPLAY_HEADERS);
     let display_body = context.contains_key(LOGGING_DISPLAY_BODY);
@@ -637,7 +848,7 @@ async fn call_websocket(
     let gql_socket = GraphqlWebSocket::new(
         convert_websocket_stream(ws_stream, subscription_hash.clone()),
         subscription_hash,
-        subgraph_cfg.protocol,
+        protocol,
         connection_params,
     )
     .await
@@ -654,10 +865,131 @@ async fn call_websocket(
             reason: format!("cannot send the subgraph request to websocket stream: {err:?}"),
         })?;
 
+    Ok((resp, gql_stream.boxed()))
+}
+
+/// call_http_subscription receives the events of a subscription in passthrough mode from an HTTP
+/// stream, with Server-Sent Events or multipart responses
+#[allow(clippy::too_many_arguments)]
+async fn call_http_subscription(
+    mut notify: Notify<String, graphql::Response>,
+    redis_notify: Option<RedisNotify>,
+    request: SubgraphRequest,
+    body: graphql::Request,
+    context: Context,
+    client_factory: HttpClientServiceFactory,
+    service_name: String,
+    subgraph_cfg: &WebSocketConfiguration,
+    subscription_hash: String,
+) -> Result<SubgraphResponse, BoxError> {
+    let subscription_stream_tx =
+        request
+            .subscription_stream
+            .clone()
+            .ok_or_else(|| FetchError::SubrequestHttpError {
+                service: service_name.clone(),
+                reason: "cannot get the subscription stream".to_string(),
+                status_code: None,
+            })?;
+
+    let (handle, created) = notify
+        .create_or_subscribe(subscription_hash.clone(), false)
+        .await?;
+    tracing::info!(
+        monotonic_counter.apollo.router.operations.subscriptions = 1u64,
+        subscriptions.mode = %"passthrough",
+        subscriptions.deduplicated = !created,
+        subgraph.service.name = service_name,
+    );
+    if !created {
+        subscription_stream_tx
+            .send(Box::pin(handle.into_stream()))
+            .await?;
+        tracing::info!(
+            monotonic_counter.apollo_router_deduplicated_subscriptions_total = 1u64,
+            mode = %"passthrough",
+        );
+
+        // Dedup happens here
+        return Ok(SubgraphResponse::builder()
+            .context(context)
+            .extensions(Object::default())
+            .build());
+    }
+
+    if let Some(redis_notify) = &redis_notify {
+        if !redis_notify.acquire(&subscription_hash).await {
+            // Another router instance owns the subscription
+            let open: OpenSubscription = {
+                let request = request.clone();
+                let body = body.clone();
+                let context = context.clone();
+                let client_factory = client_factory.clone();
+                let service_name = service_name.clone();
+                let subgraph_cfg = subgraph_cfg.clone();
+                Arc::new(move || {
+                    let request = request.clone();
+                    let body = body.clone();
+                    let context = context.clone();
+                    let client_factory = client_factory.clone();
+                    let service_name = service_name.clone();
+                    let subgraph_cfg = subgraph_cfg.clone();
+                    async move {
+                        let (_, stream) = connect_http_subscription(
+                            request,
+                            body,
+                            &context,
+                            &client_factory,
+                            service_name,
+                            &subgraph_cfg,
+                        )
+                        .await?;
+                        Ok(Some(stream))
+                    }
+                    .boxed()
+                })
+            };
+            let (handle_sink, handle_stream) = handle.split();
+            redis_notify.follow(notify, subscription_hash, handle_sink, open);
+            subscription_stream_tx.send(Box::pin(handle_stream)).await?;
+            tracing::info!(
+                monotonic_counter.apollo_router_deduplicated_subscriptions_total = 1u64,
+                mode = %"passthrough",
+            );
+
+            return Ok(SubgraphResponse::builder()
+                .context(context)
+                .extensions(Object::default())
+                .build());
+        }
+    }
+
+    let (parts, stream) = match connect_http_subscription(
+        request,
+        body,
+        &context,
+        &client_factory,
+        service_name,
+        subgraph_cfg,
+    )
+    .await
+    {
+        Ok(connection) => connection,
+        Err(err) => {
+            if let Some(redis_notify) = &redis_notify {
+                redis_notify.release(&subscription_hash).await;
+            }
+            return Err(err);
+        }
+    };
+    if let Some(redis_notify) = &redis_notify {
+        redis_notify.lead(subscription_hash, handle.clone(), None);
+    }
+
     let (handle_sink, handle_stream) = handle.split();
 
     tokio::task::spawn(async move {
-        let _ = gql_stream
+        let _ = stream
             .map(Ok::<_, graphql::Error>)
             .forward(handle_sink)
             .await;
@@ -666,11 +998,122 @@ async fn call_websocket(
     subscription_stream_tx.send(Box::pin(handle_stream)).await?;
 
     Ok(SubgraphResponse::new_from_response(
-        resp.map(|_| graphql::Response::default()),
+        http::Response::from_parts(parts, graphql::Response::default()),
         context,
     ))
 }
 
+/// connect_http_subscription opens the subscription to the subgraph over HTTP
+async fn connect_http_subscription(
+    request: SubgraphRequest,
+    body: graphql::Request,
+    context: &Context,
+    client_factory: &HttpClientServiceFactory,
+    service_name: String,
+    subgraph_cfg: &WebSocketConfiguration,
+) -> Result<(http::response::Parts, BoxStream<'static, graphql::Response>), BoxError> {
+    let operation_name = body.operation_name.clone().unwrap_or_default();
+    let (mut parts, _) = request.subgraph_request.into_parts();
+    if let Some(path) = &subgraph_cfg.path {
+        parts.uri = url::Url::parse(&parts.uri.to_string())
+            .and_then(|url| url.join(path))
+            .ok()
+            .and_then(|url| url.as_str().parse().ok())
+            .ok_or_else(|| FetchError::SubrequestHttpError {
+                service: service_name.clone(),
+                reason: "cannot parse subgraph url with the specific subscription path".to_string(),
+                status_code: None,
+            })?;
+    }
+    let accept = match subgraph_cfg.protocol {
+        PassthroughProtocol::Sse => HeaderValue::from_static(EVENT_STREAM_CONTENT_TYPE),
+        _ => HeaderValue::from_static(MULTIPART_SUBSCRIPTION_ACCEPT),
+    };
+    let mut request = http::Request::from_parts(parts, Body::from(serde_json::to_string(&body)?));
+    request
+        .headers_mut()
+        .insert(CONTENT_TYPE, APPLICATION_JSON_HEADER_VALUE.clone());
+    request.headers_mut().insert(ACCEPT, accept);
+
+    let (host, port, path) = get_uri_details(request.uri());
+    let subgraph_req_span = tracing::info_span!("subgraph_request",
+        "otel.kind" = "CLIENT",
+        "net.peer.name" = %host,
+        "net.peer.port" = %port,
+        "http.route" = %path,
+        "http.url" = %request.uri(),
+        "net.transport" = "ip_tcp",
+        "apollo.subgraph.name" = %service_name,
+        "graphql.operation.name" = %operation_name,
+    );
+
+    let response = client_factory
+        .create(&service_name)
+        .oneshot(HttpRequest {
+            http_request: request,
+            context: context.clone(),
+        })
+        .instrument(subgraph_req_span)
+        .await
+        .map_err(|err| FetchError::SubrequestHttpError {
+            status_code: None,
+            service: service_name.clone(),
+            reason: err.to_string(),
+        })?;
+    let (parts, body) = response.http_response.into_parts();
+    if !parts.status.is_success() {
+        return Err(Box::new(FetchError::SubrequestHttpError {
+            status_code: Some(parts.status.as_u16()),
+            service: service_name,
+            reason: format!(
+                "{}: {}",
+                parts.status.as_str(),
+                parts.status.canonical_reason().unwrap_or("Unknown")
+            ),
+        }));
+    }
+
+    // Heartbeats from the subgraph are data too, so the interval applies to any chunk
+    let body = match subgraph_cfg.heartbeat_interval.into_option() {
+        Some(interval) => tokio_stream::StreamExt::timeout(body, interval)
+            .map(|chunk| match chunk {
+                Ok(chunk) => chunk.map_err(BoxError::from),
+                Err(_) => Err(BoxError::from("no heartbeat received")),
+            })
+            .boxed(),
+        None => body.map(|chunk| chunk.map_err(BoxError::from)).boxed(),
+    };
+
+    let content_type = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| MediaType::parse(value).ok());
+    let stream = match content_type {
+        Some(content_type) if content_type.ty == TEXT && content_type.subty == EVENT_STREAM => {
+            sse::decode(service_name.clone(), body).boxed()
+        }
+        Some(content_type) if content_type.ty == MULTIPART && content_type.subty == MIXED => {
+            let boundary = content_type.get_param(BOUNDARY).map_or_else(
+                || "graphql".to_string(),
+                |boundary| boundary.unquoted_str().to_string(),
+            );
+            multipart::decode_subscription(service_name.clone(), boundary, body).boxed()
+        }
+        _ => {
+            return Err(Box::new(FetchError::SubrequestHttpError {
+                status_code: Some(parts.status.as_u16()),
+                service: service_name,
+                reason: format!(
+                    "subgraph didn't return a subscription stream (expected content-type: {EVENT_STREAM_CONTENT_TYPE} or content-type: {MULTIPART_SUBSCRIPTION_ACCEPT})"
+                ),
+            }));
+        }
+    };
+
+    Ok((parts, stream))
+}
+
 // Utility function to extract uri details.
 fn get_uri_details(uri: &hyper::Uri) -> (&str, u16, &str) {
     let port = uri.port_u16().unwrap_or_else(|| {
@@ -696,6 +1139,7 @@ fn http_response_to_graphql_response(
 ) -> graphql::Response {
     let mut graphql_response = match (content_type, body, parts.status.is_success()) {
         (Ok(ContentType::ApplicationGraphqlResponseJson), Some(Ok(body)), _)
+        | (Ok(ContentType::MultipartMixed(_)), Some(Ok(body)), _)
         | (Ok(ContentType::ApplicationJson), Some(Ok(body)), true) => {
             // Application graphql json expects valid graphql response
             // Application json expects valid graphql response if 2xx
@@ -826,9 +1270,10 @@ pub(crate) async fn process_batch(
 
     // Perform the actual fetch. If this fails then we didn't manage to make the call at all, so we can't do anything with it.
     tracing::debug!("fetching from subgraph: {service}");
-    let (parts, content_type, body) = do_fetch(client, &context, &service, request, display_body)
-        .instrument(subgraph_req_span)
-        .await?;
+    let (parts, content_type, body, _) =
+        do_fetch(client, &context, &service, request, display_body, false)
+            .instrument(subgraph_req_span)
+            .await?;
 
     let subgraph_response_event = context
         .extensions()
@@ -1121,7 +1566,9 @@ pub(crate) async fn call_single_http(
     service_name: &str,
 ) -> Result<SubgraphResponse, BoxError> {
     let SubgraphRequest {
-        subgraph_request, ..
+        subgraph_request,
+        deferred_stream,
+        ..
     } = request;
 
     let operation_name = subgraph_request
@@ -1138,9 +1585,13 @@ pub(crate) async fn call_single_http(
     request
         .headers_mut()
         .insert(CONTENT_TYPE, APPLICATION_JSON_HEADER_VALUE.clone());
-    request
-        .headers_mut()
-        .append(ACCEPT, ACCEPT_GRAPHQL_JSON.clone());
+    // `@defer` passed through to the subgraph is answered with a multipart response
+    let accept = if deferred_stream.is_some() {
+        ACCEPT_INCREMENTAL_GRAPHQL_JSON.clone()
+    } else {
+        ACCEPT_GRAPHQL_JSON.clone()
+    };
+    request.headers_mut().append(ACCEPT, accept);
 
     let schema_uri = request.uri();
     let (host, port, path) = get_uri_details(schema_uri);
@@ -1209,10 +1660,21 @@ pub(crate) async fn call_single_http(
     }
 
     // Perform the actual fetch. If this fails then we didn't manage to make the call at all, so we can't do anything with it.
-    let (parts, content_type, body) =
-        do_fetch(client, &context, service_name, request, display_body)
-            .instrument(subgraph_req_span)
-            .await?;
+    let (parts, content_type, body, incremental) = do_fetch(
+        client,
+        &context,
+        service_name,
+        request,
+        display_body,
+        deferred_stream.is_some(),
+    )
+    .instrument(subgraph_req_span)
+    .await?;
+    if let (Some(deferred_stream), Some(incremental)) = (deferred_stream, incremental) {
+        if deferred_stream.send(incremental).await.is_err() {
+            tracing::debug!("the incremental response of subgraph {service_name:?} is not used");
+        }
+    }
 
     let subgraph_response_event = context
         .extensions()
@@ -1267,9 +1729,16 @@ pub(crate) async fn call_single_http(
 enum ContentType {
     ApplicationJson,
     ApplicationGraphqlResponseJson,
+    /// Incremental delivery response, with the boundary between its parts
+    MultipartMixed(String),
 }
 
-fn get_graphql_content_type(service_name: &str, parts: &Parts) -> Result<ContentType, FetchError> {
+// A multipart response is only valid if the request accepted incremental delivery
+fn get_graphql_content_type(
+    service_name: &str,
+    parts: &Parts,
+    incremental: bool,
+) -> Result<ContentType, FetchError> {
     let content_type = parts
         .headers
         .get(header::CONTENT_TYPE)
@@ -1283,6 +1752,13 @@ fn get_graphql_content_type(service_name: &str, parts: &Parts) -> Result<Content
                 && content_type.suffix == Some(JSON)
             {
                 Ok(ContentType::ApplicationGraphqlResponseJson)
+            } else if incremental && content_type.ty == MULTIPART && content_type.subty == MIXED {
+                Ok(ContentType::MultipartMixed(
+                    content_type.get_param(BOUNDARY).map_or_else(
+                        || "-".to_string(),
+                        |boundary| boundary.unquoted_str().to_string(),
+                    ),
+                ))
             } else {
                 Err(FetchError::SubrequestHttpError {
                     status_code: Some(parts.status.as_u16()),
@@ -1309,11 +1785,13 @@ async fn do_fetch(
     service_name: &str,
     request: Request<Body>,
     display_body: bool,
+    incremental: bool,
 ) -> Result<
     (
         Parts,
         Result<ContentType, FetchError>,
         Option<Result<Bytes, FetchError>>,
+        Option<BoxStream<'static, graphql::Response>>,
     ),
     FetchError,
 > {
@@ -1335,7 +1813,41 @@ async fn do_fetch(
 
     let (parts, body) = response.http_response.into_parts();
 
-    let content_type = get_graphql_content_type(service_name, &parts);
+    let content_type = get_graphql_content_type(service_name, &parts, incremental);
+
+    // With incremental delivery, the first part is the initial response and the following
+    // parts are read in the background
+    if let Ok(ContentType::MultipartMixed(boundary)) = &content_type {
+        let mut parts_stream = multipart::decode_parts(boundary.clone(), body).boxed();
+        let body = match parts_stream
+            .next()
+            .instrument(tracing::debug_span!("aggregate_response_data"))
+            .await
+        {
+            Some(Ok(body)) => Ok(body),
+            Some(Err(err)) => {
+                tracing::error!(fetch_error = ?err);
+                Err(FetchError::SubrequestHttpError {
+                    status_code: Some(parts.status.as_u16()),
+                    service: service_name.to_string(),
+                    reason: err.to_string(),
+                })
+            }
+            None => Err(FetchError::SubrequestMalformedResponse {
+                service: service_name.to_string(),
+                reason: "empty multipart response".to_string(),
+            }),
+        };
+        if let Ok(body) = &body {
+            if display_body {
+                tracing::info!(
+                    http.response.body = %String::from_utf8_lossy(body), apollo.subgraph.name = %service_name, "Raw response body from subgraph {service_name:?} received"
+                );
+            }
+        }
+        let incremental = multipart::decode_incremental(service_name.to_string(), parts_stream);
+        return Ok((parts, content_type, Some(body), Some(incremental.boxed())));
+    }
 
     let body = if content_type.is_ok() {
         let body = hyper::body::to_bytes(body)
@@ -1378,13 +1890,14 @@ async fn do_fetch(
         }
         None
     };
-    Ok((parts, content_type, body))
+    Ok((parts, content_type, body, None))
 }
 
 fn get_websocket_request(
     service_name: String,
     mut parts: http::request::Parts,
     subgraph_ws_cfg: &WebSocketConfiguration,
+    protocol: WebSocketProtocol,
 ) -> Result<http::Request<()>, FetchError> {
     let mut subgraph_url = url::Url::parse(&parts.uri.to_string()).map_err(|err| {
         tracing::error!("cannot parse subgraph url {}: {err:?}", parts.uri);
@@ -1424,10 +1937,9 @@ fn get_websocket_request(
             reason: "cannot create websocket client request".to_string(),
         }
     })?;
-    request.headers_mut().insert(
-        http::header::SEC_WEBSOCKET_PROTOCOL,
-        subgraph_ws_cfg.protocol.into(),
-    );
+    request
+        .headers_mut()
+        .insert(http::header::SEC_WEBSOCKET_PROTOCOL, protocol.into());
     parts.headers.extend(request.headers_mut().drain());
     *request.headers_mut() = parts.headers;
 
@@ -1551,7 +2063,6 @@ mod tests {
     use crate::plugins::traffic_shaping::Http2Config;
     use crate::protocols::websocket::ClientMessage;
     use crate::protocols::websocket::ServerMessage;
-    use crate::protocols::websocket::WebSocketProtocol;
     use crate::query_planner::fetch::OperationKind;
     use crate::Context;
 
@@ -2072,6 +2583,72 @@ mod tests {
         server.await.unwrap();
     }
 
+    // starts a local server emulating a subgraph returning an incremental delivery response
+    async fn emulate_subgraph_with_incremental_response(listener: TcpListener) {
+        async fn handle(request: http::Request<Body>) -> Result<http::Response<Body>, Infallible> {
+            assert_eq!(
+                request.headers().get(ACCEPT),
+                Some(&ACCEPT_INCREMENTAL_GRAPHQL_JSON)
+            );
+
+            Ok(http::Response::builder()
+                .header(CONTENT_TYPE, "multipart/mixed;boundary=\"-\";deferSpec=20220824")
+                .status(StatusCode::OK)
+                .body(Body::from(
+                    "\r\n---\r\ncontent-type: application/json; charset=utf-8\r\n\r\n{\"data\":{\"me\":{\"id\":\"1\"}},\"hasNext\":true}\r\n---\r\ncontent-type: application/json; charset=utf-8\r\n\r\n{\"incremental\":[{\"data\":{\"name\":\"Ada\"},\"path\":[\"me\"],\"label\":\"name\"}],\"hasNext\":false}\r\n-----\r\n",
+                ))
+                .unwrap())
+        }
+
+        let make_svc = make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle)) });
+        let server = Server::from_tcp(listener).unwrap().serve(make_svc);
+        server.await.unwrap();
+    }
+
+    // starts a local server emulating a subgraph returning an incremental delivery response
+    // whatever the request accepts
+    async fn emulate_subgraph_unexpected_multipart_response(listener: TcpListener) {
+        async fn handle(_request: http::Request<Body>) -> Result<http::Response<Body>, Infallible> {
+            Ok(http::Response::builder()
+                .header(CONTENT_TYPE, "multipart/mixed;boundary=\"-\";deferSpec=20220824")
+                .status(StatusCode::OK)
+                .body(Body::from(
+                    "\r\n---\r\ncontent-type: application/json; charset=utf-8\r\n\r\n{\"data\":{\"me\":{\"id\":\"1\"}},\"hasNext\":false}\r\n-----\r\n",
+                ))
+                .unwrap())
+        }
+
+        let make_svc = make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle)) });
+        let server = Server::from_tcp(listener).unwrap().serve(make_svc);
+        server.await.unwrap();
+    }
+
+    async fn emulate_subgraph_with_sse_subscription(listener: TcpListener) {
+        async fn handle(request: http::Request<Body>) -> Result<http::Response<Body>, Infallible> {
+            assert_eq!(request.uri().path(), "/sse");
+            assert_eq!(
+                request.headers().get(ACCEPT),
+                Some(&HeaderValue::from_static(EVENT_STREAM_CONTENT_TYPE))
+            );
+            assert_eq!(
+                request.headers().get("x-propagated"),
+                Some(&HeaderValue::from_static("yes"))
+            );
+
+            Ok(http::Response::builder()
+                .header(CONTENT_TYPE, EVENT_STREAM_CONTENT_TYPE)
+                .status(StatusCode::OK)
+                .body(Body::from(
+                    ":\n\nevent: next\ndata: {\"data\":{\"userWasCreated\":{\"username\":\"ada_lovelace\"}}}\n\nevent: complete\ndata:\n\n",
+                ))
+                .unwrap())
+        }
+
+        let make_svc = make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle)) });
+        let server = Server::from_tcp(listener).unwrap().serve(make_svc);
+        server.await.unwrap();
+    }
+
     async fn emulate_subgraph_with_callback_data(listener: TcpListener) {
         async fn handle(request: http::Request<Body>) -> Result<http::Response<Body>, Infallible> {
             let (parts, body) = request.into_parts();
@@ -2133,20 +2710,34 @@ mod tests {
                 }),
                 passthrough: Some(SubgraphPassthroughMode {
                     all: None,
-                    subgraphs: [(
-                        "test".to_string(),
-                        WebSocketConfiguration {
-                            path: Some(String::from("/ws")),
-                            protocol: WebSocketProtocol::default(),
-                            heartbeat_interval: HeartbeatInterval::new_disabled(),
-                        },
-                    )]
+                    subgraphs: [
+                        (
+                            "test".to_string(),
+                            WebSocketConfiguration {
+                                path: Some(String::from("/ws")),
+                                protocol: PassthroughProtocol::default(),
+                                heartbeat_interval: HeartbeatInterval::new_disabled(),
+                            },
+                        ),
+                        (
+                            "test_sse".to_string(),
+                            WebSocketConfiguration {
+                                path: Some(String::from("/sse")),
+                                protocol: PassthroughProtocol::Sse,
+                                heartbeat_interval: HeartbeatInterval::new_disabled(),
+                            },
+                        ),
+                    ]
                     .into(),
                 }),
             },
             enable_deduplication: true,
             max_opened_subscriptions: None,
             queue_capacity: None,
+            redis: None,
+            per_client: None,
+            max_lifetime: None,
+            idle_timeout: None,
         }
     }
 
@@ -2248,6 +2839,88 @@ mod tests {
         assert!(response.response.body().errors.is_empty());
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_subgraph_service_incremental_response() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket_addr = listener.local_addr().unwrap();
+        tokio::task::spawn(emulate_subgraph_with_incremental_response(listener));
+        let subgraph_service = SubgraphService::new(
+            "test",
+            true,
+            None,
+            Notify::default(),
+            HttpClientServiceFactory::from_config(
+                "test",
+                &Configuration::default(),
+                Http2Config::Enable,
+            ),
+        )
+        .expect("can create a SubgraphService");
+
+        let url = Uri::from_str(&format!("http://{socket_addr}")).unwrap();
+        let mut request = SubgraphRequest::builder()
+            .supergraph_request(supergraph_request("query"))
+            .subgraph_request(subgraph_http_request(url, "query"))
+            .operation_kind(OperationKind::Query)
+            .subgraph_name(String::from("test"))
+            .context(Context::new())
+            .build();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        request.deferred_stream = Some(sender);
+        let response = subgraph_service.oneshot(request).await.unwrap();
+        assert_eq!(
+            response.response.body().data,
+            Some(serde_json_bytes::json!({"me": {"id": "1"}}))
+        );
+
+        let incremental: Vec<graphql::Response> = receiver.recv().await.unwrap().collect().await;
+        assert_eq!(incremental.len(), 1);
+        assert_eq!(
+            incremental[0].incremental[0].data,
+            Some(serde_json_bytes::json!({"name": "Ada"}))
+        );
+        assert_eq!(incremental[0].incremental[0].label.as_deref(), Some("name"));
+        assert_eq!(incremental[0].has_next, Some(false));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_subgraph_service_unexpected_incremental_response() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket_addr = listener.local_addr().unwrap();
+        tokio::task::spawn(emulate_subgraph_unexpected_multipart_response(listener));
+        let subgraph_service = SubgraphService::new(
+            "test",
+            true,
+            None,
+            Notify::default(),
+            HttpClientServiceFactory::from_config(
+                "test",
+                &Configuration::default(),
+                Http2Config::Enable,
+            ),
+        )
+        .expect("can create a SubgraphService");
+
+        let url = Uri::from_str(&format!("http://{socket_addr}")).unwrap();
+        let response = subgraph_service
+            .oneshot(
+                SubgraphRequest::builder()
+                    .supergraph_request(supergraph_request("query"))
+                    .subgraph_request(subgraph_http_request(url, "query"))
+                    .operation_kind(OperationKind::Query)
+                    .subgraph_name(String::from("test"))
+                    .context(Context::new())
+                    .build(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.response.body().data, None);
+        assert_eq!(
+            response.response.body().errors[0].message,
+            "HTTP fetch failed from 'test': subgraph didn't return JSON (expected content-type: application/json or content-type: application/graphql-response+json; found content-type: multipart/mixed; boundary=\"-\"; deferSpec=20220824)"
+        );
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_subgraph_service_content_type_application_json() {
         let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
@@ -2458,6 +3131,64 @@ mod tests {
         spawned_task.abort();
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_subgraph_service_sse_subscription() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket_addr = listener.local_addr().unwrap();
+        let spawned_task = tokio::task::spawn(emulate_subgraph_with_sse_subscription(listener));
+        let subgraph_service = SubgraphService::new(
+            "test_sse",
+            true,
+            subscription_config().into(),
+            Notify::builder().build(),
+            HttpClientServiceFactory::from_config(
+                "test_sse",
+                &Configuration::default(),
+                Http2Config::Enable,
+            ),
+        )
+        .expect("can create a SubgraphService");
+        let (tx, rx) = mpsc::channel(2);
+        let mut rx_stream = ReceiverStream::new(rx);
+
+        let url = Uri::from_str(&format!("http://{socket_addr}")).unwrap();
+        let mut subgraph_request = subgraph_http_request(
+            url,
+            "subscription {\n  userWasCreated {\n    username\n  }\n}",
+        );
+        subgraph_request
+            .headers_mut()
+            .insert("x-propagated", HeaderValue::from_static("yes"));
+        let response = subgraph_service
+            .oneshot(
+                SubgraphRequest::builder()
+                    .supergraph_request(supergraph_request(
+                        "subscription {\n  userWasCreated {\n    username\n  }\n}",
+                    ))
+                    .subgraph_request(subgraph_request)
+                    .operation_kind(OperationKind::Subscription)
+                    .subscription_stream(tx)
+                    .subgraph_name(String::from("test_sse"))
+                    .context(Context::new())
+                    .build(),
+            )
+            .await
+            .unwrap();
+        assert!(response.response.body().errors.is_empty());
+
+        let mut gql_stream = rx_stream.next().await.unwrap();
+        let message = gql_stream.next().await.unwrap();
+        assert_eq!(
+            message,
+            graphql::Response::builder()
+                .subscribed(true)
+                .data(serde_json_bytes::json!({"userWasCreated": {"username": "ada_lovelace"}}))
+                .build()
+        );
+        assert!(gql_stream.next().await.is_none());
+        spawned_task.abort();
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_subgraph_service_websocket_with_error() {
         let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
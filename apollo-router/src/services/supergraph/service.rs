@@ -4,6 +4,7 @@ use std::collections::HashMap;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::task::Poll;
+use std::time::Duration;
 use std::time::Instant;
 
 use futures::future::BoxFuture;
@@ -38,9 +39,11 @@ use crate::plugins::telemetry::config_new::events::log_event;
 use crate::plugins::telemetry::config_new::events::SupergraphEventResponseLevel;
 use crate::plugins::telemetry::tracing::apollo_telemetry::APOLLO_PRIVATE_DURATION_NS;
 use crate::plugins::telemetry::Telemetry;
+use crate::plugins::telemetry::CLIENT_NAME;
 use crate::plugins::telemetry::LOGGING_DISPLAY_BODY;
 use crate::plugins::traffic_shaping::TrafficShaping;
 use crate::plugins::traffic_shaping::APOLLO_TRAFFIC_SHAPING;
+use crate::query_planner::subscription::OpenedClientSubscription;
 use crate::query_planner::subscription::SubscriptionHandle;
 use crate::query_planner::subscription::OPENED_SUBSCRIPTIONS;
 use crate::query_planner::subscription::SUBSCRIPTION_EVENT_SPAN_NAME;
@@ -209,6 +212,15 @@ async fn service_call(
         Some(QueryPlannerContent::Response { response }) => Ok(
             SupergraphResponse::new_from_graphql_response(*response, context),
         ),
+        Some(QueryPlannerContent::Unauthorized {
+            response,
+            unauthorized,
+        }) => {
+            context.extensions().lock().insert(unauthorized);
+            Ok(SupergraphResponse::new_from_graphql_response(
+                *response, context,
+            ))
+        }
         Some(QueryPlannerContent::IntrospectionDisabled) => {
             let mut response = SupergraphResponse::new_from_graphql_response(
                 graphql::Response::builder()
@@ -224,6 +236,11 @@ async fn service_call(
         }
 
         Some(QueryPlannerContent::Plan { plan }) => {
+            let unauthorized = &plan.query.unauthorized;
+            if !unauthorized.paths.is_empty() || !unauthorized.audited_types.is_empty() {
+                context.extensions().lock().insert(unauthorized.clone());
+            }
+
             let operation_name = body.operation_name.clone();
             let is_deferred = plan.is_deferred(operation_name.as_deref(), &variables);
             let is_subscription = plan.is_subscription(operation_name.as_deref());
@@ -269,6 +286,7 @@ async fn service_call(
             let ClientRequestAccepts {
                 multipart_defer: accepts_multipart_defer,
                 multipart_subscription: accepts_multipart_subscription,
+                event_stream: accepts_event_stream,
                 ..
             } = context
                 .extensions()
@@ -277,8 +295,8 @@ async fn service_call(
                 .cloned()
                 .unwrap_or_default();
             let mut subscription_tx = None;
-            if (is_deferred && !accepts_multipart_defer)
-                || (is_subscription && !accepts_multipart_subscription)
+            if (is_deferred && !(accepts_multipart_defer || accepts_event_stream))
+                || (is_subscription && !(accepts_multipart_subscription || accepts_event_stream))
             {
                 let (error_message, error_code) = if is_deferred {
                     (String::from("the router received a query with the @defer directive but the client does not accept multipart/mixed HTTP responses. To enable @defer support, add the HTTP header 'Accept: multipart/mixed;deferSpec=20220824'"), "DEFER_BAD_HEADER")
@@ -391,6 +409,7 @@ pub struct SubscriptionTaskParams {
     pub(crate) subscription_config: SubscriptionConfig,
     pub(crate) stream_rx: ReceiverStream<BoxGqlStream>,
     pub(crate) service_name: String,
+    pub(crate) opened_client_subscription: Option<OpenedClientSubscription>,
 }
 
 async fn subscription_task(
@@ -412,6 +431,8 @@ async fn subscription_task(
     let service_name = sub_params.service_name;
     let mut receiver = sub_params.stream_rx;
     let sender = sub_params.client_sender;
+    // Counted toward the client's limit when the subscription was planned
+    let _opened_client_subscription = sub_params.opened_client_subscription;
 
     // Get the rest of the query_plan to execute for subscription events
     let query_plan = match &query_plan.root {
@@ -467,6 +488,18 @@ async fn subscription_task(
     if limit_is_set {
         OPENED_SUBSCRIPTIONS.fetch_add(1, Ordering::Relaxed);
     }
+    // Client identifiers can be unique per user, so metrics only use the client name
+    let client = context
+        .get::<_, String>(CLIENT_NAME)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    u64_counter!(
+        "apollo.router.operations.subscriptions.opened",
+        "Number of subscriptions opened by clients",
+        1,
+        subscriptions.client = client.clone()
+    );
 
     let mut configuration_updated_rx = notify.subscribe_configuration();
     let mut schema_updated_rx = notify.subscribe_schema();
@@ -474,13 +507,18 @@ async fn subscription_task(
     let expires_in = crate::plugins::authentication::jwt_expires_in(&supergraph_req.context);
 
     let mut timeout = Box::pin(tokio::time::sleep(expires_in));
+    let mut lifetime = Box::pin(tokio::time::sleep(
+        subscription_config.max_lifetime.unwrap_or(Duration::MAX),
+    ));
+    let idle_timeout = subscription_config.idle_timeout.unwrap_or(Duration::MAX);
+    let mut idle = Box::pin(tokio::time::sleep(idle_timeout));
 
-    loop {
+    let close_reason = loop {
         tokio::select! {
             // We prefer to specify the order of checks within the select
             biased;
             _ = subscription_handle.closed_signal.recv() => {
-                break;
+                break "client_closed";
             }
             _ = &mut timeout => {
                 let response = Response::builder()
@@ -493,11 +531,40 @@ async fn subscription_task(
                     )
                     .build();
                 let _ = sender.send(response).await;
-                break;
+                break "jwt_expired";
+            },
+            _ = &mut lifetime => {
+                let response = Response::builder()
+                    .subscribed(false)
+                    .error(
+                        crate::error::Error::builder()
+                            .message("subscription closed because it reached its maximum lifetime")
+                            .extension_code("SUBSCRIPTION_MAX_LIFETIME")
+                            .build(),
+                    )
+                    .build();
+                let _ = sender.send(response).await;
+                break "max_lifetime";
+            },
+            _ = &mut idle => {
+                let response = Response::builder()
+                    .subscribed(false)
+                    .error(
+                        crate::error::Error::builder()
+                            .message("subscription closed because it did not receive any event before the idle timeout")
+                            .extension_code("SUBSCRIPTION_IDLE_TIMEOUT")
+                            .build(),
+                    )
+                    .build();
+                let _ = sender.send(response).await;
+                break "idle_timeout";
             },
             message = receiver.next() => {
                 match message {
                     Some(mut val) => {
+                        if let Some(deadline) = tokio::time::Instant::now().checked_add(idle_timeout) {
+                            idle.as_mut().reset(deadline);
+                        }
                         if display_body {
                             tracing::info!(http.request.body = ?val, apollo.subgraph.name = %service_name, "Subscription event body from subgraph {service_name:?}");
                         }
@@ -511,10 +578,10 @@ async fn subscription_task(
                             ).await;
                         if let Err(err) = res {
                                 tracing::error!("cannot send the subscription to the client: {err:?}");
-                            break;
+                            break "client_closed";
                         }
                     }
-                    None => break,
+                    None => break "completed",
                 }
             }
             Some(new_configuration) = configuration_updated_rx.next() => {
@@ -525,14 +592,14 @@ async fn subscription_task(
                         Ok(plugins) => Arc::new(plugins),
                         Err(err) => {
                             tracing::error!("cannot re-create plugins with the new configuration (closing existing subscription): {err:?}");
-                            break;
+                            break "configuration_reload";
                         },
                     };
                     let subgraph_services = match create_subgraph_services(&plugins, &execution_service_factory.schema, &conf).await {
                         Ok(subgraph_services) => subgraph_services,
                         Err(err) => {
                             tracing::error!("cannot re-create subgraph service with the new configuration (closing existing subscription): {err:?}");
-                            break;
+                            break "configuration_reload";
                         },
                     };
 
@@ -556,13 +623,20 @@ async fn subscription_task(
                         )
                         .await;
 
-                    break;
+                    break "schema_reload";
                 }
             }
         }
-    }
+    };
     drop(sender);
     tracing::trace!("Leaving the task for subscription");
+    u64_counter!(
+        "apollo.router.operations.subscriptions.closed",
+        "Number of subscriptions closed, by client and reason",
+        1,
+        subscriptions.client = client,
+        subscriptions.close_reason = close_reason
+    );
     if limit_is_set {
         OPENED_SUBSCRIPTIONS.fetch_sub(1, Ordering::Relaxed);
     }
@@ -766,8 +840,8 @@ impl PluggableSupergraphServiceBuilder {
         let query_planner_service = CachingQueryPlanner::new(
             self.planner,
             schema.clone(),
-            &configuration,
-            IndexMap::new(),
+            configuration.clone(),
+            self.plugins.clone(),
         )
         .await?;
 
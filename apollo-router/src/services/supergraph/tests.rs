@@ -1068,6 +1068,116 @@ async fn subscription_callback_schema_reload() {
     .unwrap());
 }
 
+async fn subscription_with_timeouts(
+    timeouts: serde_json::Value,
+) -> (Notify<String, graphql::Response>, supergraph::Response) {
+    let mut notify = Notify::builder().build();
+    let (handle, _) = notify
+        .create_or_subscribe("TEST_TOPIC".to_string(), false)
+        .await
+        .unwrap();
+    let subgraphs = MockedSubgraphs([
+            ("user", MockSubgraph::builder().with_json(
+                    serde_json::json!{{"query":"subscription{userWasCreated{name activeOrganization{__typename id}}}"}},
+                    serde_json::json!{{"data": {"userWasCreated": { "__typename": "User", "id": "1", "activeOrganization": { "__typename": "Organization", "id": "0" } }}}}
+                ).with_subscription_stream(handle.clone()).build()),
+            ("orga", MockSubgraph::builder().build())
+        ].into_iter().collect());
+
+    let mut subscription = serde_json::json!({ "enabled": true, "mode": {"callback": {"public_url": "http://localhost:4545/callback"}}});
+    subscription
+        .as_object_mut()
+        .unwrap()
+        .extend(timeouts.as_object().unwrap().clone());
+    let mut configuration: Configuration = serde_json::from_value(
+        serde_json::json!({"include_subgraph_errors": { "all": true }, "subscription": subscription}),
+    )
+    .unwrap();
+    configuration.notify = notify.clone();
+    let service = TestHarness::builder()
+        .configuration(Arc::new(configuration))
+        .schema(SCHEMA)
+        .extra_plugin(subgraphs)
+        .build_supergraph()
+        .await
+        .unwrap();
+
+    let request = supergraph::Request::fake_builder()
+        .query("subscription { userWasCreated { name } }")
+        .context(subscription_context())
+        .build()
+        .unwrap();
+    let mut stream = service.oneshot(request).await.unwrap();
+    let res = stream.next_response().await.unwrap();
+    assert!(res.errors.is_empty());
+    (notify, stream)
+}
+
+#[tokio::test]
+async fn subscription_max_lifetime() {
+    let (mut notify, mut stream) = subscription_with_timeouts(serde_json::json!({
+        "max_lifetime": "500ms"
+    }))
+    .await;
+
+    // Events do not extend the lifetime
+    notify
+        .broadcast(
+            graphql::Response::builder()
+                .data(serde_json_bytes::json!({"userWasCreated": { "name": "test" }}))
+                .build(),
+        )
+        .await
+        .unwrap();
+    let res = stream.next_response().await.unwrap();
+    assert!(res.errors.is_empty());
+
+    let res = tokio::time::timeout(Duration::from_secs(5), stream.next_response())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(res.subscribed, Some(false));
+    assert_eq!(
+        res.errors[0].extensions.get("code").unwrap(),
+        "SUBSCRIPTION_MAX_LIFETIME"
+    );
+    assert!(stream.next_response().await.is_none());
+}
+
+#[tokio::test]
+async fn subscription_idle_timeout() {
+    let (mut notify, mut stream) = subscription_with_timeouts(serde_json::json!({
+        "idle_timeout": "500ms"
+    }))
+    .await;
+
+    // Events reset the idle timeout
+    for _ in 0..3 {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        notify
+            .broadcast(
+                graphql::Response::builder()
+                    .data(serde_json_bytes::json!({"userWasCreated": { "name": "test" }}))
+                    .build(),
+            )
+            .await
+            .unwrap();
+        let res = stream.next_response().await.unwrap();
+        assert!(res.errors.is_empty());
+    }
+
+    let res = tokio::time::timeout(Duration::from_secs(5), stream.next_response())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(res.subscribed, Some(false));
+    assert_eq!(
+        res.errors[0].extensions.get("code").unwrap(),
+        "SUBSCRIPTION_IDLE_TIMEOUT"
+    );
+    assert!(stream.next_response().await.is_none());
+}
+
 #[tokio::test]
 async fn subscription_with_callback_with_limit() {
     let mut notify = Notify::builder().build();
@@ -54,6 +54,8 @@ pub(crate) enum SpecError {
     UnknownOperation(String),
     /// subscription operation is not supported
     SubscriptionNotSupported,
+    /// the @stream directive is not supported
+    StreamNotSupported,
     /// query hashing failed: {0}
     QueryHashing(String),
 }
@@ -85,6 +87,7 @@ impl ErrorExtension for SpecError {
             SpecError::ValidationError(_) => "GRAPHQL_VALIDATION_FAILED",
             SpecError::UnknownOperation(_) => "GRAPHQL_VALIDATION_FAILED",
             SpecError::SubscriptionNotSupported => "SUBSCRIPTION_NOT_SUPPORTED",
+            SpecError::StreamNotSupported => "STREAM_NOT_SUPPORTED",
             SpecError::QueryHashing(_) => "QUERY_HASHING",
         }
         .to_string()
@@ -7,6 +7,7 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::sync::Arc;
 
+use apollo_compiler::ast;
 use apollo_compiler::executable;
 use apollo_compiler::schema::ExtendedType;
 use apollo_compiler::ExecutableDocument;
@@ -286,6 +287,11 @@ impl Query {
         };
 
         let api_schema = schema.api_schema();
+        // The query planner does not split lists into incremental payloads, so @stream is
+        // rejected up front rather than being reported as an unknown directive
+        if !api_schema.directive_definitions.contains_key("stream") && uses_stream(&ast) {
+            return Err(SpecError::StreamNotSupported);
+        }
         let executable_document = match ast.to_executable_validate(api_schema) {
             Ok(doc) => doc,
             Err(errors) => {
@@ -1095,6 +1101,35 @@ impl Query {
     }
 }
 
+/// Whether any field of the document carries the `@stream` directive
+fn uses_stream(document: &ast::Document) -> bool {
+    fn selection_set_uses_stream(selection_set: &[ast::Selection]) -> bool {
+        selection_set.iter().any(|selection| match selection {
+            ast::Selection::Field(field) => {
+                field.directives.get("stream").is_some()
+                    || selection_set_uses_stream(&field.selection_set)
+            }
+            ast::Selection::InlineFragment(inline) => {
+                selection_set_uses_stream(&inline.selection_set)
+            }
+            ast::Selection::FragmentSpread(_) => false,
+        })
+    }
+
+    document
+        .definitions
+        .iter()
+        .any(|definition| match definition {
+            ast::Definition::OperationDefinition(operation) => {
+                selection_set_uses_stream(&operation.selection_set)
+            }
+            ast::Definition::FragmentDefinition(fragment) => {
+                selection_set_uses_stream(&fragment.selection_set)
+            }
+            _ => false,
+        })
+}
+
 /// Intermediate structure for arguments passed through the entire formatting
 struct FormatParameters<'a> {
     variables: &'a Object,
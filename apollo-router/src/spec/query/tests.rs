@@ -5915,3 +5915,33 @@ fn filtered_defer_fragment() {
 
     assert_json_snapshot!(response);
 }
+
+#[test]
+fn stream_is_not_supported() {
+    let schema = with_supergraph_boilerplate(
+        "type Query { me: User } type User { name: String friends: [User] }",
+        "Query",
+    );
+    let schema = Schema::parse_test(&schema, &Default::default()).expect("could not parse schema");
+
+    for query in [
+        "{ me { friends @stream { name } } }",
+        "{ me { ... on User { friends @stream(initialCount: 1) { name } } } }",
+        "query { me { ...F } } fragment F on User { friends @stream { name } }",
+    ] {
+        let err = Query::parse_document(query, None, &schema, &Default::default())
+            .expect_err("@stream should be rejected");
+        assert!(
+            matches!(err, crate::spec::SpecError::StreamNotSupported),
+            "unexpected error for {query}: {err:?}"
+        );
+    }
+
+    Query::parse_document(
+        "{ me { friends { name } } }",
+        None,
+        &schema,
+        &Default::default(),
+    )
+    .expect("could not parse query");
+}